@@ -0,0 +1,42 @@
+-- The tables of server/migrations/20241010120000_init_schema.js that rides need: the
+-- `rides` columns the Rust `Ride` model uses, and the keys of the `users` and `drivers`
+-- rows a ride references. Every statement is guarded, so against a database the Node
+-- migrations already set up this changes nothing.
+
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+CREATE TABLE IF NOT EXISTS users (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid()
+);
+
+CREATE TABLE IF NOT EXISTS drivers (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id uuid NOT NULL,
+    CONSTRAINT drivers_user_id_foreign FOREIGN KEY (user_id)
+        REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS rides (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    rider_id uuid NOT NULL,
+    rider_phone varchar(255),
+    driver_id uuid,
+    pickup jsonb NOT NULL,
+    dropoff jsonb NOT NULL,
+    bike_type varchar(255) NOT NULL DEFAULT 'analog',
+    distance_miles decimal(8, 2),
+    price_cents integer,
+    status varchar(255) NOT NULL DEFAULT 'requested',
+    notes text,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT rides_rider_id_foreign FOREIGN KEY (rider_id)
+        REFERENCES users (id) ON DELETE CASCADE,
+    CONSTRAINT rides_driver_id_foreign FOREIGN KEY (driver_id)
+        REFERENCES drivers (id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS rides_rider_phone_index ON rides (rider_phone);
+CREATE INDEX IF NOT EXISTS rides_status_created_at_index ON rides (status, created_at);
+CREATE INDEX IF NOT EXISTS rides_driver_id_status_index ON rides (driver_id, status);
+CREATE INDEX IF NOT EXISTS rides_rider_id_created_at_index ON rides (rider_id, created_at);
@@ -0,0 +1,24 @@
+-- The indexes of server/migrations/20241020120000_add_geo_indexes.js, each only over a
+-- PostGIS column the Node schema already has. These migrations add no columns to Node
+-- tables, and the Node schema never creates `rides.pickup_location`, so in practice only
+-- `drivers.location` is indexed.
+
+DO $$
+BEGIN
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema()
+            AND table_name = 'drivers' AND column_name = 'location'
+    ) THEN
+        CREATE INDEX IF NOT EXISTS idx_drivers_location_gist ON drivers USING GIST (location);
+    END IF;
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema()
+            AND table_name = 'rides' AND column_name = 'pickup_location'
+    ) THEN
+        CREATE INDEX IF NOT EXISTS idx_rides_pickup_location_gist
+            ON rides USING GIST (pickup_location);
+    END IF;
+END
+$$;
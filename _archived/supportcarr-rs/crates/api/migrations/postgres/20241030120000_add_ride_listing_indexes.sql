@@ -0,0 +1,14 @@
+-- Ride listings order and page on `created_at` in whole milliseconds, then id. The
+-- expression goes through UTC so it is immutable and can be indexed; queries must use it
+-- verbatim (`CREATED_AT_MS` in postgres_repository.rs) for the planner to match.
+
+CREATE INDEX IF NOT EXISTS rides_created_at_ms_id_index ON rides (
+    ((extract(epoch FROM date_trunc('milliseconds', created_at AT TIME ZONE 'UTC')) * 1000)::int8),
+    id
+);
+
+CREATE INDEX IF NOT EXISTS rides_status_created_at_ms_id_index ON rides (
+    status,
+    ((extract(epoch FROM date_trunc('milliseconds', created_at AT TIME ZONE 'UTC')) * 1000)::int8),
+    id
+);
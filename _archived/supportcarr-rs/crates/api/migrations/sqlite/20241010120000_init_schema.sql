@@ -0,0 +1,24 @@
+-- The ride columns of server/migrations/20241010120000_init_schema.js that the Rust
+-- `Ride` model uses. SQLite deployments have no users or drivers tables, so rider and
+-- pilot ids are plain text; timestamps are Unix milliseconds.
+
+CREATE TABLE IF NOT EXISTS rides (
+    id TEXT PRIMARY KEY NOT NULL,
+    rider_id TEXT NOT NULL,
+    rider_phone TEXT,
+    driver_id TEXT,
+    pickup TEXT NOT NULL,
+    dropoff TEXT NOT NULL,
+    bike_type TEXT NOT NULL DEFAULT 'analog',
+    distance_miles REAL,
+    price_cents INTEGER,
+    status TEXT NOT NULL DEFAULT 'requested',
+    notes TEXT,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS rides_rider_phone_index ON rides (rider_phone);
+CREATE INDEX IF NOT EXISTS rides_status_created_at_index ON rides (status, created_at);
+CREATE INDEX IF NOT EXISTS rides_driver_id_status_index ON rides (driver_id, status);
+CREATE INDEX IF NOT EXISTS rides_rider_id_created_at_index ON rides (rider_id, created_at);
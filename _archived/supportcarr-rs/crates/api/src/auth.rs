@@ -0,0 +1,409 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::Response;
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet, KeyAlgorithm};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use supportcarr_core::error::CoreError;
+use supportcarr_core::model::Ride;
+
+use crate::{ApiError, ApiState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Rider,
+    Pilot,
+    Dispatcher,
+    Admin,
+}
+
+/// The authenticated caller, taken from a verified JWT's `sub` and `role` claims.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub subject: String,
+    pub role: Role,
+}
+
+impl Principal {
+    pub fn new(role: Role, subject: impl Into<String>) -> Self {
+        Self {
+            subject: subject.into(),
+            role,
+        }
+    }
+
+    /// Dispatchers and admins see and manage every ride.
+    pub fn is_staff(&self) -> bool {
+        matches!(self.role, Role::Dispatcher | Role::Admin)
+    }
+
+    /// Riders see their own rides and pilots the rides assigned to them.
+    pub fn can_view_ride(&self, ride: &Ride) -> bool {
+        match self.role {
+            Role::Dispatcher | Role::Admin => true,
+            Role::Rider => ride.rider_id == self.subject,
+            Role::Pilot => ride.driver_id.as_deref() == Some(self.subject.as_str()),
+        }
+    }
+
+    pub fn require_role(&self, roles: &[Role]) -> Result<(), ApiError> {
+        if roles.contains(&self.role) {
+            Ok(())
+        } else {
+            Err(self.forbidden("this route"))
+        }
+    }
+
+    pub fn require_view(&self, ride: &Ride) -> Result<(), ApiError> {
+        if self.can_view_ride(ride) {
+            Ok(())
+        } else {
+            Err(self.forbidden(&format!("ride {}", ride.id)))
+        }
+    }
+
+    pub(crate) fn forbidden(&self, what: &str) -> ApiError {
+        ApiError::Forbidden(format!(
+            "{:?} {} may not use {what}",
+            self.role, self.subject
+        ))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Principal {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Principal>()
+            .cloned()
+            .ok_or(CoreError::Unauthorized.into())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    role: Role,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JwksError {
+    #[error("reading JWKS file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("parsing JWKS: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("JWKS key {kid:?}: {reason}")]
+    Key { kid: Option<String>, reason: String },
+}
+
+struct VerifyingKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Checks bearer tokens against a shared HS256 secret and/or RSA keys from a JWKS.
+/// Tokens must carry `sub`, `role` and an unexpired `exp`; `iss` and `aud` are checked
+/// when configured.
+#[derive(Default)]
+pub struct JwtVerifier {
+    keys: Vec<VerifyingKey>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway_secs: u64,
+}
+
+impl JwtVerifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_hs256_secret(mut self, secret: &[u8]) -> Self {
+        self.keys.push(VerifyingKey {
+            kid: None,
+            algorithm: Algorithm::HS256,
+            key: DecodingKey::from_secret(secret),
+        });
+        self
+    }
+
+    /// Add the RSA keys of a JWKS. Keys without `alg` are taken as RS256.
+    pub fn with_jwks(mut self, jwks: &JwkSet) -> Result<Self, JwksError> {
+        for jwk in &jwks.keys {
+            let kid = jwk.common.key_id.clone();
+            let key_error = |reason: String| JwksError::Key {
+                kid: kid.clone(),
+                reason,
+            };
+            if !matches!(jwk.algorithm, AlgorithmParameters::RSA(_)) {
+                return Err(key_error("only RSA keys are supported".into()));
+            }
+            let algorithm = match jwk.common.key_algorithm {
+                None | Some(KeyAlgorithm::RS256) => Algorithm::RS256,
+                Some(KeyAlgorithm::RS384) => Algorithm::RS384,
+                Some(KeyAlgorithm::RS512) => Algorithm::RS512,
+                Some(other) => return Err(key_error(format!("unsupported alg {other:?}"))),
+            };
+            let key = DecodingKey::from_jwk(jwk).map_err(|err| key_error(err.to_string()))?;
+            self.keys.push(VerifyingKey {
+                kid,
+                algorithm,
+                key,
+            });
+        }
+        Ok(self)
+    }
+
+    pub fn with_jwks_file(self, path: impl AsRef<Path>) -> Result<Self, JwksError> {
+        let jwks: JwkSet = serde_json::from_slice(&std::fs::read(path)?)?;
+        self.with_jwks(&jwks)
+    }
+
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    /// Clock skew allowed on `exp` and `nbf`.
+    pub fn with_leeway(mut self, seconds: u64) -> Self {
+        self.leeway_secs = seconds;
+        self
+    }
+
+    /// The principal a token was issued to. Any failure is `Unauthorized`; the reason is
+    /// only logged, so callers cannot probe which check failed.
+    pub fn verify(&self, token: &str) -> Result<Principal, CoreError> {
+        let header = decode_header(token).map_err(|err| {
+            tracing::debug!(error = %err, "malformed bearer token");
+            CoreError::Unauthorized
+        })?;
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.leeway_secs;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let candidates = self.keys.iter().filter(|key| {
+            key.algorithm == header.alg
+                && (header.kid.is_none() || key.kid.is_none() || key.kid == header.kid)
+        });
+        let mut last_error = None;
+        for candidate in candidates {
+            match decode::<Claims>(token, &candidate.key, &validation) {
+                Ok(data) => {
+                    return Ok(Principal {
+                        subject: data.claims.sub,
+                        role: data.claims.role,
+                    })
+                }
+                Err(err) => last_error = Some(err),
+            }
+        }
+        match last_error {
+            Some(err) => tracing::debug!(error = %err, "bearer token rejected"),
+            None => {
+                tracing::debug!(alg = ?header.alg, kid = ?header.kid, "no key for bearer token")
+            }
+        }
+        Err(CoreError::Unauthorized)
+    }
+}
+
+/// Middleware that verifies the `Authorization: Bearer` token and makes the caller
+/// available to handlers as a [`Principal`]. Requests without a valid token get 401.
+pub async fn authenticate(
+    State(state): State<ApiState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(CoreError::Unauthorized)?;
+    let principal = state.auth.verify(token.trim())?;
+    request.extensions_mut().insert(principal);
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use supportcarr_core::dispatch::now_millis;
+
+    pub(crate) const TEST_SECRET: &[u8] = b"supportcarr-test-secret";
+    const RS256_PRIVATE_KEY: &[u8] = include_bytes!("../testdata/jwt/rs256-private.pem");
+    const JWKS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/jwt/jwks.json");
+
+    fn claims(role: &str, subject: &str, expires_in_secs: i64) -> serde_json::Value {
+        let now = (now_millis() / 1000) as i64;
+        serde_json::json!({ "sub": subject, "role": role, "exp": now + expires_in_secs })
+    }
+
+    /// An HS256 token for [`TEST_SECRET`], valid for an hour.
+    pub(crate) fn hs256_token(role: Role, subject: &str) -> String {
+        let role = serde_json::to_value(role).unwrap();
+        encode(
+            &Header::default(),
+            &claims(role.as_str().unwrap(), subject, 3600),
+            &EncodingKey::from_secret(TEST_SECRET),
+        )
+        .unwrap()
+    }
+
+    fn rs256_token(kid: &str, claims: &serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        let key = EncodingKey::from_rsa_pem(RS256_PRIVATE_KEY).unwrap();
+        encode(&header, claims, &key).unwrap()
+    }
+
+    #[test]
+    fn verifies_hs256_tokens() {
+        let verifier = JwtVerifier::new().with_hs256_secret(TEST_SECRET);
+        assert_eq!(
+            verifier
+                .verify(&hs256_token(Role::Dispatcher, "ops-1"))
+                .unwrap(),
+            Principal::new(Role::Dispatcher, "ops-1")
+        );
+
+        let expired = encode(
+            &Header::default(),
+            &claims("rider", "rider-1", -600),
+            &EncodingKey::from_secret(TEST_SECRET),
+        )
+        .unwrap();
+        let unknown_role = encode(
+            &Header::default(),
+            &claims("superuser", "rider-1", 600),
+            &EncodingKey::from_secret(TEST_SECRET),
+        )
+        .unwrap();
+        let other_secret = JwtVerifier::new().with_hs256_secret(b"another-secret");
+        for (verifier, token) in [
+            (&verifier, expired),
+            (&verifier, unknown_role),
+            (&verifier, "not-a-jwt".to_string()),
+            (&other_secret, hs256_token(Role::Admin, "admin-1")),
+        ] {
+            assert!(matches!(
+                verifier.verify(&token),
+                Err(CoreError::Unauthorized)
+            ));
+        }
+    }
+
+    #[test]
+    fn verifies_rs256_tokens_from_jwks_file() {
+        let verifier = JwtVerifier::new()
+            .with_jwks_file(JWKS_PATH)
+            .unwrap()
+            .with_issuer("supportcarr");
+        let mut token_claims = claims("pilot", "pilot-1", 600);
+        token_claims["iss"] = "supportcarr".into();
+        assert_eq!(
+            verifier
+                .verify(&rs256_token("test-rs256", &token_claims))
+                .unwrap(),
+            Principal::new(Role::Pilot, "pilot-1")
+        );
+
+        // Unknown key id, wrong issuer, and an HS256 token the JWKS cannot vouch for.
+        assert!(verifier
+            .verify(&rs256_token("rotated-out", &token_claims))
+            .is_err());
+        token_claims["iss"] = "someone-else".into();
+        assert!(verifier
+            .verify(&rs256_token("test-rs256", &token_claims))
+            .is_err());
+        assert!(verifier
+            .verify(&hs256_token(Role::Pilot, "pilot-1"))
+            .is_err());
+    }
+
+    #[test]
+    fn riders_and_pilots_only_see_their_rides() {
+        let mut ride = crate::repository::conformance::ride(&Default::default());
+        ride.driver_id = Some("pilot-1".to_string());
+        let rider = Principal::new(Role::Rider, ride.rider_id.clone());
+
+        assert!(rider.can_view_ride(&ride));
+        assert!(Principal::new(Role::Pilot, "pilot-1").can_view_ride(&ride));
+        assert!(Principal::new(Role::Dispatcher, "ops-1").can_view_ride(&ride));
+        assert!(!Principal::new(Role::Rider, "someone-else").can_view_ride(&ride));
+        assert!(!Principal::new(Role::Pilot, "pilot-2").can_view_ride(&ride));
+    }
+
+    #[tokio::test]
+    async fn router_enforces_authentication_and_ownership() {
+        use axum::body::Body;
+        use axum::http::{Request, StatusCode};
+        use tower::ServiceExt;
+
+        let state = crate::test_state();
+        let ride = crate::repository::conformance::ride(&Default::default());
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        let app = crate::router(state);
+
+        let get = |token: Option<String>| {
+            let mut request = Request::get(format!("/rides/{}", ride.id));
+            if let Some(token) = token {
+                request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+        let status = |response: Response| response.status();
+
+        assert_eq!(status(get(None).await.unwrap()), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(get(Some("garbage".into())).await.unwrap()),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(
+                get(Some(hs256_token(Role::Rider, "someone-else")))
+                    .await
+                    .unwrap()
+            ),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(
+                get(Some(hs256_token(Role::Rider, &ride.rider_id)))
+                    .await
+                    .unwrap()
+            ),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(
+                get(Some(hs256_token(Role::Dispatcher, "ops-1")))
+                    .await
+                    .unwrap()
+            ),
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use supportcarr_core::dispatch::{DispatchCandidate, DispatchEvent};
+use supportcarr_core::events::DispatchDecision;
+use supportcarr_core::fsm::RideStatus;
+use supportcarr_core::matching::{match_batch, MatchConfig};
+use supportcarr_core::model::Ride;
+use tokio::task::JoinHandle;
+
+use crate::offers::{offer_next_excluding, record_decision, spawn_offer_timeout};
+use crate::{ApiError, ApiState};
+
+/// Settings for the periodic batch dispatch loop.
+#[derive(Debug, Clone)]
+pub struct BatchPolicy {
+    /// Rides requested during one interval are matched together at its end.
+    pub interval: Duration,
+    /// Upper bound on rides considered per round.
+    pub max_rides: usize,
+    pub matcher: MatchConfig,
+}
+
+impl Default for BatchPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            max_rides: 50,
+            matcher: MatchConfig::default(),
+        }
+    }
+}
+
+/// Run [`run_batch_round`] every `policy.interval` until shutdown begins. A round in
+/// progress is finished first.
+pub fn spawn_batch_dispatch(state: ApiState, policy: BatchPolicy) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(policy.interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = state.readiness.shutdown_begun() => break,
+            }
+            if let Err(err) = run_batch_round(&state, &policy).await {
+                tracing::warn!(error = %err, "batch dispatch round failed");
+            }
+        }
+    })
+}
+
+/// Match every `requested` ride without a live offer against the pilots near any of them,
+/// then offer each ride to its matched pilot. Rides flagged for ops are skipped, searches
+/// leave out pilots holding a live offer, and no pilot gets more than one offer per round.
+pub async fn run_batch_round(
+    state: &ApiState,
+    policy: &BatchPolicy,
+) -> Result<Vec<DispatchEvent>, ApiError> {
+    let mut rides = Vec::new();
+    for ride in state.repo.list_by_status(RideStatus::Requested).await? {
+        if rides.len() == policy.max_rides {
+            break;
+        }
+        let ride_id = ride.id.to_string();
+        let offered = state
+            .dispatch
+            .current_offer(&ride_id)
+            .await?
+            .is_some_and(|offer| !offer.is_expired());
+        if !offered && !state.queue.is_flagged(&ride_id).await? {
+            rides.push(ride);
+        }
+    }
+    if rides.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut pilots: HashMap<String, DispatchCandidate> = HashMap::new();
+    let mut radius_by_ride: HashMap<String, f64> = HashMap::new();
+    for ride in &rides {
+        let asked = state.dispatch.offered_pilots(&ride.id.to_string()).await?;
+        let outcome = state
+            .offers
+            .search
+            .search(state.dispatch.as_ref(), &ride.pickup, &asked)
+            .await?;
+        radius_by_ride.insert(ride.id.to_string(), outcome.radius_miles);
+        for candidate in outcome.candidates {
+            pilots.entry(candidate.pilot_id.clone()).or_insert(candidate);
+        }
+    }
+    let mut pilots: Vec<DispatchCandidate> = pilots.into_values().collect();
+    pilots.sort_by(|a, b| a.pilot_id.cmp(&b.pilot_id));
+
+    let by_id: HashMap<String, &Ride> = rides.iter().map(|ride| (ride.id.to_string(), ride)).collect();
+    let assignments = match_batch(&rides, &pilots, &policy.matcher);
+    let mut claimed: HashSet<String> = assignments
+        .iter()
+        .map(|assignment| assignment.pilot_id.clone())
+        .collect();
+    let mut events = Vec::new();
+    for assignment in assignments {
+        let ride = by_id[&assignment.ride_id];
+        let asked = state.dispatch.offered_pilots(&assignment.ride_id).await?;
+        // The pool is shared, so a ride can be matched to a pilot who already declined or
+        // timed out on it, and an immediate offer can claim a matched pilot mid-round; fall
+        // back to the next unasked candidate no one else has.
+        let offer = if asked.contains(&assignment.pilot_id) {
+            None
+        } else {
+            state
+                .dispatch
+                .create_offer(&assignment.ride_id, &assignment.pilot_id, state.offers.timeout)
+                .await?
+        };
+        let Some(offer) = offer else {
+            let excluded: Vec<String> = claimed.iter().cloned().collect();
+            let search = &state.offers.search;
+            if let Some(event) = offer_next_excluding(state, ride, search, &excluded).await? {
+                claimed.insert(event.pilot_id.clone());
+                events.push(event);
+            }
+            continue;
+        };
+        spawn_offer_timeout(state.clone(), ride.id, offer);
+        let event = DispatchEvent {
+            search_radius_miles: radius_by_ride.get(&assignment.ride_id).copied(),
+            eta_minutes: Some(assignment.eta_minutes.round() as u32),
+            ride_id: assignment.ride_id,
+            pilot_id: assignment.pilot_id,
+            explanation: None,
+        };
+        record_decision(state, DispatchDecision::Claim(event.clone())).await;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::conformance::{ride, Fixture};
+    use supportcarr_core::model::RideLocation;
+
+    /// A requested ride picked up at `pickup`.
+    async fn request(state: &ApiState, pickup: RideLocation) -> Ride {
+        let mut ride = ride(&Fixture::default());
+        ride.pickup = pickup;
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        ride
+    }
+
+    async fn place(state: &ApiState, pilot_id: &str, location: &RideLocation) {
+        state
+            .dispatch
+            .store_pilot_location(pilot_id, location)
+            .await
+            .unwrap();
+    }
+
+    async fn offered_to(state: &ApiState, ride: &Ride) -> Option<String> {
+        let offer = state.dispatch.current_offer(&ride.id.to_string()).await.unwrap();
+        offer.map(|offer| offer.pilot_id)
+    }
+
+    #[tokio::test]
+    async fn pilots_holding_an_offer_are_left_out() {
+        let state = crate::test_state();
+        let here = RideLocation::new(34.0522, -118.2437);
+        let offered = request(&state, here.clone()).await;
+        place(&state, "pilot-offered", &here).await;
+        place(&state, "pilot-free", &RideLocation::new(34.0622, -118.2437)).await;
+        state
+            .dispatch
+            .create_offer(&offered.id.to_string(), "pilot-offered", state.offers.timeout)
+            .await
+            .unwrap()
+            .expect("pilot is free");
+
+        let waiting = request(&state, here).await;
+        let events = run_batch_round(&state, &BatchPolicy::default()).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(offered_to(&state, &waiting).await.as_deref(), Some("pilot-free"));
+        assert_eq!(offered_to(&state, &offered).await.as_deref(), Some("pilot-offered"));
+    }
+
+    #[tokio::test]
+    async fn fallbacks_skip_pilots_matched_in_the_same_round() {
+        let state = crate::test_state();
+        let south = RideLocation::new(34.0522, -118.2437);
+        let north = RideLocation::new(34.0722, -118.2437);
+        let declined = request(&state, south.clone()).await;
+        let other = request(&state, north.clone()).await;
+        place(&state, "pilot-south", &south).await;
+        place(&state, "pilot-north", &north).await;
+        // The south ride was already turned down by the pilot it matches best.
+        let declined_id = declined.id.to_string();
+        let timeout = state.offers.timeout;
+        state
+            .dispatch
+            .create_offer(&declined_id, "pilot-south", timeout)
+            .await
+            .unwrap()
+            .expect("pilot is free");
+        state
+            .dispatch
+            .take_offer(&declined_id, "pilot-south")
+            .await
+            .unwrap();
+
+        let events = run_batch_round(&state, &BatchPolicy::default()).await.unwrap();
+        assert_eq!(events.len(), 1, "{events:?}");
+        assert_eq!(offered_to(&state, &other).await.as_deref(), Some("pilot-north"));
+        assert_eq!(offered_to(&state, &declined).await, None);
+    }
+}
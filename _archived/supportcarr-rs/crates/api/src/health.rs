@@ -0,0 +1,308 @@
+//! `/healthz` (liveness) and `/readyz` (readiness) for load balancers and orchestrators.
+//! Liveness only says the process answers; readiness also checks each dependency and
+//! reports its status and latency. Failure details are logged, never returned.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Json;
+use serde::Serialize;
+use supportcarr_core::error::CoreResult;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_util::task::TaskTracker;
+use utoipa::ToSchema;
+
+use crate::{ApiState, Routes};
+
+/// A dependency slower than this to answer counts as down.
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Process start time, the shutdown flag, and the detached tasks shutdown waits for. Once
+/// shutdown begins, `/readyz` fails so load balancers stop sending traffic while in-flight
+/// requests finish, and the background workers stop after their current round.
+#[derive(Debug, Clone)]
+pub struct Readiness {
+    started: Instant,
+    shutting_down: Arc<watch::Sender<bool>>,
+    tasks: TaskTracker,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            shutting_down: Arc::new(watch::channel(false).0),
+            tasks: TaskTracker::new(),
+        }
+    }
+}
+
+impl Readiness {
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.send_replace(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutting_down.borrow()
+    }
+
+    /// Resolves once [`Self::begin_shutdown`] has been called.
+    pub async fn shutdown_begun(&self) {
+        // The sender lives as long as `self`, so this cannot fail.
+        let _ = self
+            .shutting_down
+            .subscribe()
+            .wait_for(|shutting_down| *shutting_down)
+            .await;
+    }
+
+    /// Spawn a task that is not a worker but that [`crate::shutdown::stop_workers`] still
+    /// waits for, such as an offer timer.
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task)
+    }
+
+    /// Resolves once every task from [`Self::spawn`] has returned.
+    pub async fn tasks_finished(&self) {
+        self.tasks.close();
+        self.tasks.wait().await;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Error,
+    Unavailable,
+    ShuttingDown,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DependencyHealth {
+    pub status: HealthStatus,
+    pub latency_ms: f64,
+}
+
+/// Like the Node `healthController`, plus `checks` on `/readyz`. `uptime` is in seconds.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub uptime: f64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<&'static str, DependencyHealth>,
+}
+
+pub fn routes() -> Routes {
+    Routes::default()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+}
+
+/// Ping the dispatch engine and the ride repository concurrently.
+pub async fn check_dependencies(state: &ApiState) -> BTreeMap<&'static str, DependencyHealth> {
+    let (dispatch, repository) = tokio::join!(
+        timed("dispatch", state.dispatch.health_check()),
+        timed("repository", state.repo.health_check())
+    );
+    BTreeMap::from([("dispatch", dispatch), ("repository", repository)])
+}
+
+async fn timed(
+    dependency: &'static str,
+    check: impl Future<Output = CoreResult<()>>,
+) -> DependencyHealth {
+    let started = Instant::now();
+    let outcome = tokio::time::timeout(CHECK_TIMEOUT, check).await;
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let status = match outcome {
+        Ok(Ok(())) => HealthStatus::Ok,
+        Ok(Err(err)) => {
+            tracing::warn!(dependency, error = %err, "health check failed");
+            HealthStatus::Error
+        }
+        Err(_) => {
+            tracing::warn!(dependency, timeout = ?CHECK_TIMEOUT, "health check timed out");
+            HealthStatus::Error
+        }
+    };
+    DependencyHealth { status, latency_ms }
+}
+
+/// Always 200 while the process can serve requests. It checks no dependency, so a slow
+/// one neither delays the probe nor makes an orchestrator restart the process.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    security(()),
+    responses(
+        (status = 200, description = "The process is up", body = HealthReport),
+    )
+)]
+pub(crate) async fn healthz(State(state): State<ApiState>) -> Json<HealthReport> {
+    Json(HealthReport {
+        status: HealthStatus::Ok,
+        uptime: state.readiness.started.elapsed().as_secs_f64(),
+        checks: BTreeMap::new(),
+    })
+}
+
+/// 503 while shutting down or when any dependency is down.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    security(()),
+    responses(
+        (status = 200, description = "Ready for traffic", body = HealthReport),
+        (status = 503, description = "Shutting down or a dependency is down", body = HealthReport),
+    )
+)]
+pub(crate) async fn readyz(State(state): State<ApiState>) -> (StatusCode, Json<HealthReport>) {
+    let checks = check_dependencies(&state).await;
+    let status = if state.readiness.is_shutting_down() {
+        HealthStatus::ShuttingDown
+    } else if checks
+        .values()
+        .any(|check| check.status != HealthStatus::Ok)
+    {
+        HealthStatus::Unavailable
+    } else {
+        HealthStatus::Ok
+    };
+    let code = if status == HealthStatus::Ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let report = HealthReport {
+        status,
+        uptime: state.readiness.started.elapsed().as_secs_f64(),
+        checks,
+    };
+    (code, Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::{
+        InMemoryRideRepository, RideCursor, RideFilter, RidePage, RideRepository,
+    };
+    use async_trait::async_trait;
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::Value;
+    use supportcarr_core::fsm::{RideEvent, RideStatus};
+    use supportcarr_core::model::Ride;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    /// The in-memory repository, except that its store never answers a health check.
+    #[derive(Default)]
+    struct Unreachable(InMemoryRideRepository);
+
+    #[async_trait]
+    impl RideRepository for Unreachable {
+        async fn create_ride(&self, ride: Ride) -> CoreResult<()> {
+            self.0.create_ride(ride).await
+        }
+
+        async fn get_ride(&self, id: &Uuid) -> CoreResult<Ride> {
+            self.0.get_ride(id).await
+        }
+
+        async fn update_ride(&self, ride: Ride) -> CoreResult<()> {
+            self.0.update_ride(ride).await
+        }
+
+        async fn transition(
+            &self,
+            id: &Uuid,
+            from: RideStatus,
+            event: RideEvent,
+            pilot_id: Option<&str>,
+        ) -> CoreResult<Ride> {
+            self.0.transition(id, from, event, pilot_id).await
+        }
+
+        async fn list_by_status(&self, status: RideStatus) -> CoreResult<Vec<Ride>> {
+            self.0.list_by_status(status).await
+        }
+
+        async fn list_rides(
+            &self,
+            filter: &RideFilter,
+            after: Option<RideCursor>,
+            limit: usize,
+        ) -> CoreResult<RidePage> {
+            self.0.list_rides(filter, after, limit).await
+        }
+
+        async fn health_check(&self) -> CoreResult<()> {
+            std::future::pending().await
+        }
+    }
+
+    async fn probe(state: &ApiState, path: &str) -> (StatusCode, Value) {
+        let response = crate::router(state.clone())
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn readiness_fails_once_shutdown_begins() {
+        let state = crate::test_state();
+        let (code, ready) = probe(&state, "/readyz").await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(ready["status"], "ok");
+        assert_eq!(ready["checks"]["dispatch"]["status"], "ok");
+        assert_eq!(ready["checks"]["repository"]["status"], "ok");
+        assert!(ready["checks"]["repository"]["latency_ms"].is_f64());
+
+        state.readiness.begin_shutdown();
+        let (code, ready) = probe(&state, "/readyz").await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ready["status"], "shutting_down");
+        assert_eq!(probe(&state, "/healthz").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unreachable_dependencies_fail_readiness_only() {
+        let state = ApiState {
+            repo: Arc::new(Unreachable::default()),
+            ..crate::test_state()
+        };
+        let (code, ready) = probe(&state, "/readyz").await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ready["status"], "unavailable");
+        assert_eq!(ready["checks"]["dispatch"]["status"], "ok");
+        let repository = &ready["checks"]["repository"];
+        assert_eq!(repository["status"], "error");
+        assert!(repository.get("error").is_none(), "details stay in the log");
+
+        // Liveness answers without waiting on the repository.
+        let started = tokio::time::Instant::now();
+        let (code, health) = probe(&state, "/healthz").await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(health["status"], "ok");
+        assert!(health.get("checks").is_none());
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+}
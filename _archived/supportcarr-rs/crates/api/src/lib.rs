@@ -1,6 +1,9 @@
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::routing::{get, post};
@@ -8,21 +11,60 @@ use axum::Json;
 use serde::{Deserialize, Serialize};
 use supportcarr_core::dispatch::DispatchEngine;
 use supportcarr_core::error::CoreError;
-use supportcarr_core::fsm::{enforce_pilot_distance, estimate_distance_miles, RideEvent, RideStatus, RideStatusMachine};
+use supportcarr_core::dispatch::{now_millis, DispatchOffer, PilotScorer};
+use supportcarr_core::fsm::{enforce_pilot_distance, estimate_distance_miles, RideEvent};
+use supportcarr_core::events::DispatchEventLog;
+use supportcarr_core::fsm::RideStatus;
+use supportcarr_core::geo::BoundingBox;
+use supportcarr_core::history::{LineString, LocationHistory};
 use supportcarr_core::model::{Ride, RideLocation};
+use supportcarr_core::queue::PendingRideQueue;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
+pub mod auth;
+pub mod batch;
+pub mod health;
+pub mod lifecycle;
+pub mod offers;
+pub mod openapi;
+pub mod pilot_api;
+pub mod pilots;
+pub mod postgres_repository;
+pub mod redis_repository;
+pub mod redispatch;
+pub mod shutdown;
+pub mod tracking;
+pub mod updates;
 pub mod repository;
+pub mod sqlite_repository;
 
-use repository::RideRepository;
+use auth::{JwtVerifier, Principal, Role};
+use health::Readiness;
+use offers::OfferPolicy;
+use pilot_api::{LocationPolicy, PilotId};
+use pilots::PilotProfileStore;
+use repository::{RideCursor, RideFilter, RideRepository};
+use updates::RideUpdateHub;
 
 #[derive(Clone)]
 pub struct ApiState {
     pub repo: Arc<dyn RideRepository>,
     pub dispatch: Arc<dyn DispatchEngine>,
+    pub pilots: Arc<dyn PilotProfileStore>,
+    pub scorer: Arc<dyn PilotScorer>,
+    pub queue: Arc<dyn PendingRideQueue>,
+    pub history: Arc<dyn LocationHistory>,
+    pub events: Arc<dyn DispatchEventLog>,
+    pub offers: OfferPolicy,
+    pub locations: LocationPolicy,
+    pub auth: Arc<JwtVerifier>,
+    pub updates: RideUpdateHub,
+    /// Fails `/readyz` once shutdown begins.
+    pub readiness: Readiness,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct RideRequest {
     pub rider_id: String,
     pub pickup: RideLocation,
@@ -32,11 +74,120 @@ pub struct RideRequest {
     pub rider_phone: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+/// Query string of `GET /rides`. `status` takes a comma-separated list, times are Unix
+/// milliseconds and `bbox` is `west,south,east,north` around the pickup.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RideListQuery {
+    /// Comma-separated statuses, e.g. `requested,accepted`.
+    pub status: Option<String>,
+    pub rider_id: Option<String>,
+    pub pilot_id: Option<String>,
+    /// Unix milliseconds, inclusive.
+    pub created_after: Option<u64>,
+    /// Unix milliseconds, exclusive.
+    pub created_before: Option<u64>,
+    /// `west,south,east,north` around the pickup.
+    pub bbox: Option<String>,
+    /// `next_cursor` from the previous page.
+    pub cursor: Option<String>,
+    /// Page size, 1 to 200; 50 when omitted.
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RideListResponse {
+    pub rides: Vec<Ride>,
+    pub next_cursor: Option<String>,
+}
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+
+impl RideListQuery {
+    fn filter(&self) -> Result<RideFilter, ApiError> {
+        let statuses = self
+            .status
+            .iter()
+            .flat_map(|statuses| statuses.split(','))
+            .map(|status| RideStatus::try_from(status.trim()).map_err(ApiError::from))
+            .collect::<Result<_, _>>()?;
+        let pickup_within = self.bbox.as_deref().map(parse_bbox).transpose()?;
+        Ok(RideFilter {
+            statuses,
+            rider_id: self.rider_id.clone(),
+            driver_id: self.pilot_id.clone(),
+            rider_phone: None,
+            created_after_ms: self.created_after,
+            created_before_ms: self.created_before,
+            pickup_within,
+        })
+    }
+}
+
+fn parse_bbox(value: &str) -> Result<BoundingBox, ApiError> {
+    let invalid = || ApiError::BadRequest(format!("bbox must be west,south,east,north: {value}"));
+    let corners: Vec<f64> = value
+        .split(',')
+        .map(|part| part.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    let [west, south, east, north] = corners[..] else {
+        return Err(invalid());
+    };
+    let bounds = BoundingBox::new(RideLocation::new(south, west), RideLocation::new(north, east));
+    pilot_api::validate_location(&bounds.south_west)?;
+    pilot_api::validate_location(&bounds.north_east)?;
+    if south > north || west > east {
+        return Err(invalid());
+    }
+    Ok(bounds)
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct RideResponse {
     pub id: Uuid,
     pub status: String,
     pub driver_id: Option<String>,
+    /// Status version for `GET /rides/:id?wait_for_change=`.
+    pub version: u64,
+}
+
+impl RideResponse {
+    fn new(state: &ApiState, ride: &Ride) -> Self {
+        Self::with_version(ride, state.updates.version(ride.id))
+    }
+
+    fn with_version(ride: &Ride, version: u64) -> Self {
+        Self {
+            id: ride.id,
+            status: ride.status.clone(),
+            driver_id: ride.driver_id.clone(),
+            version,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RideStatusQuery {
+    /// Hold the request until the ride's version differs from this one, for up to
+    /// [`updates::LONG_POLL_TIMEOUT`].
+    pub wait_for_change: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RideEventRequest {
+    pub event: RideEvent,
+}
+
+/// A ride after a lifecycle event, with the events the same caller may send next.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RideEventResponse {
+    pub id: Uuid,
+    pub status: String,
+    pub driver_id: Option<String>,
+    pub allowed_events: Vec<RideEvent>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -45,10 +196,24 @@ pub enum ApiError {
     Core(#[from] CoreError),
     #[error("bad request: {0}")]
     BadRequest(String),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("rate limited; retry in {retry_after:?}")]
+    RateLimited { retry_after: std::time::Duration },
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> axum::response::Response {
+        if let ApiError::RateLimited { retry_after } = &self {
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(axum::http::header::RETRY_AFTER, seconds.to_string())],
+                self.to_string(),
+            )
+                .into_response();
+        }
+
         let (status, message) = match &self {
             ApiError::Core(CoreError::InvalidStatusTransition { .. }) => {
                 (StatusCode::BAD_REQUEST, self.to_string())
@@ -57,8 +222,19 @@ impl IntoResponse for ApiError {
             ApiError::Core(CoreError::PilotLimitExceeded(_)) => {
                 (StatusCode::BAD_REQUEST, self.to_string())
             }
+            ApiError::Core(
+                CoreError::OfferUnavailable
+                | CoreError::OfferExpired
+                | CoreError::PilotAssigned
+                | CoreError::AlreadyExists
+                | CoreError::StatusChanged { .. },
+            ) => {
+                (StatusCode::CONFLICT, self.to_string())
+            }
             ApiError::Core(CoreError::NotFound) => (StatusCode::NOT_FOUND, self.to_string()),
+            ApiError::Core(CoreError::Unauthorized) => (StatusCode::UNAUTHORIZED, self.to_string()),
             ApiError::BadRequest(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            ApiError::Forbidden(_) => (StatusCode::FORBIDDEN, self.to_string()),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
         };
 
@@ -67,18 +243,95 @@ impl IntoResponse for ApiError {
 }
 
 pub fn router(state: ApiState) -> axum::Router {
-    axum::Router::new()
-        .route("/rides", post(create_ride))
+    routes(&state).router.with_state(state)
+}
+
+/// Every route of [`router`], before the state is attached.
+pub fn routes(state: &ApiState) -> Routes {
+    Routes::default()
+        .route("/rides", get(list_rides).post(create_ride))
         .route("/rides/:id", get(get_ride_status))
-        .with_state(state)
+        .route("/rides/:id/events", post(post_ride_event))
+        .route("/rides/:id/stream", get(updates::stream_ride_sse))
+        .route("/rides/:id/ws", get(updates::stream_ride_ws))
+        .route("/rides/:id/track", get(get_ride_track))
+        .route("/rides/:id/offer", get(get_offer))
+        .route("/rides/:id/offer/accept", post(accept_offer))
+        .route("/rides/:id/offer/decline", post(decline_offer))
+        .merge(pilot_api::routes())
+        .map(|router| {
+            router.layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth::authenticate,
+            ))
+        })
+        // Added after the auth layer: the spec, docs and probes are public.
+        .merge(openapi::routes())
+        .merge(health::routes())
+}
+
+/// A router that remembers the paths routed on it, so the OpenAPI tests can check that
+/// every one of them is documented.
+#[derive(Default)]
+pub struct Routes {
+    pub router: axum::Router<ApiState>,
+    pub paths: Vec<&'static str>,
+}
+
+impl Routes {
+    pub fn route(
+        mut self,
+        path: &'static str,
+        method_router: axum::routing::MethodRouter<ApiState>,
+    ) -> Self {
+        self.router = self.router.route(path, method_router);
+        self.paths.push(path);
+        self
+    }
+
+    pub fn merge(mut self, other: Routes) -> Self {
+        self.router = self.router.merge(other.router);
+        self.paths.extend(other.paths);
+        self
+    }
+
+    /// Change the router without adding paths, e.g. to add a layer.
+    pub fn map(
+        mut self,
+        change: impl FnOnce(axum::Router<ApiState>) -> axum::Router<ApiState>,
+    ) -> Self {
+        self.router = change(self.router);
+        self
+    }
 }
 
-async fn create_ride(State(state): State<ApiState>, Json(payload): Json<RideRequest>) -> Result<Json<RideResponse>, ApiError> {
+#[utoipa::path(
+    post,
+    path = "/rides",
+    tag = "rides",
+    request_body = RideRequest,
+    responses(
+        (status = 200, description = "Ride created; it stays `requested` until a pilot accepts", body = RideResponse),
+        (status = 400, description = "Trip is longer than a pilot may ride"),
+        (status = 403, description = "Riders may only book for themselves"),
+    )
+)]
+async fn create_ride(
+    State(state): State<ApiState>,
+    principal: Principal,
+    Json(payload): Json<RideRequest>,
+) -> Result<Json<RideResponse>, ApiError> {
+    // Riders book for themselves; staff may book on a rider's behalf.
+    if !principal.is_staff()
+        && (principal.role != Role::Rider || payload.rider_id != principal.subject)
+    {
+        return Err(principal.forbidden("booking for this rider"));
+    }
     let distance = estimate_distance_miles(&payload.pickup, &payload.dropoff);
     enforce_pilot_distance(distance)?;
 
     let price_cents = 5000; // Flat pilot price mirrors JS implementation.
-    let mut ride = Ride::new(
+    let ride = Ride::new(
         payload.rider_id.clone(),
         payload.pickup.clone(),
         payload.dropoff.clone(),
@@ -90,46 +343,714 @@ async fn create_ride(State(state): State<ApiState>, Json(payload): Json<RideRequ
     );
 
     state.repo.create_ride(ride.clone()).await?;
+    state.updates.publish_status(&ride);
+    state
+        .queue
+        .enqueue(&ride.id.to_string(), now_millis())
+        .await?;
 
-    if let Some(candidate) = state
-        .dispatch
-        .find_nearby_pilots(&ride.pickup, 15.0, 1)
-        .await
-        .ok()
-        .and_then(|mut list| list.pop())
-    {
-        ride.driver_id = Some(candidate.pilot_id.clone());
-        ride.status = RideStatusMachine::apply_event(RideStatus::Requested, RideEvent::Accept)?
-            .to_string();
-        state
-            .dispatch
-            .mark_assigned(&candidate.pilot_id, &ride.id.to_string())
-            .await?;
-        state.repo.update_ride(ride.clone()).await?;
+    // The ride stays `requested` until a pilot accepts; dispatch failures leave it queued
+    // for the redispatch worker.
+    if state.offers.immediate {
+        if let Err(err) = offers::offer_next(&state, &ride).await {
+            tracing::warn!(ride_id = %ride.id, error = %err, "immediate dispatch failed");
+        }
     }
 
-    Ok(Json(RideResponse {
-        id: ride.id,
-        status: ride.status.clone(),
-        driver_id: ride.driver_id.clone(),
+    Ok(Json(RideResponse::new(&state, &ride)))
+}
+
+/// Rides matching the query, oldest first. Riders only list their own rides and pilots
+/// the rides assigned to them; dispatchers and admins list everything.
+#[utoipa::path(
+    get,
+    path = "/rides",
+    tag = "rides",
+    params(RideListQuery),
+    responses(
+        (status = 200, description = "One page of matching rides", body = RideListResponse),
+        (status = 400, description = "Malformed filter, cursor or limit"),
+        (status = 403, description = "Riders and pilots may only list their own rides"),
+    )
+)]
+async fn list_rides(
+    State(state): State<ApiState>,
+    principal: Principal,
+    Query(query): Query<RideListQuery>,
+) -> Result<Json<RideListResponse>, ApiError> {
+    let mut filter = query.filter()?;
+    let own = match principal.role {
+        Role::Rider => Some(&mut filter.rider_id),
+        Role::Pilot => Some(&mut filter.driver_id),
+        Role::Dispatcher | Role::Admin => None,
+    };
+    if let Some(own) = own {
+        if own.as_ref().is_some_and(|id| *id != principal.subject) {
+            return Err(principal.forbidden("other callers' rides"));
+        }
+        *own = Some(principal.subject.clone());
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return Err(ApiError::BadRequest(format!(
+            "limit must be between 1 and {MAX_PAGE_SIZE}"
+        )));
+    }
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(str::parse::<RideCursor>)
+        .transpose()
+        .map_err(ApiError::BadRequest)?;
+
+    let page = state.repo.list_rides(&filter, cursor, limit).await?;
+    Ok(Json(RideListResponse {
+        rides: page.rides,
+        next_cursor: page.next_cursor.map(|cursor| cursor.to_string()),
     }))
 }
 
+/// The ride's status. With `wait_for_change`, a long poll: the response comes once the
+/// status version moves past the one given, or after the timeout with the ride unchanged.
+#[utoipa::path(
+    get,
+    path = "/rides/{id}",
+    tag = "rides",
+    params(
+        ("id" = Uuid, Path, description = "Ride id"),
+        RideStatusQuery,
+    ),
+    responses(
+        (status = 200, description = "Current status, or the changed status after a long poll", body = RideResponse),
+        (status = 403, description = "Caller may not view this ride"),
+        (status = 404, description = "No such ride"),
+    )
+)]
 async fn get_ride_status(
     State(state): State<ApiState>,
+    principal: Principal,
     Path(id): Path<Uuid>,
+    Query(query): Query<RideStatusQuery>,
 ) -> Result<Json<RideResponse>, ApiError> {
-    let ride = state.repo.get_ride(&id).await?;
-    Ok(Json(RideResponse {
+    // The version is read first, so the ride returned is at least as new as it: a change
+    // landing in between makes the next poll return at once rather than wait past it.
+    let mut version = state.updates.version(id);
+    let mut ride = state.repo.get_ride(&id).await?;
+    principal.require_view(&ride)?;
+    // Finished rides no longer change, so there is nothing to wait for.
+    let finished = RideStatus::try_from(ride.status.as_str()).is_ok_and(|s| s.is_terminal());
+    if let (Some(seen), false) = (query.wait_for_change, finished) {
+        version = state
+            .updates
+            .wait_for_change(id, seen, updates::LONG_POLL_TIMEOUT)
+            .await;
+        if version != seen {
+            ride = state.repo.get_ride(&id).await?;
+        }
+    }
+    Ok(Json(RideResponse::with_version(&ride, version)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/rides/{id}/events",
+    tag = "rides",
+    params(
+        ("id" = Uuid, Path, description = "Ride id"),
+    ),
+    request_body = RideEventRequest,
+    responses(
+        (status = 200, description = "Ride after the event", body = RideEventResponse),
+        (status = 400, description = "Event not allowed from the ride's status"),
+        (status = 403, description = "Caller may not send this event"),
+        (status = 404, description = "No such ride"),
+    )
+)]
+async fn post_ride_event(
+    State(state): State<ApiState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<RideEventRequest>,
+) -> Result<Json<RideEventResponse>, ApiError> {
+    let ride = lifecycle::apply_ride_event(&state, id, &principal, payload.event).await?;
+    Ok(Json(RideEventResponse {
+        allowed_events: lifecycle::allowed_events(&principal, &ride),
         id: ride.id,
-        status: ride.status.clone(),
-        driver_id: ride.driver_id.clone(),
+        status: ride.status,
+        driver_id: ride.driver_id,
     }))
 }
 
-pub async fn run(state: ApiState) -> Result<(), Box<dyn std::error::Error>> {
-    let app = router(state);
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
-    axum::serve(listener, app).await?;
+#[utoipa::path(
+    get,
+    path = "/rides/{id}/track",
+    tag = "rides",
+    params(
+        ("id" = Uuid, Path, description = "Ride id"),
+    ),
+    responses(
+        (status = 200, description = "The pilot's route while on this ride", body = LineString),
+        (status = 403, description = "Caller may not view this ride"),
+        (status = 404, description = "No such ride"),
+    )
+)]
+async fn get_ride_track(
+    State(state): State<ApiState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<LineString>, ApiError> {
+    let ride = state.repo.get_ride(&id).await?;
+    principal.require_view(&ride)?;
+    let trail = state.history.ride_trail(&ride.id.to_string()).await?;
+    Ok(Json(LineString::from_trail(&trail)))
+}
+
+/// The pending offer for a ride; pilots only see offers made to them.
+#[utoipa::path(
+    get,
+    path = "/rides/{id}/offer",
+    tag = "offers",
+    params(
+        ("id" = Uuid, Path, description = "Ride id"),
+    ),
+    responses(
+        (status = 200, description = "The offer waiting on a pilot", body = DispatchOffer),
+        (status = 403, description = "Only pilots and staff see offers"),
+        (status = 409, description = "No live offer for this caller"),
+    )
+)]
+async fn get_offer(
+    State(state): State<ApiState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DispatchOffer>, ApiError> {
+    if !principal.is_staff() {
+        principal.require_role(&[Role::Pilot])?;
+    }
+    state
+        .dispatch
+        .current_offer(&id.to_string())
+        .await?
+        .filter(|offer| !offer.is_expired())
+        .filter(|offer| principal.is_staff() || offer.pilot_id == principal.subject)
+        .map(Json)
+        .ok_or(CoreError::OfferUnavailable.into())
+}
+
+#[utoipa::path(
+    post,
+    path = "/rides/{id}/offer/accept",
+    tag = "offers",
+    params(
+        ("id" = Uuid, Path, description = "Ride id"),
+    ),
+    responses(
+        (status = 200, description = "Ride assigned to the calling pilot", body = RideResponse),
+        (status = 403, description = "Only pilots accept offers"),
+        (status = 409, description = "Offer expired or made to another pilot"),
+    )
+)]
+async fn accept_offer(
+    State(state): State<ApiState>,
+    PilotId(pilot_id): PilotId,
+    Path(id): Path<Uuid>,
+) -> Result<Json<RideResponse>, ApiError> {
+    let ride = offers::accept_offer(&state, id, &pilot_id).await?;
+    Ok(Json(RideResponse::new(&state, &ride)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/rides/{id}/offer/decline",
+    tag = "offers",
+    params(
+        ("id" = Uuid, Path, description = "Ride id"),
+    ),
+    responses(
+        (status = 204, description = "Offer declined; the ride goes to the next pilot"),
+        (status = 403, description = "Only pilots decline offers"),
+        (status = 409, description = "Offer expired or made to another pilot"),
+    )
+)]
+async fn decline_offer(
+    State(state): State<ApiState>,
+    PilotId(pilot_id): PilotId,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    offers::decline_offer(&state, id, &pilot_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Serve the API alone on `bind` until SIGTERM or SIGINT, then drain. `supportcarr-server`
+/// also mounts the Twilio webhook.
+pub async fn run(state: ApiState, bind: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    let app = router(state.clone());
+    let grace = shutdown::GracePeriod::new(shutdown::PRE_DRAIN_DELAY, shutdown::DRAIN_TIMEOUT);
+    serve(listener, app, grace.signal(state)).await
+}
+
+/// Serve `app` until `signal` resolves, then stop accepting connections and let in-flight
+/// requests finish until the deadline `signal` resolved to. Connections still open then are
+/// closed. Upgraded WebSockets are not waited on; they end when [`ApiState::begin_shutdown`]
+/// closes the update streams.
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    app: axum::Router,
+    signal: impl Future<Output = tokio::time::Instant>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    tracing::info!(bind = %listener.local_addr()?, "listening");
+    // `axum::serve` detaches every connection, so the deadline could not close them.
+    let mut connections = tokio::task::JoinSet::new();
+    let (draining, _) = tokio::sync::watch::channel(false);
+    tokio::pin!(signal);
+    let deadline = loop {
+        tokio::select! {
+            deadline = &mut signal => break deadline,
+            Some(_) = connections.join_next() => {}
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    connections.spawn(serve_connection(stream, app.clone(), draining.subscribe()));
+                }
+                Err(err) => {
+                    // Out of file descriptors and the like; back off as `axum::serve` does.
+                    tracing::warn!(error = %err, "failed to accept a connection");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            },
+        }
+    };
+    drop(listener);
+    tracing::info!(connections = connections.len(), "draining in-flight requests");
+    draining.send_replace(true);
+    let drained = tokio::time::timeout_at(deadline, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        tracing::warn!(
+            connections = connections.len(),
+            "drain deadline passed; closing open connections"
+        );
+        connections.shutdown().await;
+    }
     Ok(())
 }
+
+/// HTTP/1.1 with upgrades, like `axum::serve`. Once `draining` turns true the request in
+/// flight finishes and the connection closes instead of being kept alive.
+async fn serve_connection(
+    stream: tokio::net::TcpStream,
+    app: axum::Router,
+    mut draining: tokio::sync::watch::Receiver<bool>,
+) {
+    let connection = hyper::server::conn::http1::Builder::new()
+        .serve_connection(
+            hyper_util::rt::TokioIo::new(stream),
+            hyper_util::service::TowerToHyperService::new(app),
+        )
+        .with_upgrades();
+    tokio::pin!(connection);
+    let served = tokio::select! {
+        served = connection.as_mut() => served,
+        // It only ever changes to true.
+        _ = draining.changed() => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    if let Err(err) = served {
+        tracing::debug!(error = %err, "connection ended with an error");
+    }
+}
+
+impl ApiState {
+    /// Fail readiness, end update streams and long polls, and let the workers finish their
+    /// current round, so [`serve`] can drain.
+    pub fn begin_shutdown(&self) {
+        self.readiness.begin_shutdown();
+        self.updates.close();
+    }
+}
+
+/// State backed entirely by the in-memory stores, for handler tests.
+#[cfg(test)]
+pub(crate) fn test_state() -> ApiState {
+    use supportcarr_core::{
+        InMemoryDispatchEngine, InMemoryDispatchEventLog, InMemoryLocationHistory,
+        InMemoryPendingRideQueue, WeightedPilotScorer,
+    };
+
+    ApiState {
+        repo: Arc::new(repository::InMemoryRideRepository::default()),
+        dispatch: Arc::new(InMemoryDispatchEngine::default()),
+        pilots: Arc::new(pilots::InMemoryPilotProfileStore::default()),
+        scorer: Arc::new(WeightedPilotScorer::default()),
+        queue: Arc::new(InMemoryPendingRideQueue::default()),
+        history: Arc::new(InMemoryLocationHistory::default()),
+        events: Arc::new(InMemoryDispatchEventLog::default()),
+        offers: OfferPolicy::default(),
+        locations: LocationPolicy::default(),
+        auth: Arc::new(JwtVerifier::new().with_hs256_secret(auth::tests::TEST_SECRET)),
+        updates: RideUpdateHub::default(),
+        readiness: Readiness::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn seeded_state() -> ApiState {
+        let state = test_state();
+        for (rider_id, created_at_ms) in [("rider-1", 1), ("rider-1", 2), ("rider-2", 3)] {
+            let mut ride = repository::conformance::ride(&repository::conformance::Fixture {
+                rider_id: rider_id.to_string(),
+                pilot_id: "pilot-1".to_string(),
+            });
+            ride.created_at_ms = created_at_ms;
+            state.repo.create_ride(ride).await.unwrap();
+        }
+        state
+    }
+
+    async fn list(
+        state: &ApiState,
+        principal: Principal,
+        query: RideListQuery,
+    ) -> Result<RideListResponse, ApiError> {
+        list_rides(State(state.clone()), principal, Query(query))
+            .await
+            .map(|Json(response)| response)
+    }
+
+    #[tokio::test]
+    async fn listing_pages_with_cursor() {
+        let state = seeded_state().await;
+        let dispatcher = Principal::new(Role::Dispatcher, "ops-1");
+        let first = list(
+            &state,
+            dispatcher.clone(),
+            RideListQuery {
+                status: Some("requested, accepted".to_string()),
+                limit: Some(2),
+                ..RideListQuery::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(first.rides.len(), 2);
+
+        let rest = list(
+            &state,
+            dispatcher,
+            RideListQuery {
+                cursor: first.next_cursor,
+                limit: Some(2),
+                ..RideListQuery::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(rest.rides.len(), 1);
+        assert_eq!(rest.rides[0].rider_id, "rider-2");
+        assert_eq!(rest.next_cursor, None);
+    }
+
+    #[tokio::test]
+    async fn riders_list_only_their_rides() {
+        let state = seeded_state().await;
+        let rider = Principal::new(Role::Rider, "rider-1");
+        let own = list(&state, rider.clone(), RideListQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(own.rides.len(), 2);
+        assert!(own.rides.iter().all(|ride| ride.rider_id == "rider-1"));
+
+        let others = RideListQuery {
+            rider_id: Some("rider-2".to_string()),
+            ..RideListQuery::default()
+        };
+        assert!(matches!(
+            list(&state, rider, others).await,
+            Err(ApiError::Forbidden(_))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn status_long_poll_returns_on_change() {
+        let state = test_state();
+        let mut ride = repository::conformance::ride(&Default::default());
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        let rider = Principal::new(Role::Rider, ride.rider_id.clone());
+        let poll = |seen| {
+            get_ride_status(
+                State(state.clone()),
+                rider.clone(),
+                Path(ride.id),
+                Query(RideStatusQuery {
+                    wait_for_change: Some(seen),
+                }),
+            )
+        };
+
+        let Json(unchanged) = poll(0).await.unwrap();
+        assert_eq!(
+            (unchanged.status.as_str(), unchanged.version),
+            ("requested", 0)
+        );
+
+        let waiting = tokio::spawn(poll(0));
+        tokio::task::yield_now().await;
+        ride.status = "cancelled".to_string();
+        state.repo.update_ride(ride.clone()).await.unwrap();
+        let update = state.updates.publish_status(&ride);
+        let Json(changed) = waiting.await.unwrap().unwrap();
+        assert_eq!(
+            (changed.status.as_str(), changed.version),
+            ("cancelled", update.id)
+        );
+        // Finished rides answer at once.
+        let started = tokio::time::Instant::now();
+        let Json(finished) = poll(update.id).await.unwrap();
+        assert_eq!(finished.status, "cancelled");
+        assert_eq!(started.elapsed(), std::time::Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn listing_rejects_bad_queries() {
+        let state = seeded_state().await;
+        let admin = Principal::new(Role::Admin, "admin-1");
+        for query in [
+            RideListQuery {
+                bbox: Some("-118.3,34.1,-118.2".to_string()),
+                ..RideListQuery::default()
+            },
+            RideListQuery {
+                bbox: Some("-118.2,34.1,-118.3,34.0".to_string()),
+                ..RideListQuery::default()
+            },
+            RideListQuery {
+                status: Some("lost".to_string()),
+                ..RideListQuery::default()
+            },
+            RideListQuery {
+                cursor: Some("yesterday".to_string()),
+                ..RideListQuery::default()
+            },
+            RideListQuery {
+                limit: Some(MAX_PAGE_SIZE + 1),
+                ..RideListQuery::default()
+            },
+        ] {
+            assert!(list(&state, admin.clone(), query).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn track_route_replays_the_ride_trail() {
+        use axum::body::{to_bytes, Body};
+        use axum::http::header::AUTHORIZATION;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let state = test_state();
+        let mut ride = repository::conformance::ride(&Default::default());
+        ride.driver_id = Some("pilot-1".to_string());
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        let ride_key = ride.id.to_string();
+        let points = [(None, 34.0), (Some(ride_key.as_str()), 34.01), (Some(&ride_key), 34.02)];
+        for (ride_id, lat) in points {
+            state
+                .history
+                .record("pilot-1", ride_id, &RideLocation::new(lat, -118.0), now_millis())
+                .await
+                .unwrap();
+        }
+        let app = router(state);
+        let track = |role, subject: &str| {
+            let request = Request::get(format!("/rides/{}/track", ride.id))
+                .header(
+                    AUTHORIZATION,
+                    format!("Bearer {}", auth::tests::hs256_token(role, subject)),
+                )
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let response = track(Role::Rider, &ride.rider_id).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let line: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        // Only points recorded against the ride, as [lng, lat].
+        assert_eq!(
+            line,
+            serde_json::json!({
+                "type": "LineString",
+                "coordinates": [[-118.0, 34.01], [-118.0, 34.02]],
+            })
+        );
+
+        let response = track(Role::Rider, "someone-else").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    /// Send `body` to `uri` as `subject` and return the status and JSON body, if any.
+    async fn call(
+        state: &ApiState,
+        (role, subject): (Role, &str),
+        method: &str,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        use axum::body::{to_bytes, Body};
+        use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let token = auth::tests::hs256_token(role, subject);
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap();
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or_default())
+    }
+
+    const RIDER: (Role, &str) = (Role::Rider, "rider-1");
+    const NEAR: (Role, &str) = (Role::Pilot, "pilot-near");
+    const FAR: (Role, &str) = (Role::Pilot, "pilot-far");
+    const OPS: (Role, &str) = (Role::Dispatcher, "ops-1");
+
+    /// Two pilots north of the pickup every test ride uses, `pilot-near` the closer one.
+    async fn pilots_state() -> ApiState {
+        let state = test_state();
+        for ((_, pilot_id), lat) in [(NEAR, 34.0530), (FAR, 34.0600)] {
+            state
+                .dispatch
+                .store_pilot_location(pilot_id, &RideLocation::new(lat, -118.2437))
+                .await
+                .unwrap();
+        }
+        state
+    }
+
+    /// Book a ride for `rider-1` and return its id.
+    async fn book(state: &ApiState) -> String {
+        let request = serde_json::json!({
+            "rider_id": "rider-1",
+            "pickup": { "lat": 34.0522, "lng": -118.2437 },
+            "dropoff": { "lat": 34.0407, "lng": -118.2468 },
+        });
+        let (status, ride) = call(state, RIDER, "POST", "/rides", Some(request)).await;
+        assert_eq!(status, StatusCode::OK, "{ride}");
+        assert_eq!(ride["status"], "requested");
+        ride["id"].as_str().unwrap().to_string()
+    }
+
+    async fn release_reasons(state: &ApiState) -> Vec<String> {
+        let entries = state.events.read_after(None, 100).await.unwrap();
+        entries
+            .into_iter()
+            .filter_map(|entry| match entry.decision {
+                supportcarr_core::DispatchDecision::Release { reason, .. } => Some(reason),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn booked_rides_are_offered_and_accepted() {
+        let state = pilots_state().await;
+        let id = book(&state).await;
+
+        let (status, offer) = call(&state, NEAR, "GET", &format!("/rides/{id}/offer"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(offer["pilot_id"], "pilot-near");
+        let (status, _) = call(&state, FAR, "GET", &format!("/rides/{id}/offer"), None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let uri = format!("/rides/{id}/offer/accept");
+        let (status, _) = call(&state, FAR, "POST", &uri, None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, ride) = call(&state, NEAR, "POST", &uri, None).await;
+        assert_eq!(status, StatusCode::OK, "{ride}");
+        assert_eq!(ride["status"], "accepted");
+        assert_eq!(ride["driver_id"], "pilot-near");
+        let (status, assigned) = call(&state, NEAR, "GET", "/pilots/me/assignment", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(assigned["id"], id.as_str());
+        assert!(state.queue.pending(10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn assigned_pilots_cannot_accept_another_ride() {
+        let state = pilots_state().await;
+        let first = book(&state).await;
+        let uri = format!("/rides/{first}/offer/accept");
+        let (status, _) = call(&state, NEAR, "POST", &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let second = book(&state).await;
+        // Offer the second ride to the assigned pilot as well, as a stale offer would.
+        state.dispatch.take_offer(&second, "pilot-far").await.unwrap();
+        state
+            .dispatch
+            .create_offer(&second, "pilot-near", state.offers.timeout)
+            .await
+            .unwrap()
+            .expect("pilot is free");
+        let uri = format!("/rides/{second}/offer/accept");
+        let (status, _) = call(&state, NEAR, "POST", &uri, None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (_, ride) = call(&state, RIDER, "GET", &format!("/rides/{second}"), None).await;
+        assert_eq!(ride["status"], "requested");
+        let (_, assigned) = call(&state, NEAR, "GET", "/pilots/me/assignment", None).await;
+        assert_eq!(assigned["id"], first.as_str());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn declined_and_expired_offers_move_on() {
+        let state = pilots_state().await;
+        let id = book(&state).await;
+
+        let uri = format!("/rides/{id}/offer/decline");
+        let (status, _) = call(&state, NEAR, "POST", &uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, offer) = call(&state, OPS, "GET", &format!("/rides/{id}/offer"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(offer["pilot_id"], "pilot-far");
+
+        // The far pilot never answers; with nobody left the ride waits for redispatch.
+        tokio::time::sleep(state.offers.timeout + Duration::from_secs(1)).await;
+        let (status, _) = call(&state, OPS, "GET", &format!("/rides/{id}/offer"), None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(release_reasons(&state).await, vec!["declined", "timeout"]);
+        let (_, ride) = call(&state, RIDER, "GET", &format!("/rides/{id}"), None).await;
+        assert_eq!(ride["status"], "requested");
+        assert_eq!(state.queue.pending(10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn pilots_holding_an_offer_are_not_offered_another() {
+        let state = pilots_state().await;
+        let first = book(&state).await;
+        let second = book(&state).await;
+
+        for (id, pilot) in [(first, "pilot-near"), (second, "pilot-far")] {
+            let (_, offer) = call(&state, OPS, "GET", &format!("/rides/{id}/offer"), None).await;
+            assert_eq!(offer["pilot_id"], pilot, "{id}");
+        }
+        // With both pilots holding an offer, a third ride waits.
+        let third = book(&state).await;
+        let (status, _) = call(&state, OPS, "GET", &format!("/rides/{third}/offer"), None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,328 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use supportcarr_core::error::CoreError;
+use supportcarr_core::events::DispatchDecision;
+use supportcarr_core::fsm::{RideEvent, RideStatus, RideStatusMachine};
+use supportcarr_core::model::Ride;
+use supportcarr_twilio::TwilioRideStore;
+use uuid::Uuid;
+
+use crate::auth::{Principal, Role};
+use crate::offers::{record_decision, withdraw_offer};
+use crate::{ApiError, ApiState};
+
+/// Whether `principal` may send `event` for `ride`. Riders may only cancel their own
+/// rides and the assigned pilot drives the trip. Dispatchers cancel, including for no
+/// pilot, safety or geofence reasons. Accepting assigns a pilot, which only the offer
+/// routes do, so no one may send it here.
+pub fn may_send(principal: &Principal, ride: &Ride, event: RideEvent) -> bool {
+    match principal.role {
+        _ if event == RideEvent::Accept => false,
+        Role::Admin => true,
+        Role::Dispatcher => matches!(
+            event,
+            RideEvent::Cancel
+                | RideEvent::CancelSafety
+                | RideEvent::CancelNoPilot
+                | RideEvent::RejectGeofence
+        ),
+        Role::Rider => event == RideEvent::Cancel && ride.rider_id == principal.subject,
+        Role::Pilot => {
+            ride.driver_id.as_deref() == Some(principal.subject.as_str())
+                && matches!(
+                    event,
+                    RideEvent::Depart
+                        | RideEvent::Arrive
+                        | RideEvent::BeginTransit
+                        | RideEvent::Complete
+                        | RideEvent::Cancel
+                        | RideEvent::CancelNoShow
+                        | RideEvent::CancelSafety
+                )
+        }
+    }
+}
+
+/// Events `principal` may send for `ride` in its current status.
+pub fn allowed_events(principal: &Principal, ride: &Ride) -> Vec<RideEvent> {
+    let Ok(status) = RideStatus::try_from(ride.status.as_str()) else {
+        return Vec::new();
+    };
+    RideStatusMachine::allowed_events(status)
+        .into_iter()
+        .filter(|event| may_send(principal, ride, *event))
+        .collect()
+}
+
+/// Apply a lifecycle event sent by `principal` and persist the new status, as
+/// [`transition_ride`] does once the sender is allowed to send it.
+pub async fn apply_ride_event(
+    state: &ApiState,
+    ride_id: Uuid,
+    principal: &Principal,
+    event: RideEvent,
+) -> Result<Ride, ApiError> {
+    let ride = state.repo.get_ride(&ride_id).await?;
+    if !may_send(principal, &ride, event) {
+        return Err(principal.forbidden(&format!("{event:?} on ride {}", ride.id)));
+    }
+    transition_ride(state, &ride, event).await
+}
+
+/// Apply `event` to `ride` and persist the new status, without checking who sent it. The
+/// event only lands if the ride is still in the status it was read in. Finishing a ride
+/// frees its pilot for dispatch, withdraws any pending offer and drops it from the pending
+/// queue.
+pub async fn transition_ride(
+    state: &ApiState,
+    ride: &Ride,
+    event: RideEvent,
+) -> Result<Ride, ApiError> {
+    let from = RideStatus::try_from(ride.status.as_str())?;
+    let ride = state.repo.transition(&ride.id, from, event, None).await?;
+    state.updates.publish_status(&ride);
+
+    let status = RideStatus::try_from(ride.status.as_str())?;
+    if status.is_terminal() {
+        let ride_key = ride.id.to_string();
+        withdraw_offer(state, &ride_key, status.as_str()).await?;
+        state.queue.remove(&ride_key).await?;
+        if let Some(pilot_id) = &ride.driver_id {
+            state.dispatch.release_pilot(pilot_id).await?;
+            record_decision(
+                state,
+                DispatchDecision::Release {
+                    ride_id: ride_key,
+                    pilot_id: pilot_id.clone(),
+                    reason: status.to_string(),
+                },
+            )
+            .await;
+        }
+    }
+    Ok(ride)
+}
+
+/// The SMS webhook's ride store: lookups and saves go to `store`, and status changes go
+/// through [`transition_ride`], so a texted cancel releases the pilot and notifies
+/// subscribers like one sent to the API. The webhook has already checked the signature.
+pub struct SmsRideStore {
+    pub state: ApiState,
+    pub store: Arc<dyn TwilioRideStore>,
+}
+
+#[async_trait]
+impl TwilioRideStore for SmsRideStore {
+    async fn find_by_phone(&self, phone: &str) -> Result<Option<Ride>, CoreError> {
+        self.store.find_by_phone(phone).await
+    }
+
+    async fn save(&self, ride: Ride) -> Result<(), CoreError> {
+        self.store.save(ride).await
+    }
+
+    async fn update_status(&self, ride: &Ride, event: RideEvent) -> Result<Ride, CoreError> {
+        transition_ride(&self.state, ride, event)
+            .await
+            .map_err(|err| match err {
+                ApiError::Core(err) => err,
+                other => CoreError::Dispatch(other.to_string()),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::offers::accept_offer;
+    use crate::test_state;
+    use std::time::Duration;
+    use supportcarr_core::error::CoreError;
+    use supportcarr_core::model::RideLocation;
+
+    async fn assigned_ride(state: &ApiState) -> Ride {
+        let mut ride = Ride::new(
+            "rider-1".to_string(),
+            RideLocation::new(34.0522, -118.2437),
+            RideLocation::new(34.0407, -118.2468),
+            None,
+            None,
+            Some("+15555550123".to_string()),
+            1.0,
+            5000,
+        );
+        ride.status = RideStatus::Accepted.to_string();
+        ride.driver_id = Some("pilot-1".to_string());
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        state
+            .dispatch
+            .mark_assigned("pilot-1", &ride.id.to_string())
+            .await
+            .unwrap();
+        ride
+    }
+
+    #[tokio::test]
+    async fn pilot_drives_ride_to_completion() {
+        let state = test_state();
+        let ride = assigned_ride(&state).await;
+        let pilot = Principal::new(Role::Pilot, "pilot-1");
+
+        assert_eq!(
+            allowed_events(&pilot, &ride),
+            vec![
+                RideEvent::Depart,
+                RideEvent::Arrive,
+                RideEvent::Cancel,
+                RideEvent::CancelNoShow,
+                RideEvent::CancelSafety,
+            ]
+        );
+        for event in [RideEvent::Depart, RideEvent::Arrive, RideEvent::Complete] {
+            apply_ride_event(&state, ride.id, &pilot, event).await.unwrap();
+        }
+        let stored = state.repo.get_ride(&ride.id).await.unwrap();
+        assert_eq!(stored.status, RideStatus::Completed.as_str());
+        assert!(allowed_events(&pilot, &stored).is_empty());
+
+        // The pilot is free to be dispatched again.
+        assert_eq!(state.dispatch.current_assignment("pilot-1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn events_are_checked_against_the_sender() {
+        let state = test_state();
+        let ride = assigned_ride(&state).await;
+
+        for (sender, event) in [
+            (Principal::new(Role::Pilot, "pilot-2"), RideEvent::Depart),
+            (Principal::new(Role::Rider, "rider-1"), RideEvent::Complete),
+            (Principal::new(Role::Rider, "rider-2"), RideEvent::Cancel),
+            (Principal::new(Role::Pilot, "pilot-1"), RideEvent::CancelNoPilot),
+            (Principal::new(Role::Dispatcher, "ops"), RideEvent::Complete),
+        ] {
+            assert!(matches!(
+                apply_ride_event(&state, ride.id, &sender, event).await,
+                Err(ApiError::Forbidden(_))
+            ));
+        }
+        assert!(matches!(
+            apply_ride_event(&state, ride.id, &Principal::new(Role::Admin, "ops"), RideEvent::Complete)
+                .await,
+            Err(ApiError::Core(CoreError::InvalidStatusTransition { .. }))
+        ));
+
+        let rider = Principal::new(Role::Rider, "rider-1");
+        let cancelled = apply_ride_event(&state, ride.id, &rider, RideEvent::Cancel)
+            .await
+            .unwrap();
+        assert_eq!(cancelled.status, RideStatus::Cancelled.as_str());
+    }
+
+    #[tokio::test]
+    async fn accepting_is_left_to_the_offer_routes() {
+        let state = test_state();
+        let ride = Ride::new(
+            "rider-1".to_string(),
+            RideLocation::new(34.0522, -118.2437),
+            RideLocation::new(34.0407, -118.2468),
+            None,
+            None,
+            None,
+            1.0,
+            5000,
+        );
+        state.repo.create_ride(ride.clone()).await.unwrap();
+
+        let admin = Principal::new(Role::Admin, "ops");
+        assert!(!allowed_events(&admin, &ride).contains(&RideEvent::Accept));
+        assert!(matches!(
+            apply_ride_event(&state, ride.id, &admin, RideEvent::Accept).await,
+            Err(ApiError::Forbidden(_))
+        ));
+        let stored = state.repo.get_ride(&ride.id).await.unwrap();
+        assert_eq!(stored.status, RideStatus::Requested.as_str());
+        assert_eq!(stored.driver_id, None);
+    }
+
+    #[tokio::test]
+    async fn cancelling_withdraws_the_pending_offer() {
+        let state = test_state();
+        let ride = Ride::new(
+            "rider-1".to_string(),
+            RideLocation::new(34.0522, -118.2437),
+            RideLocation::new(34.0407, -118.2468),
+            None,
+            None,
+            None,
+            1.0,
+            5000,
+        );
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        let ride_key = ride.id.to_string();
+        state
+            .dispatch
+            .create_offer(&ride_key, "pilot-1", Duration::from_secs(30))
+            .await
+            .unwrap()
+            .expect("pilot is free");
+
+        let rider = Principal::new(Role::Rider, "rider-1");
+        apply_ride_event(&state, ride.id, &rider, RideEvent::Cancel)
+            .await
+            .unwrap();
+        assert_eq!(state.dispatch.current_offer(&ride_key).await.unwrap(), None);
+        assert!(matches!(
+            accept_offer(&state, ride.id, "pilot-1").await,
+            Err(ApiError::Core(CoreError::OfferUnavailable))
+        ));
+        let stored = state.repo.get_ride(&ride.id).await.unwrap();
+        assert_eq!(stored.status, RideStatus::Cancelled.as_str());
+        assert_eq!(stored.driver_id, None);
+    }
+
+    #[tokio::test]
+    async fn texted_cancels_release_the_pilot() {
+        use axum::body::Body;
+        use axum::http::{Request, StatusCode};
+        use supportcarr_twilio::{sign, TwilioConfig, TwilioState};
+        use tower::ServiceExt;
+
+        let repo = Arc::new(crate::repository::InMemoryRideRepository::default());
+        let state = ApiState {
+            repo: repo.clone(),
+            ..test_state()
+        };
+        let ride = assigned_ride(&state).await;
+        let seen = state.updates.version(ride.id);
+        let config = TwilioConfig {
+            auth_token: "token".to_string(),
+            webhook_url: "https://example.com/twilio/sms".to_string(),
+        };
+        let body = "Body=cancel+please&From=%2B15555550123";
+        let signature = sign(&config.auth_token, &config.webhook_url, body.as_bytes()).unwrap();
+        let router = supportcarr_twilio::router(TwilioState {
+            config,
+            store: Arc::new(SmsRideStore {
+                state: state.clone(),
+                store: repo,
+            }),
+        });
+
+        let request = Request::post("/twilio/sms")
+            .header("X-Twilio-Signature", signature)
+            .body(Body::from(body))
+            .unwrap();
+        // A long poll waiting on the ride hears the cancel.
+        let (version, response) = tokio::join!(
+            state.updates.wait_for_change(ride.id, seen, Duration::from_secs(5)),
+            router.oneshot(request)
+        );
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+        assert_ne!(version, seen);
+        let stored = state.repo.get_ride(&ride.id).await.unwrap();
+        assert_eq!(stored.status, RideStatus::Cancelled.as_str());
+        assert_eq!(state.dispatch.current_assignment("pilot-1").await.unwrap(), None);
+    }
+}
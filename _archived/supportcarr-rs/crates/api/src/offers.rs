@@ -0,0 +1,263 @@
+use std::time::Duration;
+
+use supportcarr_core::dispatch::{
+    now_millis, rank_candidates, DispatchEvent, DispatchOffer, ScoringContext,
+};
+use supportcarr_core::error::CoreError;
+use supportcarr_core::events::DispatchDecision;
+use supportcarr_core::fsm::{RideEvent, RideStatus};
+use supportcarr_core::model::Ride;
+use supportcarr_core::search::RingSearch;
+use uuid::Uuid;
+
+use crate::{ApiError, ApiState};
+
+/// How rides are offered to pilots before they are assigned.
+#[derive(Debug, Clone)]
+pub struct OfferPolicy {
+    /// How long a pilot has to answer before the ride moves to the next candidate.
+    pub timeout: Duration,
+    /// Rings searched around pickup; pilots already asked are skipped.
+    pub search: RingSearch,
+    /// Offer new rides as soon as they are created. Turn off when a batch dispatch loop
+    /// (see [`crate::batch`]) is matching pending rides instead.
+    pub immediate: bool,
+}
+
+impl Default for OfferPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            search: RingSearch::default(),
+            immediate: true,
+        }
+    }
+}
+
+/// Offer the ride to the best-scoring pilot who has not been asked yet and holds no other
+/// live offer, and arm the offer timeout. Searches leave out pilots holding an offer, and
+/// one claimed by a concurrent offer after the search is passed over for the next
+/// candidate. Returns `None` when nobody is left to ask; the ride stays `requested`.
+pub async fn offer_next(state: &ApiState, ride: &Ride) -> Result<Option<DispatchEvent>, ApiError> {
+    offer_next_with(state, ride, &state.offers.search).await
+}
+
+/// [`offer_next`] with an explicit search, used to widen the radius for rides that have
+/// been waiting a while.
+pub async fn offer_next_with(
+    state: &ApiState,
+    ride: &Ride,
+    search: &RingSearch,
+) -> Result<Option<DispatchEvent>, ApiError> {
+    offer_next_excluding(state, ride, search, &[]).await
+}
+
+/// [`offer_next_with`] that also skips `excluded` pilots, such as those matched to other
+/// rides in the same batch round.
+pub async fn offer_next_excluding(
+    state: &ApiState,
+    ride: &Ride,
+    search: &RingSearch,
+    excluded: &[String],
+) -> Result<Option<DispatchEvent>, ApiError> {
+    let ride_id = ride.id.to_string();
+    let mut skipped = state.dispatch.offered_pilots(&ride_id).await?;
+    skipped.extend_from_slice(excluded);
+    let outcome = search
+        .search(state.dispatch.as_ref(), &ride.pickup, &skipped)
+        .await?;
+    record_decision(
+        state,
+        DispatchDecision::Search {
+            ride_id: ride_id.clone(),
+            radius_miles: outcome.radius_miles,
+            rings_searched: outcome.rings_searched,
+        },
+    )
+    .await;
+
+    let pilot_ids: Vec<String> = outcome
+        .candidates
+        .iter()
+        .map(|candidate| candidate.pilot_id.clone())
+        .collect();
+    let profiles = state.pilots.get_profiles(&pilot_ids).await?;
+    let context = ScoringContext {
+        bike_type: ride.bike_type.clone(),
+        trip_miles: ride.distance_miles,
+        now_ms: now_millis(),
+    };
+    let ranked = rank_candidates(state.scorer.as_ref(), outcome.candidates, &profiles, &context);
+    record_decision(
+        state,
+        DispatchDecision::Candidates {
+            ride_id: ride_id.clone(),
+            pilot_ids: ranked
+                .iter()
+                .map(|scored| scored.candidate.pilot_id.clone())
+                .collect(),
+        },
+    )
+    .await;
+    for best in ranked {
+        let pilot_id = best.candidate.pilot_id;
+        let Some(offer) = state
+            .dispatch
+            .create_offer(&ride_id, &pilot_id, state.offers.timeout)
+            .await?
+        else {
+            continue;
+        };
+        spawn_offer_timeout(state.clone(), ride.id, offer);
+        let event = DispatchEvent {
+            ride_id,
+            pilot_id,
+            eta_minutes: None,
+            search_radius_miles: Some(outcome.radius_miles),
+            explanation: Some(best.explanation),
+        };
+        record_decision(state, DispatchDecision::Claim(event.clone())).await;
+        return Ok(Some(event));
+    }
+    Ok(None)
+}
+
+/// Accept a pending offer: the ride is assigned to the pilot and moves to `accepted`. The
+/// move only lands if the ride is still `requested`, so an accept racing a cancel loses.
+/// A pilot who already has a ride cannot accept another. The pilot is assigned before the
+/// ride moves, and released again if it cannot, so neither is left half done.
+pub async fn accept_offer(state: &ApiState, ride_id: Uuid, pilot_id: &str) -> Result<Ride, ApiError> {
+    let ride = state.repo.get_ride(&ride_id).await?;
+    if state.dispatch.current_assignment(pilot_id).await?.is_some() {
+        return Err(CoreError::PilotAssigned.into());
+    }
+    let offer = state
+        .dispatch
+        .take_offer(&ride_id.to_string(), pilot_id)
+        .await?
+        .ok_or(CoreError::OfferUnavailable)?;
+
+    if offer.is_expired() {
+        record_release(state, &offer, "timeout").await;
+        offer_next(state, &ride).await?;
+        return Err(CoreError::OfferExpired.into());
+    }
+
+    if let Err(err) = state
+        .dispatch
+        .mark_assigned(&offer.pilot_id, &offer.ride_id)
+        .await
+    {
+        // The offer is gone, so move the ride on rather than leave it with no one asked.
+        let _ = state.dispatch.release_pilot(&offer.pilot_id).await;
+        record_release(state, &offer, "assign_failed").await;
+        offer_next(state, &ride).await?;
+        return Err(err.into());
+    }
+    let from = RideStatus::try_from(ride.status.as_str())?;
+    let ride = match state
+        .repo
+        .transition(&ride_id, from, RideEvent::Accept, Some(&offer.pilot_id))
+        .await
+    {
+        Ok(ride) => ride,
+        Err(err) => {
+            state.dispatch.release_pilot(&offer.pilot_id).await?;
+            record_release(state, &offer, "ride_changed").await;
+            return Err(err.into());
+        }
+    };
+    state.updates.publish_status(&ride);
+    state.queue.remove(&ride.id.to_string()).await?;
+    record_decision(
+        state,
+        DispatchDecision::Assign {
+            ride_id: offer.ride_id,
+            pilot_id: offer.pilot_id,
+        },
+    )
+    .await;
+    Ok(ride)
+}
+
+/// Decline a pending offer and pass the ride on to the next candidate.
+pub async fn decline_offer(
+    state: &ApiState,
+    ride_id: Uuid,
+    pilot_id: &str,
+) -> Result<Option<DispatchEvent>, ApiError> {
+    let ride = state.repo.get_ride(&ride_id).await?;
+    let offer = state
+        .dispatch
+        .take_offer(&ride_id.to_string(), pilot_id)
+        .await?
+        .ok_or(CoreError::OfferUnavailable)?;
+    record_release(state, &offer, "declined").await;
+    offer_next(state, &ride).await
+}
+
+/// Move the ride on once the offer expires. Shutdown cancels timers still waiting, leaving
+/// the offer to expire in the store, but waits for one already moving its ride on.
+pub(crate) fn spawn_offer_timeout(state: ApiState, ride_id: Uuid, offer: DispatchOffer) {
+    let wait = Duration::from_millis(offer.expires_at_ms.saturating_sub(offer.offered_at_ms));
+    let readiness = state.readiness.clone();
+    readiness.spawn(async move {
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = state.readiness.shutdown_begun() => return,
+        }
+        // Only the side that removes the offer moves the ride on, so a late accept or
+        // decline racing this timer cannot produce a second offer.
+        let expired = state
+            .dispatch
+            .take_offer(&offer.ride_id, &offer.pilot_id)
+            .await
+            .ok()
+            .flatten();
+        let Some(expired) = expired else {
+            return;
+        };
+        record_release(&state, &expired, "timeout").await;
+        if let Ok(ride) = state.repo.get_ride(&ride_id).await {
+            if ride.status == RideStatus::Requested.as_str() {
+                let _ = offer_next(&state, &ride).await;
+            }
+        }
+    });
+}
+
+/// Append to the dispatch event log. Failures are logged rather than returned so the log
+/// can never block dispatch.
+pub(crate) async fn record_decision(state: &ApiState, decision: DispatchDecision) {
+    if let Err(err) = state.events.append(decision).await {
+        tracing::warn!(error = %err, "failed to record dispatch decision");
+    }
+}
+
+/// Take back the ride's pending offer, if any, so its pilot can no longer accept it.
+pub(crate) async fn withdraw_offer(
+    state: &ApiState,
+    ride_id: &str,
+    reason: &str,
+) -> Result<(), ApiError> {
+    let Some(pending) = state.dispatch.current_offer(ride_id).await? else {
+        return Ok(());
+    };
+    // The pilot or the timeout may take the offer first; only the taker records it.
+    if let Some(offer) = state.dispatch.take_offer(ride_id, &pending.pilot_id).await? {
+        record_release(state, &offer, reason).await;
+    }
+    Ok(())
+}
+
+async fn record_release(state: &ApiState, offer: &DispatchOffer, reason: &str) {
+    record_decision(
+        state,
+        DispatchDecision::Release {
+            ride_id: offer.ride_id.clone(),
+            pilot_id: offer.pilot_id.clone(),
+            reason: reason.to_string(),
+        },
+    )
+    .await;
+}
@@ -0,0 +1,276 @@
+//! OpenAPI 3.1 description of the HTTP API, generated from the handlers' `#[utoipa::path]`
+//! annotations and the request and response types. Served at `/openapi.json`; with the
+//! `swagger-ui` feature, browsable at `/docs`.
+
+use axum::routing::get;
+use axum::Json;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{Content, ObjectBuilder, PathItem, Ref, RefOr, ResponseBuilder, Type};
+use utoipa::{Modify, OpenApi};
+
+use crate::Routes;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "SupportCarr API",
+        description = "Ride requests, dispatch offers and the pilot app. Every operation but \
+            the health probes needs `Authorization: Bearer <jwt>`; errors are plain-text \
+            messages."
+    ),
+    paths(
+        crate::create_ride,
+        crate::list_rides,
+        crate::get_ride_status,
+        crate::post_ride_event,
+        crate::get_ride_track,
+        crate::updates::stream_ride_sse,
+        crate::updates::stream_ride_ws,
+        crate::get_offer,
+        crate::accept_offer,
+        crate::decline_offer,
+        crate::pilot_api::put_location,
+        crate::pilot_api::post_locations,
+        crate::pilot_api::put_availability,
+        crate::pilot_api::get_assignment,
+        crate::health::healthz,
+        crate::health::readyz,
+    ),
+    modifiers(&ErrorResponses),
+    security(("bearer" = [])),
+    tags(
+        (name = "rides", description = "Booking, status and lifecycle events"),
+        (name = "updates", description = "Live ride updates"),
+        (name = "offers", description = "Dispatch offers to pilots"),
+        (name = "pilots", description = "The pilot app, for the calling pilot"),
+        (name = "health", description = "Liveness and readiness probes; no token needed"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Adds the bearer scheme, a 401 on every operation that does not override `security`,
+/// and the plain-text body [`crate::ApiError`] responds with to every error without a
+/// documented body.
+struct ErrorResponses;
+
+impl Modify for ErrorResponses {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.schemas.insert(
+            "ApiError".to_string(),
+            ObjectBuilder::new()
+                .schema_type(Type::String)
+                .description(Some("Human-readable reason for the failure"))
+                .into(),
+        );
+
+        for item in openapi.paths.paths.values_mut() {
+            for operation in operations_mut(item) {
+                let public = operation.security.is_some();
+                let responses = &mut operation.responses.responses;
+                if !public {
+                    responses.entry("401".to_string()).or_insert_with(|| {
+                        ResponseBuilder::new()
+                            .description("Missing or invalid bearer token")
+                            .into()
+                    });
+                }
+                for (status, response) in responses.iter_mut() {
+                    if let (false, RefOr::T(response)) =
+                        (status.starts_with(['1', '2', '3']), response)
+                    {
+                        if response.content.is_empty() {
+                            response.content.insert(
+                                "text/plain".to_string(),
+                                Content::new(Some(Ref::from_schema_name("ApiError"))),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn operations_mut(
+    item: &mut PathItem,
+) -> impl Iterator<Item = &mut utoipa::openapi::path::Operation> {
+    [
+        &mut item.get,
+        &mut item.put,
+        &mut item.post,
+        &mut item.delete,
+        &mut item.options,
+        &mut item.head,
+        &mut item.patch,
+        &mut item.trace,
+    ]
+    .into_iter()
+    .flatten()
+}
+
+/// `/openapi.json`, and `/docs` with the `swagger-ui` feature. Neither needs a token.
+pub fn routes() -> Routes {
+    let spec = ApiDoc::openapi();
+    let routes = Routes::default().route(
+        "/openapi.json",
+        get(move || std::future::ready(Json(spec.clone()))),
+    );
+    #[cfg(feature = "swagger-ui")]
+    let routes = routes.map(|router| {
+        router.merge(
+            utoipa_swagger_ui::SwaggerUi::new("/docs")
+                .config(utoipa_swagger_ui::Config::from("/openapi.json")),
+        )
+    });
+    routes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::tests::hs256_token;
+    use crate::auth::Role;
+    use axum::body::Body;
+    use axum::http::header::AUTHORIZATION;
+    use axum::http::{Method, Request, StatusCode};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    const METHODS: [&str; 5] = ["get", "put", "post", "patch", "delete"];
+
+    fn spec() -> Value {
+        serde_json::to_value(ApiDoc::openapi()).unwrap()
+    }
+
+    fn collect_refs<'a>(value: &'a Value, refs: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(target)) = map.get("$ref") {
+                    refs.push(target);
+                }
+                map.values().for_each(|value| collect_refs(value, refs));
+            }
+            Value::Array(values) => values.iter().for_each(|value| collect_refs(value, refs)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn spec_is_self_consistent() {
+        let spec = spec();
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3.1"));
+
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            let mut template_params: Vec<&str> = path
+                .split('/')
+                .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+                .collect();
+            template_params.sort();
+            for (method, operation) in item.as_object().unwrap() {
+                let mut declared: Vec<&str> = operation["parameters"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter(|param| param["in"] == "path")
+                    .map(|param| param["name"].as_str().unwrap())
+                    .collect();
+                declared.sort();
+                assert_eq!(declared, template_params, "{method} {path}");
+                // Only operations that override `security` are public.
+                assert_eq!(
+                    operation["responses"]["401"].is_object(),
+                    operation.get("security").is_none(),
+                    "{method} {path}"
+                );
+            }
+        }
+
+        let mut refs = Vec::new();
+        collect_refs(&spec, &mut refs);
+        for target in refs {
+            let name = target.strip_prefix("#/components/schemas/").unwrap();
+            assert!(
+                spec["components"]["schemas"][name].is_object(),
+                "dangling {target}"
+            );
+        }
+    }
+
+    /// Every documented operation reaches a handler, and every other method on a documented
+    /// path is refused by the router, so the spec and `router()` cannot drift apart.
+    #[tokio::test]
+    async fn spec_matches_router() {
+        let state = crate::test_state();
+        let ride = crate::repository::conformance::ride(&Default::default());
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        let app = crate::router(state);
+        let token = hs256_token(Role::Admin, "admin-1");
+
+        for (path, item) in spec()["paths"].as_object().unwrap() {
+            let uri = path.replace("{id}", &ride.id.to_string());
+            for method in METHODS {
+                let request = Request::builder()
+                    .method(Method::from_bytes(method.to_uppercase().as_bytes()).unwrap())
+                    .uri(&uri)
+                    .header(AUTHORIZATION, format!("Bearer {token}"))
+                    .body(Body::empty())
+                    .unwrap();
+                let status = app.clone().oneshot(request).await.unwrap().status();
+                if item.get(method).is_some() {
+                    // The ride exists, so a 404 can only come from the router.
+                    assert!(
+                        ![StatusCode::NOT_FOUND, StatusCode::METHOD_NOT_ALLOWED].contains(&status),
+                        "documented {method} {path} is not routed: {status}"
+                    );
+                } else {
+                    assert_eq!(
+                        status,
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        "{method} {path} is routed but not documented"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Every path the router serves is documented, apart from the spec itself.
+    #[test]
+    fn router_paths_are_documented() {
+        let spec = spec();
+        let documented = spec["paths"].as_object().unwrap();
+        let routes = crate::routes(&crate::test_state());
+        assert!(!routes.paths.is_empty());
+        for path in routes.paths {
+            if path == "/openapi.json" {
+                continue;
+            }
+            let templated = path.replace(":id", "{id}");
+            assert!(documented.contains_key(&templated), "{path} is routed but not documented");
+        }
+    }
+
+    #[tokio::test]
+    async fn spec_is_served_without_a_token() {
+        let app = crate::router(crate::test_state());
+        let response = app
+            .oneshot(Request::get("/openapi.json").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let served: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(served, spec());
+    }
+}
@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, State};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use supportcarr_core::dispatch::now_millis;
+use supportcarr_core::error::CoreError;
+use supportcarr_core::model::{Ride, RideLocation};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::auth::{Principal, Role};
+use crate::tracking::{record_pilot_location, record_pilot_trail};
+use crate::{ApiError, ApiState, Routes};
+
+/// Limits on what the pilot app may send.
+#[derive(Debug, Clone)]
+pub struct LocationPolicy {
+    /// Applied per pilot to single and batch location uploads alike.
+    pub limiter: RateLimiter,
+    /// Most points accepted in one batch upload.
+    pub max_batch: usize,
+    /// How far past the server clock a batch timestamp may be.
+    pub max_clock_skew: Duration,
+    /// How far behind the server clock a batch timestamp may be.
+    pub max_age: Duration,
+}
+
+impl Default for LocationPolicy {
+    fn default() -> Self {
+        Self {
+            limiter: RateLimiter::new(Duration::from_secs(1), 5),
+            max_batch: 500,
+            max_clock_skew: Duration::from_secs(30),
+            max_age: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+/// Every this many checks, buckets that have refilled are dropped.
+const BUCKET_SWEEP_INTERVAL: u64 = 1024;
+
+/// Token bucket per key: `burst` requests back to back, then one per `interval`.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    interval: Duration,
+    burst: u32,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    checks: u64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration, burst: u32) -> Self {
+        Self {
+            interval,
+            burst: burst.max(1),
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Take a token for `key`, or say how long until one is available.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let burst = f64::from(self.burst);
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.checks += 1;
+        if buckets.checks.is_multiple_of(BUCKET_SWEEP_INTERVAL) {
+            // A full bucket is the same as none, so pilots who went quiet cost nothing.
+            let full_after = self.interval * self.burst;
+            buckets
+                .by_key
+                .retain(|_, bucket| now.saturating_duration_since(bucket.updated) < full_after);
+        }
+        let bucket = buckets.by_key.entry(key.to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        let refill = if self.interval.is_zero() {
+            burst
+        } else {
+            elapsed.as_secs_f64() / self.interval.as_secs_f64()
+        };
+        bucket.tokens = (bucket.tokens + refill).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(self.interval.mul_f64(1.0 - bucket.tokens))
+        }
+    }
+}
+
+/// The calling pilot; other roles are refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PilotId(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for PilotId {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let principal = Principal::from_request_parts(parts, state).await?;
+        principal.require_role(&[Role::Pilot])?;
+        Ok(PilotId(principal.subject))
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AvailabilityRequest {
+    pub available: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct LocationPoint {
+    pub lat: f64,
+    pub lng: f64,
+    pub recorded_at_ms: u64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LocationBatch {
+    pub points: Vec<LocationPoint>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LocationBatchResponse {
+    pub recorded: usize,
+}
+
+pub fn routes() -> Routes {
+    Routes::default()
+        .route("/pilots/me/location", put(put_location))
+        .route("/pilots/me/locations", post(post_locations))
+        .route("/pilots/me/availability", put(put_availability))
+        .route("/pilots/me/assignment", get(get_assignment))
+}
+
+/// Reject coordinates that are off the globe or not numbers.
+pub fn validate_location(location: &RideLocation) -> Result<(), ApiError> {
+    let valid = location.lat.is_finite()
+        && location.lng.is_finite()
+        && (-90.0..=90.0).contains(&location.lat)
+        && (-180.0..=180.0).contains(&location.lng);
+    if valid {
+        Ok(())
+    } else {
+        Err(CoreError::InvalidLocation(format!("{}, {}", location.lat, location.lng)).into())
+    }
+}
+
+/// Check a batch upload: within size, valid coordinates, oldest first, not from the
+/// future beyond `max_clock_skew`, and no older than `max_age`.
+pub fn validate_batch(
+    policy: &LocationPolicy,
+    points: &[LocationPoint],
+    now_ms: u64,
+) -> Result<Vec<(RideLocation, u64)>, ApiError> {
+    if points.is_empty() {
+        return Err(ApiError::BadRequest("batch has no points".into()));
+    }
+    if points.len() > policy.max_batch {
+        return Err(ApiError::BadRequest(format!(
+            "batch has {} points; at most {} allowed",
+            points.len(),
+            policy.max_batch
+        )));
+    }
+    let latest_allowed = now_ms.saturating_add(policy.max_clock_skew.as_millis() as u64);
+    let earliest_allowed = now_ms.saturating_sub(policy.max_age.as_millis() as u64);
+    let mut previous = 0;
+    points
+        .iter()
+        .map(|point| {
+            let location = RideLocation::new(point.lat, point.lng);
+            validate_location(&location)?;
+            if point.recorded_at_ms < previous {
+                return Err(ApiError::BadRequest(
+                    "batch points must be oldest first".into(),
+                ));
+            }
+            if point.recorded_at_ms > latest_allowed {
+                return Err(ApiError::BadRequest(format!(
+                    "point recorded at {} is in the future",
+                    point.recorded_at_ms
+                )));
+            }
+            if point.recorded_at_ms < earliest_allowed {
+                return Err(ApiError::BadRequest(format!(
+                    "point recorded at {} is older than {:?}",
+                    point.recorded_at_ms, policy.max_age
+                )));
+            }
+            previous = point.recorded_at_ms;
+            Ok((location, point.recorded_at_ms))
+        })
+        .collect()
+}
+
+fn check_rate(state: &ApiState, pilot_id: &str) -> Result<(), ApiError> {
+    state
+        .locations
+        .limiter
+        .check(pilot_id)
+        .map_err(|retry_after| ApiError::RateLimited { retry_after })
+}
+
+#[utoipa::path(
+    put,
+    path = "/pilots/me/location",
+    tag = "pilots",
+    request_body = RideLocation,
+    responses(
+        (status = 204, description = "Location recorded"),
+        (status = 400, description = "Coordinates off the globe"),
+        (status = 403, description = "Only pilots report locations"),
+        (status = 429, description = "Uploading faster than the rate limit", headers(("Retry-After" = u64, description = "Seconds until the next upload is accepted"))),
+    )
+)]
+async fn put_location(
+    State(state): State<ApiState>,
+    PilotId(pilot_id): PilotId,
+    Json(location): Json<RideLocation>,
+) -> Result<StatusCode, ApiError> {
+    validate_location(&location)?;
+    check_rate(&state, &pilot_id)?;
+    record_pilot_location(&state, &pilot_id, &location).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/pilots/me/locations",
+    tag = "pilots",
+    request_body = LocationBatch,
+    responses(
+        (status = 200, description = "All points recorded", body = LocationBatchResponse),
+        (status = 400, description = "Empty, oversized, unordered, future-dated or stale batch"),
+        (status = 403, description = "Only pilots report locations"),
+        (status = 429, description = "Uploading faster than the rate limit", headers(("Retry-After" = u64, description = "Seconds until the next upload is accepted"))),
+    )
+)]
+async fn post_locations(
+    State(state): State<ApiState>,
+    PilotId(pilot_id): PilotId,
+    Json(batch): Json<LocationBatch>,
+) -> Result<Json<LocationBatchResponse>, ApiError> {
+    let points = validate_batch(&state.locations, &batch.points, now_millis())?;
+    check_rate(&state, &pilot_id)?;
+    record_pilot_trail(&state, &pilot_id, &points).await?;
+    Ok(Json(LocationBatchResponse {
+        recorded: points.len(),
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/pilots/me/availability",
+    tag = "pilots",
+    request_body = AvailabilityRequest,
+    responses(
+        (status = 204, description = "Availability updated"),
+        (status = 400, description = "Pilot has an active assignment"),
+        (status = 403, description = "Only pilots set availability"),
+    )
+)]
+async fn put_availability(
+    State(state): State<ApiState>,
+    PilotId(pilot_id): PilotId,
+    Json(request): Json<AvailabilityRequest>,
+) -> Result<StatusCode, ApiError> {
+    // An assigned pilot becomes available again when the ride finishes, not on request.
+    if request.available
+        && state
+            .dispatch
+            .current_assignment(&pilot_id)
+            .await?
+            .is_some()
+    {
+        return Err(ApiError::BadRequest(
+            "pilot has an active assignment".into(),
+        ));
+    }
+    state
+        .dispatch
+        .set_pilot_available(&pilot_id, request.available)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The pilot's assigned ride, or 204 when they have none.
+#[utoipa::path(
+    get,
+    path = "/pilots/me/assignment",
+    tag = "pilots",
+    responses(
+        (status = 200, description = "The assigned ride", body = Ride),
+        (status = 204, description = "No assignment"),
+        (status = 403, description = "Only pilots have assignments"),
+    )
+)]
+async fn get_assignment(
+    State(state): State<ApiState>,
+    PilotId(pilot_id): PilotId,
+) -> Result<Response, ApiError> {
+    let Some(ride_id) = state.dispatch.current_assignment(&pilot_id).await? else {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    };
+    let id = Uuid::parse_str(&ride_id).map_err(|_| CoreError::NotFound)?;
+    let ride = state.repo.get_ride(&id).await?;
+    Ok(Json(ride).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_state;
+    use axum::body::Body;
+    use axum::http::header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
+    use axum::http::Request;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    fn point(lat: f64, lng: f64, recorded_at_ms: u64) -> LocationPoint {
+        LocationPoint {
+            lat,
+            lng,
+            recorded_at_ms,
+        }
+    }
+
+    #[test]
+    fn limiter_allows_burst_then_refills() {
+        let limiter = RateLimiter::new(Duration::from_secs(2), 2);
+        let start = Instant::now();
+        assert!(limiter.check_at("pilot-1", start).is_ok());
+        assert!(limiter.check_at("pilot-1", start).is_ok());
+        assert_eq!(
+            limiter.check_at("pilot-1", start),
+            Err(Duration::from_secs(2))
+        );
+        // Other pilots have their own bucket.
+        assert!(limiter.check_at("pilot-2", start).is_ok());
+        assert_eq!(
+            limiter.check_at("pilot-1", start + Duration::from_secs(1)),
+            Err(Duration::from_secs(1))
+        );
+        assert!(limiter
+            .check_at("pilot-1", start + Duration::from_secs(2))
+            .is_ok());
+    }
+
+    #[test]
+    fn limiter_drops_refilled_buckets() {
+        let limiter = RateLimiter::new(Duration::from_secs(1), 2);
+        let start = Instant::now();
+        for pilot in 0..BUCKET_SWEEP_INTERVAL - 1 {
+            assert!(limiter.check_at(&format!("pilot-{pilot}"), start).is_ok());
+        }
+        let later = start + Duration::from_secs(2);
+        assert!(limiter.check_at("pilot-busy", later).is_ok());
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_key.keys().collect::<Vec<_>>(), vec!["pilot-busy"]);
+    }
+
+    #[test]
+    fn batches_are_validated() {
+        let policy = LocationPolicy {
+            max_batch: 2,
+            ..LocationPolicy::default()
+        };
+        let now = 1_000_000_000;
+        let ok = validate_batch(
+            &policy,
+            &[point(34.0, -118.0, now - 2), point(34.1, -118.1, now - 1)],
+            now,
+        )
+        .unwrap();
+        assert_eq!(ok.len(), 2);
+
+        for bad in [
+            vec![],
+            vec![point(34.0, -118.0, now); 3],
+            vec![point(91.0, -118.0, now)],
+            vec![point(f64::NAN, -118.0, now)],
+            vec![point(34.0, -118.0, now), point(34.0, -118.0, now - 1)],
+            vec![point(34.0, -118.0, now + 60_000)],
+            vec![point(34.0, -118.0, now - 16 * 60_000)],
+        ] {
+            assert!(validate_batch(&policy, &bad, now).is_err(), "{bad:?}");
+        }
+    }
+
+    async fn send(state: &ApiState, method: &str, uri: &str, body: Value) -> Response {
+        let token = crate::auth::tests::hs256_token(Role::Pilot, "pilot-1");
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        crate::router(state.clone()).oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn uploads_over_the_rate_limit_get_retry_after() {
+        let mut state = test_state();
+        state.locations.limiter = RateLimiter::new(Duration::from_secs(10), 1);
+        let here = json!({ "lat": 34.05, "lng": -118.24 });
+        let batch = json!({
+            "points": [{ "lat": 34.05, "lng": -118.24, "recorded_at_ms": now_millis() }]
+        });
+
+        let accepted = send(&state, "PUT", "/pilots/me/location", here.clone()).await;
+        assert_eq!(accepted.status(), StatusCode::NO_CONTENT);
+        // Single and batch uploads share the pilot's bucket.
+        for (method, uri, body) in [
+            ("PUT", "/pilots/me/location", here),
+            ("POST", "/pilots/me/locations", batch),
+        ] {
+            let limited = send(&state, method, uri, body).await;
+            assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS, "{uri}");
+            let retry_after = &limited.headers()[RETRY_AFTER];
+            assert_eq!(retry_after, "10", "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn late_batches_join_the_trail_but_do_not_move_the_pilot() {
+        let state = test_state();
+        let now = now_millis();
+        let newer = json!({ "lat": 34.05, "lng": -118.24 });
+        let older = json!({
+            "points": [{ "lat": 34.5, "lng": -118.24, "recorded_at_ms": now - 60_000 }]
+        });
+        assert_eq!(
+            send(&state, "PUT", "/pilots/me/location", newer).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        let response = send(&state, "POST", "/pilots/me/locations", older).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let near_newer = RideLocation::new(34.05, -118.24);
+        let found = state
+            .dispatch
+            .find_nearby_pilots(&near_newer, 1.0, 10)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        let trail = state.history.pilot_trail("pilot-1", 0, u64::MAX).await.unwrap();
+        assert_eq!(trail.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn availability_cannot_override_an_assignment() {
+        let state = test_state();
+        let pilot = PilotId("pilot-1".to_string());
+        state
+            .dispatch
+            .mark_assigned("pilot-1", "ride-1")
+            .await
+            .unwrap();
+
+        let result = put_availability(
+            State(state.clone()),
+            pilot.clone(),
+            Json(AvailabilityRequest { available: true }),
+        )
+        .await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+
+        state.dispatch.release_pilot("pilot-1").await.unwrap();
+        let status = put_availability(
+            State(state.clone()),
+            pilot,
+            Json(AvailabilityRequest { available: false }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+}
@@ -0,0 +1,38 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use supportcarr_core::dispatch::PilotProfile;
+use supportcarr_core::error::CoreResult;
+
+#[async_trait]
+pub trait PilotProfileStore: Send + Sync {
+    /// Profiles for the given pilots keyed by id; unknown pilots are omitted.
+    async fn get_profiles(&self, pilot_ids: &[String]) -> CoreResult<HashMap<String, PilotProfile>>;
+    async fn save_profile(&self, profile: PilotProfile) -> CoreResult<()>;
+}
+
+#[derive(Default)]
+pub struct InMemoryPilotProfileStore {
+    profiles: Arc<RwLock<HashMap<String, PilotProfile>>>,
+}
+
+#[async_trait]
+impl PilotProfileStore for InMemoryPilotProfileStore {
+    async fn get_profiles(&self, pilot_ids: &[String]) -> CoreResult<HashMap<String, PilotProfile>> {
+        let profiles = self.profiles.read().await;
+        Ok(pilot_ids
+            .iter()
+            .filter_map(|id| profiles.get(id).map(|profile| (id.clone(), profile.clone())))
+            .collect())
+    }
+
+    async fn save_profile(&self, profile: PilotProfile) -> CoreResult<()> {
+        self.profiles
+            .write()
+            .await
+            .insert(profile.pilot_id.clone(), profile);
+        Ok(())
+    }
+}
@@ -0,0 +1,702 @@
+use async_trait::async_trait;
+use sqlx::postgres::{PgPool, PgPoolOptions, PgRow};
+use sqlx::types::Json;
+use sqlx::{Postgres, QueryBuilder, Row};
+use uuid::Uuid;
+
+use supportcarr_core::error::{CoreError, CoreResult};
+use supportcarr_core::fsm::{RideEvent, RideStatus};
+use supportcarr_core::model::{Ride, RideLocation};
+
+use supportcarr_twilio::TwilioRideStore;
+
+use crate::repository::{transitioned, RideCursor, RideFilter, RidePage, RideRepository};
+
+// `created_at` in whole Unix milliseconds. Rows written by the Node server carry
+// microseconds, so listings order and page on this rather than the raw column. It matches
+// the expression indexed by the `add_ride_listing_indexes` migration character for
+// character, which is what lets the planner use those indexes.
+macro_rules! created_at_ms {
+    () => {
+        concat!(
+            "(extract(epoch FROM date_trunc('milliseconds', created_at AT TIME ZONE 'UTC'))",
+            " * 1000)::int8"
+        )
+    };
+}
+const CREATED_AT_MS: &str = created_at_ms!();
+
+// Columns read back into a `Ride`.
+const SELECT_RIDE: &str = concat!(
+    "SELECT id, rider_id, pickup, dropoff, status, bike_type, notes, rider_phone, ",
+    "distance_miles::float8 AS distance_miles, price_cents, driver_id, ",
+    created_at_ms!(),
+    " AS created_at_ms FROM rides"
+);
+
+/// Rides in the Node server's Postgres `rides` table. Rider and pilot ids are the uuids of
+/// the `users` and `drivers` rows a ride references, so other ids are refused and read
+/// back in lowercase hyphenated form. A ride saved without a bike type reads back as
+/// `analog`, the column default.
+#[derive(Clone)]
+pub struct PostgresRideRepository {
+    pool: PgPool,
+}
+
+impl PostgresRideRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn connect(database_url: &str) -> CoreResult<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(10)
+            .connect(database_url)
+            .await
+            .map_err(storage_error)?;
+        Ok(Self::new(pool))
+    }
+
+    /// Apply the embedded migrations under `migrations/postgres`. They create the Node
+    /// `rides` table, the keys of the tables it references, and indexes, skipping anything
+    /// that already exists; they never alter a Node-created table.
+    pub async fn migrate(&self) -> CoreResult<()> {
+        sqlx::migrate!("./migrations/postgres")
+            .run(&self.pool)
+            .await
+            .map_err(storage_error)
+    }
+
+    /// The rider's most recent ride, for SMS replies.
+    pub async fn find_by_phone(&self, phone: &str) -> CoreResult<Option<Ride>> {
+        let row = sqlx::query(&format!(
+            "{SELECT_RIDE} WHERE rider_phone = $1 ORDER BY created_at DESC, id DESC LIMIT 1"
+        ))
+        .bind(phone)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(storage_error)?;
+        row.as_ref().map(ride_from_row).transpose()
+    }
+
+    /// Insert `ride`, or with `replace` overwrite a stored ride with the same id in the same
+    /// statement. Returns whether a row was written.
+    async fn insert(&self, ride: &Ride, replace: bool) -> CoreResult<bool> {
+        let on_conflict = if replace {
+            "DO UPDATE SET rider_id = excluded.rider_id, \
+             pickup = excluded.pickup, dropoff = excluded.dropoff, status = excluded.status, \
+             bike_type = excluded.bike_type, notes = excluded.notes, \
+             rider_phone = excluded.rider_phone, distance_miles = excluded.distance_miles, \
+             price_cents = excluded.price_cents, driver_id = excluded.driver_id, \
+             updated_at = now()"
+        } else {
+            "DO NOTHING"
+        };
+        let result = sqlx::query(&format!(
+            "INSERT INTO rides (id, rider_id, pickup, dropoff, status, bike_type, notes, \
+             rider_phone, distance_miles, price_cents, driver_id, created_at) \
+             VALUES ($1, $2, $3, $4, $5, COALESCE($6, 'analog'), $7, $8, $9, $10, \
+             $11, 'epoch'::timestamptz + $12 * interval '1 millisecond') \
+             ON CONFLICT (id) {on_conflict}"
+        ))
+        .bind(ride.id)
+        .bind(rider_key(&ride.rider_id)?)
+        .bind(Json(&ride.pickup))
+        .bind(Json(&ride.dropoff))
+        .bind(&ride.status)
+        .bind(&ride.bike_type)
+        .bind(&ride.notes)
+        .bind(&ride.rider_phone)
+        .bind(ride.distance_miles)
+        .bind(ride.price_cents)
+        .bind(driver_key(ride.driver_id.as_deref())?)
+        .bind(ride.created_at_ms as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(storage_error)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Rides matching `filter` after `cursor` in cursor order, all of them when `limit` is
+    /// `None`.
+    async fn select_rides(
+        &self,
+        filter: &RideFilter,
+        cursor: Option<RideCursor>,
+        limit: Option<usize>,
+    ) -> CoreResult<Vec<Ride>> {
+        let mut query = QueryBuilder::<Postgres>::new(SELECT_RIDE);
+        query.push(" WHERE true");
+        if !filter.statuses.is_empty() {
+            let statuses: Vec<&str> = filter.statuses.iter().map(|status| status.as_str()).collect();
+            query.push(" AND status = ANY(").push_bind(statuses).push(")");
+        }
+        // No stored ride has an id that is not a uuid.
+        if let Some(rider_id) = &filter.rider_id {
+            let Ok(rider_id) = Uuid::parse_str(rider_id) else {
+                return Ok(Vec::new());
+            };
+            query.push(" AND rider_id = ").push_bind(rider_id);
+        }
+        if let Some(driver_id) = &filter.driver_id {
+            let Ok(driver_id) = Uuid::parse_str(driver_id) else {
+                return Ok(Vec::new());
+            };
+            query.push(" AND driver_id = ").push_bind(driver_id);
+        }
+        if let Some(rider_phone) = &filter.rider_phone {
+            query.push(" AND rider_phone = ").push_bind(rider_phone);
+        }
+        if let Some(after) = filter.created_after_ms {
+            query.push(format!(" AND {CREATED_AT_MS} >= ")).push_bind(after as i64);
+        }
+        if let Some(before) = filter.created_before_ms {
+            query.push(format!(" AND {CREATED_AT_MS} < ")).push_bind(before as i64);
+        }
+        if let Some(bounds) = &filter.pickup_within {
+            query
+                .push(" AND (pickup->>'lat')::float8 BETWEEN ")
+                .push_bind(bounds.south_west.lat)
+                .push(" AND ")
+                .push_bind(bounds.north_east.lat)
+                .push(" AND (pickup->>'lng')::float8 BETWEEN ")
+                .push_bind(bounds.south_west.lng)
+                .push(" AND ")
+                .push_bind(bounds.north_east.lng);
+        }
+        if let Some(cursor) = cursor {
+            query
+                .push(format!(" AND ({CREATED_AT_MS}, id) > ("))
+                .push_bind(cursor.created_at_ms as i64)
+                .push(", ")
+                .push_bind(cursor.id)
+                .push(")");
+        }
+        query.push(format!(" ORDER BY {CREATED_AT_MS}, id"));
+        if let Some(limit) = limit {
+            query.push(" LIMIT ").push_bind(limit as i64);
+        }
+
+        let rows = query
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(storage_error)?;
+        rows.iter().map(ride_from_row).collect()
+    }
+}
+
+fn storage_error(err: impl std::fmt::Display) -> CoreError {
+    CoreError::Storage(err.to_string())
+}
+
+/// The `users.id` a rider id stands for.
+fn rider_key(rider_id: &str) -> CoreResult<Uuid> {
+    Uuid::parse_str(rider_id)
+        .map_err(|_| CoreError::Storage(format!("rider id {rider_id:?} is not a users id")))
+}
+
+/// The `drivers.id` a pilot id stands for.
+fn driver_key(pilot_id: Option<&str>) -> CoreResult<Option<Uuid>> {
+    pilot_id
+        .map(|id| {
+            Uuid::parse_str(id)
+                .map_err(|_| CoreError::Storage(format!("pilot id {id:?} is not a drivers id")))
+        })
+        .transpose()
+}
+
+fn ride_from_row(row: &PgRow) -> CoreResult<Ride> {
+    let pickup: Json<RideLocation> = row.try_get("pickup").map_err(storage_error)?;
+    let dropoff: Json<RideLocation> = row.try_get("dropoff").map_err(storage_error)?;
+    let distance_miles: Option<f64> = row.try_get("distance_miles").map_err(storage_error)?;
+    let price_cents: Option<i32> = row.try_get("price_cents").map_err(storage_error)?;
+    let created_at_ms: i64 = row.try_get("created_at_ms").map_err(storage_error)?;
+    let rider_id: Uuid = row.try_get("rider_id").map_err(storage_error)?;
+    let driver_id: Option<Uuid> = row.try_get("driver_id").map_err(storage_error)?;
+    Ok(Ride {
+        id: row.try_get("id").map_err(storage_error)?,
+        rider_id: rider_id.to_string(),
+        pickup: pickup.0,
+        dropoff: dropoff.0,
+        status: row.try_get("status").map_err(storage_error)?,
+        bike_type: row.try_get("bike_type").map_err(storage_error)?,
+        notes: row.try_get("notes").map_err(storage_error)?,
+        rider_phone: row.try_get("rider_phone").map_err(storage_error)?,
+        distance_miles: distance_miles.unwrap_or_default(),
+        price_cents: price_cents.map(i64::from).unwrap_or_default(),
+        driver_id: driver_id.map(|id| id.to_string()),
+        created_at_ms: created_at_ms.max(0) as u64,
+    })
+}
+
+#[async_trait]
+impl RideRepository for PostgresRideRepository {
+    async fn create_ride(&self, ride: Ride) -> CoreResult<()> {
+        if !self.insert(&ride, false).await? {
+            return Err(CoreError::AlreadyExists);
+        }
+        Ok(())
+    }
+
+    async fn get_ride(&self, id: &Uuid) -> CoreResult<Ride> {
+        let row = sqlx::query(&format!("{SELECT_RIDE} WHERE id = $1"))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(storage_error)?
+            .ok_or(CoreError::NotFound)?;
+        ride_from_row(&row)
+    }
+
+    async fn update_ride(&self, ride: Ride) -> CoreResult<()> {
+        let result = sqlx::query(
+            "UPDATE rides SET rider_id = $2, pickup = $3, dropoff = $4, status = $5, \
+             bike_type = COALESCE($6, 'analog'), notes = $7, rider_phone = $8, \
+             distance_miles = $9, price_cents = $10, driver_id = $11, updated_at = now() \
+             WHERE id = $1",
+        )
+        .bind(ride.id)
+        .bind(rider_key(&ride.rider_id)?)
+        .bind(Json(&ride.pickup))
+        .bind(Json(&ride.dropoff))
+        .bind(&ride.status)
+        .bind(&ride.bike_type)
+        .bind(&ride.notes)
+        .bind(&ride.rider_phone)
+        .bind(ride.distance_miles)
+        .bind(ride.price_cents)
+        .bind(driver_key(ride.driver_id.as_deref())?)
+        .execute(&self.pool)
+        .await
+        .map_err(storage_error)?;
+        if result.rows_affected() == 0 {
+            return Err(CoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    /// Runs in a transaction holding the row lock, so concurrent transitions of the same
+    /// ride are serialized.
+    async fn transition(
+        &self,
+        id: &Uuid,
+        from: RideStatus,
+        event: RideEvent,
+        pilot_id: Option<&str>,
+    ) -> CoreResult<Ride> {
+        let mut tx = self.pool.begin().await.map_err(storage_error)?;
+        let row = sqlx::query(&format!("{SELECT_RIDE} WHERE id = $1 FOR UPDATE"))
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(storage_error)?
+            .ok_or(CoreError::NotFound)?;
+        let ride = transitioned(ride_from_row(&row)?, from, event, pilot_id)?;
+        sqlx::query(
+            "UPDATE rides SET status = $2, driver_id = $3, updated_at = now() WHERE id = $1",
+        )
+        .bind(id)
+        .bind(&ride.status)
+        .bind(driver_key(ride.driver_id.as_deref())?)
+        .execute(&mut *tx)
+        .await
+        .map_err(storage_error)?;
+        tx.commit().await.map_err(storage_error)?;
+        Ok(ride)
+    }
+
+    async fn list_by_status(&self, status: RideStatus) -> CoreResult<Vec<Ride>> {
+        let filter = RideFilter {
+            statuses: vec![status],
+            ..RideFilter::default()
+        };
+        self.select_rides(&filter, None, None).await
+    }
+
+    async fn list_rides(
+        &self,
+        filter: &RideFilter,
+        cursor: Option<RideCursor>,
+        limit: usize,
+    ) -> CoreResult<RidePage> {
+        let rides = self
+            .select_rides(filter, cursor, Some(limit.saturating_add(1)))
+            .await?;
+        Ok(RidePage::from_overfetch(rides, limit))
+    }
+
+    async fn health_check(&self) -> CoreResult<()> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(storage_error)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TwilioRideStore for PostgresRideRepository {
+    async fn find_by_phone(&self, phone: &str) -> Result<Option<Ride>, CoreError> {
+        PostgresRideRepository::find_by_phone(self, phone).await
+    }
+
+    async fn save(&self, ride: Ride) -> Result<(), CoreError> {
+        if ride.rider_phone.is_none() {
+            return Err(CoreError::InvalidLocation("ride missing phone".into()));
+        }
+        self.insert(&ride, true).await?;
+        Ok(())
+    }
+
+    async fn update_status(&self, ride: &Ride, event: RideEvent) -> Result<Ride, CoreError> {
+        let from = RideStatus::try_from(ride.status.as_str())?;
+        RideRepository::transition(self, &ride.id, from, event, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use super::*;
+    use crate::repository::conformance;
+
+    /// Postgres for one test: `DATABASE_URL` when set, otherwise a throwaway cluster
+    /// created with `initdb` (from `PG_BIN` or `PATH`) and stopped on drop. Panics when
+    /// neither works, so the tests never pass without a database.
+    struct TestPostgres {
+        url: String,
+        data_dir: Option<PathBuf>,
+    }
+
+    impl TestPostgres {
+        fn start() -> Self {
+            if let Ok(url) = std::env::var("DATABASE_URL") {
+                return Self {
+                    url,
+                    data_dir: None,
+                };
+            }
+
+            let data_dir = std::env::temp_dir().join(format!("supportcarr-pg-{}", Uuid::new_v4()));
+            let port = std::net::TcpListener::bind("127.0.0.1:0")
+                .and_then(|listener| listener.local_addr())
+                .expect("pick a free port")
+                .port();
+            // Set before the cluster exists so a failed start still cleans up on drop.
+            let server = Self {
+                url: format!("postgres://postgres@127.0.0.1:{port}/postgres"),
+                data_dir: Some(data_dir.clone()),
+            };
+
+            run(Command::new(pg_bin("initdb"))
+                .args(["-U", "postgres", "-A", "trust", "--no-sync", "-D"])
+                .arg(&data_dir));
+            run(Command::new(pg_bin("pg_ctl"))
+                .arg("-D")
+                .arg(&data_dir)
+                .arg("-l")
+                .arg(data_dir.join("server.log"))
+                .arg("-o")
+                .arg(format!(
+                    "-p {port} -k {} -c listen_addresses=127.0.0.1 -c fsync=off",
+                    data_dir.display()
+                ))
+                .args(["-w", "start"]));
+            server
+        }
+    }
+
+    fn pg_bin(name: &str) -> PathBuf {
+        match std::env::var("PG_BIN") {
+            Ok(dir) => PathBuf::from(dir).join(name),
+            Err(_) => PathBuf::from(name),
+        }
+    }
+
+    /// Run a Postgres tool, panicking with its output if it fails. `initdb` refuses to run
+    /// as root, for one; set `DATABASE_URL` there instead.
+    fn run(command: &mut Command) {
+        let program = command.get_program().to_string_lossy().into_owned();
+        let output = command.output().unwrap_or_else(|err| {
+            panic!("{program}: {err}; install PostgreSQL, or set PG_BIN or DATABASE_URL")
+        });
+        assert!(
+            output.status.success(),
+            "{program} failed; set DATABASE_URL to test against an existing server\n{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    impl Drop for TestPostgres {
+        fn drop(&mut self) {
+            if let Some(data_dir) = &self.data_dir {
+                let _ = Command::new(pg_bin("pg_ctl"))
+                    .arg("-D")
+                    .arg(data_dir)
+                    .args(["-m", "immediate", "-w", "stop"])
+                    .output();
+                let _ = std::fs::remove_dir_all(data_dir);
+            }
+        }
+    }
+
+    async fn repository(server: &TestPostgres) -> PostgresRideRepository {
+        let repo = PostgresRideRepository::connect(&server.url)
+            .await
+            .expect("connect");
+        repo.migrate().await.expect("migrate");
+        repo
+    }
+
+    /// A fresh `users` row and a `drivers` row for it, the rider and pilot a ride may name.
+    async fn fixture(pool: &PgPool) -> conformance::Fixture {
+        let rider: Uuid = sqlx::query_scalar("INSERT INTO users DEFAULT VALUES RETURNING id")
+            .fetch_one(pool)
+            .await
+            .expect("insert user");
+        let pilot: Uuid =
+            sqlx::query_scalar("INSERT INTO drivers (user_id) VALUES ($1) RETURNING id")
+                .bind(rider)
+                .fetch_one(pool)
+                .await
+                .expect("insert driver");
+        conformance::Fixture {
+            rider_id: rider.to_string(),
+            pilot_id: pilot.to_string(),
+        }
+    }
+
+    fn ride(rider_id: &str) -> Ride {
+        Ride::new(
+            rider_id.to_string(),
+            RideLocation::new(34.0, -118.0),
+            RideLocation::new(34.05, -118.02),
+            Some("ebike".to_string()),
+            Some("flat tire".to_string()),
+            Some("+15555550100".to_string()),
+            3.25,
+            2500,
+        )
+    }
+
+    #[tokio::test]
+    async fn conforms_to_repository_contract() {
+        let server = TestPostgres::start();
+        let repo = repository(&server).await;
+        conformance::run_with(&repo, &fixture(&repo.pool).await).await;
+    }
+
+    #[tokio::test]
+    async fn rides_round_trip_and_filter() {
+        let server = TestPostgres::start();
+        let repo = repository(&server).await;
+        // Migrations skip what already exists, so re-running them is harmless.
+        repo.migrate().await.expect("re-run migrations");
+        let conformance::Fixture {
+            rider_id,
+            pilot_id: driver_id,
+        } = fixture(&repo.pool).await;
+
+        let first = ride(&rider_id);
+        let second = ride(&rider_id);
+        repo.create_ride(first.clone()).await.unwrap();
+        repo.create_ride(second.clone()).await.unwrap();
+        assert_eq!(repo.get_ride(&first.id).await.unwrap(), first);
+
+        let mut assigned = first.clone();
+        assigned.status = RideStatus::Accepted.to_string();
+        assigned.driver_id = Some(driver_id.clone());
+        repo.update_ride(assigned.clone()).await.unwrap();
+
+        let by_rider = RideFilter {
+            rider_id: Some(rider_id.clone()),
+            ..RideFilter::default()
+        };
+        assert_eq!(repo.list_rides(&by_rider, None, 10).await.unwrap().rides.len(), 2);
+        let by_driver = RideFilter {
+            driver_id: Some(driver_id),
+            statuses: vec![RideStatus::Accepted],
+            ..RideFilter::default()
+        };
+        let page = repo.list_rides(&by_driver, None, 10).await.unwrap();
+        assert_eq!(page.rides, vec![assigned]);
+        let by_phone = RideFilter {
+            rider_phone: Some("+15555550100".to_string()),
+            ..by_rider
+        };
+        let limited = repo.list_rides(&by_phone, None, 1).await.unwrap();
+        assert_eq!(limited.rides.len(), 1);
+        assert!(limited.next_cursor.is_some());
+
+        let missing = ride(&rider_id);
+        assert!(matches!(repo.update_ride(missing).await, Err(CoreError::NotFound)));
+        // Ids are keys of Node rows, so an unknown one is refused and matches nothing.
+        for unknown in ["rider-1".to_string(), Uuid::new_v4().to_string()] {
+            let result = repo.create_ride(ride(&unknown)).await;
+            assert!(matches!(result, Err(CoreError::Storage(_))), "{unknown}");
+        }
+        let by_subject = RideFilter {
+            rider_id: Some("rider-1".to_string()),
+            ..RideFilter::default()
+        };
+        assert!(repo.list_rides(&by_subject, None, 10).await.unwrap().rides.is_empty());
+
+        // SMS replies act on the rider's latest ride.
+        let mut latest = ride(&rider_id);
+        latest.created_at_ms = second.created_at_ms + 1_000;
+        repo.create_ride(latest.clone()).await.unwrap();
+        assert_eq!(
+            repo.find_by_phone("+15555550100").await.unwrap().map(|r| r.id),
+            Some(latest.id)
+        );
+
+        // Twilio saves insert new rides and overwrite stored ones.
+        let mut texted = ride(&rider_id);
+        TwilioRideStore::save(&repo, texted.clone()).await.unwrap();
+        assert_eq!(repo.get_ride(&texted.id).await.unwrap(), texted);
+        texted.status = RideStatus::Cancelled.to_string();
+        TwilioRideStore::save(&repo, texted.clone()).await.unwrap();
+        assert_eq!(repo.get_ride(&texted.id).await.unwrap(), texted);
+    }
+
+    #[tokio::test]
+    async fn listings_order_on_indexed_keys() {
+        let server = TestPostgres::start();
+        let repo = repository(&server).await;
+        let plan = |sql: String| {
+            let pool = repo.pool.clone();
+            async move {
+                let mut tx = pool.begin().await.expect("begin");
+                // Small test tables are cheaper to scan; this asks whether an index applies.
+                sqlx::query("SET LOCAL enable_seqscan = off")
+                    .execute(&mut *tx)
+                    .await
+                    .expect("disable seqscan");
+                let rows: Vec<String> = sqlx::query_scalar(&format!("EXPLAIN {sql}"))
+                    .fetch_all(&mut *tx)
+                    .await
+                    .expect("explain");
+                rows.join("\n")
+            }
+        };
+
+        let all = plan(format!("{SELECT_RIDE} ORDER BY {CREATED_AT_MS}, id LIMIT 10")).await;
+        assert!(all.contains("rides_created_at_ms_id_index"), "{all}");
+        assert!(!all.contains("Sort"), "{all}");
+        let by_status = plan(format!(
+            "{SELECT_RIDE} WHERE status = 'requested' ORDER BY {CREATED_AT_MS}, id LIMIT 10"
+        ))
+        .await;
+        // Either index serves the order; which one depends on how many rows match.
+        assert!(by_status.contains("created_at_ms_id_index"), "{by_status}");
+        assert!(!by_status.contains("Sort"), "{by_status}");
+    }
+
+    #[tokio::test]
+    async fn concurrent_transitions_apply_once() {
+        let server = TestPostgres::start();
+        let repo = repository(&server).await;
+        let ride = ride(&fixture(&repo.pool).await.rider_id);
+        repo.create_ride(ride.clone()).await.unwrap();
+
+        let (a, b) = tokio::join!(
+            repo.transition(&ride.id, RideStatus::Requested, RideEvent::Cancel, None),
+            repo.transition(&ride.id, RideStatus::Requested, RideEvent::Cancel, None)
+        );
+        // The second transaction waits for the row lock and then sees `cancelled`.
+        assert_eq!([a.is_ok(), b.is_ok()].iter().filter(|ok| **ok).count(), 1);
+        assert_eq!(repo.get_ride(&ride.id).await.unwrap().status, "cancelled");
+        assert!(matches!(
+            repo.transition(&Uuid::new_v4(), RideStatus::Requested, RideEvent::Cancel, None)
+                .await,
+            Err(CoreError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn migrations_leave_a_node_created_table_alone() {
+        let server = TestPostgres::start();
+        let admin = PgPool::connect(&server.url).await.expect("connect");
+        let database = format!("supportcarr_node_{}", Uuid::new_v4().simple());
+        sqlx::query(&format!("CREATE DATABASE {database}"))
+            .execute(&admin)
+            .await
+            .expect("create database");
+        let (base, _) = server.url.rsplit_once('/').expect("database in url");
+        let repo = PostgresRideRepository::connect(&format!("{base}/{database}"))
+            .await
+            .expect("connect");
+
+        // The shape the Node migrations leave behind, with a column Rust does not know.
+        sqlx::raw_sql(
+            "CREATE EXTENSION IF NOT EXISTS pgcrypto;
+             CREATE TABLE users (id uuid PRIMARY KEY DEFAULT gen_random_uuid());
+             CREATE TABLE drivers (
+                 id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+                 user_id uuid NOT NULL REFERENCES users (id)
+             );
+             CREATE TABLE rides (
+                 id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+                 rider_id uuid NOT NULL,
+                 rider_phone varchar(255),
+                 driver_id uuid,
+                 pickup jsonb NOT NULL,
+                 dropoff jsonb NOT NULL,
+                 bike_type varchar(255) NOT NULL DEFAULT 'analog',
+                 distance_miles decimal(8, 2),
+                 price_cents integer,
+                 status varchar(255) NOT NULL DEFAULT 'requested',
+                 cancellation_reason varchar(255),
+                 notes text,
+                 created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                 updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                 CONSTRAINT rides_rider_id_foreign FOREIGN KEY (rider_id) REFERENCES users (id),
+                 CONSTRAINT rides_driver_id_foreign FOREIGN KEY (driver_id) REFERENCES drivers (id)
+             );",
+        )
+        .execute(&repo.pool)
+        .await
+        .expect("create Node schema");
+        let columns = || async {
+            sqlx::query_as::<_, (String, String)>(
+                "SELECT column_name::text, data_type::text FROM information_schema.columns \
+                 WHERE table_name = 'rides' ORDER BY column_name",
+            )
+            .fetch_all(&repo.pool)
+            .await
+            .expect("read columns")
+        };
+        let constraints = || async {
+            sqlx::query_scalar::<_, String>(
+                "SELECT conname::text FROM pg_constraint \
+                 WHERE conrelid = 'rides'::regclass ORDER BY conname",
+            )
+            .fetch_all(&repo.pool)
+            .await
+            .expect("read constraints")
+        };
+        let (node_columns, node_constraints) = (columns().await, constraints().await);
+        repo.migrate().await.expect("migrate");
+        assert_eq!(columns().await, node_columns);
+        assert_eq!(constraints().await, node_constraints);
+
+        let fixture = fixture(&repo.pool).await;
+        let mut ride = ride(&fixture.rider_id);
+        repo.create_ride(ride.clone()).await.expect("create ride");
+        ride.status = RideStatus::Accepted.to_string();
+        ride.driver_id = Some(fixture.pilot_id);
+        repo.update_ride(ride.clone()).await.expect("update ride");
+        assert_eq!(repo.get_ride(&ride.id).await.unwrap(), ride);
+
+        repo.pool.close().await;
+        // An autovacuum worker may still be connected to the database.
+        sqlx::query(&format!("DROP DATABASE {database} WITH (FORCE)"))
+            .execute(&admin)
+            .await
+            .expect("drop database");
+    }
+}
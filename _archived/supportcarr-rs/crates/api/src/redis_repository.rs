@@ -0,0 +1,607 @@
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+use supportcarr_core::error::{CoreError, CoreResult};
+use supportcarr_core::fsm::{RideEvent, RideStatus};
+use supportcarr_core::geo::BoundingBox;
+use supportcarr_core::model::Ride;
+use supportcarr_twilio::TwilioRideStore;
+
+use crate::repository::{transitioned, RideCursor, RideFilter, RidePage, RideRepository};
+
+// Attempts at a compare-and-set write before giving up on a contended ride.
+const WRITE_ATTEMPTS: usize = 5;
+
+// Ids read from an index per round trip while listing rides.
+const LIST_CHUNK: isize = 200;
+
+// How long a pickup search's scratch set outlives a listing that fails to delete it.
+const SCRATCH_TTL_MS: u64 = 60_000;
+
+// Writes a ride and moves it between index sets in one step, provided the stored ride is
+// still the one the caller read.
+// KEYS[1] ride key, KEYS[2] all-rides index, KEYS[3] pickup GEO index, then the index keys of
+// the stored ride followed by those of the new one.
+// ARGV: stored ride JSON ('' when there is none), new ride JSON, ride id, index score, number
+// of stored-ride index keys, pickup longitude, pickup latitude.
+// Returns 1 once written, 0 without writing when the stored ride has changed.
+const SAVE_RIDE_SCRIPT: &str = r"
+if (redis.call('GET', KEYS[1]) or '') ~= ARGV[1] then
+    return 0
+end
+local stale = 3 + tonumber(ARGV[5])
+for i = 4, stale do
+    redis.call('ZREM', KEYS[i], ARGV[3])
+end
+redis.call('SET', KEYS[1], ARGV[2])
+redis.call('ZADD', KEYS[2], ARGV[4], ARGV[3])
+redis.call('GEOADD', KEYS[3], ARGV[6], ARGV[7], ARGV[3])
+for i = stale + 1, #KEYS do
+    redis.call('ZADD', KEYS[i], ARGV[4], ARGV[3])
+end
+return 1
+";
+
+/// Rides stored as JSON strings at `{prefix}:ride:{id}`, with sorted-set indexes scored by
+/// creation time under `{prefix}:rides:` (`status:`, `rider:`, `pilot:` and `phone:`) and a
+/// GEO set of pickups at `{prefix}:rides:pickups`.
+/// Writes go through a Lua script that compares the stored ride with the one read before
+/// writing, so a ride and its indexes never disagree and concurrent writers retry instead of
+/// overwriting each other. The script receives every key it touches in `KEYS`; on a cluster,
+/// wrap the prefix in a hash tag (`{rides}`) so they share a slot.
+#[derive(Clone)]
+pub struct RedisRideRepository {
+    client: redis::Client,
+    key_prefix: String,
+    connection: std::sync::Arc<OnceCell<ConnectionManager>>,
+}
+
+impl RedisRideRepository {
+    pub fn new(client: redis::Client, key_prefix: impl Into<String>) -> Self {
+        Self {
+            client,
+            key_prefix: key_prefix.into(),
+            connection: Default::default(),
+        }
+    }
+
+    async fn connection(&self) -> CoreResult<ConnectionManager> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .map_err(storage_error)?;
+        Ok(connection.clone())
+    }
+
+    fn ride_key(&self, id: &Uuid) -> String {
+        format!("{}:ride:{}", self.key_prefix, id)
+    }
+
+    fn index_key(&self, index: &str, value: &str) -> String {
+        format!("{}:rides:{}:{}", self.key_prefix, index, value)
+    }
+
+    fn pickups_key(&self) -> String {
+        format!("{}:rides:pickups", self.key_prefix)
+    }
+
+    fn index_keys(&self, ride: &Ride) -> Vec<String> {
+        let mut keys = vec![
+            self.index_key("status", &ride.status),
+            self.index_key("rider", &ride.rider_id),
+        ];
+        keys.extend(ride.driver_id.iter().map(|pilot| self.index_key("pilot", pilot)));
+        keys.extend(ride.rider_phone.iter().map(|phone| self.index_key("phone", phone)));
+        keys
+    }
+
+    /// The stored ride with the JSON it was read from, which [`Self::swap`] compares against.
+    async fn stored(&self, id: &Uuid) -> CoreResult<Option<(String, Ride)>> {
+        let mut conn = self.connection().await?;
+        let json: Option<String> = conn.get(self.ride_key(id)).await.map_err(storage_error)?;
+        json.map(|json| {
+            let ride = serde_json::from_str(&json).map_err(storage_error)?;
+            Ok((json, ride))
+        })
+        .transpose()
+    }
+
+    /// Write `ride` if the stored ride is still `stored` (or still missing, for `None`).
+    /// Returns whether it was written.
+    async fn swap(&self, stored: Option<&(String, Ride)>, ride: &Ride) -> CoreResult<bool> {
+        let json = serde_json::to_string(ride).map_err(storage_error)?;
+        let stale_keys = stored.map(|(_, old)| self.index_keys(old)).unwrap_or_default();
+        let created_at_ms = stored.map_or(ride.created_at_ms, |(_, old)| old.created_at_ms);
+        let mut conn = self.connection().await?;
+        let written: i64 = redis::Script::new(SAVE_RIDE_SCRIPT)
+            .key(self.ride_key(&ride.id))
+            .key(self.index_key("all", "rides"))
+            .key(self.pickups_key())
+            .key(&stale_keys)
+            .key(self.index_keys(ride))
+            .arg(stored.map_or("", |(json, _)| json.as_str()))
+            .arg(json)
+            .arg(ride.id.to_string())
+            .arg(created_at_ms)
+            .arg(stale_keys.len())
+            .arg(ride.pickup.lng)
+            .arg(ride.pickup.lat)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(storage_error)?;
+        Ok(written == 1)
+    }
+
+    /// Replace the stored ride with `change(stored)`, retrying on concurrent writes.
+    /// `change` sees `None` when the ride does not exist.
+    async fn write(
+        &self,
+        id: &Uuid,
+        change: impl Fn(Option<&Ride>) -> CoreResult<Ride>,
+    ) -> CoreResult<Ride> {
+        for _ in 0..WRITE_ATTEMPTS {
+            let stored = self.stored(id).await?;
+            let ride = change(stored.as_ref().map(|(_, ride)| ride))?;
+            if self.swap(stored.as_ref(), &ride).await? {
+                return Ok(ride);
+            }
+        }
+        Err(CoreError::Storage(format!(
+            "ride {id} changed concurrently on every write attempt"
+        )))
+    }
+
+    /// Rides listed in an index, oldest first.
+    async fn list_index(&self, index: &str, value: &str) -> CoreResult<Vec<Ride>> {
+        let mut conn = self.connection().await?;
+        let ids: Vec<String> = conn
+            .zrange(self.index_key(index, value), 0, -1)
+            .await
+            .map_err(storage_error)?;
+        self.load(&mut conn, &ids).await
+    }
+
+    async fn load(&self, conn: &mut ConnectionManager, ids: &[String]) -> CoreResult<Vec<Ride>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys: Vec<String> = ids
+            .iter()
+            .map(|id| format!("{}:ride:{}", self.key_prefix, id))
+            .collect();
+        let values: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(&keys)
+            .query_async(conn)
+            .await
+            .map_err(storage_error)?;
+        values
+            .into_iter()
+            .flatten()
+            .map(|json| serde_json::from_str(&json).map_err(storage_error))
+            .collect()
+    }
+
+    /// Up to `wanted` rides from a sorted-set index that match `filter` and follow
+    /// `cursor`, in cursor order.
+    async fn walk_index(
+        &self,
+        conn: &mut ConnectionManager,
+        index: &str,
+        filter: &RideFilter,
+        cursor: Option<RideCursor>,
+        wanted: usize,
+    ) -> CoreResult<Vec<Ride>> {
+        let min = filter
+            .created_after_ms
+            .unwrap_or(0)
+            .max(cursor.map_or(0, |cursor| cursor.created_at_ms));
+        let max = filter
+            .created_before_ms
+            .map_or("+inf".to_string(), |before| format!("({before}"));
+
+        let mut rides = Vec::new();
+        let mut offset = 0;
+        while rides.len() < wanted {
+            let ids: Vec<String> = conn
+                .zrangebyscore_limit(index, min, &max, offset, LIST_CHUNK)
+                .await
+                .map_err(storage_error)?;
+            offset += ids.len() as isize;
+            let exhausted = (ids.len() as isize) < LIST_CHUNK;
+            rides.extend(
+                self.load(conn, &ids)
+                    .await?
+                    .into_iter()
+                    .filter(|ride| cursor.is_none_or(|cursor| RideCursor::of(ride) > cursor))
+                    .filter(|ride| filter.matches(ride)),
+            );
+            if exhausted {
+                break;
+            }
+        }
+        rides.truncate(wanted);
+        Ok(rides)
+    }
+
+    /// Up to `wanted` rides picked up inside `bounds` that match `filter` and follow
+    /// `cursor`, in cursor order. The pickups found by a `BYBOX` search as wide as the box's
+    /// widest edge are stored in a scratch sorted set, rescored by creation time from the
+    /// index of every ride and walked like any other index, so a page only loads its rides.
+    async fn pickups_within(
+        &self,
+        conn: &mut ConnectionManager,
+        bounds: &BoundingBox,
+        filter: &RideFilter,
+        cursor: Option<RideCursor>,
+        wanted: usize,
+    ) -> CoreResult<Vec<Ride>> {
+        let center = bounds.center();
+        let scratch = format!("{}:rides:pickups:scratch:{}", self.key_prefix, Uuid::new_v4());
+        let stored: redis::RedisResult<()> = redis::pipe()
+            .atomic()
+            .cmd("GEOSEARCHSTORE")
+            .arg(&scratch)
+            .arg(self.pickups_key())
+            .arg("FROMLONLAT")
+            .arg(center.lng)
+            .arg(center.lat)
+            .arg("BYBOX")
+            .arg(bounds.widest_width_miles())
+            .arg(bounds.height_miles())
+            .arg("mi")
+            .ignore()
+            .cmd("ZINTERSTORE")
+            .arg(&scratch)
+            .arg(2)
+            .arg(self.index_key("all", "rides"))
+            .arg(&scratch)
+            .arg("WEIGHTS")
+            .arg(1)
+            .arg(0)
+            .ignore()
+            .cmd("PEXPIRE")
+            .arg(&scratch)
+            .arg(SCRATCH_TTL_MS)
+            .ignore()
+            .query_async(conn)
+            .await;
+        let rides = match stored {
+            Ok(()) => self.walk_index(conn, &scratch, filter, cursor, wanted).await,
+            Err(err) => Err(storage_error(err)),
+        };
+        let _: redis::RedisResult<()> = conn.del(&scratch).await;
+        rides
+    }
+
+    pub async fn list_by_rider(&self, rider_id: &str) -> CoreResult<Vec<Ride>> {
+        self.list_index("rider", rider_id).await
+    }
+
+    pub async fn list_by_pilot(&self, pilot_id: &str) -> CoreResult<Vec<Ride>> {
+        self.list_index("pilot", pilot_id).await
+    }
+
+    /// The most recently created ride for a rider phone number.
+    pub async fn find_by_phone(&self, phone: &str) -> CoreResult<Option<Ride>> {
+        let mut conn = self.connection().await?;
+        let ids: Vec<String> = conn
+            .zrevrange(self.index_key("phone", phone), 0, 0)
+            .await
+            .map_err(storage_error)?;
+        Ok(self.load(&mut conn, &ids).await?.pop())
+    }
+}
+
+fn storage_error(err: impl std::fmt::Display) -> CoreError {
+    CoreError::Storage(err.to_string())
+}
+
+#[async_trait]
+impl RideRepository for RedisRideRepository {
+    async fn create_ride(&self, ride: Ride) -> CoreResult<()> {
+        match self.swap(None, &ride).await? {
+            true => Ok(()),
+            false => Err(CoreError::AlreadyExists),
+        }
+    }
+
+    async fn get_ride(&self, id: &Uuid) -> CoreResult<Ride> {
+        let (_, ride) = self.stored(id).await?.ok_or(CoreError::NotFound)?;
+        Ok(ride)
+    }
+
+    async fn update_ride(&self, ride: Ride) -> CoreResult<()> {
+        self.write(&ride.id, |stored| match stored {
+            Some(_) => Ok(ride.clone()),
+            None => Err(CoreError::NotFound),
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// The write only lands if the ride is unchanged since it was read; otherwise the ride
+    /// is re-read and its status checked again.
+    async fn transition(
+        &self,
+        id: &Uuid,
+        from: RideStatus,
+        event: RideEvent,
+        pilot_id: Option<&str>,
+    ) -> CoreResult<Ride> {
+        self.write(id, |stored| {
+            let ride = stored.cloned().ok_or(CoreError::NotFound)?;
+            transitioned(ride, from, event, pilot_id)
+        })
+        .await
+    }
+
+    async fn list_by_status(&self, status: RideStatus) -> CoreResult<Vec<Ride>> {
+        self.list_index("status", status.as_str()).await
+    }
+
+    /// Reads the most selective index: the rider, pilot or phone index, else the pickups
+    /// inside the box, else each requested status index, else every ride. Sorted-set indexes
+    /// are walked by score (creation time), which Redis orders by id within equal scores;
+    /// the rest of `filter` is applied to the loaded rides.
+    async fn list_rides(
+        &self,
+        filter: &RideFilter,
+        cursor: Option<RideCursor>,
+        limit: usize,
+    ) -> CoreResult<RidePage> {
+        let wanted = limit.saturating_add(1);
+        let selective = if let Some(rider_id) = &filter.rider_id {
+            Some(self.index_key("rider", rider_id))
+        } else if let Some(driver_id) = &filter.driver_id {
+            Some(self.index_key("pilot", driver_id))
+        } else {
+            filter
+                .rider_phone
+                .as_ref()
+                .map(|phone| self.index_key("phone", phone))
+        };
+
+        let mut conn = self.connection().await?;
+        let mut rides = if let Some(index) = selective {
+            self.walk_index(&mut conn, &index, filter, cursor, wanted).await?
+        } else if let Some(bounds) = &filter.pickup_within {
+            self.pickups_within(&mut conn, bounds, filter, cursor, wanted).await?
+        } else if filter.statuses.is_empty() {
+            let index = self.index_key("all", "rides");
+            self.walk_index(&mut conn, &index, filter, cursor, wanted).await?
+        } else {
+            // The first `wanted` rides overall are among the first `wanted` of each status.
+            let mut rides = Vec::new();
+            for status in &filter.statuses {
+                let index = self.index_key("status", status.as_str());
+                rides.extend(self.walk_index(&mut conn, &index, filter, cursor, wanted).await?);
+            }
+            rides
+        };
+        rides.sort_by_key(RideCursor::of);
+        rides.dedup_by_key(|ride| ride.id);
+        rides.truncate(wanted);
+        Ok(RidePage::from_overfetch(rides, limit))
+    }
+
+    async fn health_check(&self) -> CoreResult<()> {
+        let mut conn = self.connection().await?;
+        redis::cmd("PING")
+            .query_async(&mut conn)
+            .await
+            .map_err(storage_error)
+            .map(|_: String| ())
+    }
+}
+
+/// Lets the Twilio webhook resolve riders by phone against the same store as the API.
+#[async_trait]
+impl TwilioRideStore for RedisRideRepository {
+    async fn find_by_phone(&self, phone: &str) -> Result<Option<Ride>, CoreError> {
+        RedisRideRepository::find_by_phone(self, phone).await
+    }
+
+    async fn save(&self, ride: Ride) -> Result<(), CoreError> {
+        if ride.rider_phone.is_none() {
+            return Err(CoreError::InvalidLocation("ride missing phone".into()));
+        }
+        self.write(&ride.id, |_| Ok(ride.clone())).await?;
+        Ok(())
+    }
+
+    async fn update_status(&self, ride: &Ride, event: RideEvent) -> Result<Ride, CoreError> {
+        let from = RideStatus::try_from(ride.status.as_str())?;
+        RideRepository::transition(self, &ride.id, from, event, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use supportcarr_core::model::RideLocation;
+    use supportcarr_dispatch_redis::test_server::TestRedis;
+
+    async fn repository() -> (TestRedis, RedisRideRepository) {
+        let server = TestRedis::start().await;
+        let repo = RedisRideRepository::new(
+            server.client(),
+            format!("supportcarr-test-{}", Uuid::new_v4()),
+        );
+        (server, repo)
+    }
+
+    fn ride(rider_id: &str, phone: &str) -> Ride {
+        Ride::new(
+            rider_id.to_string(),
+            RideLocation::new(34.0, -118.0),
+            RideLocation::new(34.05, -118.02),
+            None,
+            None,
+            Some(phone.to_string()),
+            3.2,
+            2500,
+        )
+    }
+
+    #[tokio::test]
+    async fn conforms_to_repository_contract() {
+        let (_server, repo) = repository().await;
+        crate::repository::conformance::run(&repo).await;
+    }
+
+    #[tokio::test]
+    async fn indexes_follow_ride_updates() {
+        let (_server, repo) = repository().await;
+        let first = ride("rider-1", "+15555550100");
+        let second = Ride {
+            created_at_ms: first.created_at_ms + 1,
+            ..ride("rider-1", "+15555550100")
+        };
+        repo.create_ride(first.clone()).await.unwrap();
+        repo.create_ride(second.clone()).await.unwrap();
+
+        assert_eq!(repo.get_ride(&first.id).await.unwrap(), first);
+        assert_eq!(repo.list_by_rider("rider-1").await.unwrap().len(), 2);
+        assert_eq!(
+            repo.find_by_phone("+15555550100").await.unwrap().map(|r| r.id),
+            Some(second.id)
+        );
+
+        let mut assigned = first.clone();
+        assigned.status = RideStatus::Accepted.to_string();
+        assigned.driver_id = Some("pilot-1".to_string());
+        repo.update_ride(assigned.clone()).await.unwrap();
+
+        let requested = repo.list_by_status(RideStatus::Requested).await.unwrap();
+        assert_eq!(requested.iter().map(|r| r.id).collect::<Vec<_>>(), vec![second.id]);
+        assert_eq!(repo.list_by_status(RideStatus::Accepted).await.unwrap(), vec![assigned]);
+        assert_eq!(repo.list_by_pilot("pilot-1").await.unwrap().len(), 1);
+
+        let missing = ride("rider-2", "+15555550101");
+        assert!(matches!(repo.update_ride(missing).await, Err(CoreError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn listings_read_status_and_pickup_indexes() {
+        let (_server, repo) = repository().await;
+        let new_york = RideLocation::new(40.7128, -74.006);
+        let statuses = [
+            RideStatus::Requested,
+            RideStatus::Accepted,
+            RideStatus::Cancelled,
+            RideStatus::Requested,
+        ];
+        let mut rides = Vec::new();
+        for (n, status) in statuses.into_iter().enumerate() {
+            let mut ride = ride(&format!("rider-{n}"), "+15555550100");
+            ride.created_at_ms += n as u64;
+            ride.status = status.to_string();
+            if n >= 2 {
+                ride.pickup = new_york.clone();
+            }
+            repo.create_ride(ride.clone()).await.unwrap();
+            rides.push(ride);
+        }
+
+        let open = RideFilter {
+            statuses: vec![RideStatus::Requested, RideStatus::Accepted],
+            ..RideFilter::default()
+        };
+        let mut cursor = None;
+        let mut paged = Vec::new();
+        loop {
+            let page = repo.list_rides(&open, cursor, 1).await.unwrap();
+            paged.extend(page.rides);
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(paged, vec![rides[0].clone(), rides[1].clone(), rides[3].clone()]);
+
+        let in_new_york = RideFilter {
+            pickup_within: Some(BoundingBox::new(
+                RideLocation::new(40.5, -74.3),
+                RideLocation::new(40.9, -73.7),
+            )),
+            ..RideFilter::default()
+        };
+        let page = repo.list_rides(&in_new_york, None, 10).await.unwrap();
+        assert_eq!(page.rides, rides[2..].to_vec());
+        let page = repo.list_rides(&in_new_york, None, 1).await.unwrap();
+        assert_eq!(page.rides, rides[2..3].to_vec());
+        let page = repo.list_rides(&in_new_york, page.next_cursor, 1).await.unwrap();
+        assert_eq!((page.rides, page.next_cursor), (rides[3..].to_vec(), None));
+
+        // Moving a pickup moves the ride in the GEO index.
+        let mut moved = rides[3].clone();
+        moved.pickup = RideLocation::new(34.0, -118.0);
+        repo.update_ride(moved).await.unwrap();
+        let page = repo.list_rides(&in_new_york, None, 10).await.unwrap();
+        assert_eq!(page.rides, rides[2..3].to_vec());
+    }
+
+    #[tokio::test]
+    async fn pickup_listings_reach_the_corners_of_the_box() {
+        let (_server, repo) = repository().await;
+        // Far from the equator, the box's southern edge is much wider than its middle.
+        let bounds = BoundingBox::new(RideLocation::new(59.5, 10.0), RideLocation::new(60.5, 12.0));
+        let pickups = [
+            RideLocation::new(59.51, 10.01),
+            RideLocation::new(59.51, 11.99),
+            RideLocation::new(60.49, 10.01),
+            RideLocation::new(60.49, 11.99),
+            RideLocation::new(60.49, 12.01),
+        ];
+        let mut rides = Vec::new();
+        for (n, pickup) in pickups.into_iter().enumerate() {
+            let mut ride = ride(&format!("rider-{n}"), "+15555550100");
+            ride.created_at_ms += n as u64;
+            ride.pickup = pickup;
+            repo.create_ride(ride.clone()).await.unwrap();
+            rides.push(ride);
+        }
+
+        let filter = RideFilter {
+            pickup_within: Some(bounds),
+            ..RideFilter::default()
+        };
+        let mut cursor = None;
+        let mut paged = Vec::new();
+        loop {
+            let page = repo.list_rides(&filter, cursor, 3).await.unwrap();
+            paged.extend(page.rides);
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(paged, rides[..4].to_vec());
+    }
+
+    #[tokio::test]
+    async fn transitions_are_checked_against_stored_status() {
+        let (_server, repo) = repository().await;
+        let ride = ride("rider-1", "+15555550100");
+        repo.create_ride(ride.clone()).await.unwrap();
+
+        let cancelled = repo
+            .transition(&ride.id, RideStatus::Requested, RideEvent::Cancel, None)
+            .await
+            .unwrap();
+        assert_eq!(cancelled.status, "cancelled");
+        assert!(matches!(
+            repo.transition(&ride.id, RideStatus::Cancelled, RideEvent::Accept, None).await,
+            Err(CoreError::InvalidStatusTransition { .. })
+        ));
+
+        // A write based on the ride as it was before the cancel does not land.
+        let stale = (serde_json::to_string(&ride).unwrap(), ride.clone());
+        assert!(!repo.swap(Some(&stale), &ride).await.unwrap());
+        assert!(repo.list_by_status(RideStatus::Requested).await.unwrap().is_empty());
+    }
+}
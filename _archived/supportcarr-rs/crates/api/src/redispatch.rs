@@ -0,0 +1,356 @@
+use std::time::Duration;
+
+use supportcarr_core::dispatch::{now_millis, DispatchEvent};
+use supportcarr_core::fsm::{RideEvent, RideStatus};
+use supportcarr_core::queue::PendingRide;
+use supportcarr_core::search::{RingSearch, SearchRing};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::offers::{offer_next_with, withdraw_offer};
+use crate::{ApiError, ApiState};
+
+/// What happens to a ride still unassigned once the SLA runs out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlaAction {
+    /// Move the ride to `cancelled` via [`RideEvent::CancelNoPilot`].
+    Cancel,
+    /// Leave the ride `requested` but stop retrying and list it as flagged for ops. The
+    /// batch loop skips flagged rides too.
+    Flag,
+}
+
+/// Search used once a ride has waited at least `after`.
+#[derive(Debug, Clone)]
+pub struct EscalationStep {
+    pub after: Duration,
+    pub search: RingSearch,
+}
+
+#[derive(Debug, Clone)]
+pub struct RedispatchPolicy {
+    pub interval: Duration,
+    /// Oldest pending rides handled per round.
+    pub batch_size: usize,
+    /// Steps in ascending `after` order; the last step already reached applies.
+    pub escalation: Vec<EscalationStep>,
+    pub sla: Duration,
+    pub sla_action: SlaAction,
+}
+
+impl Default for RedispatchPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            batch_size: 100,
+            escalation: vec![
+                EscalationStep {
+                    after: Duration::ZERO,
+                    search: RingSearch::default(),
+                },
+                EscalationStep {
+                    after: Duration::from_secs(2 * 60),
+                    search: RingSearch {
+                        rings: vec![
+                            SearchRing::new(5.0, 5),
+                            SearchRing::new(10.0, 8),
+                            SearchRing::new(20.0, 10),
+                        ],
+                        min_candidates: 1,
+                    },
+                },
+                EscalationStep {
+                    after: Duration::from_secs(5 * 60),
+                    search: RingSearch {
+                        rings: vec![SearchRing::new(15.0, 10), SearchRing::new(30.0, 15)],
+                        min_candidates: 1,
+                    },
+                },
+            ],
+            sla: Duration::from_secs(15 * 60),
+            sla_action: SlaAction::Cancel,
+        }
+    }
+}
+
+impl RedispatchPolicy {
+    fn search_for(&self, age: Duration) -> Option<&RingSearch> {
+        self.escalation
+            .iter()
+            .rev()
+            .find(|step| step.after <= age)
+            .map(|step| &step.search)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedispatchOutcome {
+    Offered(DispatchEvent),
+    NoCandidates,
+    /// A live offer is still waiting on its pilot.
+    AwaitingReply,
+    /// The ride left `requested` by other means and was dropped from the queue.
+    Resolved,
+    Cancelled,
+    Flagged,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedispatchAttempt {
+    pub ride_id: String,
+    pub age: Duration,
+    pub outcome: RedispatchOutcome,
+}
+
+/// Run [`run_redispatch_round`] every `policy.interval` until shutdown begins. A round in
+/// progress is finished first.
+pub fn spawn_redispatch_worker(state: ApiState, policy: RedispatchPolicy) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(policy.interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = state.readiness.shutdown_begun() => break,
+            }
+            if let Err(err) = run_redispatch_round(&state, &policy).await {
+                tracing::warn!(error = %err, "redispatch round failed");
+            }
+        }
+    })
+}
+
+/// Retry dispatch for the oldest pending rides, widening the search as they age and
+/// applying the SLA action to rides that waited too long. Every attempt is logged.
+pub async fn run_redispatch_round(
+    state: &ApiState,
+    policy: &RedispatchPolicy,
+) -> Result<Vec<RedispatchAttempt>, ApiError> {
+    let now_ms = now_millis();
+    let mut attempts = Vec::new();
+
+    for pending in state.queue.pending(policy.batch_size).await? {
+        let age = Duration::from_millis(now_ms.saturating_sub(pending.requested_at_ms));
+        match redispatch_ride(state, policy, &pending, age).await {
+            Ok(outcome) => {
+                tracing::info!(
+                    ride_id = %pending.ride_id,
+                    age_secs = age.as_secs(),
+                    outcome = ?outcome,
+                    "redispatch attempt"
+                );
+                attempts.push(RedispatchAttempt {
+                    ride_id: pending.ride_id,
+                    age,
+                    outcome,
+                });
+            }
+            Err(err) => tracing::warn!(
+                ride_id = %pending.ride_id,
+                age_secs = age.as_secs(),
+                error = %err,
+                "redispatch attempt failed"
+            ),
+        }
+    }
+
+    Ok(attempts)
+}
+
+async fn redispatch_ride(
+    state: &ApiState,
+    policy: &RedispatchPolicy,
+    pending: &PendingRide,
+    age: Duration,
+) -> Result<RedispatchOutcome, ApiError> {
+    let Ok(id) = Uuid::parse_str(&pending.ride_id) else {
+        state.queue.remove(&pending.ride_id).await?;
+        return Ok(RedispatchOutcome::Resolved);
+    };
+    let ride = state.repo.get_ride(&id).await?;
+    if ride.status != RideStatus::Requested.as_str() {
+        state.queue.remove(&pending.ride_id).await?;
+        return Ok(RedispatchOutcome::Resolved);
+    }
+
+    if age >= policy.sla {
+        // Neither a pilot nor the offer timer may move the ride on after this.
+        withdraw_offer(state, &pending.ride_id, "sla_expired").await?;
+        return match policy.sla_action {
+            SlaAction::Cancel => {
+                let ride = state
+                    .repo
+                    .transition(&id, RideStatus::Requested, RideEvent::CancelNoPilot, None)
+                    .await?;
+                state.updates.publish_status(&ride);
+                state.queue.remove(&pending.ride_id).await?;
+                Ok(RedispatchOutcome::Cancelled)
+            }
+            SlaAction::Flag => {
+                state.queue.flag(&pending.ride_id).await?;
+                Ok(RedispatchOutcome::Flagged)
+            }
+        };
+    }
+
+    let live_offer = state
+        .dispatch
+        .current_offer(&pending.ride_id)
+        .await?
+        .is_some_and(|offer| !offer.is_expired());
+    if live_offer {
+        return Ok(RedispatchOutcome::AwaitingReply);
+    }
+
+    let search = policy.search_for(age).unwrap_or(&state.offers.search);
+    Ok(match offer_next_with(state, &ride, search).await? {
+        Some(event) => RedispatchOutcome::Offered(event),
+        None => RedispatchOutcome::NoCandidates,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::batch::{run_batch_round, BatchPolicy};
+    use crate::repository::conformance::{ride, Fixture};
+    use supportcarr_core::events::DispatchDecision;
+    use supportcarr_core::model::{Ride, RideLocation};
+
+    /// A requested, queued ride picked up at `pickup` that has waited `age`.
+    async fn queued(state: &ApiState, pickup: RideLocation, age: Duration) -> Ride {
+        let mut ride = ride(&Fixture::default());
+        ride.pickup = pickup;
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        let requested_at_ms = now_millis() - age.as_millis() as u64;
+        state
+            .queue
+            .enqueue(&ride.id.to_string(), requested_at_ms)
+            .await
+            .unwrap();
+        ride
+    }
+
+    async fn offer(state: &ApiState, ride: &Ride, pilot_id: &str) {
+        state
+            .dispatch
+            .create_offer(&ride.id.to_string(), pilot_id, state.offers.timeout)
+            .await
+            .unwrap()
+            .expect("pilot is free");
+    }
+
+    async fn round(
+        state: &ApiState,
+        policy: &RedispatchPolicy,
+    ) -> HashMap<String, RedispatchOutcome> {
+        run_redispatch_round(state, policy)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|attempt| (attempt.ride_id, attempt.outcome))
+            .collect()
+    }
+
+    /// Why offers for `ride` were released, oldest first.
+    async fn released(state: &ApiState, ride: &Ride) -> Vec<String> {
+        let ride_key = ride.id.to_string();
+        let entries = state.events.read_after(None, 100).await.unwrap();
+        entries
+            .into_iter()
+            .filter_map(|entry| match entry.decision {
+                DispatchDecision::Release {
+                    ride_id, reason, ..
+                } if ride_id == ride_key => Some(reason),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rounds_offer_wait_drop_and_cancel() {
+        let state = crate::test_state();
+        let policy = RedispatchPolicy::default();
+        let here = RideLocation::new(34.0522, -118.2437);
+        let nowhere = RideLocation::new(40.0, -100.0);
+        state
+            .dispatch
+            .store_pilot_location("pilot-near", &here)
+            .await
+            .unwrap();
+
+        let fresh = queued(&state, here.clone(), Duration::ZERO).await;
+        let stranded = queued(&state, nowhere.clone(), Duration::from_secs(60)).await;
+        let waiting = queued(&state, nowhere, Duration::from_secs(90)).await;
+        offer(&state, &waiting, "pilot-elsewhere").await;
+        let mut finished = queued(&state, here.clone(), Duration::from_secs(120)).await;
+        finished.status = RideStatus::Cancelled.to_string();
+        state.repo.update_ride(finished.clone()).await.unwrap();
+        let overdue = queued(&state, here, policy.sla).await;
+        offer(&state, &overdue, "pilot-late").await;
+
+        let outcomes = round(&state, &policy).await;
+        assert_eq!(outcomes.len(), 5, "{outcomes:?}");
+        let outcome = |ride: &Ride| outcomes[&ride.id.to_string()].clone();
+        match outcome(&fresh) {
+            RedispatchOutcome::Offered(event) => assert_eq!(event.pilot_id, "pilot-near"),
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(outcome(&stranded), RedispatchOutcome::NoCandidates);
+        assert_eq!(outcome(&waiting), RedispatchOutcome::AwaitingReply);
+        assert_eq!(outcome(&finished), RedispatchOutcome::Resolved);
+        assert_eq!(outcome(&overdue), RedispatchOutcome::Cancelled);
+
+        // Cancelled and resolved rides leave the queue; the others are retried.
+        let pending: Vec<String> = state
+            .queue
+            .pending(10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|pending| pending.ride_id)
+            .collect();
+        assert_eq!(pending.len(), 3);
+        assert!(!pending.contains(&finished.id.to_string()));
+        assert!(!pending.contains(&overdue.id.to_string()));
+
+        // The cancelled ride's pilot can no longer accept it.
+        let overdue_id = overdue.id.to_string();
+        assert_eq!(state.dispatch.current_offer(&overdue_id).await.unwrap(), None);
+        assert_eq!(released(&state, &overdue).await, vec!["sla_expired"]);
+        let cancelled = state.repo.get_ride(&overdue.id).await.unwrap();
+        assert_eq!(cancelled.status, RideStatus::Cancelled.as_str());
+    }
+
+    #[tokio::test]
+    async fn flagged_rides_are_left_for_ops() {
+        let mut state = crate::test_state();
+        state.offers.immediate = false;
+        let policy = RedispatchPolicy {
+            sla_action: SlaAction::Flag,
+            ..RedispatchPolicy::default()
+        };
+        let here = RideLocation::new(34.0522, -118.2437);
+        let overdue = queued(&state, here.clone(), policy.sla).await;
+        offer(&state, &overdue, "pilot-late").await;
+        state
+            .dispatch
+            .store_pilot_location("pilot-near", &here)
+            .await
+            .unwrap();
+
+        let outcomes = round(&state, &policy).await;
+        assert_eq!(outcomes[&overdue.id.to_string()], RedispatchOutcome::Flagged);
+        let overdue_id = overdue.id.to_string();
+        assert!(state.queue.is_flagged(&overdue_id).await.unwrap());
+        assert_eq!(state.dispatch.current_offer(&overdue_id).await.unwrap(), None);
+        assert_eq!(released(&state, &overdue).await, vec!["sla_expired"]);
+
+        // Still requested, but neither loop offers it again.
+        assert!(round(&state, &policy).await.is_empty());
+        let batch = run_batch_round(&state, &BatchPolicy::default()).await.unwrap();
+        assert!(batch.is_empty(), "{batch:?}");
+        assert_eq!(state.dispatch.current_offer(&overdue_id).await.unwrap(), None);
+    }
+}
@@ -1,17 +1,161 @@
 use async_trait::async_trait;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use supportcarr_core::error::{CoreError, CoreResult};
+use supportcarr_core::fsm::{RideEvent, RideStatus, RideStatusMachine};
+use supportcarr_core::geo::BoundingBox;
 use supportcarr_core::model::Ride;
+use supportcarr_twilio::TwilioRideStore;
+
+/// Criteria for listing rides; unset fields match every ride.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RideFilter {
+    /// Rides in any of these statuses; empty matches every status.
+    pub statuses: Vec<RideStatus>,
+    pub rider_id: Option<String>,
+    pub driver_id: Option<String>,
+    pub rider_phone: Option<String>,
+    /// Created at or after this Unix millisecond.
+    pub created_after_ms: Option<u64>,
+    /// Created strictly before this Unix millisecond.
+    pub created_before_ms: Option<u64>,
+    /// Pickup inside this box.
+    pub pickup_within: Option<BoundingBox>,
+}
+
+impl RideFilter {
+    pub fn matches(&self, ride: &Ride) -> bool {
+        (self.statuses.is_empty()
+            || self.statuses.iter().any(|status| ride.status == status.as_str()))
+            && self.rider_id.as_ref().is_none_or(|id| &ride.rider_id == id)
+            && self
+                .driver_id
+                .as_ref()
+                .is_none_or(|id| ride.driver_id.as_ref() == Some(id))
+            && self
+                .rider_phone
+                .as_ref()
+                .is_none_or(|phone| ride.rider_phone.as_ref() == Some(phone))
+            && self.created_after_ms.is_none_or(|ms| ride.created_at_ms >= ms)
+            && self.created_before_ms.is_none_or(|ms| ride.created_at_ms < ms)
+            && self
+                .pickup_within
+                .as_ref()
+                .is_none_or(|bounds| bounds.contains(&ride.pickup))
+    }
+}
+
+/// Position in a listing ordered by creation time, then id. A page resumes strictly after
+/// its cursor, so rides created while a client pages through are never skipped or repeated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RideCursor {
+    pub created_at_ms: u64,
+    pub id: Uuid,
+}
+
+impl RideCursor {
+    pub fn of(ride: &Ride) -> Self {
+        Self {
+            created_at_ms: ride.created_at_ms,
+            id: ride.id,
+        }
+    }
+}
+
+impl Display for RideCursor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.created_at_ms, self.id)
+    }
+}
+
+impl FromStr for RideCursor {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid ride cursor: {value}");
+        let (created_at_ms, id) = value.split_once('_').ok_or_else(invalid)?;
+        Ok(Self {
+            created_at_ms: created_at_ms.parse().map_err(|_| invalid())?,
+            id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// One page of [`RideRepository::list_rides`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RidePage {
+    pub rides: Vec<Ride>,
+    /// Pass back to fetch the next page; `None` on the last page.
+    pub next_cursor: Option<RideCursor>,
+}
+
+impl RidePage {
+    /// Build a page from up to `limit + 1` rides in cursor order; the extra ride only
+    /// signals that another page exists.
+    pub fn from_overfetch(mut rides: Vec<Ride>, limit: usize) -> Self {
+        let next_cursor = if rides.len() > limit {
+            rides.truncate(limit);
+            rides.last().map(RideCursor::of)
+        } else {
+            None
+        };
+        Self { rides, next_cursor }
+    }
+}
 
 #[async_trait]
 pub trait RideRepository: Send + Sync {
+    /// Store a new ride; [`CoreError::AlreadyExists`] if its id is taken.
     async fn create_ride(&self, ride: Ride) -> CoreResult<()>;
     async fn get_ride(&self, id: &Uuid) -> CoreResult<Ride>;
     async fn update_ride(&self, ride: Ride) -> CoreResult<()>;
+    /// Apply `event` to a ride the caller read as `from`, assigning `pilot_id` if given, as
+    /// one compare-and-set: [`CoreError::StatusChanged`] if the stored status has moved on,
+    /// so of two racing transitions only one lands.
+    async fn transition(
+        &self,
+        id: &Uuid,
+        from: RideStatus,
+        event: RideEvent,
+        pilot_id: Option<&str>,
+    ) -> CoreResult<Ride>;
+    async fn list_by_status(&self, status: RideStatus) -> CoreResult<Vec<Ride>>;
+    /// Rides matching `filter` in creation order (ties broken by id), starting after
+    /// `cursor`, at most `limit` per page.
+    async fn list_rides(
+        &self,
+        filter: &RideFilter,
+        cursor: Option<RideCursor>,
+        limit: usize,
+    ) -> CoreResult<RidePage>;
+    /// Fail when the backing store cannot be reached, for readiness probes.
+    async fn health_check(&self) -> CoreResult<()>;
+}
+
+/// The stored ride after [`RideRepository::transition`], for backends to write back.
+pub(crate) fn transitioned(
+    mut ride: Ride,
+    from: RideStatus,
+    event: RideEvent,
+    pilot_id: Option<&str>,
+) -> CoreResult<Ride> {
+    if ride.status != from.as_str() {
+        return Err(CoreError::StatusChanged {
+            expected: from.to_string(),
+            found: ride.status,
+        });
+    }
+    ride.status = RideStatusMachine::apply_event(from, event)?.to_string();
+    if let Some(pilot_id) = pilot_id {
+        ride.driver_id = Some(pilot_id.to_string());
+    }
+    Ok(ride)
 }
 
 #[derive(Default)]
@@ -22,8 +166,13 @@ pub struct InMemoryRideRepository {
 #[async_trait]
 impl RideRepository for InMemoryRideRepository {
     async fn create_ride(&self, ride: Ride) -> CoreResult<()> {
-        self.rides.write().await.insert(ride.id, ride);
-        Ok(())
+        match self.rides.write().await.entry(ride.id) {
+            Entry::Occupied(_) => Err(CoreError::AlreadyExists),
+            Entry::Vacant(slot) => {
+                slot.insert(ride);
+                Ok(())
+            }
+        }
     }
 
     async fn get_ride(&self, id: &Uuid) -> CoreResult<Ride> {
@@ -37,10 +186,408 @@ impl RideRepository for InMemoryRideRepository {
     }
 
     async fn update_ride(&self, ride: Ride) -> CoreResult<()> {
-        if self.rides.write().await.insert(ride.id, ride).is_some() {
-            Ok(())
-        } else {
+        match self.rides.write().await.get_mut(&ride.id) {
+            Some(stored) => {
+                *stored = ride;
+                Ok(())
+            }
+            None => Err(CoreError::NotFound),
+        }
+    }
+
+    async fn transition(
+        &self,
+        id: &Uuid,
+        from: RideStatus,
+        event: RideEvent,
+        pilot_id: Option<&str>,
+    ) -> CoreResult<Ride> {
+        let mut rides = self.rides.write().await;
+        let stored = rides.get_mut(id).ok_or(CoreError::NotFound)?;
+        *stored = transitioned(stored.clone(), from, event, pilot_id)?;
+        Ok(stored.clone())
+    }
+
+    async fn list_by_status(&self, status: RideStatus) -> CoreResult<Vec<Ride>> {
+        Ok(self
+            .rides
+            .read()
+            .await
+            .values()
+            .filter(|ride| ride.status == status.as_str())
+            .cloned()
+            .collect())
+    }
+
+    async fn list_rides(
+        &self,
+        filter: &RideFilter,
+        cursor: Option<RideCursor>,
+        limit: usize,
+    ) -> CoreResult<RidePage> {
+        let mut rides: Vec<Ride> = self
+            .rides
+            .read()
+            .await
+            .values()
+            .filter(|ride| filter.matches(ride))
+            .filter(|ride| cursor.is_none_or(|cursor| RideCursor::of(ride) > cursor))
+            .cloned()
+            .collect();
+        rides.sort_by_key(RideCursor::of);
+        rides.truncate(limit.saturating_add(1));
+        Ok(RidePage::from_overfetch(rides, limit))
+    }
+
+    async fn health_check(&self) -> CoreResult<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TwilioRideStore for InMemoryRideRepository {
+    async fn find_by_phone(&self, phone: &str) -> Result<Option<Ride>, CoreError> {
+        Ok(self
+            .rides
+            .read()
+            .await
+            .values()
+            .filter(|ride| ride.rider_phone.as_deref() == Some(phone))
+            .max_by_key(|ride| RideCursor::of(ride))
+            .cloned())
+    }
+
+    async fn save(&self, ride: Ride) -> Result<(), CoreError> {
+        if ride.rider_phone.is_none() {
+            return Err(CoreError::InvalidLocation("ride missing phone".into()));
+        }
+        self.rides.write().await.insert(ride.id, ride);
+        Ok(())
+    }
+
+    async fn update_status(&self, ride: &Ride, event: RideEvent) -> Result<Ride, CoreError> {
+        let from = RideStatus::try_from(ride.status.as_str())?;
+        RideRepository::transition(self, &ride.id, from, event, None).await
+    }
+}
+
+/// Behaviour every [`RideRepository`] must share. Backends call [`conformance::run`] from
+/// their own tests so that swapping storage never changes what the API sees.
+pub mod conformance {
+    use supportcarr_core::dispatch::now_millis;
+    use supportcarr_core::error::CoreError;
+    use supportcarr_core::fsm::{RideEvent, RideStatus};
+    use supportcarr_core::geo::BoundingBox;
+    use supportcarr_core::model::{Ride, RideLocation};
+    use uuid::Uuid;
+
+    use super::{RideCursor, RideFilter, RideRepository};
+
+    /// Ids the suite puts on its rides. The defaults are fresh for every run, so runs that
+    /// share a database never see each other's rides.
+    #[derive(Debug, Clone)]
+    pub struct Fixture {
+        pub rider_id: String,
+        pub pilot_id: String,
+    }
+
+    impl Default for Fixture {
+        fn default() -> Self {
+            Self {
+                rider_id: format!("rider-{}", Uuid::new_v4()),
+                pilot_id: format!("pilot-{}", Uuid::new_v4()),
+            }
+        }
+    }
+
+    /// Run the whole suite with default ids.
+    pub async fn run<R: RideRepository>(repo: &R) {
+        run_with(repo, &Fixture::default()).await;
+    }
+
+    /// Run the whole suite. Assertions only look at rides the suite created, so it can
+    /// share a database with other tests.
+    pub async fn run_with<R: RideRepository>(repo: &R, fixture: &Fixture) {
+        repo.health_check().await.expect("healthy repository");
+        create_and_get(repo, fixture).await;
+        missing_rides_are_not_found(repo, fixture).await;
+        updates_replace_the_ride(repo, fixture).await;
+        concurrent_updates(repo, fixture).await;
+        transitions_check_the_stored_status(repo, fixture).await;
+        racing_transitions_apply_once(repo, fixture).await;
+        listing_by_status(repo, fixture).await;
+        paging_through_filtered_rides(repo, fixture).await;
+    }
+
+    /// A ride with values every backend stores exactly: the default bike type, and a
+    /// distance with two decimals.
+    pub fn ride(fixture: &Fixture) -> Ride {
+        Ride::new(
+            fixture.rider_id.clone(),
+            RideLocation::new(34.0522, -118.2437),
+            RideLocation::new(34.0407, -118.2468),
+            Some("analog".to_string()),
+            Some("chain snapped".to_string()),
+            Some("+15555550123".to_string()),
+            1.25,
+            1800,
+        )
+    }
+
+    async fn create_and_get<R: RideRepository>(repo: &R, fixture: &Fixture) {
+        let ride = ride(fixture);
+        repo.create_ride(ride.clone()).await.expect("create ride");
+        assert_eq!(repo.get_ride(&ride.id).await.expect("get ride"), ride);
+
+        // Creating never overwrites: a second ride with the same id is refused.
+        let duplicate = Ride {
+            price_cents: ride.price_cents + 100,
+            ..ride.clone()
+        };
+        assert!(matches!(
+            repo.create_ride(duplicate).await,
+            Err(CoreError::AlreadyExists)
+        ));
+        assert_eq!(repo.get_ride(&ride.id).await.expect("get ride"), ride);
+    }
+
+    async fn missing_rides_are_not_found<R: RideRepository>(repo: &R, fixture: &Fixture) {
+        assert!(matches!(
+            repo.get_ride(&Uuid::new_v4()).await,
             Err(CoreError::NotFound)
+        ));
+        let never_created = ride(fixture);
+        assert!(matches!(
+            repo.update_ride(never_created.clone()).await,
+            Err(CoreError::NotFound)
+        ));
+        // A failed update must not create the ride as a side effect.
+        assert!(matches!(
+            repo.get_ride(&never_created.id).await,
+            Err(CoreError::NotFound)
+        ));
+    }
+
+    async fn updates_replace_the_ride<R: RideRepository>(repo: &R, fixture: &Fixture) {
+        let mut ride = ride(fixture);
+        repo.create_ride(ride.clone()).await.expect("create ride");
+
+        ride.status = RideStatus::Accepted.to_string();
+        ride.driver_id = Some(fixture.pilot_id.clone());
+        ride.notes = None;
+        ride.price_cents = 2100;
+        repo.update_ride(ride.clone()).await.expect("update ride");
+        assert_eq!(repo.get_ride(&ride.id).await.expect("get ride"), ride);
+    }
+
+    async fn concurrent_updates<R: RideRepository>(repo: &R, fixture: &Fixture) {
+        let (mut first, mut second) = (ride(fixture), ride(fixture));
+        repo.create_ride(first.clone()).await.expect("create ride");
+        repo.create_ride(second.clone()).await.expect("create ride");
+
+        // Writes to different rides never interfere.
+        first.status = RideStatus::Accepted.to_string();
+        second.status = RideStatus::Cancelled.to_string();
+        let (a, b) = tokio::join!(
+            repo.update_ride(first.clone()),
+            repo.update_ride(second.clone())
+        );
+        a.expect("update first");
+        b.expect("update second");
+        assert_eq!(repo.get_ride(&first.id).await.expect("get first"), first);
+        assert_eq!(repo.get_ride(&second.id).await.expect("get second"), second);
+
+        // Racing writes to one ride leave exactly one of them, never a mix.
+        let versions: Vec<Ride> = (0..4)
+            .map(|n| Ride {
+                price_cents: 1000 + n,
+                notes: Some(format!("writer {n}")),
+                ..first.clone()
+            })
+            .collect();
+        let (a, b, c, d) = tokio::join!(
+            repo.update_ride(versions[0].clone()),
+            repo.update_ride(versions[1].clone()),
+            repo.update_ride(versions[2].clone()),
+            repo.update_ride(versions[3].clone())
+        );
+        for result in [a, b, c, d] {
+            result.expect("concurrent update");
         }
+        let stored = repo.get_ride(&first.id).await.expect("get ride");
+        assert!(versions.contains(&stored), "mixed concurrent writes: {stored:?}");
+    }
+
+    async fn transitions_check_the_stored_status<R: RideRepository>(repo: &R, fixture: &Fixture) {
+        assert!(matches!(
+            repo.transition(&Uuid::new_v4(), RideStatus::Requested, RideEvent::Cancel, None)
+                .await,
+            Err(CoreError::NotFound)
+        ));
+
+        let (ride, raced) = (ride(fixture), ride(fixture));
+        repo.create_ride(ride.clone()).await.expect("create ride");
+        let accepted = repo
+            .transition(
+                &ride.id,
+                RideStatus::Requested,
+                RideEvent::Accept,
+                Some(&fixture.pilot_id),
+            )
+            .await
+            .expect("accept ride");
+        assert_eq!(accepted.status, RideStatus::Accepted.as_str());
+        assert_eq!(accepted.driver_id.as_ref(), Some(&fixture.pilot_id));
+        assert_eq!(repo.get_ride(&ride.id).await.expect("get ride"), accepted);
+
+        // A writer that still sees the ride as requested is refused instead of overwriting.
+        assert!(matches!(
+            repo.transition(&ride.id, RideStatus::Requested, RideEvent::Cancel, None)
+                .await,
+            Err(CoreError::StatusChanged { .. })
+        ));
+        assert!(matches!(
+            repo.transition(&ride.id, RideStatus::Accepted, RideEvent::Complete, None)
+                .await,
+            Err(CoreError::InvalidStatusTransition { .. })
+        ));
+        assert_eq!(repo.get_ride(&ride.id).await.expect("get ride"), accepted);
+
+        // A cancel racing an accept: exactly one lands and the stored ride is the winner's.
+        repo.create_ride(raced.clone()).await.expect("create ride");
+        let (cancel, accept) = tokio::join!(
+            repo.transition(&raced.id, RideStatus::Requested, RideEvent::Cancel, None),
+            repo.transition(
+                &raced.id,
+                RideStatus::Requested,
+                RideEvent::Accept,
+                Some(&fixture.pilot_id)
+            )
+        );
+        let stored = repo.get_ride(&raced.id).await.expect("get ride");
+        match (cancel, accept) {
+            (Ok(winner), Err(CoreError::StatusChanged { .. }))
+            | (Err(CoreError::StatusChanged { .. }), Ok(winner)) => assert_eq!(stored, winner),
+            other => panic!("expected exactly one transition to land: {other:?}"),
+        }
+    }
+
+    async fn racing_transitions_apply_once<R: RideRepository>(repo: &R, fixture: &Fixture) {
+        let ride = ride(fixture);
+        repo.create_ride(ride.clone()).await.expect("create ride");
+
+        // Four writers all read the ride as requested; a read-apply-update would let every
+        // one of them succeed, each overwriting the last.
+        let cancel = || repo.transition(&ride.id, RideStatus::Requested, RideEvent::Cancel, None);
+        let (a, b, c, d) = tokio::join!(cancel(), cancel(), cancel(), cancel());
+        let mut landed = Vec::new();
+        for result in [a, b, c, d] {
+            match result {
+                Ok(ride) => landed.push(ride),
+                Err(CoreError::StatusChanged { expected, found }) => {
+                    assert_eq!((expected.as_str(), found.as_str()), ("requested", "cancelled"));
+                }
+                Err(err) => panic!("racing transition failed: {err}"),
+            }
+        }
+        assert_eq!(landed.len(), 1, "lost update: {landed:?}");
+        assert_eq!(repo.get_ride(&ride.id).await.expect("get ride"), landed[0]);
+    }
+
+    async fn listing_by_status<R: RideRepository>(repo: &R, fixture: &Fixture) {
+        let requested = ride(fixture);
+        let mut moved = ride(fixture);
+        repo.create_ride(requested.clone()).await.expect("create ride");
+        repo.create_ride(moved.clone()).await.expect("create ride");
+        moved.status = RideStatus::Accepted.to_string();
+        moved.driver_id = Some(fixture.pilot_id.clone());
+        repo.update_ride(moved.clone()).await.expect("update ride");
+
+        let ours = |rides: Vec<Ride>| -> Vec<Uuid> {
+            rides
+                .into_iter()
+                .filter(|ride| ride.id == requested.id || ride.id == moved.id)
+                .map(|ride| ride.id)
+                .collect()
+        };
+        let listed = repo.list_by_status(RideStatus::Requested).await.expect("list");
+        assert_eq!(ours(listed), vec![requested.id]);
+        let listed = repo.list_by_status(RideStatus::Accepted).await.expect("list");
+        assert_eq!(ours(listed), vec![moved.id]);
+        let listed = repo.list_by_status(RideStatus::Completed).await.expect("list");
+        assert!(ours(listed).is_empty());
+    }
+
+    async fn paging_through_filtered_rides<R: RideRepository>(repo: &R, fixture: &Fixture) {
+        // Rides in a window of their own a day back, so other rides never match; two share
+        // a timestamp to exercise the id tie-break.
+        let base = now_millis() - 86_400_000 - u64::from(Uuid::new_v4().as_u128() as u16);
+        let offsets = [0, 10, 10, 20, 30];
+        let mut created = Vec::new();
+        for (n, offset) in offsets.into_iter().enumerate() {
+            let mut ride = ride(fixture);
+            ride.created_at_ms = base + offset;
+            if n == 4 {
+                ride.status = RideStatus::Cancelled.to_string();
+                ride.pickup = RideLocation::new(40.7128, -74.006);
+            }
+            repo.create_ride(ride.clone()).await.expect("create ride");
+            created.push(ride);
+        }
+        created.sort_by_key(RideCursor::of);
+        let window = RideFilter {
+            rider_id: Some(fixture.rider_id.clone()),
+            created_after_ms: Some(base),
+            created_before_ms: Some(base + 31),
+            ..RideFilter::default()
+        };
+
+        let mut cursor = None;
+        let mut paged = Vec::new();
+        loop {
+            let page = repo.list_rides(&window, cursor, 2).await.expect("list page");
+            assert!(page.rides.len() <= 2);
+            paged.extend(page.rides);
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(paged, created);
+
+        let open = RideFilter {
+            statuses: vec![RideStatus::Requested, RideStatus::Accepted],
+            ..window.clone()
+        };
+        let page = repo.list_rides(&open, None, 10).await.expect("list open");
+        assert_eq!(page.rides.len(), 4);
+        assert_eq!(page.next_cursor, None);
+
+        let new_york = RideFilter {
+            pickup_within: Some(BoundingBox::new(
+                RideLocation::new(40.5, -74.3),
+                RideLocation::new(40.9, -73.7),
+            )),
+            ..window.clone()
+        };
+        let page = repo.list_rides(&new_york, None, 10).await.expect("list box");
+        assert_eq!(page.rides, vec![created[4].clone()]);
+
+        let later = RideFilter {
+            created_after_ms: Some(base + 20),
+            ..window
+        };
+        let page = repo.list_rides(&later, None, 10).await.expect("list range");
+        assert_eq!(page.rides, created[3..].to_vec());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn in_memory_repository_conforms() {
+        conformance::run(&InMemoryRideRepository::default()).await;
     }
 }
@@ -0,0 +1,288 @@
+//! Graceful shutdown. On SIGTERM or SIGINT, [`crate::ApiState::begin_shutdown`] fails
+//! readiness and ends update streams. After [`PRE_DRAIN_DELAY`], [`crate::serve`] stops
+//! accepting connections and drains in-flight requests, and [`stop_workers`] waits for the
+//! background workers to finish their current round. A `create_ride` in flight therefore
+//! gets to `mark_assigned` instead of leaving its pilot busy with no ride. Both share one
+//! deadline, set by [`GracePeriod`].
+
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use futures_util::future::join_all;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::ApiState;
+
+/// Default for how long after the signal in-flight requests and the workers get to finish.
+/// Below Kubernetes' default 30s grace period, so the process exits on its own before it
+/// is killed.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Default for how long the listener stays open after `/readyz` starts failing, so load
+/// balancers notice before connections are refused.
+pub const PRE_DRAIN_DELAY: Duration = Duration::from_secs(5);
+
+/// The time from the shutdown signal to the drain deadline, shared by the HTTP drain and
+/// [`stop_workers`]. The deadline is fixed by the signal, or by the first call to
+/// [`Self::deadline`] when serving ends without one.
+#[derive(Debug, Clone)]
+pub struct GracePeriod {
+    pre_drain_delay: Duration,
+    drain_timeout: Duration,
+    deadline: Arc<OnceLock<Instant>>,
+}
+
+impl GracePeriod {
+    pub fn new(pre_drain_delay: Duration, drain_timeout: Duration) -> Self {
+        Self {
+            pre_drain_delay,
+            drain_timeout,
+            deadline: Arc::default(),
+        }
+    }
+
+    pub fn deadline(&self) -> Instant {
+        *self
+            .deadline
+            .get_or_init(|| Instant::now() + self.drain_timeout)
+    }
+
+    /// The `signal` for [`crate::serve`]: on SIGTERM or SIGINT, begin shutdown, wait out
+    /// the pre-drain delay, and resolve to the deadline.
+    pub async fn signal(self, state: ApiState) -> Instant {
+        signal().await;
+        let deadline = self.deadline();
+        state.begin_shutdown();
+        tokio::time::sleep(self.pre_drain_delay).await;
+        deadline
+    }
+}
+
+/// Resolves on the first SIGTERM or SIGINT.
+pub async fn signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %err, "cannot listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                tracing::error!(error = %err, "cannot listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => tracing::info!("SIGINT received; shutting down"),
+        _ = terminate => tracing::info!("SIGTERM received; shutting down"),
+    }
+}
+
+/// Wait until `deadline` for the workers and the offer timers to return after shutdown
+/// began, then abort the workers. Timers still running are dropped with the runtime.
+pub async fn stop_workers(state: &ApiState, workers: Vec<JoinHandle<()>>, deadline: Instant) {
+    let aborts: Vec<_> = workers.iter().map(JoinHandle::abort_handle).collect();
+    let stopped = async {
+        join_all(workers).await;
+        state.readiness.tasks_finished().await;
+    };
+    if tokio::time::timeout_at(deadline, stopped).await.is_err() {
+        tracing::warn!("background workers still running at the deadline; aborting them");
+        for abort in aborts {
+            abort.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::offers::offer_next;
+    use crate::redispatch::{spawn_redispatch_worker, RedispatchPolicy};
+    use async_trait::async_trait;
+    use axum::routing::get;
+    use axum::Router;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use supportcarr_core::error::CoreResult;
+    use supportcarr_core::events::{
+        DispatchDecision, DispatchEventLog, DispatchLogEntry, InMemoryDispatchEventLog,
+    };
+    use supportcarr_core::model::RideLocation;
+    use tokio::sync::{oneshot, Notify};
+
+    /// Serve a router whose `/slow` handler waits on `release`, and send it one request.
+    /// Returns once the handler is running.
+    async fn serve_slow(
+        release: Arc<Notify>,
+        drain_timeout: Duration,
+    ) -> (
+        TcpStream,
+        oneshot::Sender<()>,
+        JoinHandle<Result<(), String>>,
+        std::net::SocketAddr,
+    ) {
+        let started = Arc::new(Notify::new());
+        let app = Router::new().route(
+            "/slow",
+            get({
+                let started = started.clone();
+                move || async move {
+                    started.notify_one();
+                    release.notified().await;
+                    "done"
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel();
+        let server = tokio::spawn(async move {
+            let signal = async {
+                let _ = stopped.await;
+                Instant::now() + drain_timeout
+            };
+            crate::serve(listener, app, signal)
+                .await
+                .map_err(|err| err.to_string())
+        });
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /slow HTTP/1.1\r\nhost: test\r\n\r\n")
+            .await
+            .unwrap();
+        started.notified().await;
+        (client, stop, server, addr)
+    }
+
+    #[tokio::test]
+    async fn in_flight_requests_finish_before_serve_returns() {
+        let release = Arc::new(Notify::new());
+        let (mut client, stop, server, addr) =
+            serve_slow(release.clone(), Duration::from_secs(10)).await;
+
+        stop.send(()).unwrap();
+        tokio::task::yield_now().await;
+        assert!(!server.is_finished());
+        release.notify_one();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("done"), "{response}");
+        server.await.unwrap().unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn drain_deadline_drops_stuck_requests() {
+        let (mut client, stop, server, _) =
+            serve_slow(Arc::new(Notify::new()), Duration::from_millis(50)).await;
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("serve returns at the deadline")
+            .unwrap()
+            .unwrap();
+
+        // The connection is closed without a response, not left to the handler.
+        let mut response = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut response))
+            .await
+            .expect("the server closes the connection");
+        assert!(read.is_err() || response.is_empty(), "{response:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn workers_stop_once_shutdown_begins() {
+        let state = crate::test_state();
+        let worker = spawn_redispatch_worker(state.clone(), RedispatchPolicy::default());
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(!worker.is_finished());
+
+        state.begin_shutdown();
+        let stopped = Instant::now();
+        stop_workers(&state, vec![worker], stopped + Duration::from_secs(10)).await;
+        assert!(stopped.elapsed() < Duration::from_secs(10));
+    }
+
+    /// An event log whose releases wait for `gate`, to hold an offer timer mid-round.
+    #[derive(Default)]
+    struct GatedLog {
+        log: InMemoryDispatchEventLog,
+        held: Notify,
+        gate: Notify,
+    }
+
+    #[async_trait]
+    impl DispatchEventLog for GatedLog {
+        async fn append(&self, decision: DispatchDecision) -> CoreResult<String> {
+            if matches!(decision, DispatchDecision::Release { .. }) {
+                self.held.notify_one();
+                self.gate.notified().await;
+            }
+            self.log.append(decision).await
+        }
+
+        async fn read_after(
+            &self,
+            after_id: Option<&str>,
+            limit: usize,
+        ) -> CoreResult<Vec<DispatchLogEntry>> {
+            self.log.read_after(after_id, limit).await
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn offer_timers_finish_their_round_and_idle_ones_stop() {
+        let events = Arc::new(GatedLog::default());
+        let mut state = crate::test_state();
+        state.events = events.clone();
+        state.offers.timeout = Duration::from_secs(1);
+        let ride = crate::repository::conformance::ride(&Default::default());
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        for (pilot_id, lat) in [("pilot-near", 34.0523), ("pilot-far", 34.0540)] {
+            let location = RideLocation::new(lat, -118.2437);
+            state
+                .dispatch
+                .store_pilot_location(pilot_id, &location)
+                .await
+                .unwrap();
+        }
+        let ride_id = ride.id.to_string();
+        let first = offer_next(&state, &ride).await.unwrap().unwrap();
+        assert_eq!(first.pilot_id, "pilot-near");
+
+        // The timer fires and is stopped while releasing the first offer.
+        events.held.notified().await;
+        state.begin_shutdown();
+        let stopping = tokio::spawn({
+            let state = state.clone();
+            async move {
+                let stopped = Instant::now();
+                stop_workers(&state, Vec::new(), stopped + Duration::from_secs(10)).await;
+                stopped.elapsed()
+            }
+        });
+        tokio::task::yield_now().await;
+        assert!(!stopping.is_finished());
+        events.gate.notify_one();
+
+        // The round offers the ride on; that offer's timer stops at once.
+        let waited = stopping.await.unwrap();
+        assert!(waited < state.offers.timeout, "{waited:?}");
+        let offer = state.dispatch.current_offer(&ride_id).await.unwrap().unwrap();
+        assert_eq!(offer.pilot_id, "pilot-far");
+    }
+}
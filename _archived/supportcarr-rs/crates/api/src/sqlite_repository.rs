@@ -0,0 +1,440 @@
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::sqlite::{
+    SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteRow,
+    SqliteSynchronous,
+};
+use sqlx::types::Json;
+use sqlx::{QueryBuilder, Row, Sqlite};
+use uuid::Uuid;
+
+use supportcarr_core::dispatch::now_millis;
+use supportcarr_core::error::{CoreError, CoreResult};
+use supportcarr_core::fsm::{RideEvent, RideStatus};
+use supportcarr_core::model::{Ride, RideLocation};
+use supportcarr_twilio::TwilioRideStore;
+
+use crate::repository::{transitioned, RideCursor, RideFilter, RidePage, RideRepository};
+
+const SELECT_RIDE: &str = "SELECT id, rider_id, pickup, dropoff, status, bike_type, notes, \
+    rider_phone, distance_miles, price_cents, driver_id, created_at FROM rides";
+
+/// Rides in a single SQLite file, opened in WAL mode so readers never block the writer.
+/// Follows the Postgres schema, including reading a ride saved without a bike type back as
+/// `analog`.
+#[derive(Clone)]
+pub struct SqliteRideRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRideRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Open (creating if needed) the database at `path` and apply the embedded migrations.
+    pub async fn open(path: impl AsRef<Path>) -> CoreResult<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .max_connections(4)
+            .connect_with(options)
+            .await
+            .map_err(storage_error)?;
+        let repo = Self::new(pool);
+        repo.migrate().await?;
+        Ok(repo)
+    }
+
+    /// Apply the embedded migrations under `migrations/sqlite`.
+    pub async fn migrate(&self) -> CoreResult<()> {
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&self.pool)
+            .await
+            .map_err(storage_error)
+    }
+
+    /// Rides matching `filter` after `cursor` in cursor order, all of them when `limit` is
+    /// `None`.
+    async fn select_rides(
+        &self,
+        filter: &RideFilter,
+        cursor: Option<RideCursor>,
+        limit: Option<usize>,
+    ) -> CoreResult<Vec<Ride>> {
+        let mut query = QueryBuilder::<Sqlite>::new(SELECT_RIDE);
+        query.push(" WHERE 1 = 1");
+        if !filter.statuses.is_empty() {
+            query.push(" AND status IN (");
+            let mut statuses = query.separated(", ");
+            for status in &filter.statuses {
+                statuses.push_bind(status.as_str());
+            }
+            query.push(")");
+        }
+        if let Some(rider_id) = &filter.rider_id {
+            query.push(" AND rider_id = ").push_bind(rider_id);
+        }
+        if let Some(driver_id) = &filter.driver_id {
+            query.push(" AND driver_id = ").push_bind(driver_id);
+        }
+        if let Some(rider_phone) = &filter.rider_phone {
+            query.push(" AND rider_phone = ").push_bind(rider_phone);
+        }
+        if let Some(after) = filter.created_after_ms {
+            query.push(" AND created_at >= ").push_bind(after as i64);
+        }
+        if let Some(before) = filter.created_before_ms {
+            query.push(" AND created_at < ").push_bind(before as i64);
+        }
+        if let Some(bounds) = &filter.pickup_within {
+            query
+                .push(" AND json_extract(pickup, '$.lat') BETWEEN ")
+                .push_bind(bounds.south_west.lat)
+                .push(" AND ")
+                .push_bind(bounds.north_east.lat)
+                .push(" AND json_extract(pickup, '$.lng') BETWEEN ")
+                .push_bind(bounds.south_west.lng)
+                .push(" AND ")
+                .push_bind(bounds.north_east.lng);
+        }
+        if let Some(cursor) = cursor {
+            // Ids are lowercase hyphenated UUIDs, so text order is byte order.
+            query
+                .push(" AND (created_at, id) > (")
+                .push_bind(cursor.created_at_ms as i64)
+                .push(", ")
+                .push_bind(cursor.id.to_string())
+                .push(")");
+        }
+        query.push(" ORDER BY created_at, id");
+        if let Some(limit) = limit {
+            query.push(" LIMIT ").push_bind(limit as i64);
+        }
+
+        let rows = query
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(storage_error)?;
+        rows.iter().map(ride_from_row).collect()
+    }
+
+    /// The most recently created ride for a rider phone number.
+    pub async fn find_by_phone(&self, phone: &str) -> CoreResult<Option<Ride>> {
+        let row = sqlx::query(&format!(
+            "{SELECT_RIDE} WHERE rider_phone = ? ORDER BY created_at DESC, rowid DESC LIMIT 1"
+        ))
+        .bind(phone)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(storage_error)?;
+        row.as_ref().map(ride_from_row).transpose()
+    }
+
+    /// Insert `ride`, overwriting a stored ride with the same id when `replace` is set and
+    /// leaving it alone otherwise. Returns whether a row was written.
+    async fn insert(&self, ride: &Ride, replace: bool) -> CoreResult<bool> {
+        let now = now_millis() as i64;
+        let on_conflict = if replace {
+            "DO UPDATE SET rider_id = excluded.rider_id, \
+             pickup = excluded.pickup, dropoff = excluded.dropoff, status = excluded.status, \
+             bike_type = excluded.bike_type, notes = excluded.notes, \
+             rider_phone = excluded.rider_phone, distance_miles = excluded.distance_miles, \
+             price_cents = excluded.price_cents, driver_id = excluded.driver_id, \
+             updated_at = excluded.updated_at"
+        } else {
+            "DO NOTHING"
+        };
+        let result = sqlx::query(&format!(
+            "INSERT INTO rides (id, rider_id, pickup, dropoff, status, bike_type, notes, \
+             rider_phone, distance_miles, price_cents, driver_id, created_at, updated_at) \
+             VALUES (?, ?, ?, ?, ?, COALESCE(?, 'analog'), ?, ?, ?, ?, ?, ?, ?) \
+             ON CONFLICT (id) {on_conflict}"
+        ))
+        .bind(ride.id.to_string())
+        .bind(&ride.rider_id)
+        .bind(Json(&ride.pickup))
+        .bind(Json(&ride.dropoff))
+        .bind(&ride.status)
+        .bind(&ride.bike_type)
+        .bind(&ride.notes)
+        .bind(&ride.rider_phone)
+        .bind(ride.distance_miles)
+        .bind(ride.price_cents)
+        .bind(&ride.driver_id)
+        .bind(ride.created_at_ms as i64)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .map_err(storage_error)?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+fn storage_error(err: impl std::fmt::Display) -> CoreError {
+    CoreError::Storage(err.to_string())
+}
+
+fn ride_from_row(row: &SqliteRow) -> CoreResult<Ride> {
+    let id: String = row.try_get("id").map_err(storage_error)?;
+    let pickup: Json<RideLocation> = row.try_get("pickup").map_err(storage_error)?;
+    let dropoff: Json<RideLocation> = row.try_get("dropoff").map_err(storage_error)?;
+    let distance_miles: Option<f64> = row.try_get("distance_miles").map_err(storage_error)?;
+    let price_cents: Option<i64> = row.try_get("price_cents").map_err(storage_error)?;
+    let created_at_ms: i64 = row.try_get("created_at").map_err(storage_error)?;
+    Ok(Ride {
+        id: Uuid::parse_str(&id).map_err(storage_error)?,
+        rider_id: row.try_get("rider_id").map_err(storage_error)?,
+        pickup: pickup.0,
+        dropoff: dropoff.0,
+        status: row.try_get("status").map_err(storage_error)?,
+        bike_type: row.try_get("bike_type").map_err(storage_error)?,
+        notes: row.try_get("notes").map_err(storage_error)?,
+        rider_phone: row.try_get("rider_phone").map_err(storage_error)?,
+        distance_miles: distance_miles.unwrap_or_default(),
+        price_cents: price_cents.unwrap_or_default(),
+        driver_id: row.try_get("driver_id").map_err(storage_error)?,
+        created_at_ms: created_at_ms.max(0) as u64,
+    })
+}
+
+#[async_trait]
+impl RideRepository for SqliteRideRepository {
+    async fn create_ride(&self, ride: Ride) -> CoreResult<()> {
+        match self.insert(&ride, false).await? {
+            true => Ok(()),
+            false => Err(CoreError::AlreadyExists),
+        }
+    }
+
+    async fn get_ride(&self, id: &Uuid) -> CoreResult<Ride> {
+        let row = sqlx::query(&format!("{SELECT_RIDE} WHERE id = ?"))
+            .bind(id.to_string())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(storage_error)?
+            .ok_or(CoreError::NotFound)?;
+        ride_from_row(&row)
+    }
+
+    async fn update_ride(&self, ride: Ride) -> CoreResult<()> {
+        let result = sqlx::query(
+            "UPDATE rides SET rider_id = ?, pickup = ?, dropoff = ?, status = ?, \
+             bike_type = COALESCE(?, 'analog'), notes = ?, rider_phone = ?, \
+             distance_miles = ?, price_cents = ?, driver_id = ?, updated_at = ? WHERE id = ?",
+        )
+        .bind(&ride.rider_id)
+        .bind(Json(&ride.pickup))
+        .bind(Json(&ride.dropoff))
+        .bind(&ride.status)
+        .bind(&ride.bike_type)
+        .bind(&ride.notes)
+        .bind(&ride.rider_phone)
+        .bind(ride.distance_miles)
+        .bind(ride.price_cents)
+        .bind(&ride.driver_id)
+        .bind(now_millis() as i64)
+        .bind(ride.id.to_string())
+        .execute(&self.pool)
+        .await
+        .map_err(storage_error)?;
+        if result.rows_affected() == 0 {
+            return Err(CoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    /// Runs in an immediate transaction, which takes the write lock up front so concurrent
+    /// transitions of the same ride are serialized.
+    async fn transition(
+        &self,
+        id: &Uuid,
+        from: RideStatus,
+        event: RideEvent,
+        pilot_id: Option<&str>,
+    ) -> CoreResult<Ride> {
+        let mut tx = self
+            .pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(storage_error)?;
+        let row = sqlx::query(&format!("{SELECT_RIDE} WHERE id = ?"))
+            .bind(id.to_string())
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(storage_error)?
+            .ok_or(CoreError::NotFound)?;
+        let ride = transitioned(ride_from_row(&row)?, from, event, pilot_id)?;
+        sqlx::query("UPDATE rides SET status = ?, driver_id = ?, updated_at = ? WHERE id = ?")
+            .bind(&ride.status)
+            .bind(&ride.driver_id)
+            .bind(now_millis() as i64)
+            .bind(id.to_string())
+            .execute(&mut *tx)
+            .await
+            .map_err(storage_error)?;
+        tx.commit().await.map_err(storage_error)?;
+        Ok(ride)
+    }
+
+    async fn list_by_status(&self, status: RideStatus) -> CoreResult<Vec<Ride>> {
+        let filter = RideFilter {
+            statuses: vec![status],
+            ..RideFilter::default()
+        };
+        self.select_rides(&filter, None, None).await
+    }
+
+    async fn list_rides(
+        &self,
+        filter: &RideFilter,
+        cursor: Option<RideCursor>,
+        limit: usize,
+    ) -> CoreResult<RidePage> {
+        let rides = self
+            .select_rides(filter, cursor, Some(limit.saturating_add(1)))
+            .await?;
+        Ok(RidePage::from_overfetch(rides, limit))
+    }
+
+    async fn health_check(&self) -> CoreResult<()> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(storage_error)?;
+        Ok(())
+    }
+}
+
+/// Lets the Twilio webhook resolve riders by phone against the same database as the API.
+#[async_trait]
+impl TwilioRideStore for SqliteRideRepository {
+    async fn find_by_phone(&self, phone: &str) -> Result<Option<Ride>, CoreError> {
+        SqliteRideRepository::find_by_phone(self, phone).await
+    }
+
+    async fn save(&self, ride: Ride) -> Result<(), CoreError> {
+        if ride.rider_phone.is_none() {
+            return Err(CoreError::InvalidLocation("ride missing phone".into()));
+        }
+        self.insert(&ride, true).await?;
+        Ok(())
+    }
+
+    async fn update_status(&self, ride: &Ride, event: RideEvent) -> Result<Ride, CoreError> {
+        let from = RideStatus::try_from(ride.status.as_str())?;
+        RideRepository::transition(self, &ride.id, from, event, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Database file removed, with its WAL side files, on drop.
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("supportcarr-{}.db", Uuid::new_v4())))
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    fn ride(rider_id: &str) -> Ride {
+        Ride::new(
+            rider_id.to_string(),
+            RideLocation::new(34.0, -118.0),
+            RideLocation::new(34.05, -118.02),
+            None,
+            Some("flat tire".to_string()),
+            Some("+15555550100".to_string()),
+            3.25,
+            2500,
+        )
+    }
+
+    #[tokio::test]
+    async fn conforms_to_repository_contract() {
+        let db = TempDb::new();
+        let repo = SqliteRideRepository::open(&db.0).await.unwrap();
+        crate::repository::conformance::run(&repo).await;
+    }
+
+    #[tokio::test]
+    async fn rides_survive_reopen() {
+        let db = TempDb::new();
+        let first = ride("rider-1");
+        let second = ride("rider-1");
+        {
+            let repo = SqliteRideRepository::open(&db.0).await.unwrap();
+            repo.create_ride(first.clone()).await.unwrap();
+            repo.create_ride(second.clone()).await.unwrap();
+            repo.pool.close().await;
+        }
+
+        let repo = SqliteRideRepository::open(&db.0).await.unwrap();
+        let stored = repo.get_ride(&first.id).await.unwrap();
+        assert_eq!(stored.bike_type.as_deref(), Some("analog"));
+        assert_eq!(stored.notes, first.notes);
+        assert_eq!(
+            repo.find_by_phone("+15555550100").await.unwrap().map(|r| r.id),
+            Some(second.id)
+        );
+
+        let mut assigned = stored.clone();
+        assigned.status = RideStatus::Accepted.to_string();
+        assigned.driver_id = Some("pilot-1".to_string());
+        repo.update_ride(assigned.clone()).await.unwrap();
+        let by_pilot = repo
+            .list_rides(
+                &RideFilter {
+                    driver_id: Some("pilot-1".to_string()),
+                    ..RideFilter::default()
+                },
+                None,
+                10,
+            )
+            .await
+            .unwrap();
+        assert_eq!(by_pilot.rides, vec![assigned]);
+        assert_eq!(repo.list_by_status(RideStatus::Requested).await.unwrap().len(), 1);
+        assert!(matches!(
+            repo.update_ride(ride("rider-2")).await,
+            Err(CoreError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrent_transitions_apply_once() {
+        let db = TempDb::new();
+        let repo = SqliteRideRepository::open(&db.0).await.unwrap();
+        let ride = ride("rider-1");
+        repo.create_ride(ride.clone()).await.unwrap();
+
+        let (a, b) = tokio::join!(
+            repo.transition(&ride.id, RideStatus::Requested, RideEvent::Cancel, None),
+            repo.transition(&ride.id, RideStatus::Requested, RideEvent::Cancel, None)
+        );
+        assert_eq!([a.is_ok(), b.is_ok()].iter().filter(|ok| **ok).count(), 1);
+        assert_eq!(repo.get_ride(&ride.id).await.unwrap().status, "cancelled");
+    }
+}
@@ -0,0 +1,71 @@
+use supportcarr_core::dispatch::now_millis;
+use supportcarr_core::fsm::RideStatus;
+use supportcarr_core::model::RideLocation;
+use uuid::Uuid;
+
+use crate::updates::{eta_minutes, RideChange};
+use crate::{ApiError, ApiState};
+
+/// Statuses during which a pilot's breadcrumbs also belong to the ride's route.
+const TRACKED_STATUSES: [RideStatus; 4] = [
+    RideStatus::Accepted,
+    RideStatus::EnRoute,
+    RideStatus::Arrived,
+    RideStatus::InTransit,
+];
+
+/// Store a pilot's latest position for dispatch and append it to their breadcrumb trail,
+/// and to their assigned ride's trail while that ride is in progress.
+pub async fn record_pilot_location(
+    state: &ApiState,
+    pilot_id: &str,
+    location: &RideLocation,
+) -> Result<(), ApiError> {
+    record_pilot_trail(state, pilot_id, &[(location.clone(), now_millis())]).await
+}
+
+/// [`record_pilot_location`] for points the pilot app buffered while offline, oldest
+/// first. Every point joins the trails; the last becomes the dispatch position unless a
+/// later one is already stored, as when an older batch arrives after a newer upload.
+pub async fn record_pilot_trail(
+    state: &ApiState,
+    pilot_id: &str,
+    points: &[(RideLocation, u64)],
+) -> Result<(), ApiError> {
+    let Some((latest, latest_ms)) = points.last() else {
+        return Ok(());
+    };
+    let moved = state
+        .dispatch
+        .store_pilot_location_at(pilot_id, latest, *latest_ms)
+        .await?;
+
+    let mut active_ride = None;
+    if let Some(ride_id) = state.dispatch.current_assignment(pilot_id).await? {
+        if let Ok(id) = Uuid::parse_str(&ride_id) {
+            let ride = state.repo.get_ride(&id).await?;
+            if TRACKED_STATUSES.iter().any(|status| ride.status == status.as_str()) {
+                active_ride = Some(ride);
+            }
+        }
+    }
+
+    let active_ride_id = active_ride.as_ref().map(|ride| ride.id.to_string());
+    for (location, recorded_at_ms) in points {
+        state
+            .history
+            .record(pilot_id, active_ride_id.as_deref(), location, *recorded_at_ms)
+            .await?;
+    }
+    if let Some(ride) = active_ride.filter(|_| moved) {
+        state.updates.publish(
+            ride.id,
+            RideChange::PilotLocation {
+                pilot_id: pilot_id.to_string(),
+                location: latest.clone(),
+                eta_minutes: eta_minutes(&ride, latest),
+            },
+        );
+    }
+    Ok(())
+}
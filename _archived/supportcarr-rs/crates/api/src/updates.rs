@@ -0,0 +1,834 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use supportcarr_core::fsm::RideStatus;
+use supportcarr_core::geo::haversine_miles;
+use supportcarr_core::matching::MatchConfig;
+use supportcarr_core::model::{Ride, RideLocation};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio::time::Instant;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::auth::Principal;
+use crate::{ApiError, ApiState};
+
+/// Interval between SSE keep-alive comments and WebSocket pings.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long `GET /rides/:id?wait_for_change=` holds a request, matching the Node
+/// server's `LONG_POLL_TIMEOUT_MS`.
+pub const LONG_POLL_TIMEOUT: Duration = Duration::from_millis(25_000);
+
+/// What changed about a ride.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RideChange {
+    /// Status or assigned pilot changed. Also sent as a snapshot when a stream opens.
+    Status {
+        status: String,
+        driver_id: Option<String>,
+    },
+    /// The assigned pilot moved. `eta_minutes` is to pickup until the rider is aboard,
+    /// then to dropoff.
+    PilotLocation {
+        pilot_id: String,
+        location: RideLocation,
+        eta_minutes: Option<f64>,
+    },
+}
+
+/// A change with its place in the feed. Ids increase across all rides and are used as
+/// SSE event ids, so clients resume with `Last-Event-ID`.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct RideUpdate {
+    pub id: u64,
+    pub ride_id: Uuid,
+    #[serde(flatten)]
+    pub change: RideChange,
+}
+
+/// Process-wide broadcast of ride changes, keeping the most recent updates so reconnecting
+/// clients can catch up on what they missed.
+#[derive(Clone)]
+pub struct RideUpdateHub {
+    inner: Arc<HubInner>,
+}
+
+struct HubInner {
+    sender: broadcast::Sender<RideUpdate>,
+    replay_capacity: usize,
+    feed: Mutex<Feed>,
+    versions: Mutex<HashMap<Uuid, VersionSlot>>,
+    /// Set by [`RideUpdateHub::close`].
+    closed: watch::Sender<bool>,
+}
+
+/// Versions of rides whose status has not changed for this long are dropped once no one
+/// waits on them, so rides that never finish (or finish on another instance) do not pile up.
+/// A poll for a dropped ride answers at once with version 0, as after a restart.
+const VERSION_IDLE: Duration = Duration::from_secs(60 * 60);
+
+/// Idle versions are swept once every this many updates.
+const VERSION_SWEEP_INTERVAL: u64 = 1024;
+
+/// Per-ride status version: the id of the ride's latest status update.
+struct VersionSlot {
+    sender: watch::Sender<u64>,
+    /// The ride reached a terminal status and will not change again.
+    finished: bool,
+    changed_at: Instant,
+}
+
+impl Default for VersionSlot {
+    fn default() -> Self {
+        Self {
+            sender: watch::channel(0).0,
+            finished: false,
+            changed_at: Instant::now(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Feed {
+    last_id: u64,
+    recent: VecDeque<RideUpdate>,
+}
+
+impl Default for RideUpdateHub {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+impl RideUpdateHub {
+    /// `replay_capacity` updates (across all rides) are kept for resuming streams.
+    pub fn new(replay_capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(replay_capacity.max(16));
+        Self {
+            inner: Arc::new(HubInner {
+                sender,
+                replay_capacity,
+                feed: Mutex::default(),
+                versions: Mutex::default(),
+                closed: watch::channel(false).0,
+            }),
+        }
+    }
+
+    pub fn publish(&self, ride_id: Uuid, change: RideChange) -> RideUpdate {
+        // Ids are assigned and sent under the lock, so the replay buffer and the broadcast
+        // agree on order.
+        let mut feed = self.inner.feed.lock().unwrap();
+        feed.last_id += 1;
+        let update = RideUpdate {
+            id: feed.last_id,
+            ride_id,
+            change,
+        };
+        feed.recent.push_back(update.clone());
+        while feed.recent.len() > self.inner.replay_capacity {
+            feed.recent.pop_front();
+        }
+        let _ = self.inner.sender.send(update.clone());
+        drop(feed);
+        if let RideChange::Status { status, .. } = &update.change {
+            self.bump_version(ride_id, update.id, status);
+        }
+        update
+    }
+
+    fn bump_version(&self, ride_id: Uuid, version: u64, status: &str) {
+        let mut versions = self.inner.versions.lock().unwrap();
+        let slot = versions.entry(ride_id).or_default();
+        slot.sender.send_replace(version);
+        slot.changed_at = Instant::now();
+        // Finished rides stop changing; keep their slot only while someone is waiting.
+        slot.finished = RideStatus::try_from(status).is_ok_and(|status| status.is_terminal());
+        if slot.finished && slot.sender.receiver_count() == 0 {
+            versions.remove(&ride_id);
+        }
+        if version.is_multiple_of(VERSION_SWEEP_INTERVAL) {
+            evict_idle_versions(&mut versions);
+        }
+    }
+
+    /// The ride's status version, 0 when it has not changed since this process started.
+    pub fn version(&self, ride_id: Uuid) -> u64 {
+        self.inner
+            .versions
+            .lock()
+            .unwrap()
+            .get(&ride_id)
+            .map_or(0, |slot| *slot.sender.borrow())
+    }
+
+    /// Wait until the ride's version differs from `seen`, `timeout` passes or the hub is
+    /// closed. Returns the version at that point; immediately if it already differs.
+    pub async fn wait_for_change(&self, ride_id: Uuid, seen: u64, timeout: Duration) -> u64 {
+        let mut receiver = self
+            .inner
+            .versions
+            .lock()
+            .unwrap()
+            .entry(ride_id)
+            .or_default()
+            .sender
+            .subscribe();
+        let mut closed = self.inner.closed.subscribe();
+        let _ = tokio::time::timeout(timeout, async {
+            tokio::select! {
+                _ = receiver.wait_for(|version| *version != seen) => {}
+                _ = closed.wait_for(|closed| *closed) => {}
+            }
+        })
+        .await;
+        let version = *receiver.borrow();
+        drop(receiver);
+
+        // The last waiter drops slots of finished rides and those only waiters created, so
+        // neither accumulates.
+        let mut versions = self.inner.versions.lock().unwrap();
+        if let Some(slot) = versions.get(&ride_id) {
+            let unused = slot.finished || *slot.sender.borrow() == 0;
+            if unused && slot.sender.receiver_count() == 0 {
+                versions.remove(&ride_id);
+            }
+        }
+        version
+    }
+
+    pub fn publish_status(&self, ride: &Ride) -> RideUpdate {
+        self.publish(ride.id, status_change(ride))
+    }
+
+    /// For shutdown: long polls answer now, and streams end once they have delivered the
+    /// updates already published, so their connections can drain. Clients resume elsewhere
+    /// with `Last-Event-ID`.
+    pub fn close(&self) {
+        self.inner.closed.send_replace(true);
+    }
+
+    /// Subscribe to updates for one ride, first replaying those after `last_event_id`.
+    /// `complete` is false when the buffer no longer reaches back that far (or the id is
+    /// from before a restart), in which case nothing is replayed and the caller should send
+    /// a fresh snapshot instead: a partial replay would follow the snapshot with changes
+    /// older than it.
+    fn subscribe(&self, ride_id: Uuid, last_event_id: Option<u64>) -> Subscription {
+        let feed = self.inner.feed.lock().unwrap();
+        let receiver = self.inner.sender.subscribe();
+        let oldest = feed
+            .recent
+            .front()
+            .map_or(feed.last_id + 1, |update| update.id);
+        let (replay, complete) = match last_event_id {
+            Some(last) if last <= feed.last_id && last + 1 >= oldest => {
+                let replay = feed
+                    .recent
+                    .iter()
+                    .filter(|update| update.id > last && update.ride_id == ride_id)
+                    .cloned()
+                    .collect();
+                (replay, true)
+            }
+            _ => (Vec::new(), false),
+        };
+        Subscription {
+            receiver,
+            closed: self.inner.closed.subscribe(),
+            replay,
+            complete,
+            last_id: feed.last_id,
+        }
+    }
+}
+
+/// Drop versions idle for [`VERSION_IDLE`] that no one is waiting on.
+fn evict_idle_versions(versions: &mut HashMap<Uuid, VersionSlot>) {
+    versions.retain(|_, slot| {
+        slot.sender.receiver_count() > 0 || slot.changed_at.elapsed() < VERSION_IDLE
+    });
+}
+
+struct Subscription {
+    receiver: broadcast::Receiver<RideUpdate>,
+    closed: watch::Receiver<bool>,
+    replay: Vec<RideUpdate>,
+    complete: bool,
+    last_id: u64,
+}
+
+pub(crate) fn status_change(ride: &Ride) -> RideChange {
+    RideChange::Status {
+        status: ride.status.clone(),
+        driver_id: ride.driver_id.clone(),
+    }
+}
+
+/// Minutes for the pilot at `location` to reach the ride's next stop, at the batch
+/// matcher's assumed speed. `None` once there is no next stop.
+pub fn eta_minutes(ride: &Ride, location: &RideLocation) -> Option<f64> {
+    let target = match RideStatus::try_from(ride.status.as_str()).ok()? {
+        RideStatus::Accepted | RideStatus::EnRoute => &ride.pickup,
+        RideStatus::Arrived | RideStatus::InTransit => &ride.dropoff,
+        _ => return None,
+    };
+    Some(haversine_miles(location, target) / MatchConfig::default().average_speed_mph * 60.0)
+}
+
+/// Updates for one ride: the missed updates when `last_event_id` can be fully replayed,
+/// otherwise a snapshot of the current state, then live ones. A subscriber that falls behind the
+/// broadcast gets a fresh snapshot instead of the updates it lost. Ends when the hub is
+/// closed.
+pub fn ride_updates(
+    state: ApiState,
+    ride_id: Uuid,
+    last_event_id: Option<u64>,
+) -> impl Stream<Item = RideUpdate> + Send {
+    let subscription = state.updates.subscribe(ride_id, last_event_id);
+    let newest_seen = subscription.last_id;
+    // Read after subscribing, so a change landing in between is at worst sent twice.
+    let snapshot = stream::iter((!subscription.complete).then(|| state.clone())).filter_map(
+        move |state| async move {
+            let ride = state.repo.get_ride(&ride_id).await.ok()?;
+            Some(RideUpdate {
+                id: newest_seen,
+                ride_id,
+                change: status_change(&ride),
+            })
+        },
+    );
+    let backlog = snapshot.chain(stream::iter(subscription.replay));
+
+    let live = stream::unfold(
+        (
+            state,
+            subscription.receiver,
+            subscription.closed,
+            newest_seen,
+        ),
+        move |(state, mut receiver, mut closed, newest_seen)| async move {
+            loop {
+                // Biased, so updates published before the hub closed are still sent.
+                let received = tokio::select! {
+                    biased;
+                    received = receiver.recv() => received,
+                    _ = closed.wait_for(|closed| *closed) => return None,
+                };
+                match received {
+                    Ok(update) if update.ride_id == ride_id && update.id > newest_seen => {
+                        let newest_seen = update.id;
+                        return Some((update, (state, receiver, closed, newest_seen)));
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(_)) => {
+                        let Ok(ride) = state.repo.get_ride(&ride_id).await else {
+                            continue;
+                        };
+                        let snapshot = RideUpdate {
+                            id: newest_seen,
+                            ride_id,
+                            change: status_change(&ride),
+                        };
+                        return Some((snapshot, (state, receiver, closed, newest_seen)));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        },
+    );
+    backlog.chain(live)
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamQuery {
+    /// For clients that cannot set `Last-Event-ID`, such as browser WebSockets.
+    pub last_event_id: Option<u64>,
+}
+
+fn last_event_id(headers: &HeaderMap, query: &StreamQuery) -> Option<u64> {
+    headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .or(query.last_event_id)
+}
+
+/// `GET /rides/:id/stream`: Server-Sent Events, one `ride_update` event per change.
+#[utoipa::path(
+    get,
+    path = "/rides/{id}/stream",
+    tag = "updates",
+    params(
+        ("id" = Uuid, Path, description = "Ride id"),
+        StreamQuery,
+        ("Last-Event-ID" = Option<u64>, Header, description = "Id of the last update received; missed updates are replayed"),
+    ),
+    responses(
+        (status = 200, description = "Event stream; each `ride_update` event's data is one update", body = RideUpdate, content_type = "text/event-stream"),
+        (status = 403, description = "Caller may not view this ride"),
+        (status = 404, description = "No such ride"),
+    )
+)]
+pub async fn stream_ride_sse(
+    State(state): State<ApiState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+    Query(query): Query<StreamQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let ride = state.repo.get_ride(&id).await?;
+    principal.require_view(&ride)?;
+    let resume_from = last_event_id(&headers, &query);
+    let events = ride_updates(state, ride.id, resume_from).map(|update| {
+        Ok(Event::default()
+            .id(update.id.to_string())
+            .event("ride_update")
+            .json_data(&update)
+            .unwrap_or_default())
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(HEARTBEAT_INTERVAL)))
+}
+
+/// `GET /rides/:id/ws`: the same updates as JSON text messages over a WebSocket.
+#[utoipa::path(
+    get,
+    path = "/rides/{id}/ws",
+    tag = "updates",
+    params(
+        ("id" = Uuid, Path, description = "Ride id"),
+        StreamQuery,
+        ("Last-Event-ID" = Option<u64>, Header, description = "Id of the last update received; missed updates are replayed"),
+    ),
+    responses(
+        (status = 101, description = "WebSocket carrying one `RideUpdate` JSON text message per update"),
+        (status = 403, description = "Caller may not view this ride"),
+        (status = 404, description = "No such ride"),
+    )
+)]
+pub async fn stream_ride_ws(
+    State(state): State<ApiState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+    Query(query): Query<StreamQuery>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let ride = state.repo.get_ride(&id).await?;
+    principal.require_view(&ride)?;
+    let resume_from = last_event_id(&headers, &query);
+    Ok(upgrade
+        .on_upgrade(move |socket| forward_updates(socket, state, ride.id, resume_from))
+        .into_response())
+}
+
+async fn forward_updates(
+    mut socket: WebSocket,
+    state: ApiState,
+    ride_id: Uuid,
+    resume: Option<u64>,
+) {
+    let updates = ride_updates(state, ride_id, resume);
+    futures_util::pin_mut!(updates);
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat.tick().await;
+    loop {
+        tokio::select! {
+            update = updates.next() => {
+                let Some(update) = update else {
+                    // The hub closed: the server is shutting down.
+                    let frame = CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "server shutting down".into(),
+                    };
+                    let _ = socket.send(Message::Close(Some(frame))).await;
+                    break;
+                };
+                let Ok(text) = serde_json::to_string(&update) else { continue };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            _ = heartbeat.tick() => {
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_state;
+
+    async fn next(updates: &mut (impl Stream<Item = RideUpdate> + Unpin)) -> RideUpdate {
+        tokio::time::timeout(Duration::from_secs(1), updates.next())
+            .await
+            .expect("update in time")
+            .expect("stream open")
+    }
+
+    fn location_change(pilot_id: &str) -> RideChange {
+        RideChange::PilotLocation {
+            pilot_id: pilot_id.to_string(),
+            location: RideLocation::new(34.05, -118.25),
+            eta_minutes: Some(3.0),
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_snapshot_then_live_updates_for_one_ride() {
+        let state = test_state();
+        let mut ride = crate::repository::conformance::ride(&Default::default());
+        state.repo.create_ride(ride.clone()).await.unwrap();
+
+        let updates = ride_updates(state.clone(), ride.id, None);
+        futures_util::pin_mut!(updates);
+        assert_eq!(next(&mut updates).await.change, status_change(&ride));
+
+        state
+            .updates
+            .publish(Uuid::new_v4(), location_change("pilot-9"));
+        ride.status = RideStatus::Accepted.to_string();
+        ride.driver_id = Some("pilot-1".to_string());
+        let accepted = state.updates.publish_status(&ride);
+        let moved = state.updates.publish(ride.id, location_change("pilot-1"));
+        assert_eq!(next(&mut updates).await, accepted);
+        assert_eq!(next(&mut updates).await, moved);
+    }
+
+    #[tokio::test]
+    async fn resumes_after_last_event_id() {
+        let state = test_state();
+        let mut ride = crate::repository::conformance::ride(&Default::default());
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        let first = state.updates.publish_status(&ride);
+        ride.status = RideStatus::Accepted.to_string();
+        let second = state.updates.publish_status(&ride);
+        let third = state.updates.publish(ride.id, location_change("pilot-1"));
+
+        // Everything after `first` is still buffered, so there is no snapshot.
+        let updates = ride_updates(state.clone(), ride.id, Some(first.id));
+        futures_util::pin_mut!(updates);
+        assert_eq!(next(&mut updates).await, second);
+        assert_eq!(next(&mut updates).await, third);
+
+        // An id from before a restart cannot be replayed, so the stream starts over.
+        let updates = ride_updates(state.clone(), ride.id, Some(third.id + 100));
+        futures_util::pin_mut!(updates);
+        let snapshot = next(&mut updates).await;
+        let stored = state.repo.get_ride(&ride.id).await.unwrap();
+        assert_eq!(snapshot.change, status_change(&stored));
+        assert_eq!(snapshot.id, third.id);
+    }
+
+    #[test]
+    fn replay_buffer_is_bounded() {
+        let hub = RideUpdateHub::new(2);
+        let ride_id = Uuid::new_v4();
+        let first = hub.publish(ride_id, location_change("pilot-1"));
+        for _ in 0..3 {
+            hub.publish(ride_id, location_change("pilot-1"));
+        }
+        let subscription = hub.subscribe(ride_id, Some(first.id));
+        assert!(!subscription.complete);
+        // The caller sends a snapshot, which already covers what is left in the buffer.
+        assert!(subscription.replay.is_empty());
+    }
+
+    fn bearer(role: crate::auth::Role, subject: &str) -> String {
+        format!("Bearer {}", crate::auth::tests::hs256_token(role, subject))
+    }
+
+    /// The next SSE event's `id` and JSON data, skipping keep-alive comments.
+    async fn next_event(
+        body: &mut (impl Stream<Item = Result<axum::body::Bytes, axum::Error>> + Unpin),
+        buffer: &mut String,
+    ) -> (u64, RideUpdate) {
+        loop {
+            if let Some(end) = buffer.find("\n\n") {
+                let event: String = buffer.drain(..end + 2).collect();
+                let field = |name: &str| {
+                    event
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .map(str::to_string)
+                };
+                let (Some(id), Some(data)) = (field("id: "), field("data: ")) else {
+                    continue;
+                };
+                let data: serde_json::Value = serde_json::from_str(&data).unwrap();
+                let update = RideUpdate {
+                    id: data["id"].as_u64().unwrap(),
+                    ride_id: data["ride_id"].as_str().unwrap().parse().unwrap(),
+                    change: match data["type"].as_str().unwrap() {
+                        "status" => RideChange::Status {
+                            status: data["status"].as_str().unwrap().to_string(),
+                            driver_id: data["driver_id"].as_str().map(str::to_string),
+                        },
+                        _ => location_change(data["pilot_id"].as_str().unwrap()),
+                    },
+                };
+                return (id.parse().unwrap(), update);
+            }
+            let chunk = tokio::time::timeout(Duration::from_secs(1), body.next())
+                .await
+                .expect("event in time")
+                .expect("stream open")
+                .unwrap();
+            buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn sse_route_replays_or_snapshots_then_streams() {
+        use axum::body::Body;
+        use axum::http::header::AUTHORIZATION;
+        use axum::http::{Request, StatusCode};
+        use tower::ServiceExt;
+
+        use crate::auth::Role;
+
+        let mut state = test_state();
+        state.updates = RideUpdateHub::new(3);
+        let mut ride = crate::repository::conformance::ride(&Default::default());
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        let first = state.updates.publish_status(&ride);
+        state.updates.publish(Uuid::new_v4(), location_change("pilot-9"));
+        let second = state.updates.publish(ride.id, location_change("pilot-1"));
+        let app = crate::router(state.clone());
+        let open = |subject: &str, last_event_id: u64| {
+            let request = Request::get(format!("/rides/{}/stream", ride.id))
+                .header(AUTHORIZATION, bearer(Role::Rider, subject))
+                .header("last-event-id", last_event_id.to_string())
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let response = open("someone-else", first.id).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Fully buffered: the missed update, with its id, and no snapshot.
+        let response = open(&ride.rider_id, first.id).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut resumed = response.into_body().into_data_stream();
+        let mut resumed_buffer = String::new();
+        assert_eq!(
+            next_event(&mut resumed, &mut resumed_buffer).await,
+            (second.id, second.clone())
+        );
+
+        // Push `first` and the update after it out of the buffer but keep `second`: resuming
+        // after `first` now gets only a snapshot, without replaying `second` behind it, then
+        // live updates.
+        state.updates.publish(Uuid::new_v4(), location_change("pilot-9"));
+        let newest = state.updates.publish(Uuid::new_v4(), location_change("pilot-9"));
+        let response = open(&ride.rider_id, first.id).await.unwrap();
+        let mut restarted = response.into_body().into_data_stream();
+        let mut restarted_buffer = String::new();
+        let (id, snapshot) = next_event(&mut restarted, &mut restarted_buffer).await;
+        assert_eq!((id, snapshot.change), (newest.id, status_change(&ride)));
+
+        ride.status = RideStatus::Cancelled.to_string();
+        let cancelled = state.updates.publish_status(&ride);
+        assert_eq!(
+            next_event(&mut restarted, &mut restarted_buffer).await,
+            (cancelled.id, cancelled.clone())
+        );
+        assert_eq!(
+            next_event(&mut resumed, &mut resumed_buffer).await,
+            (cancelled.id, cancelled)
+        );
+    }
+
+    #[tokio::test]
+    async fn ws_route_resumes_and_closes_on_shutdown() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+        use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        use crate::auth::Role;
+
+        let state = test_state();
+        let mut ride = crate::repository::conformance::ride(&Default::default());
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        let first = state.updates.publish_status(&ride);
+        let second = state.updates.publish(ride.id, location_change("pilot-1"));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = crate::router(state.clone());
+        let server = tokio::spawn(async move { axum::serve(listener, app).await });
+        let connect = |last_event_id: Option<u64>| {
+            let query = last_event_id.map_or(String::new(), |id| format!("?last_event_id={id}"));
+            let mut request = format!("ws://{addr}/rides/{}/ws{query}", ride.id)
+                .into_client_request()
+                .unwrap();
+            request.headers_mut().insert(
+                "authorization",
+                bearer(Role::Rider, &ride.rider_id).parse().unwrap(),
+            );
+            tokio_tungstenite::connect_async(request)
+        };
+        async fn receive(
+            socket: &mut (impl Stream<Item = tokio_tungstenite::tungstenite::Result<WsMessage>>
+                      + Unpin),
+        ) -> WsMessage {
+            tokio::time::timeout(Duration::from_secs(1), socket.next())
+                .await
+                .expect("message in time")
+                .expect("socket open")
+                .unwrap()
+        }
+        let update_id = |message: WsMessage| {
+            let text = message.into_text().unwrap();
+            serde_json::from_str::<serde_json::Value>(&text).unwrap()["id"]
+                .as_u64()
+                .unwrap()
+        };
+
+        // A fresh connection starts with a snapshot; a resumed one with what it missed.
+        let (mut fresh, _) = connect(None).await.unwrap();
+        assert_eq!(update_id(receive(&mut fresh).await), second.id);
+        let (mut resumed, _) = connect(Some(first.id)).await.unwrap();
+        assert_eq!(update_id(receive(&mut resumed).await), second.id);
+
+        ride.status = RideStatus::Cancelled.to_string();
+        let cancelled = state.updates.publish_status(&ride);
+        assert_eq!(update_id(receive(&mut fresh).await), cancelled.id);
+        assert_eq!(update_id(receive(&mut resumed).await), cancelled.id);
+
+        state.updates.close();
+        match receive(&mut resumed).await {
+            WsMessage::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Away),
+            other => panic!("expected a close frame, got {other:?}"),
+        }
+        server.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn long_poll_wakes_on_status_change_or_times_out() {
+        let hub = RideUpdateHub::default();
+        let mut ride = crate::repository::conformance::ride(&Default::default());
+        assert_eq!(hub.version(ride.id), 0);
+
+        // No change: the wait runs the full timeout and reports the same version.
+        assert_eq!(hub.wait_for_change(ride.id, 0, LONG_POLL_TIMEOUT).await, 0);
+
+        let waiter = tokio::spawn({
+            let hub = hub.clone();
+            let ride_id = ride.id;
+            async move { hub.wait_for_change(ride_id, 0, LONG_POLL_TIMEOUT).await }
+        });
+        tokio::task::yield_now().await;
+        // Location updates do not bump the version; status changes do.
+        hub.publish(ride.id, location_change("pilot-1"));
+        ride.status = RideStatus::Accepted.to_string();
+        let accepted = hub.publish_status(&ride);
+        assert_eq!(waiter.await.unwrap(), accepted.id);
+        assert_eq!(hub.version(ride.id), accepted.id);
+
+        // An out-of-date version returns at once.
+        let started = tokio::time::Instant::now();
+        assert_eq!(
+            hub.wait_for_change(ride.id, 0, LONG_POLL_TIMEOUT).await,
+            accepted.id
+        );
+        assert_eq!(started.elapsed(), Duration::ZERO);
+
+        ride.status = RideStatus::Cancelled.to_string();
+        hub.publish_status(&ride);
+        assert!(hub.inner.versions.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_versions_of_unfinished_rides_are_evicted() {
+        let hub = RideUpdateHub::default();
+        let (stale, waited, fresh) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut ride = crate::repository::conformance::ride(&Default::default());
+        for ride_id in [stale, waited] {
+            ride.id = ride_id;
+            hub.publish_status(&ride);
+        }
+        let waiter = tokio::spawn({
+            let hub = hub.clone();
+            let seen = hub.version(waited);
+            async move { hub.wait_for_change(waited, seen, VERSION_IDLE * 2).await }
+        });
+        tokio::task::yield_now().await;
+        tokio::time::sleep(VERSION_IDLE).await;
+
+        // The sweep runs with the update whose id is a multiple of the interval.
+        ride.id = fresh;
+        while hub.publish_status(&ride).id % VERSION_SWEEP_INTERVAL != 0 {}
+        assert_eq!(hub.version(stale), 0);
+        assert_ne!(hub.version(waited), 0);
+        assert_ne!(hub.version(fresh), 0);
+        waiter.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closing_flushes_streams_then_ends_them() {
+        let state = test_state();
+        let mut ride = crate::repository::conformance::ride(&Default::default());
+        state.repo.create_ride(ride.clone()).await.unwrap();
+        let updates = ride_updates(state.clone(), ride.id, None);
+        futures_util::pin_mut!(updates);
+        next(&mut updates).await;
+        let waiter = tokio::spawn({
+            let hub = state.updates.clone();
+            let ride_id = ride.id;
+            async move { hub.wait_for_change(ride_id, 0, LONG_POLL_TIMEOUT).await }
+        });
+        tokio::task::yield_now().await;
+
+        ride.status = RideStatus::Accepted.to_string();
+        let accepted = state.updates.publish_status(&ride);
+        let started = tokio::time::Instant::now();
+        state.updates.close();
+        assert_eq!(next(&mut updates).await, accepted);
+        assert!(updates.next().await.is_none());
+        assert_eq!(waiter.await.unwrap(), accepted.id);
+
+        // Long polls that start while closed answer at once.
+        assert_eq!(
+            state
+                .updates
+                .wait_for_change(ride.id, accepted.id, LONG_POLL_TIMEOUT)
+                .await,
+            accepted.id
+        );
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn eta_targets_the_next_stop() {
+        let mut ride = crate::repository::conformance::ride(&Default::default());
+        ride.status = RideStatus::EnRoute.to_string();
+        let at_pickup = ride.pickup.clone();
+        assert_eq!(eta_minutes(&ride, &at_pickup), Some(0.0));
+        ride.status = RideStatus::InTransit.to_string();
+        assert!(eta_minutes(&ride, &at_pickup).unwrap() > 0.0);
+        ride.status = RideStatus::Completed.to_string();
+        assert_eq!(eta_minutes(&ride, &at_pickup), None);
+    }
+}
@@ -1,18 +1,37 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
 use crate::error::CoreResult;
+use crate::events::EventRetention;
+use crate::geo::{haversine_miles, BoundingBox, ServiceZone};
 use crate::model::RideLocation;
 
+pub mod conformance;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DispatchEngineConfig {
     pub key_prefix: String,
+    /// Approximate cap on breadcrumbs kept per pilot or ride trail.
+    #[serde(default = "default_trail_max_len")]
+    pub trail_max_len: usize,
+    #[serde(default)]
+    pub event_retention: EventRetention,
+}
+
+fn default_trail_max_len() -> usize {
+    10_000
 }
 
 impl Default for DispatchEngineConfig {
     fn default() -> Self {
         Self {
             key_prefix: "supportcarr".to_string(),
+            trail_max_len: default_trail_max_len(),
+            event_retention: EventRetention::default(),
         }
     }
 }
@@ -21,6 +40,7 @@ impl Default for DispatchEngineConfig {
 pub struct DispatchCandidate {
     pub pilot_id: String,
     pub distance_meters: Option<f64>,
+    pub location: Option<RideLocation>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -28,18 +48,73 @@ pub struct DispatchEvent {
     pub ride_id: String,
     pub pilot_id: String,
     pub eta_minutes: Option<u32>,
+    /// Radius of the search ring the pilot was found in.
+    pub search_radius_miles: Option<f64>,
+    /// Why this pilot ranked first, when a [`PilotScorer`] chose them.
+    pub explanation: Option<ScoreExplanation>,
+}
+
+/// A ride offered to a single pilot, waiting for them to accept or decline.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DispatchOffer {
+    pub ride_id: String,
+    pub pilot_id: String,
+    pub offered_at_ms: u64,
+    pub expires_at_ms: u64,
+}
+
+impl DispatchOffer {
+    pub fn new(ride_id: &str, pilot_id: &str, ttl: Duration) -> Self {
+        let offered_at_ms = now_millis();
+        Self {
+            ride_id: ride_id.to_string(),
+            pilot_id: pilot_id.to_string(),
+            offered_at_ms,
+            expires_at_ms: offered_at_ms + ttl.as_millis() as u64,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        now_millis() >= self.expires_at_ms
+    }
+}
+
+/// Milliseconds since the Unix epoch, the timestamp unit used in dispatch state.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
 }
 
 #[async_trait]
 pub trait DispatchEngine: Send + Sync {
+    /// Store the pilot's position as of now.
     async fn store_pilot_location(
         &self,
         pilot_id: &str,
         location: &RideLocation,
-    ) -> CoreResult<()>;
+    ) -> CoreResult<()> {
+        self.store_pilot_location_at(pilot_id, location, now_millis())
+            .await
+            .map(drop)
+    }
+
+    /// Store the pilot's position as recorded at `recorded_at_ms`, unless a later one is
+    /// already stored. Returns whether it was stored.
+    async fn store_pilot_location_at(
+        &self,
+        pilot_id: &str,
+        location: &RideLocation,
+        recorded_at_ms: u64,
+    ) -> CoreResult<bool>;
 
+    /// Pilots marked unavailable are left out of every search, as are pilots holding a live
+    /// offer. Pilots never marked either way count as available.
     async fn set_pilot_available(&self, pilot_id: &str, available: bool) -> CoreResult<()>;
 
+    /// Available pilots within the radius, nearest first.
     async fn find_nearby_pilots(
         &self,
         location: &RideLocation,
@@ -47,5 +122,639 @@ pub trait DispatchEngine: Send + Sync {
         limit: usize,
     ) -> CoreResult<Vec<DispatchCandidate>>;
 
+    /// Available pilots inside a rectangle, nearest to its center first.
+    async fn find_pilots_in_box(
+        &self,
+        bounds: &BoundingBox,
+        limit: usize,
+    ) -> CoreResult<Vec<DispatchCandidate>>;
+
+    /// Pilots inside a service zone polygon, built on [`Self::find_pilots_in_box`] over the
+    /// zone's bounding box. The default only sees the first `limit` box results, so engines
+    /// that can query the box unbounded should override it.
+    async fn find_pilots_in_zone(
+        &self,
+        zone: &ServiceZone,
+        limit: usize,
+    ) -> CoreResult<Vec<DispatchCandidate>> {
+        let Some(bounds) = zone.bounding_box() else {
+            return Ok(Vec::new());
+        };
+        let candidates = self.find_pilots_in_box(&bounds, limit).await?;
+        Ok(filter_to_zone(candidates, zone, limit))
+    }
+
+    /// Assign the ride to the pilot and take them out of searches until released.
     async fn mark_assigned(&self, pilot_id: &str, ride_id: &str) -> CoreResult<()>;
+
+    /// Clear the pilot's assignment and make them available to searches again.
+    async fn release_pilot(&self, pilot_id: &str) -> CoreResult<()>;
+
+    /// The ride last assigned to the pilot through [`Self::mark_assigned`], if any.
+    async fn current_assignment(&self, pilot_id: &str) -> CoreResult<Option<String>>;
+
+    /// Record a pending offer of `ride_id` to `pilot_id`, replacing any previous offer for
+    /// the ride, and remember that the pilot has been asked. Returns `None`, recording
+    /// nothing, when the pilot already holds a live offer of another ride; the check and
+    /// the claim are one atomic step, so a pilot never holds two. The claim ends when the
+    /// offer is taken, replaced or expires.
+    async fn create_offer(
+        &self,
+        ride_id: &str,
+        pilot_id: &str,
+        ttl: Duration,
+    ) -> CoreResult<Option<DispatchOffer>>;
+
+    /// The pending offer for a ride, whether or not it has expired.
+    async fn current_offer(&self, ride_id: &str) -> CoreResult<Option<DispatchOffer>>;
+
+    /// Atomically remove the ride's pending offer if it belongs to `pilot_id`, ending the
+    /// pilot's claim. Returns the removed offer so callers can check
+    /// [`DispatchOffer::is_expired`].
+    async fn take_offer(&self, ride_id: &str, pilot_id: &str)
+        -> CoreResult<Option<DispatchOffer>>;
+
+    /// Every pilot the ride has been offered to so far, including declines and timeouts.
+    async fn offered_pilots(&self, ride_id: &str) -> CoreResult<Vec<String>>;
+
+    /// Fail when the engine cannot reach its backing store, for readiness probes.
+    async fn health_check(&self) -> CoreResult<()>;
+}
+
+/// Keep candidates whose reported location lies inside `zone`, preserving order.
+pub fn filter_to_zone(
+    candidates: Vec<DispatchCandidate>,
+    zone: &ServiceZone,
+    limit: usize,
+) -> Vec<DispatchCandidate> {
+    candidates
+        .into_iter()
+        .filter(|candidate| {
+            candidate
+                .location
+                .as_ref()
+                .is_some_and(|location| zone.contains(location))
+        })
+        .take(limit)
+        .collect()
+}
+
+const METERS_PER_MILE: f64 = 1609.34;
+
+#[derive(Default)]
+struct InMemoryDispatchState {
+    locations: HashMap<String, RideLocation>,
+    located_at_ms: HashMap<String, u64>,
+    unavailable: HashSet<String>,
+    assignments: HashMap<String, String>,
+    offers: HashMap<String, DispatchOffer>,
+    offered: HashMap<String, Vec<String>>,
+    /// The ride each pilot was last offered; the claim is live while that offer is.
+    claims: HashMap<String, String>,
+}
+
+impl InMemoryDispatchState {
+    /// The ride whose live offer `pilot_id` holds, if any.
+    fn claimed_ride(&self, pilot_id: &str) -> Option<&String> {
+        self.claims.get(pilot_id).filter(|ride_id| {
+            self.offers
+                .get(*ride_id)
+                .is_some_and(|offer| offer.pilot_id == pilot_id && !offer.is_expired())
+        })
+    }
+
+    /// End `pilot_id`'s claim if it is on `ride_id`.
+    fn release_claim(&mut self, pilot_id: &str, ride_id: &str) {
+        if self.claims.get(pilot_id).is_some_and(|claimed| claimed == ride_id) {
+            self.claims.remove(pilot_id);
+        }
+    }
+
+    /// Available pilots accepted by `inside`, nearest to `origin` first.
+    fn search(
+        &self,
+        origin: &RideLocation,
+        inside: impl Fn(&RideLocation, f64) -> bool,
+        limit: usize,
+    ) -> Vec<DispatchCandidate> {
+        let mut found: Vec<(f64, &String, &RideLocation)> = self
+            .locations
+            .iter()
+            .filter(|(pilot_id, _)| {
+                !self.unavailable.contains(*pilot_id) && self.claimed_ride(pilot_id).is_none()
+            })
+            .map(|(pilot_id, location)| (haversine_miles(origin, location), pilot_id, location))
+            .filter(|(miles, _, location)| inside(location, *miles))
+            .collect();
+        found.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(b.1)));
+        found
+            .into_iter()
+            .take(limit)
+            .map(|(miles, pilot_id, location)| DispatchCandidate {
+                pilot_id: pilot_id.clone(),
+                distance_meters: Some(miles * METERS_PER_MILE),
+                location: Some(location.clone()),
+            })
+            .collect()
+    }
+}
+
+/// Process-local engine for tests and single-instance deployments without Redis.
+#[derive(Default)]
+pub struct InMemoryDispatchEngine {
+    state: Mutex<InMemoryDispatchState>,
+}
+
+#[async_trait]
+impl DispatchEngine for InMemoryDispatchEngine {
+    async fn store_pilot_location_at(
+        &self,
+        pilot_id: &str,
+        location: &RideLocation,
+        recorded_at_ms: u64,
+    ) -> CoreResult<bool> {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .located_at_ms
+            .get(pilot_id)
+            .is_some_and(|stored| *stored > recorded_at_ms)
+        {
+            return Ok(false);
+        }
+        state
+            .located_at_ms
+            .insert(pilot_id.to_string(), recorded_at_ms);
+        state
+            .locations
+            .insert(pilot_id.to_string(), location.clone());
+        Ok(true)
+    }
+
+    async fn set_pilot_available(&self, pilot_id: &str, available: bool) -> CoreResult<()> {
+        let mut state = self.state.lock().unwrap();
+        if available {
+            state.unavailable.remove(pilot_id);
+        } else {
+            state.unavailable.insert(pilot_id.to_string());
+        }
+        Ok(())
+    }
+
+    async fn find_nearby_pilots(
+        &self,
+        location: &RideLocation,
+        radius_miles: f64,
+        limit: usize,
+    ) -> CoreResult<Vec<DispatchCandidate>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.search(location, |_, miles| miles <= radius_miles, limit))
+    }
+
+    async fn find_pilots_in_box(
+        &self,
+        bounds: &BoundingBox,
+        limit: usize,
+    ) -> CoreResult<Vec<DispatchCandidate>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.search(&bounds.center(), |location, _| bounds.contains(location), limit))
+    }
+
+    async fn find_pilots_in_zone(
+        &self,
+        zone: &ServiceZone,
+        limit: usize,
+    ) -> CoreResult<Vec<DispatchCandidate>> {
+        let Some(bounds) = zone.bounding_box() else {
+            return Ok(Vec::new());
+        };
+        let state = self.state.lock().unwrap();
+        Ok(state.search(&bounds.center(), |location, _| zone.contains(location), limit))
+    }
+
+    async fn mark_assigned(&self, pilot_id: &str, ride_id: &str) -> CoreResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.unavailable.insert(pilot_id.to_string());
+        state
+            .assignments
+            .insert(pilot_id.to_string(), ride_id.to_string());
+        Ok(())
+    }
+
+    async fn release_pilot(&self, pilot_id: &str) -> CoreResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.unavailable.remove(pilot_id);
+        state.assignments.remove(pilot_id);
+        Ok(())
+    }
+
+    async fn current_assignment(&self, pilot_id: &str) -> CoreResult<Option<String>> {
+        Ok(self.state.lock().unwrap().assignments.get(pilot_id).cloned())
+    }
+
+    async fn create_offer(
+        &self,
+        ride_id: &str,
+        pilot_id: &str,
+        ttl: Duration,
+    ) -> CoreResult<Option<DispatchOffer>> {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .claimed_ride(pilot_id)
+            .is_some_and(|claimed| claimed != ride_id)
+        {
+            return Ok(None);
+        }
+        let offer = DispatchOffer::new(ride_id, pilot_id, ttl);
+        if let Some(replaced) = state.offers.insert(ride_id.to_string(), offer.clone()) {
+            state.release_claim(&replaced.pilot_id, ride_id);
+        }
+        state
+            .claims
+            .insert(pilot_id.to_string(), ride_id.to_string());
+        let offered = state.offered.entry(ride_id.to_string()).or_default();
+        if !offered.iter().any(|id| id == pilot_id) {
+            offered.push(pilot_id.to_string());
+        }
+        Ok(Some(offer))
+    }
+
+    async fn current_offer(&self, ride_id: &str) -> CoreResult<Option<DispatchOffer>> {
+        Ok(self.state.lock().unwrap().offers.get(ride_id).cloned())
+    }
+
+    async fn take_offer(
+        &self,
+        ride_id: &str,
+        pilot_id: &str,
+    ) -> CoreResult<Option<DispatchOffer>> {
+        let mut state = self.state.lock().unwrap();
+        match state.offers.get(ride_id) {
+            Some(offer) if offer.pilot_id == pilot_id => {
+                state.release_claim(pilot_id, ride_id);
+                Ok(state.offers.remove(ride_id))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn offered_pilots(&self, ride_id: &str) -> CoreResult<Vec<String>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .offered
+            .get(ride_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn health_check(&self) -> CoreResult<()> {
+        Ok(())
+    }
+}
+
+/// Dispatch-relevant facts about a pilot beyond their position.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PilotProfile {
+    pub pilot_id: String,
+    /// Average rider rating on a 0-5 scale.
+    pub rating: Option<f64>,
+    /// Share of offers accepted, 0-1.
+    pub acceptance_rate: Option<f64>,
+    /// Bike types the pilot's vehicle can carry; empty means unknown.
+    pub bike_types: Vec<String>,
+    pub last_ride_completed_at_ms: Option<u64>,
+    pub shift_ends_at_ms: Option<u64>,
+}
+
+/// What the scorer knows about the ride being dispatched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoringContext {
+    pub bike_type: Option<String>,
+    pub trip_miles: f64,
+    pub now_ms: u64,
+}
+
+/// One factor's part in a pilot's score. `value` is normalized to 0-1 (higher is better)
+/// and `contribution` is `value * weight / total weight`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScoreComponent {
+    pub factor: String,
+    pub value: f64,
+    pub weight: f64,
+    pub contribution: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScoreExplanation {
+    pub score: f64,
+    pub components: Vec<ScoreComponent>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoredCandidate {
+    pub candidate: DispatchCandidate,
+    pub explanation: ScoreExplanation,
+}
+
+pub trait PilotScorer: Send + Sync {
+    fn score(
+        &self,
+        candidate: &DispatchCandidate,
+        profile: Option<&PilotProfile>,
+        context: &ScoringContext,
+    ) -> ScoreExplanation;
+}
+
+/// Order candidates best score first; ties keep the engine's distance order.
+pub fn rank_candidates(
+    scorer: &dyn PilotScorer,
+    candidates: Vec<DispatchCandidate>,
+    profiles: &HashMap<String, PilotProfile>,
+    context: &ScoringContext,
+) -> Vec<ScoredCandidate> {
+    let mut ranked: Vec<ScoredCandidate> = candidates
+        .into_iter()
+        .map(|candidate| ScoredCandidate {
+            explanation: scorer.score(&candidate, profiles.get(&candidate.pilot_id), context),
+            candidate,
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.explanation.score.total_cmp(&a.explanation.score));
+    ranked
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScoringWeights {
+    pub distance: f64,
+    pub rating: f64,
+    pub acceptance_rate: f64,
+    pub bike_type: f64,
+    pub fairness: f64,
+    pub shift_end: f64,
+}
+
+impl Default for ScoringWeights {
+    fn default() -> Self {
+        Self {
+            distance: 0.4,
+            rating: 0.15,
+            acceptance_rate: 0.1,
+            bike_type: 0.15,
+            fairness: 0.1,
+            shift_end: 0.1,
+        }
+    }
+}
+
+/// Weighted sum of normalized factors. Missing profile data, or a missing distance, scores
+/// a neutral 0.5 so new pilots are neither favored nor buried.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WeightedPilotScorer {
+    pub weights: ScoringWeights,
+    /// Distance at which the distance factor bottoms out.
+    pub max_distance_miles: f64,
+    /// Idle time after which a pilot gets the full fairness bonus.
+    pub fairness_horizon: Duration,
+    /// Slack a pilot should have left on shift after finishing the ride.
+    pub shift_buffer: Duration,
+    /// Used to estimate pickup and trip time against the shift end.
+    pub average_speed_mph: f64,
+}
+
+impl Default for WeightedPilotScorer {
+    fn default() -> Self {
+        Self {
+            weights: ScoringWeights::default(),
+            max_distance_miles: 15.0,
+            fairness_horizon: Duration::from_secs(60 * 60),
+            shift_buffer: Duration::from_secs(30 * 60),
+            average_speed_mph: 20.0,
+        }
+    }
+}
+
+impl PilotScorer for WeightedPilotScorer {
+    fn score(
+        &self,
+        candidate: &DispatchCandidate,
+        profile: Option<&PilotProfile>,
+        context: &ScoringContext,
+    ) -> ScoreExplanation {
+        let distance_miles = candidate.distance_meters.map(|meters| meters / METERS_PER_MILE);
+        let distance = distance_miles
+            .map_or(0.5, |miles| 1.0 - (miles / self.max_distance_miles).min(1.0));
+
+        let rating = profile
+            .and_then(|p| p.rating)
+            .map_or(0.5, |rating| (rating / 5.0).clamp(0.0, 1.0));
+        let acceptance_rate = profile
+            .and_then(|p| p.acceptance_rate)
+            .map_or(0.5, |rate| rate.clamp(0.0, 1.0));
+
+        let bike_type = match (&context.bike_type, profile) {
+            (None, _) => 1.0,
+            (Some(_), None) => 0.5,
+            (Some(_), Some(p)) if p.bike_types.is_empty() => 0.5,
+            (Some(wanted), Some(p)) => {
+                if p.bike_types.iter().any(|t| t.eq_ignore_ascii_case(wanted)) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        };
+
+        let fairness = match profile.and_then(|p| p.last_ride_completed_at_ms) {
+            Some(at) => {
+                let idle_ms = context.now_ms.saturating_sub(at) as f64;
+                (idle_ms / self.fairness_horizon.as_millis() as f64).min(1.0)
+            }
+            None => 1.0,
+        };
+
+        let shift_end = match profile.and_then(|p| p.shift_ends_at_ms) {
+            Some(ends_at) => {
+                let busy_miles = distance_miles.unwrap_or(0.0) + context.trip_miles;
+                let busy_ms = busy_miles / self.average_speed_mph * 3_600_000.0;
+                let left_ms = ends_at as f64 - context.now_ms as f64 - busy_ms;
+                (left_ms / self.shift_buffer.as_millis() as f64).clamp(0.0, 1.0)
+            }
+            None => 1.0,
+        };
+
+        let weights = &self.weights;
+        let factors = [
+            ("distance", distance, weights.distance),
+            ("rating", rating, weights.rating),
+            ("acceptance_rate", acceptance_rate, weights.acceptance_rate),
+            ("bike_type", bike_type, weights.bike_type),
+            ("fairness", fairness, weights.fairness),
+            ("shift_end", shift_end, weights.shift_end),
+        ];
+        let total_weight: f64 = factors.iter().map(|(_, _, weight)| weight).sum();
+        let components: Vec<ScoreComponent> = factors
+            .into_iter()
+            .map(|(factor, value, weight)| ScoreComponent {
+                factor: factor.to_string(),
+                value,
+                weight,
+                contribution: if total_weight > 0.0 {
+                    value * weight / total_weight
+                } else {
+                    0.0
+                },
+            })
+            .collect();
+
+        ScoreExplanation {
+            score: components.iter().map(|c| c.contribution).sum(),
+            components,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn in_memory_engine_conforms() {
+        conformance::run(&InMemoryDispatchEngine::default()).await;
+    }
+
+    #[test]
+    fn offer_expiry() {
+        let offer = DispatchOffer::new("ride-1", "pilot-1", Duration::from_secs(30));
+        assert_eq!(offer.expires_at_ms - offer.offered_at_ms, 30_000);
+        assert!(!offer.is_expired());
+
+        let offer = DispatchOffer::new("ride-1", "pilot-1", Duration::ZERO);
+        assert!(offer.is_expired());
+    }
+
+    fn candidate(pilot_id: &str, miles: f64) -> DispatchCandidate {
+        DispatchCandidate {
+            pilot_id: pilot_id.to_string(),
+            distance_meters: Some(miles * METERS_PER_MILE),
+            location: None,
+        }
+    }
+
+    fn context(bike_type: Option<&str>) -> ScoringContext {
+        ScoringContext {
+            bike_type: bike_type.map(str::to_string),
+            trip_miles: 5.0,
+            now_ms: 10 * 60 * 60 * 1000,
+        }
+    }
+
+    #[test]
+    fn scorer_prefers_capable_pilot_over_closer_one() {
+        let scorer = WeightedPilotScorer::default();
+        let profiles = HashMap::from([
+            (
+                "close".to_string(),
+                PilotProfile {
+                    pilot_id: "close".to_string(),
+                    bike_types: vec!["standard".to_string()],
+                    ..PilotProfile::default()
+                },
+            ),
+            (
+                "cargo".to_string(),
+                PilotProfile {
+                    pilot_id: "cargo".to_string(),
+                    bike_types: vec!["cargo".to_string()],
+                    ..PilotProfile::default()
+                },
+            ),
+        ]);
+
+        let ranked = rank_candidates(
+            &scorer,
+            vec![candidate("close", 1.0), candidate("cargo", 3.0)],
+            &profiles,
+            &context(Some("Cargo")),
+        );
+        assert_eq!(ranked[0].candidate.pilot_id, "cargo");
+
+        let bike = |scored: &ScoredCandidate| {
+            scored
+                .explanation
+                .components
+                .iter()
+                .find(|c| c.factor == "bike_type")
+                .map(|c| c.value)
+        };
+        assert_eq!(bike(&ranked[0]), Some(1.0));
+        assert_eq!(bike(&ranked[1]), Some(0.0));
+    }
+
+    #[test]
+    fn scorer_penalizes_shift_ending_and_recent_ride() {
+        let scorer = WeightedPilotScorer::default();
+        let ctx = context(None);
+        let leaving = PilotProfile {
+            pilot_id: "leaving".to_string(),
+            shift_ends_at_ms: Some(ctx.now_ms + 5 * 60 * 1000),
+            last_ride_completed_at_ms: Some(ctx.now_ms),
+            ..PilotProfile::default()
+        };
+        let fresh = PilotProfile {
+            pilot_id: "fresh".to_string(),
+            ..PilotProfile::default()
+        };
+        let profiles = HashMap::from([
+            ("leaving".to_string(), leaving),
+            ("fresh".to_string(), fresh),
+        ]);
+
+        let ranked = rank_candidates(
+            &scorer,
+            vec![candidate("leaving", 2.0), candidate("fresh", 2.0)],
+            &profiles,
+            &ctx,
+        );
+        assert_eq!(ranked[0].candidate.pilot_id, "fresh");
+        // Neutral rating and acceptance, full marks for bike type, fairness and shift.
+        let expected = 0.4 * (1.0 - 2.0 / 15.0) + 0.15 * 0.5 + 0.1 * 0.5 + 0.15 + 0.1 + 0.1;
+        assert!((ranked[0].explanation.score - expected).abs() < 1e-9);
+
+        let explanation = &ranked[1].explanation;
+        let total: f64 = explanation.components.iter().map(|c| c.contribution).sum();
+        assert!((explanation.score - total).abs() < 1e-12);
+        for factor in ["fairness", "shift_end"] {
+            let component = explanation
+                .components
+                .iter()
+                .find(|c| c.factor == factor)
+                .expect("factor present");
+            assert_eq!(component.value, 0.0, "{factor}");
+        }
+    }
+
+    #[test]
+    fn scorer_treats_unknown_distance_as_neutral() {
+        let scorer = WeightedPilotScorer::default();
+        let unknown = DispatchCandidate {
+            distance_meters: None,
+            ..candidate("unknown", 0.0)
+        };
+        let ranked = rank_candidates(
+            &scorer,
+            vec![unknown, candidate("near", 1.0), candidate("far", 14.0)],
+            &HashMap::new(),
+            &context(None),
+        );
+        let order: Vec<_> = ranked.iter().map(|s| s.candidate.pilot_id.as_str()).collect();
+        assert_eq!(order, ["near", "unknown", "far"]);
+        let distance = ranked[1]
+            .explanation
+            .components
+            .iter()
+            .find(|c| c.factor == "distance")
+            .expect("factor present");
+        assert_eq!(distance.value, 0.5);
+    }
 }
@@ -0,0 +1,295 @@
+//! Behaviour every [`DispatchEngine`] must share. Engine crates call [`run`] from their own
+//! tests so dispatch ranks and filters pilots the same way on every backend.
+
+use std::time::Duration;
+
+use crate::geo::BoundingBox;
+use crate::model::RideLocation;
+
+use super::{DispatchCandidate, DispatchEngine, METERS_PER_MILE};
+
+// Degrees of latitude per mile, close enough for fixtures a few miles across.
+const DEGREES_PER_MILE: f64 = 1.0 / 69.09;
+
+/// Run the whole suite. Each case places pilots around its own point on the 20°E meridian,
+/// between 10°N and 16°N or at 60°N, so the engine must have no other pilots near there.
+pub async fn run(engine: &dyn DispatchEngine) {
+    engine.health_check().await.expect("healthy engine");
+    orders_by_distance(engine, origin(0)).await;
+    respects_radius_boundary(engine, origin(1)).await;
+    applies_limit(engine, origin(2)).await;
+    skips_unavailable_pilots(engine, origin(3)).await;
+    assign_and_release(engine, origin(4)).await;
+    keeps_the_latest_position(engine, origin(5)).await;
+    offers_claim_their_pilot(engine, origin(6)).await;
+    boxes_reach_their_corners(engine, RideLocation::new(60.0, 20.0)).await;
+}
+
+fn origin(case: u8) -> RideLocation {
+    RideLocation::new(10.0 + f64::from(case), 20.0)
+}
+
+fn north_of(origin: &RideLocation, miles: f64) -> RideLocation {
+    RideLocation::new(origin.lat + miles * DEGREES_PER_MILE, origin.lng)
+}
+
+async fn place(engine: &dyn DispatchEngine, origin: &RideLocation, pilots: &[(&str, f64)]) {
+    for (pilot_id, miles) in pilots {
+        engine
+            .store_pilot_location(pilot_id, &north_of(origin, *miles))
+            .await
+            .expect("store pilot location");
+    }
+}
+
+fn ids(candidates: &[DispatchCandidate]) -> Vec<&str> {
+    candidates.iter().map(|c| c.pilot_id.as_str()).collect()
+}
+
+async fn nearby(
+    engine: &dyn DispatchEngine,
+    origin: &RideLocation,
+    radius_miles: f64,
+    limit: usize,
+) -> Vec<DispatchCandidate> {
+    engine
+        .find_nearby_pilots(origin, radius_miles, limit)
+        .await
+        .expect("find nearby pilots")
+}
+
+async fn orders_by_distance(engine: &dyn DispatchEngine, origin: RideLocation) {
+    place(
+        engine,
+        &origin,
+        &[("order-far", 3.0), ("order-near", 1.0), ("order-mid", 2.0)],
+    )
+    .await;
+
+    let found = nearby(engine, &origin, 5.0, 10).await;
+    assert_eq!(ids(&found), vec!["order-near", "order-mid", "order-far"]);
+    let distances: Vec<f64> = found.iter().filter_map(|c| c.distance_meters).collect();
+    assert!(
+        distances.windows(2).all(|pair| pair[0] <= pair[1]),
+        "{distances:?}"
+    );
+    if let Some(meters) = found[0].distance_meters {
+        assert!(
+            (meters - METERS_PER_MILE).abs() < 50.0,
+            "nearest pilot at {meters} m"
+        );
+    }
+
+    let bounds = BoundingBox::new(
+        RideLocation::new(origin.lat - 4.0 * DEGREES_PER_MILE, origin.lng - 0.1),
+        RideLocation::new(origin.lat + 4.0 * DEGREES_PER_MILE, origin.lng + 0.1),
+    );
+    let boxed = engine
+        .find_pilots_in_box(&bounds, 10)
+        .await
+        .expect("find pilots in box");
+    // Box results are ordered from the box center, which is the origin here.
+    assert_eq!(ids(&boxed), vec!["order-near", "order-mid", "order-far"]);
+}
+
+async fn respects_radius_boundary(engine: &dyn DispatchEngine, origin: RideLocation) {
+    place(
+        engine,
+        &origin,
+        &[("radius-inside", 4.5), ("radius-outside", 5.5)],
+    )
+    .await;
+
+    assert_eq!(
+        ids(&nearby(engine, &origin, 5.0, 10).await),
+        vec!["radius-inside"]
+    );
+    assert_eq!(
+        ids(&nearby(engine, &origin, 6.0, 10).await),
+        vec!["radius-inside", "radius-outside"]
+    );
+    assert!(nearby(engine, &origin, 4.0, 10).await.is_empty());
+}
+
+async fn applies_limit(engine: &dyn DispatchEngine, origin: RideLocation) {
+    place(
+        engine,
+        &origin,
+        &[
+            ("limit-1", 1.0),
+            ("limit-2", 2.0),
+            ("limit-3", 3.0),
+            ("limit-4", 4.0),
+        ],
+    )
+    .await;
+
+    assert_eq!(
+        ids(&nearby(engine, &origin, 5.0, 2).await),
+        vec!["limit-1", "limit-2"]
+    );
+    assert_eq!(nearby(engine, &origin, 5.0, 10).await.len(), 4);
+    assert!(nearby(engine, &origin, 5.0, 0).await.is_empty());
+}
+
+async fn skips_unavailable_pilots(engine: &dyn DispatchEngine, origin: RideLocation) {
+    // `avail-far` is never marked either way and still counts as available.
+    place(engine, &origin, &[("avail-near", 1.0), ("avail-far", 2.0)]).await;
+    engine
+        .set_pilot_available("avail-near", false)
+        .await
+        .expect("mark unavailable");
+
+    assert_eq!(
+        ids(&nearby(engine, &origin, 5.0, 10).await),
+        vec!["avail-far"]
+    );
+    // Skipped pilots must not use up the limit.
+    assert_eq!(
+        ids(&nearby(engine, &origin, 5.0, 1).await),
+        vec!["avail-far"]
+    );
+
+    engine
+        .set_pilot_available("avail-near", true)
+        .await
+        .expect("mark available");
+    assert_eq!(
+        ids(&nearby(engine, &origin, 5.0, 10).await),
+        vec!["avail-near", "avail-far"]
+    );
+}
+
+async fn assign_and_release(engine: &dyn DispatchEngine, origin: RideLocation) {
+    place(engine, &origin, &[("assign-pilot", 1.0)]).await;
+    assert_eq!(
+        engine
+            .current_assignment("assign-pilot")
+            .await
+            .expect("current assignment"),
+        None
+    );
+
+    engine
+        .mark_assigned("assign-pilot", "assign-ride")
+        .await
+        .expect("mark assigned");
+    assert_eq!(
+        engine
+            .current_assignment("assign-pilot")
+            .await
+            .expect("current assignment")
+            .as_deref(),
+        Some("assign-ride")
+    );
+    assert!(nearby(engine, &origin, 5.0, 10).await.is_empty());
+
+    engine
+        .release_pilot("assign-pilot")
+        .await
+        .expect("release pilot");
+    assert_eq!(
+        engine
+            .current_assignment("assign-pilot")
+            .await
+            .expect("current assignment"),
+        None
+    );
+    assert_eq!(
+        ids(&nearby(engine, &origin, 5.0, 10).await),
+        vec!["assign-pilot"]
+    );
+}
+
+async fn keeps_the_latest_position(engine: &dyn DispatchEngine, origin: RideLocation) {
+    let store = |miles: f64, recorded_at_ms: u64| {
+        let location = north_of(&origin, miles);
+        async move {
+            engine
+                .store_pilot_location_at("latest-pilot", &location, recorded_at_ms)
+                .await
+                .expect("store pilot location")
+        }
+    };
+    assert!(store(1.0, 2_000).await);
+    // A point buffered offline arrives after a newer one and is not stored.
+    assert!(!store(3.0, 1_000).await);
+    assert_eq!(ids(&nearby(engine, &origin, 2.0, 10).await), vec!["latest-pilot"]);
+    // One recorded at the same time as the stored point replaces it.
+    assert!(store(2.5, 2_000).await);
+    assert!(nearby(engine, &origin, 2.0, 10).await.is_empty());
+}
+
+async fn offers_claim_their_pilot(engine: &dyn DispatchEngine, origin: RideLocation) {
+    place(engine, &origin, &[("claim-near", 1.0), ("claim-far", 2.0)]).await;
+    let offer = |ride_id: &'static str, pilot_id: &'static str, ttl: Duration| async move {
+        engine
+            .create_offer(ride_id, pilot_id, ttl)
+            .await
+            .expect("create offer")
+    };
+    let ttl = Duration::from_secs(60);
+
+    assert!(offer("claim-ride-1", "claim-near", ttl).await.is_some());
+    // A pilot holding a live offer is left out of searches and cannot be offered another.
+    assert_eq!(ids(&nearby(engine, &origin, 5.0, 10).await), vec!["claim-far"]);
+    assert!(offer("claim-ride-2", "claim-near", ttl).await.is_none());
+    assert!(engine
+        .offered_pilots("claim-ride-2")
+        .await
+        .expect("offered pilots")
+        .is_empty());
+    assert_eq!(
+        engine.current_offer("claim-ride-2").await.expect("current offer"),
+        None
+    );
+
+    // Replacing the ride's offer frees its previous pilot, and taking it frees the new one.
+    assert!(offer("claim-ride-1", "claim-far", ttl).await.is_some());
+    assert_eq!(ids(&nearby(engine, &origin, 5.0, 10).await), vec!["claim-near"]);
+    let taken = engine
+        .take_offer("claim-ride-1", "claim-far")
+        .await
+        .expect("take offer");
+    assert!(taken.is_some());
+    assert_eq!(
+        ids(&nearby(engine, &origin, 5.0, 10).await),
+        vec!["claim-near", "claim-far"]
+    );
+
+    // An expired offer claims no one.
+    assert!(offer("claim-ride-3", "claim-near", Duration::ZERO).await.is_some());
+    assert_eq!(nearby(engine, &origin, 5.0, 10).await.len(), 2);
+    assert!(offer("claim-ride-4", "claim-near", ttl).await.is_some());
+}
+
+async fn boxes_reach_their_corners(engine: &dyn DispatchEngine, center: RideLocation) {
+    // This far north, a degree of longitude is a tenth wider on the box's southern edge than
+    // on its northern one.
+    let bounds = BoundingBox::new(
+        RideLocation::new(center.lat - 1.0, center.lng - 0.2),
+        RideLocation::new(center.lat + 1.0, center.lng + 0.2),
+    );
+    let corners = [
+        ("corner-sw", RideLocation::new(center.lat - 0.95, center.lng - 0.195)),
+        ("corner-se", RideLocation::new(center.lat - 0.95, center.lng + 0.195)),
+        ("corner-nw", RideLocation::new(center.lat + 0.95, center.lng - 0.195)),
+        ("corner-ne", RideLocation::new(center.lat + 0.95, center.lng + 0.195)),
+    ];
+    // Just east of the box, yet nearer its center than any corner.
+    let outside = RideLocation::new(center.lat, center.lng + 0.2005);
+    for (pilot_id, location) in corners.iter().chain([&("corner-out", outside)]) {
+        engine
+            .store_pilot_location(pilot_id, location)
+            .await
+            .expect("store pilot location");
+    }
+
+    let found = engine
+        .find_pilots_in_box(&bounds, 4)
+        .await
+        .expect("find pilots in box");
+    let mut found = ids(&found);
+    found.sort();
+    assert_eq!(found, vec!["corner-ne", "corner-nw", "corner-se", "corner-sw"]);
+}
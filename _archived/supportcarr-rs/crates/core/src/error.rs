@@ -12,8 +12,18 @@ pub enum CoreError {
     Dispatch(String),
     #[error("storage error: {0}")]
     Storage(String),
+    #[error("no pending offer for this pilot")]
+    OfferUnavailable,
+    #[error("offer expired")]
+    OfferExpired,
+    #[error("pilot is already assigned a ride")]
+    PilotAssigned,
     #[error("ride not found")]
     NotFound,
+    #[error("ride already exists")]
+    AlreadyExists,
+    #[error("ride is {found}, not {expected}")]
+    StatusChanged { expected: String, found: String },
     #[error("unauthorized")]
     Unauthorized,
 }
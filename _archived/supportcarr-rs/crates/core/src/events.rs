@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::dispatch::{now_millis, DispatchEvent};
+use crate::error::{CoreError, CoreResult};
+
+/// A single dispatch decision, recorded so a bad assignment can be reconstructed later.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DispatchDecision {
+    /// Pilot search around a ride's pickup.
+    Search {
+        ride_id: String,
+        radius_miles: f64,
+        rings_searched: usize,
+    },
+    /// Candidates considered for a ride, best first.
+    Candidates {
+        ride_id: String,
+        pilot_ids: Vec<String>,
+    },
+    /// A pilot was offered the ride.
+    Claim(DispatchEvent),
+    /// A pilot accepted and the ride was assigned to them.
+    Assign { ride_id: String, pilot_id: String },
+    /// A pilot gave up an offer through a decline or timeout.
+    Release {
+        ride_id: String,
+        pilot_id: String,
+        reason: String,
+    },
+}
+
+impl DispatchDecision {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DispatchDecision::Search { .. } => "search",
+            DispatchDecision::Candidates { .. } => "candidates",
+            DispatchDecision::Claim(_) => "claim",
+            DispatchDecision::Assign { .. } => "assign",
+            DispatchDecision::Release { .. } => "release",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DispatchLogEntry {
+    /// Log position; Redis stream IDs for the Redis log.
+    pub id: String,
+    pub recorded_at_ms: u64,
+    pub decision: DispatchDecision,
+}
+
+/// How long dispatch decisions are kept. Either limit, or both, may be set.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EventRetention {
+    /// Approximate cap on stored entries.
+    pub max_len: Option<usize>,
+    /// Entries older than this are dropped as new ones arrive.
+    pub max_age_secs: Option<u64>,
+}
+
+impl Default for EventRetention {
+    fn default() -> Self {
+        Self {
+            max_len: Some(100_000),
+            max_age_secs: Some(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// Append-only log of dispatch decisions.
+#[async_trait]
+pub trait DispatchEventLog: Send + Sync {
+    /// Append a decision and return its log id.
+    async fn append(&self, decision: DispatchDecision) -> CoreResult<String>;
+
+    /// Up to `limit` entries after `after_id` (from the start when `None`), oldest first.
+    async fn read_after(
+        &self,
+        after_id: Option<&str>,
+        limit: usize,
+    ) -> CoreResult<Vec<DispatchLogEntry>>;
+}
+
+#[derive(Default)]
+pub struct InMemoryDispatchEventLog {
+    retention: EventRetention,
+    entries: Mutex<VecDeque<(u64, DispatchLogEntry)>>,
+}
+
+impl InMemoryDispatchEventLog {
+    pub fn new(retention: EventRetention) -> Self {
+        Self {
+            retention,
+            entries: Mutex::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl DispatchEventLog for InMemoryDispatchEventLog {
+    async fn append(&self, decision: DispatchDecision) -> CoreResult<String> {
+        let recorded_at_ms = now_millis();
+        let mut entries = self.entries.lock().unwrap();
+        let sequence = entries.back().map_or(0, |(sequence, _)| sequence + 1);
+        let id = format!("{recorded_at_ms}-{sequence}");
+        entries.push_back((
+            sequence,
+            DispatchLogEntry {
+                id: id.clone(),
+                recorded_at_ms,
+                decision,
+            },
+        ));
+
+        if let Some(max_len) = self.retention.max_len {
+            while entries.len() > max_len {
+                entries.pop_front();
+            }
+        }
+        if let Some(max_age_secs) = self.retention.max_age_secs {
+            let cutoff = recorded_at_ms.saturating_sub(max_age_secs * 1000);
+            while entries
+                .front()
+                .is_some_and(|(_, entry)| entry.recorded_at_ms < cutoff)
+            {
+                entries.pop_front();
+            }
+        }
+        Ok(id)
+    }
+
+    async fn read_after(
+        &self,
+        after_id: Option<&str>,
+        limit: usize,
+    ) -> CoreResult<Vec<DispatchLogEntry>> {
+        // Like a Redis stream, resume after the id's position rather than the entry
+        // itself, which may already have been trimmed.
+        let after = after_id
+            .map(|id| {
+                id.rsplit_once('-')
+                    .and_then(|(_, sequence)| sequence.parse::<u64>().ok())
+                    .ok_or_else(|| CoreError::Dispatch(format!("invalid event log id: {id}")))
+            })
+            .transpose()?;
+        let entries = self.entries.lock().unwrap();
+        let start = after.map_or(0, |after| {
+            entries.partition_point(|(sequence, _)| *sequence <= after)
+        });
+        Ok(entries
+            .iter()
+            .skip(start)
+            .take(limit)
+            .map(|(_, entry)| entry.clone())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(n: usize) -> DispatchDecision {
+        DispatchDecision::Release {
+            ride_id: format!("ride-{n}"),
+            pilot_id: "pilot-1".to_string(),
+            reason: "declined".to_string(),
+        }
+    }
+
+    #[test]
+    fn decisions_serialize_with_kind_tag() {
+        let json = serde_json::to_value(release(1)).unwrap();
+        assert_eq!(json["kind"], "release");
+        assert_eq!(json["reason"], "declined");
+        assert_eq!(release(1).kind(), "release");
+
+        let claim = DispatchDecision::Claim(DispatchEvent {
+            ride_id: "ride-1".to_string(),
+            pilot_id: "pilot-1".to_string(),
+            eta_minutes: Some(4),
+            search_radius_miles: Some(2.0),
+            explanation: None,
+        });
+        let json = serde_json::to_string(&claim).unwrap();
+        assert_eq!(serde_json::from_str::<DispatchDecision>(&json).unwrap(), claim);
+    }
+
+    #[tokio::test]
+    async fn in_memory_log_reads_after_and_trims() {
+        let log = InMemoryDispatchEventLog::new(EventRetention {
+            max_len: Some(3),
+            max_age_secs: None,
+        });
+        let mut ids = Vec::new();
+        for n in 0..5 {
+            ids.push(log.append(release(n)).await.unwrap());
+        }
+
+        let all = log.read_after(None, 10).await.unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].id, ids[2]);
+
+        let rest = log.read_after(Some(&ids[3]), 10).await.unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].decision, release(4));
+
+        // A reader whose last id was trimmed resumes at the oldest entry after it.
+        let resumed = log.read_after(Some(&ids[0]), 10).await.unwrap();
+        assert_eq!(resumed, all);
+        let resumed = log.read_after(Some(&ids[1]), 1).await.unwrap();
+        assert_eq!(resumed[0].id, ids[2]);
+        assert!(log.read_after(Some(&ids[4]), 10).await.unwrap().is_empty());
+        let ahead = format!("{}-9", now_millis());
+        assert!(log.read_after(Some(&ahead), 10).await.unwrap().is_empty());
+        assert!(log.read_after(Some("not-an-id"), 10).await.is_err());
+    }
+}
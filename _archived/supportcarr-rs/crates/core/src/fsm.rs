@@ -1,6 +1,9 @@
 use std::fmt::{Display, Formatter};
 
+use serde::{Deserialize, Serialize};
+
 use crate::error::{CoreError, CoreResult};
+use crate::geo::haversine_miles;
 use crate::model::RideLocation;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -32,6 +35,18 @@ impl RideStatus {
             RideStatus::RejectedGeofence => "rejected_geofence",
         }
     }
+
+    /// Completed or cancelled in any way; no event moves the ride on.
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            RideStatus::Completed
+                | RideStatus::Cancelled
+                | RideStatus::CancelledRiderNoShow
+                | RideStatus::CancelledSafety
+                | RideStatus::RejectedGeofence
+        )
+    }
 }
 
 impl Display for RideStatus {
@@ -65,7 +80,9 @@ impl TryFrom<&str> for RideStatus {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum RideEvent {
     Accept,
     Depart,
@@ -76,6 +93,23 @@ pub enum RideEvent {
     CancelNoShow,
     CancelSafety,
     RejectGeofence,
+    /// No pilot could be dispatched within the unassigned-ride SLA.
+    CancelNoPilot,
+}
+
+impl RideEvent {
+    pub const ALL: [RideEvent; 10] = [
+        RideEvent::Accept,
+        RideEvent::Depart,
+        RideEvent::Arrive,
+        RideEvent::BeginTransit,
+        RideEvent::Complete,
+        RideEvent::Cancel,
+        RideEvent::CancelNoShow,
+        RideEvent::CancelSafety,
+        RideEvent::RejectGeofence,
+        RideEvent::CancelNoPilot,
+    ];
 }
 
 pub struct RideStatusMachine;
@@ -135,6 +169,14 @@ impl RideStatusMachine {
         }
     }
 
+    /// Events [`Self::apply_event`] accepts from `current`, in [`RideEvent::ALL`] order.
+    pub fn allowed_events(current: RideStatus) -> Vec<RideEvent> {
+        RideEvent::ALL
+            .into_iter()
+            .filter(|event| Self::apply_event(current, *event).is_ok())
+            .collect()
+    }
+
     pub fn apply_event(current: RideStatus, event: RideEvent) -> CoreResult<RideStatus> {
         let target = match (current, event) {
             (RideStatus::Requested, RideEvent::Accept) => RideStatus::Accepted,
@@ -142,6 +184,7 @@ impl RideStatusMachine {
             (RideStatus::Requested, RideEvent::CancelNoShow) => RideStatus::CancelledRiderNoShow,
             (RideStatus::Requested, RideEvent::CancelSafety) => RideStatus::CancelledSafety,
             (RideStatus::Requested, RideEvent::RejectGeofence) => RideStatus::RejectedGeofence,
+            (RideStatus::Requested, RideEvent::CancelNoPilot) => RideStatus::Cancelled,
             (RideStatus::Accepted, RideEvent::Depart) => RideStatus::EnRoute,
             (RideStatus::Accepted, RideEvent::Arrive) => RideStatus::Arrived,
             (RideStatus::Accepted, RideEvent::Cancel) => RideStatus::Cancelled,
@@ -183,17 +226,7 @@ pub fn estimate_distance_miles(pickup: &RideLocation, dropoff: &RideLocation) ->
         return 2.0;
     }
 
-    let to_radians = |deg: f64| deg * (std::f64::consts::PI / 180.0);
-    let r = 3959.0_f64;
-    let lat1 = to_radians(pickup.lat);
-    let lat2 = to_radians(dropoff.lat);
-    let delta_lat = to_radians(dropoff.lat - pickup.lat);
-    let delta_lng = to_radians(dropoff.lng - pickup.lng);
-
-    let a = (delta_lat / 2.0).sin().powi(2)
-        + lat1.cos() * lat2.cos() * (delta_lng / 2.0).sin().powi(2);
-    let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
-    let distance = r * c;
+    let distance = haversine_miles(pickup, dropoff);
 
     distance.max(1.0)
 }
@@ -248,6 +281,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn cancel_no_pilot_only_applies_to_requested_rides() {
+        assert_eq!(
+            RideStatusMachine::apply_event(RideStatus::Requested, RideEvent::CancelNoPilot).unwrap(),
+            RideStatus::Cancelled
+        );
+        assert!(RideStatusMachine::apply_event(RideStatus::Accepted, RideEvent::CancelNoPilot).is_err());
+    }
+
+    #[test]
+    fn allowed_events_follow_apply_event() {
+        assert_eq!(
+            RideStatusMachine::allowed_events(RideStatus::Arrived),
+            vec![
+                RideEvent::BeginTransit,
+                RideEvent::Complete,
+                RideEvent::Cancel,
+                RideEvent::CancelNoShow,
+                RideEvent::CancelSafety,
+            ]
+        );
+        assert!(RideStatusMachine::allowed_events(RideStatus::Completed).is_empty());
+        assert!(RideStatus::Completed.is_terminal() && !RideStatus::InTransit.is_terminal());
+        assert_eq!(
+            serde_json::to_string(&RideEvent::BeginTransit).unwrap(),
+            "\"begin_transit\""
+        );
+    }
+
     #[test]
     fn pilot_distance_matches_js_helper() {
         let pickup = location(34.0522, -118.2437);
@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+
+use crate::model::RideLocation;
+
+const EARTH_RADIUS_MILES: f64 = 3959.0;
+
+/// Raw great-circle distance in miles, without the JS minimum/fallback rules applied by
+/// [`crate::fsm::estimate_distance_miles`].
+pub fn haversine_miles(from: &RideLocation, to: &RideLocation) -> f64 {
+    let to_radians = |deg: f64| deg * (std::f64::consts::PI / 180.0);
+    let lat1 = to_radians(from.lat);
+    let lat2 = to_radians(to.lat);
+    let delta_lat = to_radians(to.lat - from.lat);
+    let delta_lng = to_radians(to.lng - from.lng);
+
+    let a = (delta_lat / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * (delta_lng / 2.0).sin().powi(2);
+    let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+    EARTH_RADIUS_MILES * c
+}
+
+/// Axis-aligned rectangle described by its south-west and north-east corners.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BoundingBox {
+    pub south_west: RideLocation,
+    pub north_east: RideLocation,
+}
+
+impl BoundingBox {
+    pub fn new(south_west: RideLocation, north_east: RideLocation) -> Self {
+        Self {
+            south_west,
+            north_east,
+        }
+    }
+
+    pub fn center(&self) -> RideLocation {
+        RideLocation::new(
+            (self.south_west.lat + self.north_east.lat) / 2.0,
+            (self.south_west.lng + self.north_east.lng) / 2.0,
+        )
+    }
+
+    /// East-west extent measured along the center latitude.
+    pub fn width_miles(&self) -> f64 {
+        let lat = self.center().lat;
+        haversine_miles(
+            &RideLocation::new(lat, self.south_west.lng),
+            &RideLocation::new(lat, self.north_east.lng),
+        )
+    }
+
+    /// East-west extent along the edge nearest the equator, where the box is widest. Redis
+    /// measures a `BYBOX` width at each member's latitude, so a search this wide covers the
+    /// whole box.
+    pub fn widest_width_miles(&self) -> f64 {
+        let lat = if self.south_west.lat <= 0.0 && self.north_east.lat >= 0.0 {
+            0.0
+        } else if self.south_west.lat.abs() < self.north_east.lat.abs() {
+            self.south_west.lat
+        } else {
+            self.north_east.lat
+        };
+        haversine_miles(
+            &RideLocation::new(lat, self.south_west.lng),
+            &RideLocation::new(lat, self.north_east.lng),
+        )
+    }
+
+    /// North-south extent measured along the center longitude.
+    pub fn height_miles(&self) -> f64 {
+        let lng = self.center().lng;
+        haversine_miles(
+            &RideLocation::new(self.south_west.lat, lng),
+            &RideLocation::new(self.north_east.lat, lng),
+        )
+    }
+
+    /// Distance from the center to a corner; the smallest circle covering the box.
+    pub fn circumradius_miles(&self) -> f64 {
+        haversine_miles(&self.center(), &self.north_east)
+    }
+
+    pub fn contains(&self, point: &RideLocation) -> bool {
+        point.lat >= self.south_west.lat
+            && point.lat <= self.north_east.lat
+            && point.lng >= self.south_west.lng
+            && point.lng <= self.north_east.lng
+    }
+}
+
+/// Named dispatch area, such as a neighborhood, described by a closed polygon.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ServiceZone {
+    pub name: String,
+    pub polygon: Vec<RideLocation>,
+}
+
+impl ServiceZone {
+    pub fn new(name: impl Into<String>, polygon: Vec<RideLocation>) -> Self {
+        Self {
+            name: name.into(),
+            polygon,
+        }
+    }
+
+    /// Smallest [`BoundingBox`] containing every vertex, or `None` for an empty polygon.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let first = self.polygon.first()?;
+        let mut south_west = first.clone();
+        let mut north_east = first.clone();
+        for point in &self.polygon[1..] {
+            south_west.lat = south_west.lat.min(point.lat);
+            south_west.lng = south_west.lng.min(point.lng);
+            north_east.lat = north_east.lat.max(point.lat);
+            north_east.lng = north_east.lng.max(point.lng);
+        }
+        Some(BoundingBox::new(south_west, north_east))
+    }
+
+    /// Ray-casting point-in-polygon test on raw coordinates.
+    pub fn contains(&self, point: &RideLocation) -> bool {
+        let vertices = &self.polygon;
+        if vertices.len() < 3 {
+            return false;
+        }
+
+        let mut inside = false;
+        let mut j = vertices.len() - 1;
+        for i in 0..vertices.len() {
+            let (a, b) = (&vertices[i], &vertices[j]);
+            if (a.lat > point.lat) != (b.lat > point.lat)
+                && point.lng < (b.lng - a.lng) * (point.lat - a.lat) / (b.lat - a.lat) + a.lng
+            {
+                inside = !inside;
+            }
+            j = i;
+        }
+        inside
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(lat: f64, lng: f64) -> RideLocation {
+        RideLocation { lat, lng }
+    }
+
+    #[test]
+    fn bounding_box_dimensions() {
+        let bounds = BoundingBox::new(location(34.0, -118.3), location(34.1, -118.2));
+        assert_eq!(bounds.center(), location(34.05, -118.25));
+        assert!((bounds.height_miles() - 6.91).abs() < 0.05);
+        assert!((bounds.width_miles() - 5.72).abs() < 0.05);
+        assert!(bounds.circumradius_miles() > bounds.height_miles() / 2.0);
+        assert!(bounds.contains(&location(34.05, -118.25)));
+        assert!(!bounds.contains(&location(34.2, -118.25)));
+    }
+
+    #[test]
+    fn service_zone_polygon_contains() {
+        // L-shaped zone: the north-east quadrant of the bounding box is excluded.
+        let zone = ServiceZone::new(
+            "downtown",
+            vec![
+                location(0.0, 0.0),
+                location(0.0, 2.0),
+                location(1.0, 2.0),
+                location(1.0, 1.0),
+                location(2.0, 1.0),
+                location(2.0, 0.0),
+            ],
+        );
+
+        let bounds = zone.bounding_box().expect("bounding box");
+        assert_eq!(bounds.south_west, location(0.0, 0.0));
+        assert_eq!(bounds.north_east, location(2.0, 2.0));
+
+        assert!(zone.contains(&location(0.5, 0.5)));
+        assert!(zone.contains(&location(0.5, 1.5)));
+        assert!(zone.contains(&location(1.5, 0.5)));
+        assert!(!zone.contains(&location(1.5, 1.5)));
+        assert!(!zone.contains(&location(3.0, 3.0)));
+        assert!(ServiceZone::new("empty", Vec::new()).bounding_box().is_none());
+    }
+}
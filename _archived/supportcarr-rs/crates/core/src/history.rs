@@ -0,0 +1,198 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::error::CoreResult;
+use crate::geo::haversine_miles;
+use crate::model::RideLocation;
+
+/// One recorded pilot position.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Breadcrumb {
+    pub pilot_id: String,
+    pub location: RideLocation,
+    pub recorded_at_ms: u64,
+}
+
+/// Time-ordered pilot positions, kept per pilot and per ride so routes can be replayed.
+#[async_trait]
+pub trait LocationHistory: Send + Sync {
+    /// Append a position to the pilot's trail and, when `ride_id` is set, to that ride's.
+    async fn record(
+        &self,
+        pilot_id: &str,
+        ride_id: Option<&str>,
+        location: &RideLocation,
+        recorded_at_ms: u64,
+    ) -> CoreResult<()>;
+
+    /// The pilot's positions recorded between `since_ms` and `until_ms`, inclusive.
+    async fn pilot_trail(
+        &self,
+        pilot_id: &str,
+        since_ms: u64,
+        until_ms: u64,
+    ) -> CoreResult<Vec<Breadcrumb>>;
+
+    /// Every position recorded against a ride, oldest first.
+    async fn ride_trail(&self, ride_id: &str) -> CoreResult<Vec<Breadcrumb>>;
+}
+
+/// Total distance along a trail, summing each leg.
+pub fn trail_distance_miles(trail: &[Breadcrumb]) -> f64 {
+    trail
+        .windows(2)
+        .map(|leg| haversine_miles(&leg[0].location, &leg[1].location))
+        .sum()
+}
+
+/// GeoJSON `LineString` geometry; coordinates are `[lng, lat]` per the spec.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LineString {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub coordinates: Vec<[f64; 2]>,
+}
+
+impl LineString {
+    pub fn from_trail(trail: &[Breadcrumb]) -> Self {
+        Self {
+            kind: "LineString".to_string(),
+            coordinates: trail
+                .iter()
+                .map(|crumb| [crumb.location.lng, crumb.location.lat])
+                .collect(),
+        }
+    }
+}
+
+/// Keeps the newest `max_points` breadcrumbs per trail.
+pub struct InMemoryLocationHistory {
+    max_points: usize,
+    pilots: Mutex<HashMap<String, VecDeque<Breadcrumb>>>,
+    rides: Mutex<HashMap<String, VecDeque<Breadcrumb>>>,
+}
+
+impl InMemoryLocationHistory {
+    pub fn new(max_points: usize) -> Self {
+        Self {
+            max_points,
+            pilots: Mutex::default(),
+            rides: Mutex::default(),
+        }
+    }
+}
+
+impl Default for InMemoryLocationHistory {
+    fn default() -> Self {
+        Self::new(10_000)
+    }
+}
+
+fn push_trimmed(trail: &mut VecDeque<Breadcrumb>, crumb: Breadcrumb, max_points: usize) {
+    trail.push_back(crumb);
+    while trail.len() > max_points {
+        trail.pop_front();
+    }
+}
+
+#[async_trait]
+impl LocationHistory for InMemoryLocationHistory {
+    async fn record(
+        &self,
+        pilot_id: &str,
+        ride_id: Option<&str>,
+        location: &RideLocation,
+        recorded_at_ms: u64,
+    ) -> CoreResult<()> {
+        let crumb = Breadcrumb {
+            pilot_id: pilot_id.to_string(),
+            location: location.clone(),
+            recorded_at_ms,
+        };
+        if let Some(ride_id) = ride_id {
+            let mut rides = self.rides.lock().unwrap();
+            push_trimmed(
+                rides.entry(ride_id.to_string()).or_default(),
+                crumb.clone(),
+                self.max_points,
+            );
+        }
+        let mut pilots = self.pilots.lock().unwrap();
+        push_trimmed(
+            pilots.entry(pilot_id.to_string()).or_default(),
+            crumb,
+            self.max_points,
+        );
+        Ok(())
+    }
+
+    async fn pilot_trail(
+        &self,
+        pilot_id: &str,
+        since_ms: u64,
+        until_ms: u64,
+    ) -> CoreResult<Vec<Breadcrumb>> {
+        Ok(self
+            .pilots
+            .lock()
+            .unwrap()
+            .get(pilot_id)
+            .map(|trail| {
+                trail
+                    .iter()
+                    .filter(|crumb| (since_ms..=until_ms).contains(&crumb.recorded_at_ms))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn ride_trail(&self, ride_id: &str) -> CoreResult<Vec<Breadcrumb>> {
+        Ok(self
+            .rides
+            .lock()
+            .unwrap()
+            .get(ride_id)
+            .map(|trail| trail.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn records_trails_and_replays_as_line_string() {
+        let history = InMemoryLocationHistory::new(3);
+        let points = [(34.00, -118.00), (34.01, -118.00), (34.02, -118.00), (34.03, -118.00)];
+        for (i, (lat, lng)) in points.iter().enumerate() {
+            let ride = if i == 0 { None } else { Some("ride-1") };
+            history
+                .record("pilot-1", ride, &RideLocation::new(*lat, *lng), 1_000 * i as u64)
+                .await
+                .unwrap();
+        }
+
+        // Trimmed to the newest three points.
+        let pilot = history.pilot_trail("pilot-1", 0, u64::MAX).await.unwrap();
+        assert_eq!(pilot.len(), 3);
+        assert_eq!(pilot[0].recorded_at_ms, 1_000);
+        let window = history.pilot_trail("pilot-1", 1_500, 2_500).await.unwrap();
+        assert_eq!(window.len(), 1);
+
+        let ride = history.ride_trail("ride-1").await.unwrap();
+        assert_eq!(ride.len(), 3);
+        assert!((trail_distance_miles(&ride) - 2.0 * 0.691).abs() < 0.01);
+
+        let line = LineString::from_trail(&ride);
+        let json = serde_json::to_value(&line).unwrap();
+        assert_eq!(json["type"], "LineString");
+        assert_eq!(json["coordinates"][0], serde_json::json!([-118.0, 34.01]));
+        assert!(history.ride_trail("unknown").await.unwrap().is_empty());
+    }
+}
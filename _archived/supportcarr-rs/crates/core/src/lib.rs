@@ -2,8 +2,23 @@ pub mod model;
 pub mod fsm;
 pub mod dispatch;
 pub mod error;
+pub mod events;
+pub mod geo;
+pub mod history;
+pub mod matching;
+pub mod queue;
+pub mod search;
 
-pub use dispatch::{DispatchEngine, DispatchEngineConfig, DispatchEvent};
+pub use dispatch::{
+    DispatchEngine, DispatchEngineConfig, DispatchEvent, DispatchOffer, InMemoryDispatchEngine,
+    PilotProfile, PilotScorer, WeightedPilotScorer,
+};
 pub use error::CoreError;
+pub use events::{DispatchDecision, DispatchEventLog, InMemoryDispatchEventLog};
+pub use geo::{BoundingBox, ServiceZone};
+pub use history::{Breadcrumb, InMemoryLocationHistory, LineString, LocationHistory};
+pub use matching::{Assignment, MatchConfig};
+pub use queue::{InMemoryPendingRideQueue, PendingRide, PendingRideQueue};
+pub use search::{RingSearch, SearchRing};
 pub use fsm::{RideEvent, RideStatus, RideStatusMachine};
 pub use model::{Ride, RideLocation, RideSummary};
@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+
+use crate::dispatch::DispatchCandidate;
+use crate::geo::haversine_miles;
+use crate::model::Ride;
+
+// Cost given to pairs that exceed the ETA cap. Large enough that the solver always prefers
+// matching more rides over shaving minutes off feasible ones.
+const INFEASIBLE: f64 = 1.0e9;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchConfig {
+    /// Assumed pilot travel speed used to turn distance into ETA.
+    pub average_speed_mph: f64,
+    /// Pairs with a longer pickup ETA are never matched.
+    pub max_eta_minutes: f64,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        Self {
+            average_speed_mph: 20.0,
+            max_eta_minutes: 45.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Assignment {
+    pub ride_id: String,
+    pub pilot_id: String,
+    pub eta_minutes: f64,
+}
+
+/// Pair pending rides with pilots so that the total pickup ETA across the batch is
+/// minimal. Pilots without a known location are skipped.
+pub fn match_batch(
+    rides: &[Ride],
+    pilots: &[DispatchCandidate],
+    config: &MatchConfig,
+) -> Vec<Assignment> {
+    let pilots: Vec<&DispatchCandidate> = pilots.iter().filter(|p| p.location.is_some()).collect();
+    if rides.is_empty() || pilots.is_empty() {
+        return Vec::new();
+    }
+
+    let costs: Vec<Vec<f64>> = rides
+        .iter()
+        .map(|ride| pilots.iter().map(|pilot| pair_cost(ride, pilot, config)).collect())
+        .collect();
+
+    let pairs = if rides.len() <= pilots.len() {
+        hungarian(&costs)
+    } else {
+        let transposed: Vec<Vec<f64>> = (0..pilots.len())
+            .map(|p| costs.iter().map(|row| row[p]).collect())
+            .collect();
+        hungarian(&transposed)
+            .into_iter()
+            .map(|(pilot, ride)| (ride, pilot))
+            .collect()
+    };
+
+    let mut assignments: Vec<Assignment> = pairs
+        .into_iter()
+        .filter(|&(ride, pilot)| costs[ride][pilot] < INFEASIBLE)
+        .map(|(ride, pilot)| Assignment {
+            ride_id: rides[ride].id.to_string(),
+            pilot_id: pilots[pilot].pilot_id.clone(),
+            eta_minutes: costs[ride][pilot],
+        })
+        .collect();
+    assignments.sort_by(|a, b| a.ride_id.cmp(&b.ride_id));
+    assignments
+}
+
+/// One-ride-at-a-time matching in input order, each ride taking the closest free pilot.
+/// Kept as the baseline the batch matcher is measured against.
+pub fn match_greedy(
+    rides: &[Ride],
+    pilots: &[DispatchCandidate],
+    config: &MatchConfig,
+) -> Vec<Assignment> {
+    let mut taken = vec![false; pilots.len()];
+    let mut assignments = Vec::new();
+
+    for ride in rides {
+        let best = pilots
+            .iter()
+            .enumerate()
+            .filter(|(index, pilot)| !taken[*index] && pilot.location.is_some())
+            .map(|(index, pilot)| (index, pair_cost(ride, pilot, config)))
+            .filter(|(_, cost)| *cost < INFEASIBLE)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        if let Some((index, eta_minutes)) = best {
+            taken[index] = true;
+            assignments.push(Assignment {
+                ride_id: ride.id.to_string(),
+                pilot_id: pilots[index].pilot_id.clone(),
+                eta_minutes,
+            });
+        }
+    }
+
+    assignments.sort_by(|a, b| a.ride_id.cmp(&b.ride_id));
+    assignments
+}
+
+/// Pickup ETA in minutes for a pilot to reach a ride.
+pub fn eta_minutes(ride: &Ride, pilot: &DispatchCandidate, config: &MatchConfig) -> Option<f64> {
+    let location = pilot.location.as_ref()?;
+    Some(haversine_miles(location, &ride.pickup) / config.average_speed_mph * 60.0)
+}
+
+fn pair_cost(ride: &Ride, pilot: &DispatchCandidate, config: &MatchConfig) -> f64 {
+    match eta_minutes(ride, pilot, config) {
+        Some(eta) if eta <= config.max_eta_minutes => eta,
+        _ => INFEASIBLE,
+    }
+}
+
+/// Minimum-cost assignment (Hungarian algorithm with potentials, O(n²m)) for an `n x m`
+/// matrix with `n <= m`. Returns `(row, column)` pairs, one per row.
+fn hungarian(costs: &[Vec<f64>]) -> Vec<(usize, usize)> {
+    let n = costs.len();
+    let m = costs[0].len();
+    // 1-indexed potentials and matching; column 0 is a virtual start.
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; m + 1];
+    let mut row_of = vec![0usize; m + 1];
+    let mut way = vec![0usize; m + 1];
+
+    for row in 1..=n {
+        row_of[0] = row;
+        let mut col = 0;
+        let mut min_to = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+
+        loop {
+            used[col] = true;
+            let current_row = row_of[col];
+            let mut delta = f64::INFINITY;
+            let mut next_col = 0;
+            for j in 1..=m {
+                if used[j] {
+                    continue;
+                }
+                let reduced = costs[current_row - 1][j - 1] - u[current_row] - v[j];
+                if reduced < min_to[j] {
+                    min_to[j] = reduced;
+                    way[j] = col;
+                }
+                if min_to[j] < delta {
+                    delta = min_to[j];
+                    next_col = j;
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    u[row_of[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min_to[j] -= delta;
+                }
+            }
+            col = next_col;
+            if row_of[col] == 0 {
+                break;
+            }
+        }
+
+        loop {
+            let previous = way[col];
+            row_of[col] = row_of[previous];
+            col = previous;
+            if col == 0 {
+                break;
+            }
+        }
+    }
+
+    (1..=m)
+        .filter(|&col| row_of[col] != 0)
+        .map(|col| (row_of[col] - 1, col - 1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::RideLocation;
+
+    fn ride(lat: f64, lng: f64) -> Ride {
+        Ride::new(
+            "rider".to_string(),
+            RideLocation::new(lat, lng),
+            RideLocation::new(lat + 0.01, lng),
+            None,
+            None,
+            None,
+            1.0,
+            5000,
+        )
+    }
+
+    fn pilot(id: &str, lat: f64, lng: f64) -> DispatchCandidate {
+        DispatchCandidate {
+            pilot_id: id.to_string(),
+            distance_meters: None,
+            location: Some(RideLocation::new(lat, lng)),
+        }
+    }
+
+    fn total(assignments: &[Assignment]) -> f64 {
+        assignments.iter().map(|a| a.eta_minutes).sum()
+    }
+
+    /// Small deterministic LCG so the corpus is stable without a rand dependency.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+    }
+
+    #[test]
+    fn batch_beats_greedy_on_crossed_pairs() {
+        // Greedy hands the first ride the pilot the second ride needs, leaving the second
+        // ride with a long trip across town.
+        let rides = vec![ride(34.00, -118.00), ride(34.00, -118.02)];
+        let pilots = vec![pilot("a", 34.00, -118.01), pilot("b", 34.00, -117.97)];
+        let config = MatchConfig::default();
+
+        let greedy = match_greedy(&rides, &pilots, &config);
+        let batch = match_batch(&rides, &pilots, &config);
+        assert_eq!(greedy.len(), 2);
+        assert_eq!(batch.len(), 2);
+        assert!(total(&batch) < total(&greedy));
+
+        let second = batch
+            .iter()
+            .find(|a| a.ride_id == rides[1].id.to_string())
+            .expect("second ride matched");
+        assert_eq!(second.pilot_id, "a");
+    }
+
+    #[test]
+    fn infeasible_pairs_are_left_unmatched() {
+        let rides = vec![ride(34.0, -118.0), ride(40.0, -74.0)];
+        let pilots = vec![
+            pilot("near", 34.0, -118.01),
+            pilot("far", 10.0, 10.0),
+            DispatchCandidate {
+                pilot_id: "unknown".to_string(),
+                distance_meters: Some(10.0),
+                location: None,
+            },
+        ];
+
+        let batch = match_batch(&rides, &pilots, &MatchConfig::default());
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].pilot_id, "near");
+    }
+
+    #[test]
+    fn batch_never_worse_than_greedy_on_corpus() {
+        let config = MatchConfig::default();
+        let mut rng = Lcg(42);
+        let mut strictly_better = 0;
+
+        for case in 0..200 {
+            let ride_count = 1 + case % 9;
+            let pilot_count = 1 + (case * 7) % 11;
+            let rides: Vec<Ride> = (0..ride_count)
+                .map(|_| ride(34.0 + rng.next() * 0.2, -118.3 + rng.next() * 0.2))
+                .collect();
+            let pilots: Vec<DispatchCandidate> = (0..pilot_count)
+                .map(|i| pilot(&format!("p{i}"), 34.0 + rng.next() * 0.2, -118.3 + rng.next() * 0.2))
+                .collect();
+
+            let greedy = match_greedy(&rides, &pilots, &config);
+            let batch = match_batch(&rides, &pilots, &config);
+
+            assert_eq!(batch.len(), greedy.len(), "case {case}");
+            assert!(total(&batch) <= total(&greedy) + 1e-9, "case {case}");
+            if total(&batch) + 1e-9 < total(&greedy) {
+                strictly_better += 1;
+            }
+
+            let mut pilots_used: Vec<&str> = batch.iter().map(|a| a.pilot_id.as_str()).collect();
+            pilots_used.sort();
+            pilots_used.dedup();
+            assert_eq!(pilots_used.len(), batch.len(), "case {case}");
+        }
+
+        assert!(strictly_better > 0);
+    }
+}
@@ -1,8 +1,11 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::dispatch::now_millis;
+
 /// Represents a geospatial coordinate.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct RideLocation {
     pub lat: f64,
     pub lng: f64,
@@ -16,6 +19,7 @@ impl RideLocation {
 
 /// Minimal ride representation used by the dispatch and API layers.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Ride {
     pub id: Uuid,
     pub rider_id: String,
@@ -28,9 +32,13 @@ pub struct Ride {
     pub distance_miles: f64,
     pub price_cents: i64,
     pub driver_id: Option<String>,
+    /// Unix milliseconds; rides serialized before this field existed read back as 0.
+    #[serde(default)]
+    pub created_at_ms: u64,
 }
 
 impl Ride {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         rider_id: String,
         pickup: RideLocation,
@@ -53,6 +61,7 @@ impl Ride {
             distance_miles,
             price_cents,
             driver_id: None,
+            created_at_ms: now_millis(),
         }
     }
 }
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::error::CoreResult;
+
+/// A ride still waiting for a pilot, with the time it was first queued.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PendingRide {
+    pub ride_id: String,
+    pub requested_at_ms: u64,
+}
+
+/// Rides without a pilot, oldest first, so a background worker can keep retrying them.
+#[async_trait]
+pub trait PendingRideQueue: Send + Sync {
+    /// Queue a ride. Re-queuing keeps the original request time so SLA clocks never reset.
+    async fn enqueue(&self, ride_id: &str, requested_at_ms: u64) -> CoreResult<()>;
+
+    async fn remove(&self, ride_id: &str) -> CoreResult<()>;
+
+    /// Up to `limit` pending rides, oldest request first.
+    async fn pending(&self, limit: usize) -> CoreResult<Vec<PendingRide>>;
+
+    /// Stop retrying a ride and set it aside for manual follow-up.
+    async fn flag(&self, ride_id: &str) -> CoreResult<()>;
+
+    /// Flagged rides, oldest request first.
+    async fn flagged(&self, limit: usize) -> CoreResult<Vec<PendingRide>>;
+
+    async fn is_flagged(&self, ride_id: &str) -> CoreResult<bool>;
+}
+
+#[derive(Default)]
+pub struct InMemoryPendingRideQueue {
+    pending: Mutex<HashMap<String, u64>>,
+    flagged: Mutex<HashMap<String, u64>>,
+}
+
+fn oldest_first(rides: &HashMap<String, u64>, limit: usize) -> Vec<PendingRide> {
+    let mut list: Vec<PendingRide> = rides
+        .iter()
+        .map(|(ride_id, requested_at_ms)| PendingRide {
+            ride_id: ride_id.clone(),
+            requested_at_ms: *requested_at_ms,
+        })
+        .collect();
+    list.sort_by(|a, b| {
+        a.requested_at_ms
+            .cmp(&b.requested_at_ms)
+            .then_with(|| a.ride_id.cmp(&b.ride_id))
+    });
+    list.truncate(limit);
+    list
+}
+
+#[async_trait]
+impl PendingRideQueue for InMemoryPendingRideQueue {
+    async fn enqueue(&self, ride_id: &str, requested_at_ms: u64) -> CoreResult<()> {
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(ride_id.to_string())
+            .or_insert(requested_at_ms);
+        Ok(())
+    }
+
+    async fn remove(&self, ride_id: &str) -> CoreResult<()> {
+        self.pending.lock().unwrap().remove(ride_id);
+        Ok(())
+    }
+
+    async fn pending(&self, limit: usize) -> CoreResult<Vec<PendingRide>> {
+        Ok(oldest_first(&self.pending.lock().unwrap(), limit))
+    }
+
+    async fn flag(&self, ride_id: &str) -> CoreResult<()> {
+        let requested_at_ms = self.pending.lock().unwrap().remove(ride_id);
+        if let Some(requested_at_ms) = requested_at_ms {
+            self.flagged
+                .lock()
+                .unwrap()
+                .insert(ride_id.to_string(), requested_at_ms);
+        }
+        Ok(())
+    }
+
+    async fn flagged(&self, limit: usize) -> CoreResult<Vec<PendingRide>> {
+        Ok(oldest_first(&self.flagged.lock().unwrap(), limit))
+    }
+
+    async fn is_flagged(&self, ride_id: &str) -> CoreResult<bool> {
+        Ok(self.flagged.lock().unwrap().contains_key(ride_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn in_memory_queue_orders_and_flags() {
+        let queue = InMemoryPendingRideQueue::default();
+        queue.enqueue("late", 200).await.unwrap();
+        queue.enqueue("early", 100).await.unwrap();
+        queue.enqueue("early", 300).await.unwrap();
+
+        let pending = queue.pending(10).await.unwrap();
+        let ids: Vec<&str> = pending.iter().map(|p| p.ride_id.as_str()).collect();
+        assert_eq!(ids, vec!["early", "late"]);
+        assert_eq!(pending[0].requested_at_ms, 100);
+
+        queue.flag("early").await.unwrap();
+        assert_eq!(queue.pending(10).await.unwrap().len(), 1);
+        assert!(queue.is_flagged("early").await.unwrap());
+        assert!(!queue.is_flagged("late").await.unwrap());
+        assert_eq!(queue.flagged(10).await.unwrap()[0].ride_id, "early");
+
+        queue.remove("late").await.unwrap();
+        assert!(queue.pending(10).await.unwrap().is_empty());
+    }
+}
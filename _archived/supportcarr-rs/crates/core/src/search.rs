@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+
+use crate::dispatch::{DispatchCandidate, DispatchEngine};
+use crate::error::CoreResult;
+use crate::model::RideLocation;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SearchRing {
+    pub radius_miles: f64,
+    /// Candidates requested from the engine for this ring.
+    pub limit: usize,
+}
+
+impl SearchRing {
+    pub fn new(radius_miles: f64, limit: usize) -> Self {
+        Self {
+            radius_miles,
+            limit,
+        }
+    }
+}
+
+/// Expanding-radius pilot search: query increasingly large rings around pickup and stop
+/// at the first one that yields `min_candidates` eligible pilots.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RingSearch {
+    /// Rings in ascending radius order.
+    pub rings: Vec<SearchRing>,
+    pub min_candidates: usize,
+}
+
+impl Default for RingSearch {
+    fn default() -> Self {
+        Self {
+            rings: vec![
+                SearchRing::new(2.0, 3),
+                SearchRing::new(5.0, 5),
+                SearchRing::new(10.0, 8),
+                SearchRing::new(15.0, 10),
+            ],
+            min_candidates: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RingSearchOutcome {
+    /// Eligible candidates from the last ring searched, nearest first.
+    pub candidates: Vec<DispatchCandidate>,
+    /// Radius of the last ring searched.
+    pub radius_miles: f64,
+    pub rings_searched: usize,
+}
+
+impl RingSearch {
+    pub fn max_radius_miles(&self) -> f64 {
+        self.rings.last().map_or(0.0, |ring| ring.radius_miles)
+    }
+
+    /// Search outward from `location`, skipping pilots listed in `exclude` (for example
+    /// those who already declined the ride).
+    pub async fn search(
+        &self,
+        engine: &dyn DispatchEngine,
+        location: &RideLocation,
+        exclude: &[String],
+    ) -> CoreResult<RingSearchOutcome> {
+        let mut outcome = RingSearchOutcome {
+            candidates: Vec::new(),
+            radius_miles: 0.0,
+            rings_searched: 0,
+        };
+
+        for ring in &self.rings {
+            let found = engine
+                .find_nearby_pilots(location, ring.radius_miles, ring.limit + exclude.len())
+                .await?;
+            outcome.candidates = found
+                .into_iter()
+                .filter(|candidate| !exclude.contains(&candidate.pilot_id))
+                .take(ring.limit)
+                .collect();
+            outcome.radius_miles = ring.radius_miles;
+            outcome.rings_searched += 1;
+
+            if outcome.candidates.len() >= self.min_candidates {
+                break;
+            }
+        }
+
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::dispatch::{DispatchOffer, InMemoryDispatchEngine};
+    use crate::geo::{BoundingBox, ServiceZone};
+
+    /// The in-memory engine, recording the radius of each nearby-pilot query.
+    #[derive(Default)]
+    struct RecordingRadii {
+        engine: InMemoryDispatchEngine,
+        radii: Mutex<Vec<f64>>,
+    }
+
+    #[async_trait]
+    impl DispatchEngine for RecordingRadii {
+        async fn store_pilot_location_at(
+            &self,
+            pilot_id: &str,
+            location: &RideLocation,
+            recorded_at_ms: u64,
+        ) -> CoreResult<bool> {
+            self.engine
+                .store_pilot_location_at(pilot_id, location, recorded_at_ms)
+                .await
+        }
+
+        async fn set_pilot_available(&self, pilot_id: &str, available: bool) -> CoreResult<()> {
+            self.engine.set_pilot_available(pilot_id, available).await
+        }
+
+        async fn find_nearby_pilots(
+            &self,
+            location: &RideLocation,
+            radius_miles: f64,
+            limit: usize,
+        ) -> CoreResult<Vec<DispatchCandidate>> {
+            self.radii.lock().unwrap().push(radius_miles);
+            self.engine
+                .find_nearby_pilots(location, radius_miles, limit)
+                .await
+        }
+
+        async fn find_pilots_in_box(
+            &self,
+            bounds: &BoundingBox,
+            limit: usize,
+        ) -> CoreResult<Vec<DispatchCandidate>> {
+            self.engine.find_pilots_in_box(bounds, limit).await
+        }
+
+        async fn find_pilots_in_zone(
+            &self,
+            zone: &ServiceZone,
+            limit: usize,
+        ) -> CoreResult<Vec<DispatchCandidate>> {
+            self.engine.find_pilots_in_zone(zone, limit).await
+        }
+
+        async fn mark_assigned(&self, pilot_id: &str, ride_id: &str) -> CoreResult<()> {
+            self.engine.mark_assigned(pilot_id, ride_id).await
+        }
+
+        async fn release_pilot(&self, pilot_id: &str) -> CoreResult<()> {
+            self.engine.release_pilot(pilot_id).await
+        }
+
+        async fn current_assignment(&self, pilot_id: &str) -> CoreResult<Option<String>> {
+            self.engine.current_assignment(pilot_id).await
+        }
+
+        async fn create_offer(
+            &self,
+            ride_id: &str,
+            pilot_id: &str,
+            ttl: Duration,
+        ) -> CoreResult<Option<DispatchOffer>> {
+            self.engine.create_offer(ride_id, pilot_id, ttl).await
+        }
+
+        async fn current_offer(&self, ride_id: &str) -> CoreResult<Option<DispatchOffer>> {
+            self.engine.current_offer(ride_id).await
+        }
+
+        async fn take_offer(
+            &self,
+            ride_id: &str,
+            pilot_id: &str,
+        ) -> CoreResult<Option<DispatchOffer>> {
+            self.engine.take_offer(ride_id, pilot_id).await
+        }
+
+        async fn offered_pilots(&self, ride_id: &str) -> CoreResult<Vec<String>> {
+            self.engine.offered_pilots(ride_id).await
+        }
+
+        async fn health_check(&self) -> CoreResult<()> {
+            self.engine.health_check().await
+        }
+    }
+
+    async fn engine(pilots: &[(&str, f64)]) -> RecordingRadii {
+        let engine = RecordingRadii::default();
+        // Pilots due north of the origin, `miles` away.
+        for (id, miles) in pilots {
+            let location = RideLocation::new(34.0 + miles / 69.09, -118.0);
+            engine
+                .store_pilot_location(id, &location)
+                .await
+                .expect("store pilot location");
+        }
+        engine
+    }
+
+    #[tokio::test]
+    async fn stops_at_first_ring_with_enough_candidates() {
+        let engine = engine(&[("near", 1.0), ("mid", 4.0), ("far", 14.0)]).await;
+        let origin = RideLocation::new(34.0, -118.0);
+
+        let outcome = RingSearch::default()
+            .search(&engine, &origin, &[])
+            .await
+            .expect("search");
+        assert_eq!(outcome.rings_searched, 1);
+        assert_eq!(outcome.radius_miles, 2.0);
+        assert_eq!(outcome.candidates[0].pilot_id, "near");
+        assert_eq!(*engine.radii.lock().unwrap(), vec![2.0]);
+    }
+
+    #[tokio::test]
+    async fn expands_past_excluded_and_missing_pilots() {
+        let engine = engine(&[("near", 1.0), ("far", 14.0)]).await;
+        let origin = RideLocation::new(34.0, -118.0);
+
+        let outcome = RingSearch::default()
+            .search(&engine, &origin, &["near".to_string()])
+            .await
+            .expect("search");
+        assert_eq!(outcome.rings_searched, 4);
+        assert_eq!(outcome.radius_miles, 15.0);
+        assert_eq!(outcome.candidates.len(), 1);
+        assert_eq!(outcome.candidates[0].pilot_id, "far");
+
+        let empty = RingSearch::default()
+            .search(&self::engine(&[]).await, &origin, &[])
+            .await
+            .expect("search");
+        assert!(empty.candidates.is_empty());
+        assert_eq!(empty.rings_searched, 4);
+    }
+
+    #[tokio::test]
+    async fn min_candidates_widens_search() {
+        let engine = engine(&[("near", 1.0), ("mid", 4.0), ("far", 9.0)]).await;
+        let origin = RideLocation::new(34.0, -118.0);
+        let search = RingSearch {
+            min_candidates: 2,
+            ..RingSearch::default()
+        };
+
+        let outcome = search.search(&engine, &origin, &[]).await.expect("search");
+        assert_eq!(outcome.radius_miles, 5.0);
+        let ids: Vec<&str> = outcome.candidates.iter().map(|c| c.pilot_id.as_str()).collect();
+        assert_eq!(ids, vec!["near", "mid"]);
+    }
+}
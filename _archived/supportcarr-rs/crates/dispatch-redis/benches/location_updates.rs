@@ -0,0 +1,175 @@
+//! Location-update throughput against a live Redis.
+//!
+//! Runs the same engine operations with the old connection-per-operation behavior and with
+//! the shared connection manager, sequentially, concurrently, and batched. Each case writes
+//! under its own key prefix, and every key is deleted afterwards. Skipped unless `REDIS_URL`
+//! is set:
+//!
+//! ```bash
+//! REDIS_URL=redis://127.0.0.1/ cargo bench -p supportcarr-dispatch-redis
+//! ```
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use redis::AsyncCommands;
+use supportcarr_core::dispatch::{DispatchEngine, DispatchEngineConfig};
+use supportcarr_core::model::RideLocation;
+use supportcarr_dispatch_redis::RedisDispatchEngine;
+
+const UPDATES: usize = 2_000;
+const BATCH: usize = 100;
+
+fn location(i: usize) -> RideLocation {
+    RideLocation::new(34.0 + (i % 100) as f64 * 0.001, -118.0 - (i % 97) as f64 * 0.001)
+}
+
+fn updates() -> Vec<(String, RideLocation)> {
+    (0..UPDATES)
+        .map(|i| (format!("pilot-{i}"), location(i)))
+        .collect()
+}
+
+fn report(label: &str, elapsed: Duration) {
+    println!(
+        "{label:<36} {UPDATES} updates in {:>8.1} ms ({:>9.0} updates/s)",
+        elapsed.as_secs_f64() * 1000.0,
+        UPDATES as f64 / elapsed.as_secs_f64()
+    );
+}
+
+/// Engine writing under `prefix`. A new engine opens its own connection on first use.
+fn engine(client: &redis::Client, prefix: &str) -> RedisDispatchEngine {
+    RedisDispatchEngine::new(
+        client.clone(),
+        DispatchEngineConfig {
+            key_prefix: prefix.to_string(),
+            ..DispatchEngineConfig::default()
+        },
+    )
+}
+
+async fn connection_per_update(client: &redis::Client, prefix: &str) -> Duration {
+    let start = Instant::now();
+    for (pilot_id, at) in updates() {
+        engine(client, prefix)
+            .store_pilot_location(&pilot_id, &at)
+            .await
+            .expect("store location");
+    }
+    start.elapsed()
+}
+
+async fn shared_sequential(engine: &RedisDispatchEngine) -> Duration {
+    let start = Instant::now();
+    for (pilot_id, at) in updates() {
+        engine
+            .store_pilot_location(&pilot_id, &at)
+            .await
+            .expect("store location");
+    }
+    start.elapsed()
+}
+
+async fn shared_concurrent(engine: &RedisDispatchEngine) -> Duration {
+    let start = Instant::now();
+    let tasks: Vec<_> = updates()
+        .into_iter()
+        .map(|(pilot_id, at)| {
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                engine
+                    .store_pilot_location(&pilot_id, &at)
+                    .await
+                    .expect("store location");
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.expect("join");
+    }
+    start.elapsed()
+}
+
+async fn connection_per_batch(client: &redis::Client, prefix: &str) -> Duration {
+    let updates = updates();
+    let start = Instant::now();
+    for chunk in updates.chunks(BATCH) {
+        engine(client, prefix)
+            .store_pilot_locations(chunk)
+            .await
+            .expect("store locations");
+    }
+    start.elapsed()
+}
+
+async fn shared_batched(engine: &RedisDispatchEngine) -> Duration {
+    let updates = updates();
+    let start = Instant::now();
+    for chunk in updates.chunks(BATCH) {
+        engine
+            .store_pilot_locations(chunk)
+            .await
+            .expect("store locations");
+    }
+    start.elapsed()
+}
+
+/// Delete every key under `prefix`.
+async fn clean_up(client: &redis::Client, prefix: &str) {
+    let mut conn = client
+        .get_multiplexed_async_connection()
+        .await
+        .expect("connect");
+    let mut keys: Vec<String> = Vec::new();
+    {
+        let mut found = conn
+            .scan_match::<_, String>(format!("{prefix}:*"))
+            .await
+            .expect("scan");
+        while let Some(key) = found.next_item().await {
+            keys.push(key);
+        }
+    }
+    for chunk in keys.chunks(BATCH) {
+        let _: i64 = conn.del(chunk).await.expect("delete");
+    }
+}
+
+fn main() {
+    let Some(url) = std::env::var("REDIS_URL").ok() else {
+        eprintln!("REDIS_URL not set; skipping location update benchmark");
+        return;
+    };
+    let client = redis::Client::open(url).expect("redis url");
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock after 1970")
+        .as_millis();
+    let prefix = format!("supportcarr:bench:{}:{started}", std::process::id());
+    let case = |name: &str| format!("{prefix}:{name}");
+
+    let runtime = tokio::runtime::Runtime::new().expect("runtime");
+    runtime.block_on(async {
+        report(
+            "before: connection per update",
+            connection_per_update(&client, &case("per-update")).await,
+        );
+        report(
+            "after: shared, sequential",
+            shared_sequential(&engine(&client, &case("sequential"))).await,
+        );
+        report(
+            "after: shared, concurrent",
+            shared_concurrent(&engine(&client, &case("concurrent"))).await,
+        );
+        report(
+            "before: connection per batch of 100",
+            connection_per_batch(&client, &case("per-batch")).await,
+        );
+        report(
+            "after: shared, batches of 100",
+            shared_batched(&engine(&client, &case("batched"))).await,
+        );
+        clean_up(&client, &prefix).await;
+    });
+}
@@ -0,0 +1,274 @@
+use std::sync::Arc;
+
+use redis::aio::{ConnectionLike, ConnectionManager};
+use redis::cluster::ClusterClientBuilder;
+use redis::cluster_async::ClusterConnection;
+use redis::sentinel::{Sentinel, SentinelNodeConnectionInfo};
+use redis::{Cmd, ErrorKind, Pipeline, RedisFuture, RedisResult, Value};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::RedisConnectionConfig;
+
+/// Where the dispatch engine's data lives.
+#[derive(Clone)]
+pub enum RedisBackend {
+    /// A single Redis server.
+    Standalone(redis::Client),
+    /// A Redis Cluster reached through any of its seed nodes. Keys are hash-tagged so
+    /// commands touching several keys stay within one slot.
+    Cluster { nodes: Vec<String> },
+    /// A primary discovered through Sentinel; the engine re-resolves the primary after a
+    /// failover.
+    Sentinel(SentinelConfig),
+}
+
+#[derive(Clone)]
+pub struct SentinelConfig {
+    /// Sentinel addresses, e.g. `redis://10.0.0.1:26379`.
+    pub sentinels: Vec<String>,
+    /// Name of the monitored primary (`sentinel monitor <name> ...`).
+    pub service_name: String,
+    /// TLS and auth settings for the data nodes, when they differ from the defaults.
+    pub node_connection_info: Option<SentinelNodeConnectionInfo>,
+}
+
+impl RedisBackend {
+    pub(crate) fn uses_hash_tags(&self) -> bool {
+        matches!(self, RedisBackend::Cluster { .. })
+    }
+
+    pub(crate) async fn connect(
+        &self,
+        settings: &RedisConnectionConfig,
+    ) -> RedisResult<DispatchConnection> {
+        match self {
+            RedisBackend::Standalone(client) => Ok(DispatchConnection::Single(
+                manager_for(client.clone(), settings).await?,
+            )),
+            RedisBackend::Cluster { nodes } => {
+                let client = ClusterClientBuilder::new(nodes.clone())
+                    .connection_timeout(settings.connection_timeout)
+                    .response_timeout(settings.response_timeout)
+                    .retries(settings.reconnect_retries as u32)
+                    .build()?;
+                Ok(DispatchConnection::Cluster(
+                    client.get_async_connection().await?,
+                ))
+            }
+            RedisBackend::Sentinel(config) => {
+                let mut sentinel = Sentinel::build(config.sentinels.clone())?;
+                let client = sentinel
+                    .async_master_for(&config.service_name, config.node_connection_info.as_ref())
+                    .await?;
+                let manager = manager_for(client, settings).await?;
+                Ok(DispatchConnection::Sentinel(SentinelConnection {
+                    sentinel: Arc::new(Mutex::new(sentinel)),
+                    config: config.clone(),
+                    settings: settings.clone(),
+                    current: Arc::new(RwLock::new(manager)),
+                }))
+            }
+        }
+    }
+}
+
+async fn manager_for(
+    client: redis::Client,
+    settings: &RedisConnectionConfig,
+) -> RedisResult<ConnectionManager> {
+    ConnectionManager::new_with_backoff_and_timeouts(
+        client,
+        settings.backoff_base_ms,
+        settings.backoff_factor,
+        settings.reconnect_retries,
+        settings.response_timeout,
+        settings.connection_timeout,
+    )
+    .await
+}
+
+/// Cheaply cloneable connection handed to every engine operation.
+#[derive(Clone)]
+pub(crate) enum DispatchConnection {
+    Single(ConnectionManager),
+    Cluster(ClusterConnection),
+    Sentinel(SentinelConnection),
+}
+
+impl ConnectionLike for DispatchConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            DispatchConnection::Single(conn) => conn.req_packed_command(cmd),
+            DispatchConnection::Cluster(conn) => conn.req_packed_command(cmd),
+            DispatchConnection::Sentinel(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            DispatchConnection::Single(conn) => conn.req_packed_commands(cmd, offset, count),
+            DispatchConnection::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+            DispatchConnection::Sentinel(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            DispatchConnection::Single(conn) => conn.get_db(),
+            DispatchConnection::Cluster(conn) => conn.get_db(),
+            DispatchConnection::Sentinel(_) => 0,
+        }
+    }
+}
+
+/// Connection to the current Sentinel primary. A command that fails because the node was
+/// demoted or went away triggers a new primary lookup; the failing command still returns
+/// its error, and later commands go to the new primary.
+#[derive(Clone)]
+pub(crate) struct SentinelConnection {
+    sentinel: Arc<Mutex<Sentinel>>,
+    config: SentinelConfig,
+    settings: RedisConnectionConfig,
+    current: Arc<RwLock<ConnectionManager>>,
+}
+
+impl SentinelConnection {
+    async fn failover(&self) -> RedisResult<()> {
+        let client = self
+            .sentinel
+            .lock()
+            .await
+            .async_master_for(
+                &self.config.service_name,
+                self.config.node_connection_info.as_ref(),
+            )
+            .await?;
+        let manager = manager_for(client, &self.settings).await?;
+        *self.current.write().await = manager;
+        Ok(())
+    }
+
+    async fn after<T>(&self, result: RedisResult<T>) -> RedisResult<T> {
+        if let Err(err) = &result {
+            if is_failover_error(err) {
+                self.failover().await?;
+            }
+        }
+        result
+    }
+}
+
+impl ConnectionLike for SentinelConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let mut conn = self.current.read().await.clone();
+            let result = conn.req_packed_command(cmd).await;
+            self.after(result).await
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let mut conn = self.current.read().await.clone();
+            let result = conn.req_packed_commands(cmd, offset, count).await;
+            self.after(result).await
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+}
+
+fn is_failover_error(err: &redis::RedisError) -> bool {
+    err.kind() == ErrorKind::ReadOnly
+        || err.is_io_error()
+        || err.is_connection_refusal()
+        || err.is_connection_dropped()
+}
+
+/// Builds keys so that, in cluster mode, everything a single command or pipeline touches
+/// hashes to the same slot.
+#[derive(Debug, Clone)]
+pub(crate) struct KeySpace {
+    prefix: String,
+    hash_tags: bool,
+}
+
+impl KeySpace {
+    pub(crate) fn new(prefix: &str, hash_tags: bool) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            hash_tags,
+        }
+    }
+
+    /// Keys of one group share the `{prefix:group}` hash tag in cluster mode. A group holds
+    /// only keys that scripts, transactions or `GEOSEARCHSTORE` combine: the pilot GEO index,
+    /// statuses, offer claims and snapshots (`drivers`), the pending and flagged queues
+    /// (`rides`), and the dispatch log (`dispatch`).
+    pub(crate) fn grouped(&self, group: &str, suffix: &str) -> String {
+        if self.hash_tags {
+            format!("{{{}:{}}}:{}", self.prefix, group, suffix)
+        } else {
+            format!("{}:{}:{}", self.prefix, group, suffix)
+        }
+    }
+
+    /// Per-ride state. Only keys of the same ride are used together, so each ride gets its
+    /// own tag and rides spread across the cluster.
+    pub(crate) fn ride(&self, ride_id: &str, suffix: &str) -> String {
+        self.grouped(&format!("ride:{ride_id}"), suffix)
+    }
+
+    /// Per-pilot state (assignment, breadcrumb trail), tagged per pilot like rides.
+    pub(crate) fn pilot(&self, pilot_id: &str, suffix: &str) -> String {
+        self.grouped(&format!("pilot:{pilot_id}"), suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_keys_match_js_layout() {
+        let keys = KeySpace::new("supportcarr", false);
+        assert_eq!(keys.grouped("drivers", "geo"), "supportcarr:drivers:geo");
+        assert_eq!(keys.grouped("rides", "pending"), "supportcarr:rides:pending");
+        assert_eq!(keys.ride("r1", "offer"), "supportcarr:ride:r1:offer");
+        assert_eq!(keys.pilot("p1", "ride"), "supportcarr:pilot:p1:ride");
+    }
+
+    #[test]
+    fn cluster_keys_share_slots_only_where_combined() {
+        let keys = KeySpace::new("supportcarr", true);
+        let geo = keys.grouped("drivers", "geo");
+        assert_eq!(geo, "{supportcarr:drivers}:geo");
+
+        let slot = |key: &str| redis::cluster_routing::get_slot(key.as_bytes());
+        assert_eq!(slot(&geo), slot(&keys.grouped("drivers", "status")));
+        assert_eq!(slot(&geo), slot(&keys.grouped("drivers", "snapshots:zone")));
+        assert_eq!(
+            slot(&keys.grouped("rides", "pending")),
+            slot(&keys.grouped("rides", "flagged"))
+        );
+        assert_eq!(slot(&keys.ride("r1", "offer")), slot(&keys.ride("r1", "offered")));
+        assert_eq!(slot(&keys.pilot("p1", "ride")), slot(&keys.pilot("p1", "trail")));
+
+        // Unrelated groups, rides and pilots land on different slots.
+        assert_ne!(slot(&geo), slot(&keys.grouped("rides", "pending")));
+        assert_ne!(slot(&geo), slot(&keys.pilot("p1", "ride")));
+        assert_ne!(slot(&keys.ride("r1", "offer")), slot(&keys.ride("r2", "offer")));
+    }
+}
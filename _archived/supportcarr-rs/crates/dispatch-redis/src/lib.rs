@@ -1,53 +1,816 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{Mutex, OnceCell};
+
 use async_trait::async_trait;
-use redis::aio::MultiplexedConnection;
-use supportcarr_core::dispatch::{DispatchCandidate, DispatchEngine, DispatchEngineConfig};
+use redis::streams::{StreamId, StreamRangeReply, StreamReadReply};
+use supportcarr_core::dispatch::{
+    filter_to_zone, DispatchCandidate, DispatchEngine, DispatchEngineConfig, DispatchOffer,
+};
+use supportcarr_core::dispatch::now_millis;
 use supportcarr_core::error::{CoreError, CoreResult};
+use supportcarr_core::events::{
+    DispatchDecision, DispatchEventLog, DispatchLogEntry, EventRetention,
+};
+use supportcarr_core::geo::{BoundingBox, ServiceZone};
+use supportcarr_core::history::{Breadcrumb, LocationHistory};
 use supportcarr_core::model::RideLocation;
+use supportcarr_core::queue::{PendingRide, PendingRideQueue};
+
+mod backend;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_server;
+
+pub use backend::{RedisBackend, SentinelConfig};
+
+use backend::{DispatchConnection, KeySpace};
+
+// Redis expects kilometers; JS dispatch multiplied by 1.60934.
+const KM_PER_MILE: f64 = 1.60934;
+
+// Offers stay readable this long past expiry so the timeout worker can still claim them.
+const OFFER_GRACE: Duration = Duration::from_secs(60);
+// How far a pilot's clock may run ahead of the server's when windowing trails.
+const TRAIL_CLOCK_SKEW: Duration = Duration::from_secs(60);
+// How long the set of already-offered pilots is kept for a ride.
+const OFFERED_PILOTS_TTL_SECS: u64 = 24 * 60 * 60;
+
+// Removes the offer only if it is still held by ARGV[1]; returns its timestamps.
+const TAKE_OFFER_SCRIPT: &str = r"
+if redis.call('HGET', KEYS[1], 'pilot_id') ~= ARGV[1] then
+    return false
+end
+local offer = redis.call('HMGET', KEYS[1], 'offered_at_ms', 'expires_at_ms')
+redis.call('DEL', KEYS[1])
+return offer
+";
+
+// Claims pilot ARGV[1] for ride ARGV[2] until ARGV[4] ms, unless they hold a claim on
+// another ride that is live at ARGV[3] ms; returns 1 if claimed. Claims are stored as
+// `<expires_at_ms>:<ride_id>`.
+const CLAIM_PILOT_SCRIPT: &str = r"
+local held = redis.call('HGET', KEYS[1], ARGV[1])
+if held then
+    local expires_at, ride_id = string.match(held, '^(%d+):(.*)$')
+    if ride_id ~= ARGV[2] and tonumber(expires_at) > tonumber(ARGV[3]) then
+        return 0
+    end
+end
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[4] .. ':' .. ARGV[2])
+return 1
+";
+
+// Ends pilot ARGV[1]'s claim if it is on ride ARGV[2].
+const RELEASE_PILOT_CLAIM_SCRIPT: &str = r"
+local held = redis.call('HGET', KEYS[1], ARGV[1])
+if held and string.match(held, '^%d+:(.*)$') == ARGV[2] then
+    return redis.call('HDEL', KEYS[1], ARGV[1])
+end
+return 0
+";
+
+// Moves the pilot in the GEO set unless a later position is stored; returns 1 if moved.
+const STORE_LOCATION_SCRIPT: &str = r"
+local stored = tonumber(redis.call('HGET', KEYS[2], ARGV[1]))
+if stored and stored > tonumber(ARGV[4]) then
+    return 0
+end
+redis.call('GEOADD', KEYS[1], ARGV[2], ARGV[3], ARGV[1])
+redis.call('HSET', KEYS[2], ARGV[1], ARGV[4])
+return 1
+";
+
+// Moves a ride from the pending to the flagged set, keeping its request time.
+const FLAG_RIDE_SCRIPT: &str = r"
+local score = redis.call('ZSCORE', KEYS[1], ARGV[1])
+if score then
+    redis.call('ZREM', KEYS[1], ARGV[1])
+    redis.call('ZADD', KEYS[2], score, ARGV[1])
+end
+return 0
+";
+
+enum GeoArea<'a> {
+    Radius {
+        location: &'a RideLocation,
+        radius_km: f64,
+    },
+    Box(&'a BoundingBox),
+}
+
+/// One `WITHDIST WITHCOORD` entry: member, distance in km, (lng, lat).
+type GeoMatch = (String, f64, (f64, f64));
+
+/// How the engine's shared connection is established and kept alive.
+#[derive(Debug, Clone)]
+pub struct RedisConnectionConfig {
+    /// Per-command timeout; a command that takes longer fails with a dispatch error.
+    pub response_timeout: Duration,
+    /// Timeout for each connect or reconnect attempt.
+    pub connection_timeout: Duration,
+    /// Reconnect attempts after the connection drops, with jittered exponential backoff.
+    pub reconnect_retries: usize,
+    pub backoff_base_ms: u64,
+    pub backoff_factor: u64,
+}
+
+impl Default for RedisConnectionConfig {
+    fn default() -> Self {
+        Self {
+            response_timeout: Duration::from_secs(2),
+            connection_timeout: Duration::from_secs(5),
+            reconnect_retries: 6,
+            backoff_base_ms: 2,
+            backoff_factor: 100,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct RedisDispatchEngine {
-    client: redis::Client,
-    config: DispatchEngineConfig,
+    backend: RedisBackend,
+    keys: KeySpace,
+    trail_max_len: usize,
+    event_retention: EventRetention,
+    connection_config: RedisConnectionConfig,
+    // One auto-reconnecting connection shared by every clone of the engine, opened on
+    // first use.
+    connection: Arc<OnceCell<DispatchConnection>>,
+    // A second connection for blocking consumer-group reads, opened on first use. A
+    // blocking read holds its connection until it returns, so reads take turns on it.
+    blocking_connection: Arc<Mutex<Option<DispatchConnection>>>,
+    // Set once the server rejects GEOSEARCH (Redis < 6.2); later queries go straight to
+    // GEORADIUS.
+    legacy_geo: Arc<AtomicBool>,
 }
 
 impl RedisDispatchEngine {
     pub fn new(client: redis::Client, config: DispatchEngineConfig) -> Self {
-        Self { client, config }
+        Self::with_connection_config(client, config, RedisConnectionConfig::default())
+    }
+
+    pub fn with_connection_config(
+        client: redis::Client,
+        config: DispatchEngineConfig,
+        connection_config: RedisConnectionConfig,
+    ) -> Self {
+        Self::with_backend(RedisBackend::Standalone(client), config, connection_config)
     }
 
-    async fn connection(&self) -> CoreResult<MultiplexedConnection> {
-        self.client
-            .get_multiplexed_async_connection()
+    /// Engine on a standalone server, a cluster, or a Sentinel-managed primary. Cluster
+    /// backends hash-tag keys per group, ride and pilot (`{supportcarr:drivers}:geo`,
+    /// `{supportcarr:ride:r1}:offer`), so switching an existing deployment to cluster mode
+    /// moves its keys.
+    pub fn with_backend(
+        backend: RedisBackend,
+        config: DispatchEngineConfig,
+        connection_config: RedisConnectionConfig,
+    ) -> Self {
+        Self {
+            keys: KeySpace::new(&config.key_prefix, backend.uses_hash_tags()),
+            trail_max_len: config.trail_max_len,
+            event_retention: config.event_retention,
+            backend,
+            connection_config,
+            connection: Arc::new(OnceCell::new()),
+            blocking_connection: Arc::new(Mutex::new(None)),
+            legacy_geo: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    async fn connection(&self) -> CoreResult<DispatchConnection> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| self.backend.connect(&self.connection_config))
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+        Ok(connection.clone())
+    }
+
+    /// Move a pilot as of now and set their availability in one round trip.
+    pub async fn update_pilot(
+        &self,
+        pilot_id: &str,
+        location: &RideLocation,
+        available: bool,
+    ) -> CoreResult<()> {
+        let mut conn = self.connection().await?;
+        let status = if available { "available" } else { "busy" };
+        let _: () = redis::pipe()
+            .atomic()
+            .cmd("GEOADD")
+            .arg(self.geo_key())
+            .arg(location.lng)
+            .arg(location.lat)
+            .arg(pilot_id)
+            .ignore()
+            .cmd("HSET")
+            .arg(self.located_at_key())
+            .arg(pilot_id)
+            .arg(now_millis())
+            .ignore()
+            .cmd("HSET")
+            .arg(self.status_key())
+            .arg(pilot_id)
+            .arg(status)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+        Ok(())
+    }
+
+    /// Store many pilot locations as of now with a single `GEOADD`.
+    pub async fn store_pilot_locations(
+        &self,
+        locations: &[(String, RideLocation)],
+    ) -> CoreResult<()> {
+        if locations.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.connection().await?;
+        let now_ms = now_millis();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        let geoadd = pipe.cmd("GEOADD").arg(self.geo_key());
+        for (pilot_id, location) in locations {
+            geoadd.arg(location.lng).arg(location.lat).arg(pilot_id);
+        }
+        geoadd.ignore();
+        let located_at = pipe.cmd("HSET").arg(self.located_at_key());
+        for (pilot_id, _) in locations {
+            located_at.arg(pilot_id).arg(now_ms);
+        }
+        located_at.ignore();
+        pipe.query_async(&mut conn)
             .await
             .map_err(|err| CoreError::Dispatch(err.to_string()))
     }
 
     fn geo_key(&self) -> String {
-        format!("{}:drivers:geo", self.config.key_prefix)
+        self.keys.grouped("drivers", "geo")
     }
 
     fn status_key(&self) -> String {
-        format!("{}:drivers:status", self.config.key_prefix)
+        self.keys.grouped("drivers", "status")
+    }
+
+    /// When each pilot's GEO position was recorded, in ms.
+    fn located_at_key(&self) -> String {
+        self.keys.grouped("drivers", "located_at")
     }
+
+    /// The ride each pilot was last offered and until when, see [`CLAIM_PILOT_SCRIPT`].
+    fn claims_key(&self) -> String {
+        self.keys.grouped("drivers", "claims")
+    }
+
+    fn offer_key(&self, ride_id: &str) -> String {
+        self.keys.ride(ride_id, "offer")
+    }
+
+    fn offered_key(&self, ride_id: &str) -> String {
+        self.keys.ride(ride_id, "offered")
+    }
+
+    fn assignment_key(&self, pilot_id: &str) -> String {
+        self.keys.pilot(pilot_id, "ride")
+    }
+
+    fn pilot_trail_key(&self, pilot_id: &str) -> String {
+        self.keys.pilot(pilot_id, "trail")
+    }
+
+    fn ride_trail_key(&self, ride_id: &str) -> String {
+        self.keys.ride(ride_id, "trail")
+    }
+
+    async fn read_trail(
+        &self,
+        key: String,
+        start: String,
+        end: String,
+    ) -> CoreResult<Vec<Breadcrumb>> {
+        let mut conn = self.connection().await?;
+        let reply: StreamRangeReply = redis::cmd("XRANGE")
+            .arg(key)
+            .arg(start)
+            .arg(end)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+
+        reply
+            .ids
+            .into_iter()
+            .map(|entry| {
+                let field = |name: &str| {
+                    entry
+                        .get::<String>(name)
+                        .ok_or_else(|| CoreError::Dispatch(format!("breadcrumb missing {name}")))
+                };
+                let number = |name: &str| -> CoreResult<f64> {
+                    field(name)?
+                        .parse()
+                        .map_err(|_| CoreError::Dispatch(format!("breadcrumb has invalid {name}")))
+                };
+                Ok(Breadcrumb {
+                    pilot_id: field("pilot_id")?,
+                    location: RideLocation::new(number("lat")?, number("lng")?),
+                    recorded_at_ms: number("recorded_at_ms")? as u64,
+                })
+            })
+            .collect()
+    }
+
+    fn events_key(&self) -> String {
+        self.keys.grouped("dispatch", "events")
+    }
+
+    /// Create a consumer group on the dispatch event stream. `from_start` replays
+    /// the retained history to the group; otherwise it only sees new decisions. Creating a
+    /// group that already exists is not an error.
+    pub async fn create_event_group(&self, group: &str, from_start: bool) -> CoreResult<()> {
+        let mut conn = self.connection().await?;
+        let result: redis::RedisResult<()> = redis::cmd("XGROUP")
+            .arg("CREATE")
+            .arg(self.events_key())
+            .arg(group)
+            .arg(if from_start { "0" } else { "$" })
+            .arg("MKSTREAM")
+            .query_async(&mut conn)
+            .await;
+        match result {
+            Err(err) if err.code() == Some("BUSYGROUP") => Ok(()),
+            other => other.map_err(|err| CoreError::Dispatch(err.to_string())),
+        }
+    }
+
+    /// Read decisions not yet delivered to `group`, on behalf of `consumer`. With `block`
+    /// set the call waits up to that long for new entries on the engine's blocking
+    /// connection, so the shared dispatch connection is never held by a blocking read.
+    /// Concurrent blocking reads on one engine take turns.
+    pub async fn read_event_group(
+        &self,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        block: Option<Duration>,
+    ) -> CoreResult<Vec<DispatchLogEntry>> {
+        let mut cmd = redis::cmd("XREADGROUP");
+        cmd.arg("GROUP").arg(group).arg(consumer).arg("COUNT").arg(count);
+        if let Some(block) = block {
+            cmd.arg("BLOCK").arg(block.as_millis() as u64);
+        }
+        cmd.arg("STREAMS").arg(self.events_key()).arg(">");
+
+        let reply: Option<StreamReadReply> = match block {
+            Some(block) => {
+                let mut blocking = self.blocking_connection.lock().await;
+                let conn = match &mut *blocking {
+                    Some(conn) => conn,
+                    None => blocking.insert(self.open_blocking_connection().await?),
+                };
+                // The connection itself never times out; the read gets `block` plus the
+                // usual response timeout. A read that overruns it is still pending on the
+                // connection, so the connection is dropped and the next read opens another.
+                let deadline = block + self.connection_config.response_timeout;
+                match tokio::time::timeout(deadline, cmd.query_async(conn)).await {
+                    Ok(reply) => reply,
+                    Err(_) => {
+                        blocking.take();
+                        return Err(CoreError::Dispatch(format!(
+                            "blocking read timed out after {deadline:?}"
+                        )));
+                    }
+                }
+            }
+            None => cmd.query_async(&mut self.connection().await?).await,
+        }
+        .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+
+        reply
+            .into_iter()
+            .flat_map(|reply| reply.keys)
+            .flat_map(|key| key.ids)
+            .map(|entry| parse_log_entry(&entry))
+            .collect()
+    }
+
+    async fn open_blocking_connection(&self) -> CoreResult<DispatchConnection> {
+        let config = RedisConnectionConfig {
+            response_timeout: Duration::MAX,
+            ..self.connection_config.clone()
+        };
+        self.backend
+            .connect(&config)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+    }
+
+    /// Acknowledge processed entries so they leave the group's pending list.
+    pub async fn ack_events(&self, group: &str, ids: &[String]) -> CoreResult<usize> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let mut conn = self.connection().await?;
+        redis::cmd("XACK")
+            .arg(self.events_key())
+            .arg(group)
+            .arg(ids)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+    }
+
+    /// Take over entries another consumer received but has not acknowledged for at least
+    /// `min_idle`, e.g. after that processor crashed (`XAUTOCLAIM`, Redis 6.2+).
+    pub async fn claim_stale_events(
+        &self,
+        group: &str,
+        consumer: &str,
+        min_idle: Duration,
+        count: usize,
+    ) -> CoreResult<Vec<DispatchLogEntry>> {
+        let mut conn = self.connection().await?;
+        // Reply is [next-cursor, entries] plus, on Redis 7, [deleted-ids].
+        let reply: Vec<redis::Value> = redis::cmd("XAUTOCLAIM")
+            .arg(self.events_key())
+            .arg(group)
+            .arg(consumer)
+            .arg(min_idle.as_millis() as u64)
+            .arg("0-0")
+            .arg("COUNT")
+            .arg(count)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+
+        let entries = match reply.get(1) {
+            Some(value) => redis::from_redis_value::<StreamRangeReply>(value)
+                .map_err(|err| CoreError::Dispatch(err.to_string()))?,
+            None => return Ok(Vec::new()),
+        };
+        entries.ids.iter().map(parse_log_entry).collect()
+    }
+
+    fn pending_key(&self) -> String {
+        self.keys.grouped("rides", "pending")
+    }
+
+    fn flagged_key(&self) -> String {
+        self.keys.grouped("rides", "flagged")
+    }
+
+    async fn oldest_first(&self, key: String, limit: usize) -> CoreResult<Vec<PendingRide>> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.connection().await?;
+        let rides: Vec<(String, f64)> = redis::cmd("ZRANGE")
+            .arg(key)
+            .arg(0)
+            .arg(limit - 1)
+            .arg("WITHSCORES")
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+        Ok(rides
+            .into_iter()
+            .map(|(ride_id, requested_at_ms)| PendingRide {
+                ride_id,
+                requested_at_ms: requested_at_ms as u64,
+            })
+            .collect())
+    }
+
+    /// Key next to the pilot GEO index (`{prefix}:drivers:snapshots:{name}`, sharing its
+    /// hash tag in cluster mode) suitable as a [`Self::store_pilots_in_box`] destination.
+    pub fn snapshot_key(&self, name: &str) -> String {
+        self.keys.grouped("drivers", &format!("snapshots:{name}"))
+    }
+
+    /// Copy the pilots inside `bounds` into a GEO set at `destination` with
+    /// `GEOSEARCHSTORE`, returning how many were stored. On servers without `GEOSEARCH` the
+    /// circle circumscribing the box is stored instead. In cluster mode the destination
+    /// must share the GEO index's hash tag; see [`Self::snapshot_key`].
+    pub async fn store_pilots_in_box(
+        &self,
+        bounds: &BoundingBox,
+        destination: &str,
+    ) -> CoreResult<usize> {
+        let mut conn = self.connection().await?;
+        let center = bounds.center();
+
+        if !self.legacy_geo.load(Ordering::Relaxed) {
+            let result = redis::cmd("GEOSEARCHSTORE")
+                .arg(destination)
+                .arg(self.geo_key())
+                .arg("FROMLONLAT")
+                .arg(center.lng)
+                .arg(center.lat)
+                .arg("BYBOX")
+                .arg(bounds.width_miles() * KM_PER_MILE)
+                .arg(bounds.height_miles() * KM_PER_MILE)
+                .arg("km")
+                .query_async(&mut conn)
+                .await;
+            match result {
+                Err(err) if is_unknown_command(&err) => {
+                    self.legacy_geo.store(true, Ordering::Relaxed)
+                }
+                other => return other.map_err(|err| CoreError::Dispatch(err.to_string())),
+            }
+        }
+
+        redis::cmd("GEORADIUS")
+            .arg(self.geo_key())
+            .arg(center.lng)
+            .arg(center.lat)
+            .arg(bounds.circumradius_miles() * KM_PER_MILE)
+            .arg("km")
+            .arg("STORE")
+            .arg(destination)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+    }
+
+    async fn search_radius(
+        &self,
+        location: &RideLocation,
+        radius_km: f64,
+        limit: Option<usize>,
+    ) -> CoreResult<Vec<DispatchCandidate>> {
+        let mut conn = self.connection().await?;
+
+        if !self.legacy_geo.load(Ordering::Relaxed) {
+            let mut cmd = redis::cmd("GEOSEARCH");
+            cmd.arg(self.geo_key())
+                .arg("FROMLONLAT")
+                .arg(location.lng)
+                .arg(location.lat)
+                .arg("BYRADIUS")
+                .arg(radius_km)
+                .arg("km");
+            match self.query_geo(&mut conn, cmd, limit).await {
+                Err(err) if is_unknown_command(&err) => {
+                    self.legacy_geo.store(true, Ordering::Relaxed)
+                }
+                other => return other.map_err(|err| CoreError::Dispatch(err.to_string())),
+            }
+        }
+
+        let mut cmd = redis::cmd("GEORADIUS");
+        cmd.arg(self.geo_key())
+            .arg(location.lng)
+            .arg(location.lat)
+            .arg(radius_km)
+            .arg("km");
+        self.query_geo(&mut conn, cmd, limit)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+    }
+
+    /// Pilots inside `bounds`, nearest the center first. `BYBOX` measures its width at each
+    /// pilot's latitude, so the search is as wide as the box's widest edge and trimmed to the
+    /// box, with the query's `COUNT` doubling until `limit` pilots survive the trim.
+    async fn search_box(
+        &self,
+        bounds: &BoundingBox,
+        limit: Option<usize>,
+    ) -> CoreResult<Vec<DispatchCandidate>> {
+        if !self.legacy_geo.load(Ordering::Relaxed) {
+            let mut conn = self.connection().await?;
+            let center = bounds.center();
+            let mut count = limit;
+            loop {
+                let mut cmd = redis::cmd("GEOSEARCH");
+                cmd.arg(self.geo_key())
+                    .arg("FROMLONLAT")
+                    .arg(center.lng)
+                    .arg(center.lat)
+                    .arg("BYBOX")
+                    .arg(bounds.widest_width_miles() * KM_PER_MILE)
+                    .arg(bounds.height_miles() * KM_PER_MILE)
+                    .arg("km");
+                let found = match self.query_geo(&mut conn, cmd, count).await {
+                    Err(err) if is_unknown_command(&err) => {
+                        self.legacy_geo.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                    other => other.map_err(|err| CoreError::Dispatch(err.to_string()))?,
+                };
+                let exhausted = count.is_none_or(|count| found.len() < count);
+                let inside = within(bounds, found, limit);
+                match count {
+                    Some(searched) if !exhausted && limit.is_some_and(|n| inside.len() < n) => {
+                        count = Some(searched * 2)
+                    }
+                    _ => return Ok(inside),
+                }
+            }
+        }
+
+        // GEORADIUS has no box shape: search the circumscribed circle unbounded, then trim
+        // to the rectangle before applying the limit.
+        let candidates = self
+            .search_radius(
+                &bounds.center(),
+                bounds.circumradius_miles() * KM_PER_MILE,
+                None,
+            )
+            .await?;
+        Ok(within(bounds, candidates, limit))
+    }
+
+    /// Drop candidates marked `busy` in the status hash or holding a live offer; pilots
+    /// without a status count as available.
+    async fn available_only(
+        &self,
+        candidates: Vec<DispatchCandidate>,
+    ) -> CoreResult<Vec<DispatchCandidate>> {
+        if candidates.is_empty() {
+            return Ok(candidates);
+        }
+        let mut conn = self.connection().await?;
+        let pilot_ids: Vec<&str> = candidates
+            .iter()
+            .map(|candidate| candidate.pilot_id.as_str())
+            .collect();
+        let (statuses, claims): (Vec<Option<String>>, Vec<Option<String>>) = redis::pipe()
+            .cmd("HMGET")
+            .arg(self.status_key())
+            .arg(&pilot_ids)
+            .cmd("HMGET")
+            .arg(self.claims_key())
+            .arg(&pilot_ids)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+        let now_ms = now_millis();
+        let live = |claim: &str| {
+            claim
+                .split_once(':')
+                .and_then(|(expires_at_ms, _)| expires_at_ms.parse::<u64>().ok())
+                .is_some_and(|expires_at_ms| expires_at_ms > now_ms)
+        };
+        Ok(candidates
+            .into_iter()
+            .zip(statuses.into_iter().zip(claims))
+            .filter(|(_, (status, claim))| {
+                status.as_deref() != Some("busy") && !claim.as_deref().is_some_and(live)
+            })
+            .map(|(candidate, _)| candidate)
+            .collect())
+    }
+
+    /// End `pilot_id`'s claim if it is on `ride_id`.
+    async fn release_claim(&self, pilot_id: &str, ride_id: &str) -> CoreResult<()> {
+        let mut conn = self.connection().await?;
+        redis::Script::new(RELEASE_PILOT_CLAIM_SCRIPT)
+            .key(self.claims_key())
+            .arg(pilot_id)
+            .arg(ride_id)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+            .map(|_: i32| ())
+    }
+
+    /// Up to `limit` available pilots. Busy pilots are filtered after the GEO query, so the
+    /// query's `COUNT` doubles until enough candidates survive or the area runs out.
+    async fn search_available(
+        &self,
+        area: GeoArea<'_>,
+        limit: Option<usize>,
+    ) -> CoreResult<Vec<DispatchCandidate>> {
+        let Some(limit) = limit else {
+            let found = self.search_area(&area, None).await?;
+            return self.available_only(found).await;
+        };
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut count = limit;
+        loop {
+            let found = self.search_area(&area, Some(count)).await?;
+            let exhausted = found.len() < count;
+            let mut available = self.available_only(found).await?;
+            if available.len() >= limit || exhausted {
+                available.truncate(limit);
+                return Ok(available);
+            }
+            count *= 2;
+        }
+    }
+
+    async fn search_area(
+        &self,
+        area: &GeoArea<'_>,
+        limit: Option<usize>,
+    ) -> CoreResult<Vec<DispatchCandidate>> {
+        match area {
+            GeoArea::Radius { location, radius_km } => {
+                self.search_radius(location, *radius_km, limit).await
+            }
+            GeoArea::Box(bounds) => self.search_box(bounds, limit).await,
+        }
+    }
+
+    async fn query_geo(
+        &self,
+        conn: &mut DispatchConnection,
+        mut cmd: redis::Cmd,
+        limit: Option<usize>,
+    ) -> redis::RedisResult<Vec<DispatchCandidate>> {
+        cmd.arg("WITHDIST").arg("WITHCOORD").arg("ASC");
+        if let Some(limit) = limit {
+            cmd.arg("COUNT").arg(limit);
+        }
+        // Decoded entry by entry: asking for `Vec<GeoMatch>` directly makes redis-rs read
+        // the reply as one flat list of tuple fields.
+        let results: Vec<redis::Value> = cmd.query_async(conn).await?;
+
+        results
+            .iter()
+            .map(|entry| {
+                let (pilot_id, distance_km, (lng, lat)): GeoMatch =
+                    redis::FromRedisValue::from_redis_value(entry)?;
+                Ok(DispatchCandidate {
+                    pilot_id,
+                    distance_meters: Some(distance_km * 1000.0),
+                    location: Some(RideLocation::new(lat, lng)),
+                })
+            })
+            .collect()
+    }
+}
+
+fn parse_log_entry(entry: &StreamId) -> CoreResult<DispatchLogEntry> {
+    let field = |name: &str| {
+        entry
+            .get::<String>(name)
+            .ok_or_else(|| CoreError::Dispatch(format!("dispatch event missing {name}")))
+    };
+    let decision: DispatchDecision = serde_json::from_str(&field("decision")?)
+        .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+    Ok(DispatchLogEntry {
+        id: entry.id.clone(),
+        recorded_at_ms: field("recorded_at_ms")?
+            .parse()
+            .map_err(|_| CoreError::Dispatch("dispatch event has invalid recorded_at_ms".into()))?,
+        decision,
+    })
+}
+
+/// The first `limit` candidates located inside `bounds`.
+fn within(
+    bounds: &BoundingBox,
+    candidates: Vec<DispatchCandidate>,
+    limit: Option<usize>,
+) -> Vec<DispatchCandidate> {
+    candidates
+        .into_iter()
+        .filter(|candidate| {
+            candidate
+                .location
+                .as_ref()
+                .is_some_and(|location| bounds.contains(location))
+        })
+        .take(limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+fn is_unknown_command(err: &redis::RedisError) -> bool {
+    err.kind() == redis::ErrorKind::ResponseError
+        && err.to_string().to_lowercase().contains("unknown command")
 }
 
 #[async_trait]
 impl DispatchEngine for RedisDispatchEngine {
-    async fn store_pilot_location(
+    async fn store_pilot_location_at(
         &self,
         pilot_id: &str,
         location: &RideLocation,
-    ) -> CoreResult<()> {
+        recorded_at_ms: u64,
+    ) -> CoreResult<bool> {
         let mut conn = self.connection().await?;
-        redis::cmd("GEOADD")
-            .arg(self.geo_key())
+        redis::Script::new(STORE_LOCATION_SCRIPT)
+            .key(self.geo_key())
+            .key(self.located_at_key())
+            .arg(pilot_id)
             .arg(location.lng)
             .arg(location.lat)
-            .arg(pilot_id)
-            .query_async(&mut conn)
+            .arg(recorded_at_ms)
+            .invoke_async(&mut conn)
             .await
             .map_err(|err| CoreError::Dispatch(err.to_string()))
-            .map(|_: i32| ())
+            .map(|stored: i32| stored == 1)
     }
 
     async fn set_pilot_available(&self, pilot_id: &str, available: bool) -> CoreResult<()> {
@@ -69,71 +832,462 @@ impl DispatchEngine for RedisDispatchEngine {
         radius_miles: f64,
         limit: usize,
     ) -> CoreResult<Vec<DispatchCandidate>> {
+        let area = GeoArea::Radius {
+            location,
+            radius_km: radius_miles * KM_PER_MILE,
+        };
+        self.search_available(area, Some(limit)).await
+    }
+
+    async fn find_pilots_in_box(
+        &self,
+        bounds: &BoundingBox,
+        limit: usize,
+    ) -> CoreResult<Vec<DispatchCandidate>> {
+        self.search_available(GeoArea::Box(bounds), Some(limit))
+            .await
+    }
+
+    async fn find_pilots_in_zone(
+        &self,
+        zone: &ServiceZone,
+        limit: usize,
+    ) -> CoreResult<Vec<DispatchCandidate>> {
+        let Some(bounds) = zone.bounding_box() else {
+            return Ok(Vec::new());
+        };
+        let candidates = self
+            .search_available(GeoArea::Box(&bounds), None)
+            .await?;
+        Ok(filter_to_zone(candidates, zone, limit))
+    }
+
+    // The status hash and the pilot's assignment live in different cluster slots, so they
+    // are written one after the other rather than in a pipeline. Each order leaves the
+    // pilot busy if the second write fails, which keeps them out of new offers.
+    async fn mark_assigned(&self, pilot_id: &str, ride_id: &str) -> CoreResult<()> {
         let mut conn = self.connection().await?;
-        // Redis expects kilometers; JS dispatch multiplied by 1.60934.
-        let radius_km = radius_miles * 1.60934;
-        let results: Vec<(String, f64)> = redis::cmd("GEORADIUS")
-            .arg(self.geo_key())
-            .arg(location.lng)
-            .arg(location.lat)
-            .arg(radius_km)
-            .arg("km")
-            .arg("WITHDIST")
-            .arg("ASC")
-            .arg("COUNT")
-            .arg(limit)
+        let _: () = redis::cmd("HSET")
+            .arg(self.status_key())
+            .arg(pilot_id)
+            .arg("busy")
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+        redis::cmd("SET")
+            .arg(self.assignment_key(pilot_id))
+            .arg(ride_id)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+    }
+
+    async fn release_pilot(&self, pilot_id: &str) -> CoreResult<()> {
+        let mut conn = self.connection().await?;
+        let _: () = redis::cmd("DEL")
+            .arg(self.assignment_key(pilot_id))
             .query_async(&mut conn)
             .await
             .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+        redis::cmd("HSET")
+            .arg(self.status_key())
+            .arg(pilot_id)
+            .arg("available")
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+            .map(|_: i32| ())
+    }
 
-        Ok(results
-            .into_iter()
-            .map(|(pilot_id, distance_km)| DispatchCandidate {
-                pilot_id,
-                distance_meters: Some(distance_km * 1000.0),
-            })
-            .collect())
+    async fn current_assignment(&self, pilot_id: &str) -> CoreResult<Option<String>> {
+        let mut conn = self.connection().await?;
+        redis::cmd("GET")
+            .arg(self.assignment_key(pilot_id))
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
     }
 
-    async fn mark_assigned(&self, pilot_id: &str, ride_id: &str) -> CoreResult<()> {
+    async fn create_offer(
+        &self,
+        ride_id: &str,
+        pilot_id: &str,
+        ttl: Duration,
+    ) -> CoreResult<Option<DispatchOffer>> {
         let mut conn = self.connection().await?;
-        let _: () = redis::pipe()
+        let offer = DispatchOffer::new(ride_id, pilot_id, ttl);
+        // The claim lives with the other pilot state and the offer with its ride, in
+        // different cluster slots, so the pilot is claimed first and freed again if the
+        // offer cannot be written.
+        let claimed: i32 = redis::Script::new(CLAIM_PILOT_SCRIPT)
+            .key(self.claims_key())
+            .arg(pilot_id)
+            .arg(ride_id)
+            .arg(offer.offered_at_ms)
+            .arg(offer.expires_at_ms)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+        if claimed == 0 {
+            return Ok(None);
+        }
+
+        let offer_key = self.offer_key(ride_id);
+        let offered_key = self.offered_key(ride_id);
+        let written: redis::RedisResult<(Option<String>,)> = redis::pipe()
+            .atomic()
+            .cmd("HGET")
+            .arg(&offer_key)
+            .arg("pilot_id")
+            .cmd("DEL")
+            .arg(&offer_key)
+            .ignore()
             .cmd("HSET")
-            .arg(self.status_key())
+            .arg(&offer_key)
+            .arg("pilot_id")
+            .arg(&offer.pilot_id)
+            .arg("offered_at_ms")
+            .arg(offer.offered_at_ms)
+            .arg("expires_at_ms")
+            .arg(offer.expires_at_ms)
+            .ignore()
+            .cmd("PEXPIRE")
+            .arg(&offer_key)
+            .arg((ttl + OFFER_GRACE).as_millis() as u64)
+            .ignore()
+            .cmd("SADD")
+            .arg(&offered_key)
             .arg(pilot_id)
-            .arg("busy")
             .ignore()
-            .cmd("SET")
-            .arg(format!("{}:pilot:{}:ride", self.config.key_prefix, pilot_id))
-            .arg(ride_id)
+            .cmd("EXPIRE")
+            .arg(&offered_key)
+            .arg(OFFERED_PILOTS_TTL_SECS)
+            .ignore()
+            .query_async(&mut conn)
+            .await;
+        let (replaced,) = match written {
+            Ok(replaced) => replaced,
+            Err(err) => {
+                let _ = self.release_claim(pilot_id, ride_id).await;
+                return Err(CoreError::Dispatch(err.to_string()));
+            }
+        };
+        if let Some(replaced) = replaced.filter(|replaced| replaced != pilot_id) {
+            self.release_claim(&replaced, ride_id).await?;
+        }
+        Ok(Some(offer))
+    }
+
+    async fn current_offer(&self, ride_id: &str) -> CoreResult<Option<DispatchOffer>> {
+        let mut conn = self.connection().await?;
+        let fields: HashMap<String, String> = redis::cmd("HGETALL")
+            .arg(self.offer_key(ride_id))
             .query_async(&mut conn)
             .await
             .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+
+        let parse = |name: &str| -> CoreResult<u64> {
+            fields
+                .get(name)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| CoreError::Dispatch(format!("offer missing {name}")))
+        };
+        match fields.get("pilot_id") {
+            Some(pilot_id) => Ok(Some(DispatchOffer {
+                ride_id: ride_id.to_string(),
+                pilot_id: pilot_id.clone(),
+                offered_at_ms: parse("offered_at_ms")?,
+                expires_at_ms: parse("expires_at_ms")?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn take_offer(
+        &self,
+        ride_id: &str,
+        pilot_id: &str,
+    ) -> CoreResult<Option<DispatchOffer>> {
+        let mut conn = self.connection().await?;
+        let taken: Option<(u64, u64)> = redis::Script::new(TAKE_OFFER_SCRIPT)
+            .key(self.offer_key(ride_id))
+            .arg(pilot_id)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+        if taken.is_some() {
+            self.release_claim(pilot_id, ride_id).await?;
+        }
+
+        Ok(taken.map(|(offered_at_ms, expires_at_ms)| DispatchOffer {
+            ride_id: ride_id.to_string(),
+            pilot_id: pilot_id.to_string(),
+            offered_at_ms,
+            expires_at_ms,
+        }))
+    }
+
+    async fn offered_pilots(&self, ride_id: &str) -> CoreResult<Vec<String>> {
+        let mut conn = self.connection().await?;
+        redis::cmd("SMEMBERS")
+            .arg(self.offered_key(ride_id))
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+    }
+
+    async fn health_check(&self) -> CoreResult<()> {
+        let mut conn = self.connection().await?;
+        redis::cmd("PING")
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+            .map(|_: String| ())
+    }
+}
+
+/// Pending rides live in a sorted set scored by request time.
+#[async_trait]
+impl PendingRideQueue for RedisDispatchEngine {
+    async fn enqueue(&self, ride_id: &str, requested_at_ms: u64) -> CoreResult<()> {
+        let mut conn = self.connection().await?;
+        redis::cmd("ZADD")
+            .arg(self.pending_key())
+            .arg("NX")
+            .arg(requested_at_ms)
+            .arg(ride_id)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+            .map(|_: i32| ())
+    }
+
+    async fn remove(&self, ride_id: &str) -> CoreResult<()> {
+        let mut conn = self.connection().await?;
+        redis::cmd("ZREM")
+            .arg(self.pending_key())
+            .arg(ride_id)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+            .map(|_: i32| ())
+    }
+
+    async fn pending(&self, limit: usize) -> CoreResult<Vec<PendingRide>> {
+        self.oldest_first(self.pending_key(), limit).await
+    }
+
+    async fn flag(&self, ride_id: &str) -> CoreResult<()> {
+        let mut conn = self.connection().await?;
+        redis::Script::new(FLAG_RIDE_SCRIPT)
+            .key(self.pending_key())
+            .key(self.flagged_key())
+            .arg(ride_id)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+            .map(|_: i32| ())
+    }
+
+    async fn flagged(&self, limit: usize) -> CoreResult<Vec<PendingRide>> {
+        self.oldest_first(self.flagged_key(), limit).await
+    }
+
+    async fn is_flagged(&self, ride_id: &str) -> CoreResult<bool> {
+        let mut conn = self.connection().await?;
+        redis::cmd("ZSCORE")
+            .arg(self.flagged_key())
+            .arg(ride_id)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))
+            .map(|score: Option<f64>| score.is_some())
+    }
+}
+
+/// Breadcrumbs are appended to Redis Streams capped with `MAXLEN ~`. Like the in-memory
+/// history, [`LocationHistory::pilot_trail`] windows on each point's `recorded_at_ms`, not
+/// on the stream ID, which is when the server appended it.
+#[async_trait]
+impl LocationHistory for RedisDispatchEngine {
+    async fn record(
+        &self,
+        pilot_id: &str,
+        ride_id: Option<&str>,
+        location: &RideLocation,
+        recorded_at_ms: u64,
+    ) -> CoreResult<()> {
+        let mut conn = self.connection().await?;
+        let mut keys = vec![self.pilot_trail_key(pilot_id)];
+        keys.extend(ride_id.map(|ride_id| self.ride_trail_key(ride_id)));
+
+        // Pilot and ride trails hash to different cluster slots, and cluster connections
+        // only pipeline commands for one slot, so each trail gets its own XADD.
+        for key in keys {
+            let _: String = redis::cmd("XADD")
+                .arg(key)
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(self.trail_max_len)
+                .arg("*")
+                .arg("pilot_id")
+                .arg(pilot_id)
+                .arg("lat")
+                .arg(location.lat)
+                .arg("lng")
+                .arg(location.lng)
+                .arg("recorded_at_ms")
+                .arg(recorded_at_ms)
+                .query_async(&mut conn)
+                .await
+                .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+        }
         Ok(())
     }
+
+    async fn pilot_trail(
+        &self,
+        pilot_id: &str,
+        since_ms: u64,
+        until_ms: u64,
+    ) -> CoreResult<Vec<Breadcrumb>> {
+        // A point is appended no earlier than it was recorded, give or take clock skew, so
+        // entries added before the window opened are skipped; later ones may still carry
+        // an older timestamp, so the rest of the stream is filtered on it.
+        let start = since_ms.saturating_sub(TRAIL_CLOCK_SKEW.as_millis() as u64);
+        let trail = self
+            .read_trail(self.pilot_trail_key(pilot_id), start.to_string(), "+".to_string())
+            .await?;
+        Ok(trail
+            .into_iter()
+            .filter(|crumb| (since_ms..=until_ms).contains(&crumb.recorded_at_ms))
+            .collect())
+    }
+
+    async fn ride_trail(&self, ride_id: &str) -> CoreResult<Vec<Breadcrumb>> {
+        self.read_trail(self.ride_trail_key(ride_id), "-".to_string(), "+".to_string())
+            .await
+    }
+}
+
+/// Decisions go to a single Redis Stream trimmed per [`EventRetention`]: `MAXLEN ~` on
+/// append and `XTRIM MINID ~` for the age limit.
+#[async_trait]
+impl DispatchEventLog for RedisDispatchEngine {
+    async fn append(&self, decision: DispatchDecision) -> CoreResult<String> {
+        let mut conn = self.connection().await?;
+        let recorded_at_ms = now_millis();
+        let payload =
+            serde_json::to_string(&decision).map_err(|err| CoreError::Dispatch(err.to_string()))?;
+
+        let mut pipe = redis::pipe();
+        let xadd = pipe.cmd("XADD").arg(self.events_key());
+        if let Some(max_len) = self.event_retention.max_len {
+            xadd.arg("MAXLEN").arg("~").arg(max_len);
+        }
+        xadd.arg("*")
+            .arg("kind")
+            .arg(decision.kind())
+            .arg("recorded_at_ms")
+            .arg(recorded_at_ms)
+            .arg("decision")
+            .arg(payload);
+        if let Some(max_age_secs) = self.event_retention.max_age_secs {
+            let min_id = recorded_at_ms.saturating_sub(max_age_secs * 1000);
+            pipe.cmd("XTRIM")
+                .arg(self.events_key())
+                .arg("MINID")
+                .arg("~")
+                .arg(min_id)
+                .ignore();
+        }
+
+        let (id,): (String,) = pipe
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+        Ok(id)
+    }
+
+    async fn read_after(
+        &self,
+        after_id: Option<&str>,
+        limit: usize,
+    ) -> CoreResult<Vec<DispatchLogEntry>> {
+        let mut conn = self.connection().await?;
+        let start = after_id.map_or("-".to_string(), |id| format!("({id}"));
+        let reply: StreamRangeReply = redis::cmd("XRANGE")
+            .arg(self.events_key())
+            .arg(start)
+            .arg("+")
+            .arg("COUNT")
+            .arg(limit)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| CoreError::Dispatch(err.to_string()))?;
+        reply.ids.iter().map(parse_log_entry).collect()
+    }
+}
+
+#[cfg(test)]
+mod conformance_tests {
+    use supportcarr_core::dispatch::conformance;
+
+    use super::*;
+    use crate::test_server::TestRedis;
+
+    #[tokio::test]
+    async fn redis_engine_conforms() {
+        let server = TestRedis::start().await;
+        let config = DispatchEngineConfig {
+            key_prefix: format!("conformance-{}-{}", std::process::id(), now_millis()),
+            ..DispatchEngineConfig::default()
+        };
+        let engine = RedisDispatchEngine::new(server.client(), config);
+        conformance::run(&engine).await;
+    }
+
+    #[tokio::test]
+    async fn redis_cluster_engine_conforms() {
+        let server = TestRedis::start_cluster().await;
+        let engine = RedisDispatchEngine::with_backend(
+            RedisBackend::Cluster {
+                nodes: vec![server.url.clone()],
+            },
+            DispatchEngineConfig::default(),
+            RedisConnectionConfig::default(),
+        );
+        conformance::run(&engine).await;
+    }
 }
 
-#[cfg(all(test, feature = "redis-tests"))]
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_server::TestRedis;
     use supportcarr_core::dispatch::DispatchEngine;
+    use supportcarr_core::events::DispatchEventLog;
+    use supportcarr_core::history::LocationHistory;
+    use supportcarr_core::queue::PendingRideQueue;
 
-    fn url() -> Option<String> {
-        std::env::var("REDIS_URL").ok()
+    /// An engine on its own test server, under a prefix of its own in case that server is
+    /// a shared `REDIS_URL`.
+    fn config(name: &str) -> DispatchEngineConfig {
+        DispatchEngineConfig {
+            key_prefix: format!("supportcarr:test:{name}:{}-{}", std::process::id(), now_millis()),
+            ..DispatchEngineConfig::default()
+        }
     }
 
-    fn client() -> Option<redis::Client> {
-        url().and_then(|url| redis::Client::open(url).ok())
+    async fn engine(name: &str) -> (TestRedis, RedisDispatchEngine) {
+        let server = TestRedis::start().await;
+        let engine = RedisDispatchEngine::new(server.client(), config(name));
+        (server, engine)
     }
 
     #[tokio::test]
     async fn round_trip_geo_commands() {
-        let client = match client() {
-            Some(c) => c,
-            None => return,
-        };
-
-        let engine = RedisDispatchEngine::new(client, DispatchEngineConfig::default());
+        let (_server, engine) = engine("geo").await;
         let location = RideLocation { lat: 34.0, lng: -118.0 };
         engine
             .store_pilot_location("pilot-1", &location)
@@ -149,10 +1303,304 @@ mod tests {
             .await
             .expect("find pilots");
         assert!(!nearby.is_empty());
+        assert_eq!(nearby[0].location.as_ref().map(|l| l.lat.round()), Some(34.0));
+
+        let bounds = BoundingBox::new(
+            RideLocation::new(33.9, -118.1),
+            RideLocation::new(34.1, -117.9),
+        );
+        let boxed = engine
+            .find_pilots_in_box(&bounds, 5)
+            .await
+            .expect("find pilots in box");
+        assert!(boxed.iter().any(|c| c.pilot_id == "pilot-1"));
+
+        let zone = ServiceZone::new(
+            "test-zone",
+            vec![
+                RideLocation::new(33.9, -118.1),
+                RideLocation::new(33.9, -117.9),
+                RideLocation::new(34.1, -118.0),
+            ],
+        );
+        let zoned = engine
+            .find_pilots_in_zone(&zone, 5)
+            .await
+            .expect("find pilots in zone");
+        assert!(zoned.iter().any(|c| c.pilot_id == "pilot-1"));
+
+        let stored = engine
+            .store_pilots_in_box(&bounds, &engine.snapshot_key("test-zone"))
+            .await
+            .expect("store pilots in box");
+        assert!(stored >= 1);
 
         engine
             .mark_assigned("pilot-1", "ride-123")
             .await
             .expect("mark assigned");
+        assert_eq!(
+            engine.current_assignment("pilot-1").await.expect("assignment"),
+            Some("ride-123".to_string())
+        );
+
+        engine
+            .update_pilot("pilot-2", &location, true)
+            .await
+            .expect("update pilot");
+        engine
+            .store_pilot_locations(&[
+                ("pilot-3".to_string(), location.clone()),
+                ("pilot-4".to_string(), location.clone()),
+            ])
+            .await
+            .expect("store pilot locations");
+        let nearby = engine
+            .find_nearby_pilots(&location, 1.0, 10)
+            .await
+            .expect("find pilots");
+        for pilot in ["pilot-2", "pilot-3", "pilot-4"] {
+            assert!(nearby.iter().any(|c| c.pilot_id == pilot), "{pilot}");
+        }
+    }
+
+    #[tokio::test]
+    async fn cluster_backend_keeps_multi_key_commands_in_one_slot() {
+        let server = TestRedis::start_cluster().await;
+        let engine = RedisDispatchEngine::with_backend(
+            RedisBackend::Cluster {
+                nodes: vec![server.url.clone()],
+            },
+            DispatchEngineConfig::default(),
+            RedisConnectionConfig::default(),
+        );
+        let location = RideLocation::new(34.0, -118.0);
+
+        engine
+            .update_pilot("pilot-1", &location, true)
+            .await
+            .expect("update pilot");
+        let bounds = BoundingBox::new(
+            RideLocation::new(33.9, -118.1),
+            RideLocation::new(34.1, -117.9),
+        );
+        let stored = engine
+            .store_pilots_in_box(&bounds, &engine.snapshot_key("zone"))
+            .await
+            .expect("store pilots in box");
+        assert_eq!(stored, 1);
+
+        engine
+            .record("pilot-1", Some("ride-1"), &location, now_millis())
+            .await
+            .expect("record breadcrumb");
+        assert_eq!(engine.ride_trail("ride-1").await.expect("ride trail").len(), 1);
+
+        engine.enqueue("ride-1", 1).await.expect("enqueue");
+        engine.flag("ride-1").await.expect("flag");
+        assert_eq!(engine.flagged(10).await.expect("flagged").len(), 1);
+
+        engine
+            .create_event_group("workers", true)
+            .await
+            .expect("create group");
+        engine
+            .append(DispatchDecision::Assign {
+                ride_id: "ride-1".to_string(),
+                pilot_id: "pilot-1".to_string(),
+            })
+            .await
+            .expect("append");
+        let read = engine
+            .read_event_group("workers", "worker-1", 10, Some(Duration::from_millis(50)))
+            .await
+            .expect("blocking group read");
+        assert_eq!(read.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn offers_are_taken_once_by_their_pilot() {
+        let (_server, engine) = engine("offers").await;
+        let offer = engine
+            .create_offer("ride-offer", "pilot-1", Duration::from_secs(30))
+            .await
+            .expect("create offer")
+            .expect("pilot is free");
+        assert_eq!(
+            engine.current_offer("ride-offer").await.expect("current offer"),
+            Some(offer.clone())
+        );
+
+        assert_eq!(
+            engine.take_offer("ride-offer", "pilot-2").await.expect("take"),
+            None
+        );
+        assert_eq!(
+            engine.take_offer("ride-offer", "pilot-1").await.expect("take"),
+            Some(offer)
+        );
+        assert_eq!(
+            engine.take_offer("ride-offer", "pilot-1").await.expect("take"),
+            None
+        );
+        assert!(engine
+            .offered_pilots("ride-offer")
+            .await
+            .expect("offered pilots")
+            .contains(&"pilot-1".to_string()));
+    }
+
+    #[tokio::test]
+    async fn breadcrumbs_replay_in_order() {
+        let (_server, engine) = engine("breadcrumbs").await;
+        let ride_id = format!("ride-track-{}", std::process::id());
+        for (i, lat) in [34.0, 34.01, 34.02].into_iter().enumerate() {
+            let ride = (i > 0).then_some(ride_id.as_str());
+            engine
+                .record("pilot-track", ride, &RideLocation::new(lat, -118.0), i as u64)
+                .await
+                .expect("record breadcrumb");
+        }
+
+        let trail = engine.ride_trail(&ride_id).await.expect("ride trail");
+        assert_eq!(trail.len(), 2);
+        assert_eq!(trail[0].location, RideLocation::new(34.01, -118.0));
+        assert_eq!(trail[1].recorded_at_ms, 2);
+        let pilot = engine
+            .pilot_trail("pilot-track", 0, u64::MAX)
+            .await
+            .expect("pilot trail");
+        assert_eq!(pilot.len(), 3);
+
+        // Windows apply to when points were recorded, not when they reached the server.
+        let window = engine
+            .pilot_trail("pilot-track", 1, 1)
+            .await
+            .expect("pilot trail");
+        assert_eq!(window.len(), 1);
+        assert_eq!(window[0].location, RideLocation::new(34.01, -118.0));
+        let now = now_millis();
+        engine
+            .record("pilot-late", None, &RideLocation::new(34.0, -118.0), now - 120_000)
+            .await
+            .expect("record breadcrumb");
+        let recent = engine
+            .pilot_trail("pilot-late", now - 60_000, now)
+            .await
+            .expect("pilot trail");
+        assert!(recent.is_empty());
+        let uploaded_late = engine
+            .pilot_trail("pilot-late", now - 180_000, now - 60_000)
+            .await
+            .expect("pilot trail");
+        assert_eq!(uploaded_late.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn dispatch_events_flow_through_consumer_group() {
+        let (_server, engine) = engine("events").await;
+        engine
+            .create_event_group("processors", true)
+            .await
+            .expect("create group");
+        engine
+            .create_event_group("processors", true)
+            .await
+            .expect("existing group is fine");
+
+        let decision = DispatchDecision::Release {
+            ride_id: "ride-1".to_string(),
+            pilot_id: "pilot-1".to_string(),
+            reason: "declined".to_string(),
+        };
+        let id = engine.append(decision.clone()).await.expect("append");
+        let replay = engine.read_after(None, 10).await.expect("read");
+        assert_eq!(replay.len(), 1);
+        assert_eq!(replay[0].id, id);
+        assert!(engine.read_after(Some(&id), 10).await.expect("read").is_empty());
+
+        let delivered = engine
+            .read_event_group("processors", "worker-1", 10, Some(Duration::from_millis(100)))
+            .await
+            .expect("read group");
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].decision, decision);
+
+        let stale = engine
+            .claim_stale_events("processors", "worker-2", Duration::ZERO, 10)
+            .await
+            .expect("claim");
+        assert_eq!(stale.len(), 1);
+        assert_eq!(
+            engine.ack_events("processors", &[id]).await.expect("ack"),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn blocking_reads_outlast_the_response_timeout() {
+        let server = TestRedis::start().await;
+        let engine = RedisDispatchEngine::with_connection_config(
+            server.client(),
+            config("blocking"),
+            RedisConnectionConfig {
+                response_timeout: Duration::from_millis(50),
+                ..RedisConnectionConfig::default()
+            },
+        );
+        engine
+            .create_event_group("processors", true)
+            .await
+            .expect("create group");
+
+        let writer = engine.clone();
+        let append = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            writer
+                .append(DispatchDecision::Assign {
+                    ride_id: "ride-1".to_string(),
+                    pilot_id: "pilot-1".to_string(),
+                })
+                .await
+        });
+        let delivered = engine
+            .read_event_group("processors", "worker-1", 10, Some(Duration::from_secs(5)))
+            .await
+            .expect("read group");
+        assert_eq!(delivered.len(), 1);
+        append.await.unwrap().expect("append");
+
+        // An empty read waits out its whole window, longer than the response timeout.
+        let started = tokio::time::Instant::now();
+        let empty = engine
+            .read_event_group("processors", "worker-1", 10, Some(Duration::from_millis(150)))
+            .await
+            .expect("read group");
+        assert!(empty.is_empty());
+        assert!(started.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn pending_queue_keeps_request_order() {
+        let (_server, engine) = engine("queue").await;
+        engine.enqueue("ride-late", 2_000).await.expect("enqueue");
+        engine.enqueue("ride-early", 1_000).await.expect("enqueue");
+        engine.enqueue("ride-early", 3_000).await.expect("re-enqueue");
+
+        let pending = engine.pending(10).await.expect("pending");
+        assert_eq!(pending[0].ride_id, "ride-early");
+        assert_eq!(pending[0].requested_at_ms, 1_000);
+
+        engine.flag("ride-early").await.expect("flag");
+        assert!(engine
+            .flagged(10)
+            .await
+            .expect("flagged")
+            .iter()
+            .any(|ride| ride.ride_id == "ride-early"));
+        assert!(engine.is_flagged("ride-early").await.expect("is flagged"));
+        assert!(!engine.is_flagged("ride-late").await.expect("is flagged"));
+        engine.remove("ride-late").await.expect("remove");
+        assert!(engine.pending(10).await.expect("pending").is_empty());
     }
 }
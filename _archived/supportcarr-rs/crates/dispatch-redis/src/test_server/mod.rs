@@ -0,0 +1,1000 @@
+//! Redis for tests that must not skip: `REDIS_URL` when set, otherwise a `redis-server`
+//! from `PATH`, otherwise an embedded stand-in speaking enough RESP for every Redis-backed
+//! store in the workspace: strings, hashes, sets, sorted sets, GEO search, streams with
+//! consumer groups, `MULTI`/`EXEC` and Lua scripts. [`TestRedis::start_cluster`] always
+//! runs the stand-in, as a one-node cluster that rejects cross-slot commands.
+//!
+//! Enabled in other crates' tests through the `test-support` feature:
+//!
+//! ```ignore
+//! let redis = TestRedis::start().await;
+//! let engine = RedisDispatchEngine::new(redis.client(), config);
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use supportcarr_core::geo::haversine_miles;
+use supportcarr_core::model::RideLocation;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use crate::KM_PER_MILE;
+
+mod scripting;
+mod streams;
+
+use streams::Stream;
+
+// How often a blocked `XREADGROUP` looks for new entries.
+const BLOCK_POLL: Duration = Duration::from_millis(5);
+
+/// A Redis server that lives as long as the value. Each stand-in starts empty; a shared
+/// `REDIS_URL` server does not, so tests should still use a unique key prefix.
+pub struct TestRedis {
+    pub url: String,
+    server: Option<Child>,
+    stand_in: Option<JoinHandle<()>>,
+}
+
+impl TestRedis {
+    pub async fn start() -> Self {
+        if let Ok(url) = std::env::var("REDIS_URL") {
+            return Self {
+                url,
+                server: None,
+                stand_in: None,
+            };
+        }
+        if let Some(redis) = Self::spawn_server().await {
+            return redis;
+        }
+
+        Self::stand_in(false).await
+    }
+
+    /// A one-node Redis Cluster: the stand-in answers `CLUSTER SLOTS` with itself for
+    /// every slot and, like a real cluster, fails commands and transactions whose keys
+    /// hash to different slots. Connect with `RedisBackend::Cluster { nodes: vec![url] }`.
+    pub async fn start_cluster() -> Self {
+        Self::stand_in(true).await
+    }
+
+    async fn stand_in(cluster: bool) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind stand-in");
+        let addr = listener.local_addr().expect("stand-in address");
+        let store = Arc::new(Mutex::new(Store {
+            cluster_node: cluster.then_some(addr),
+            ..Store::default()
+        }));
+        let stand_in = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, store.clone(), cluster));
+            }
+        });
+        Self {
+            url: format!("redis://{addr}"),
+            server: None,
+            stand_in: Some(stand_in),
+        }
+    }
+
+    pub fn client(&self) -> redis::Client {
+        redis::Client::open(self.url.as_str()).expect("test Redis client")
+    }
+
+    async fn spawn_server() -> Option<Self> {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .ok()?
+            .local_addr()
+            .ok()?
+            .port();
+        let server = Command::new("redis-server")
+            .args([
+                "--port",
+                &port.to_string(),
+                "--save",
+                "",
+                "--appendonly",
+                "no",
+            ])
+            .stdout(Stdio::null())
+            .spawn()
+            .ok()?;
+        let redis = Self {
+            url: format!("redis://127.0.0.1:{port}"),
+            server: Some(server),
+            stand_in: None,
+        };
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        for _ in 0..50 {
+            if TcpStream::connect(addr).await.is_ok() {
+                return Some(redis);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        None
+    }
+}
+
+impl Drop for TestRedis {
+    fn drop(&mut self) {
+        if let Some(server) = &mut self.server {
+            let _ = server.kill();
+            let _ = server.wait();
+        }
+        if let Some(stand_in) = &self.stand_in {
+            stand_in.abort();
+        }
+    }
+}
+
+enum Entry {
+    String(String),
+    Hash(HashMap<String, String>),
+    Set(BTreeSet<String>),
+    /// Member to score.
+    SortedSet(HashMap<String, f64>),
+    /// Member to `(lng, lat)`.
+    Geo(HashMap<String, (f64, f64)>),
+    Stream(Stream),
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<String, Entry>,
+    expires: HashMap<String, Instant>,
+    /// Loaded scripts by SHA1.
+    scripts: HashMap<String, String>,
+    /// Set when the stand-in plays a one-node cluster.
+    cluster_node: Option<SocketAddr>,
+}
+
+enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Reply::Status("OK".to_string())
+    }
+
+    fn is_nil(&self) -> bool {
+        matches!(self, Reply::Bulk(None))
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Status(status) => out.extend_from_slice(format!("+{status}\r\n").as_bytes()),
+            Reply::Error(message) => out.extend_from_slice(format!("-{message}\r\n").as_bytes()),
+            Reply::Integer(n) => out.extend_from_slice(format!(":{n}\r\n").as_bytes()),
+            Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Bulk(Some(value)) => {
+                out.extend_from_slice(format!("${}\r\n{value}\r\n", value.len()).as_bytes())
+            }
+            Reply::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+        }
+    }
+}
+
+const WRONG_TYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+const CROSS_SLOT: &str = "CROSSSLOT Keys in request don't hash to the same slot";
+
+async fn serve(stream: TcpStream, store: Arc<Mutex<Store>>, cluster: bool) {
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    // Commands queued since `MULTI`.
+    let mut transaction: Option<Vec<Vec<String>>> = None;
+    while let Some(args) = read_command(&mut reader).await {
+        let name = args
+            .first()
+            .map(|name| name.to_uppercase())
+            .unwrap_or_default();
+        let reply = match (name.as_str(), transaction.as_mut()) {
+            ("MULTI", None) => {
+                transaction = Some(Vec::new());
+                Reply::ok()
+            }
+            ("EXEC", Some(_)) => {
+                let queued = transaction.take().unwrap_or_default();
+                if cluster && !same_slot(queued.iter().flat_map(|args| command_keys(args))) {
+                    Reply::Error(format!("EXECABORT {CROSS_SLOT}"))
+                } else {
+                    let mut store = store.lock().unwrap();
+                    Reply::Array(queued.iter().map(|args| store.execute(args)).collect())
+                }
+            }
+            ("DISCARD", Some(_)) => {
+                transaction = None;
+                Reply::ok()
+            }
+            (_, Some(queued)) => {
+                queued.push(args);
+                Reply::Status("QUEUED".to_string())
+            }
+            _ if cluster && !same_slot(command_keys(&args)) => {
+                Reply::Error(CROSS_SLOT.to_string())
+            }
+            _ => execute(&store, &args).await,
+        };
+        let mut out = Vec::new();
+        reply.encode(&mut out);
+        if write.write_all(&out).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Run one command, polling while a blocking read has nothing to return yet.
+async fn execute(store: &Mutex<Store>, args: &[String]) -> Reply {
+    let deadline = block_for(args).map(|block| tokio::time::Instant::now() + block);
+    loop {
+        let reply = store.lock().unwrap().execute(args);
+        match deadline {
+            Some(deadline) if reply.is_nil() && tokio::time::Instant::now() < deadline => {
+                tokio::time::sleep(BLOCK_POLL).await
+            }
+            _ => return reply,
+        }
+    }
+}
+
+/// The keys a command touches, for the cluster's slot check.
+fn command_keys(args: &[String]) -> Vec<&str> {
+    let name = args.first().map(|name| name.to_uppercase()).unwrap_or_default();
+    let keys: Vec<&String> = match name.as_str() {
+        "PING" | "CLIENT" | "CLUSTER" | "SCRIPT" | "MULTI" | "EXEC" | "DISCARD" => Vec::new(),
+        "MGET" | "DEL" => args.iter().skip(1).collect(),
+        "GEOSEARCHSTORE" => args.iter().skip(1).take(2).collect(),
+        "ZINTERSTORE" => {
+            let count = args.get(2).and_then(|count| count.parse().ok()).unwrap_or(0);
+            args.iter().skip(1).take(1).chain(args.iter().skip(3).take(count)).collect()
+        }
+        "EVAL" | "EVALSHA" => {
+            let count = args.get(2).and_then(|count| count.parse().ok()).unwrap_or(0);
+            args.iter().skip(3).take(count).collect()
+        }
+        "XREADGROUP" => {
+            let streams = args
+                .iter()
+                .position(|arg| arg.eq_ignore_ascii_case("STREAMS"))
+                .map_or(&[][..], |at| &args[at + 1..]);
+            streams.iter().take(streams.len() / 2).collect()
+        }
+        "GEORADIUS" => {
+            let store = args
+                .iter()
+                .position(|arg| arg.eq_ignore_ascii_case("STORE"))
+                .and_then(|at| args.get(at + 1));
+            args.get(1).into_iter().chain(store).collect()
+        }
+        _ => args.get(1).into_iter().collect(),
+    };
+    keys.into_iter().map(String::as_str).collect()
+}
+
+fn same_slot<'a>(keys: impl IntoIterator<Item = &'a str>) -> bool {
+    let mut slots = keys
+        .into_iter()
+        .map(|key| redis::cluster_routing::get_slot(key.as_bytes()));
+    let first = slots.next();
+    slots.all(|slot| Some(slot) == first)
+}
+
+/// The `BLOCK` argument of an `XREADGROUP`; `BLOCK 0` waits for a day.
+fn block_for(args: &[String]) -> Option<Duration> {
+    if !args.first()?.eq_ignore_ascii_case("XREADGROUP") {
+        return None;
+    }
+    let at = args
+        .iter()
+        .position(|arg| arg.eq_ignore_ascii_case("BLOCK"))?;
+    match args.get(at + 1)?.parse().ok()? {
+        0 => Some(Duration::from_secs(24 * 60 * 60)),
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+/// One RESP array of bulk strings, or `None` once the client hangs up.
+async fn read_command<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<Vec<String>> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+    let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line).await.ok()?;
+        let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+        let mut data = vec![0; len + 2];
+        reader.read_exact(&mut data).await.ok()?;
+        data.truncate(len);
+        args.push(String::from_utf8(data).ok()?);
+    }
+    Some(args)
+}
+
+/// Read access to a key of one type, and write access that creates it when missing.
+macro_rules! typed {
+    ($get:ident, $get_mut:ident, $variant:ident, $ty:ty) => {
+        fn $get(&self, key: &str) -> Result<Option<&$ty>, String> {
+            match self.entries.get(key) {
+                None => Ok(None),
+                Some(Entry::$variant(value)) => Ok(Some(value)),
+                Some(_) => Err(WRONG_TYPE.to_string()),
+            }
+        }
+
+        fn $get_mut(&mut self, key: &str) -> Result<&mut $ty, String> {
+            let entry = self
+                .entries
+                .entry(key.to_string())
+                .or_insert_with(|| Entry::$variant(Default::default()));
+            match entry {
+                Entry::$variant(value) => Ok(value),
+                _ => Err(WRONG_TYPE.to_string()),
+            }
+        }
+    };
+}
+
+impl Store {
+    typed!(hash, hash_mut, Hash, HashMap<String, String>);
+    typed!(members, members_mut, Set, BTreeSet<String>);
+    typed!(sorted_set, sorted_set_mut, SortedSet, HashMap<String, f64>);
+    typed!(geo, geo_mut, Geo, HashMap<String, (f64, f64)>);
+    typed!(stream, stream_mut, Stream, Stream);
+
+    fn execute(&mut self, args: &[String]) -> Reply {
+        let Some(name) = args.first() else {
+            return Reply::Error("ERR empty command".to_string());
+        };
+        self.expire_keys();
+        let result = match name.to_uppercase().as_str() {
+            "PING" => Ok(Reply::Status("PONG".to_string())),
+            "CLIENT" => Ok(Reply::ok()),
+            "GET" => self.get(args),
+            "MGET" => Ok(self.mget(args)),
+            "SET" => self.set(args),
+            "DEL" => Ok(self.del(args)),
+            "EXPIRE" => self.expire(args, 1000),
+            "PEXPIRE" => self.expire(args, 1),
+            "HSET" => self.hset(args),
+            "HGET" => self.hget(args),
+            "HDEL" => self.hdel(args),
+            "HMGET" => self.hmget(args),
+            "HGETALL" => self.hgetall(args),
+            "SADD" => self.sadd(args),
+            "SMEMBERS" => self.smembers(args),
+            "ZADD" => self.zadd(args),
+            "ZREM" => self.zrem(args),
+            "ZSCORE" => self.zscore(args),
+            "ZRANGE" => self.zrange(args, false),
+            "ZREVRANGE" => self.zrange(args, true),
+            "ZRANGEBYSCORE" => self.zrangebyscore(args),
+            "ZINTERSTORE" => self.zinterstore(args),
+            "GEOADD" => self.geoadd(args),
+            "GEOSEARCH" => self.geosearch(args),
+            "GEOSEARCHSTORE" => self.geosearchstore(args),
+            "GEORADIUS" => self.georadius(args),
+            "XADD" => self.xadd(args),
+            "XRANGE" => self.xrange(args),
+            "XTRIM" => self.xtrim(args),
+            "XGROUP" => self.xgroup(args),
+            "XREADGROUP" => self.xreadgroup(args),
+            "XACK" => self.xack(args),
+            "XAUTOCLAIM" => self.xautoclaim(args),
+            "EVAL" => self.eval(args),
+            "EVALSHA" => self.evalsha(args),
+            "SCRIPT" => self.script(args),
+            "CLUSTER" => self.cluster(args),
+            _ => Err(format!("ERR unknown command '{name}'")),
+        };
+        result.unwrap_or_else(Reply::Error)
+    }
+
+    /// `CLUSTER SLOTS`: this node serves every slot.
+    fn cluster(&self, args: &[String]) -> Result<Reply, String> {
+        let Some(node) = self.cluster_node else {
+            return Err("ERR This instance has cluster support disabled".to_string());
+        };
+        if !arg(args, 1)?.eq_ignore_ascii_case("SLOTS") {
+            return Err(format!("ERR unknown subcommand '{}'", args[1]));
+        }
+        Ok(Reply::Array(vec![Reply::Array(vec![
+            Reply::Integer(0),
+            Reply::Integer(16383),
+            Reply::Array(vec![
+                Reply::Bulk(Some(node.ip().to_string())),
+                Reply::Integer(node.port().into()),
+                Reply::Bulk(Some("stand-in".to_string())),
+            ]),
+        ])]))
+    }
+
+    fn expire_keys(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .expires
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.expires.remove(&key);
+            self.entries.remove(&key);
+        }
+    }
+
+    fn get(&self, args: &[String]) -> Result<Reply, String> {
+        match self.entries.get(arg(args, 1)?) {
+            None => Ok(Reply::Bulk(None)),
+            Some(Entry::String(value)) => Ok(Reply::Bulk(Some(value.clone()))),
+            Some(_) => Err(WRONG_TYPE.to_string()),
+        }
+    }
+
+    fn mget(&self, args: &[String]) -> Reply {
+        Reply::Array(
+            args[1..]
+                .iter()
+                .map(|key| match self.entries.get(key) {
+                    Some(Entry::String(value)) => Reply::Bulk(Some(value.clone())),
+                    _ => Reply::Bulk(None),
+                })
+                .collect(),
+        )
+    }
+
+    fn set(&mut self, args: &[String]) -> Result<Reply, String> {
+        let key = arg(args, 1)?.clone();
+        let value = arg(args, 2)?.clone();
+        self.expires.remove(&key);
+        self.entries.insert(key, Entry::String(value));
+        Ok(Reply::ok())
+    }
+
+    fn del(&mut self, args: &[String]) -> Reply {
+        let removed = args[1..]
+            .iter()
+            .filter(|key| {
+                self.expires.remove(*key);
+                self.entries.remove(*key).is_some()
+            })
+            .count();
+        Reply::Integer(removed as i64)
+    }
+
+    /// `EXPIRE` or `PEXPIRE`, with `unit_ms` milliseconds per unit.
+    fn expire(&mut self, args: &[String], unit_ms: u64) -> Result<Reply, String> {
+        let key = arg(args, 1)?;
+        let ttl = integer(arg(args, 2)?)?;
+        if !self.entries.contains_key(key) {
+            return Ok(Reply::Integer(0));
+        }
+        let ttl = Duration::from_millis((ttl.max(0) as u64).saturating_mul(unit_ms));
+        self.expires.insert(key.clone(), Instant::now() + ttl);
+        Ok(Reply::Integer(1))
+    }
+
+    fn hset(&mut self, args: &[String]) -> Result<Reply, String> {
+        let fields = &args[2.min(args.len())..];
+        if fields.is_empty() || !fields.len().is_multiple_of(2) {
+            return Err("ERR wrong number of arguments for 'hset' command".to_string());
+        }
+        let hash = self.hash_mut(arg(args, 1)?)?;
+        let mut added = 0;
+        for pair in fields.chunks(2) {
+            if hash.insert(pair[0].clone(), pair[1].clone()).is_none() {
+                added += 1;
+            }
+        }
+        Ok(Reply::Integer(added))
+    }
+
+    fn hget(&self, args: &[String]) -> Result<Reply, String> {
+        let hash = self.hash(arg(args, 1)?)?;
+        let field = arg(args, 2)?;
+        Ok(Reply::Bulk(hash.and_then(|hash| hash.get(field)).cloned()))
+    }
+
+    fn hdel(&mut self, args: &[String]) -> Result<Reply, String> {
+        let key = arg(args, 1)?;
+        if self.hash(key)?.is_none() {
+            return Ok(Reply::Integer(0));
+        }
+        let hash = self.hash_mut(key)?;
+        let removed = args[2..]
+            .iter()
+            .filter(|field| hash.remove(*field).is_some())
+            .count();
+        if hash.is_empty() {
+            self.entries.remove(key);
+        }
+        Ok(Reply::Integer(removed as i64))
+    }
+
+    fn hmget(&self, args: &[String]) -> Result<Reply, String> {
+        let hash = self.hash(arg(args, 1)?)?;
+        Ok(Reply::Array(
+            args[2..]
+                .iter()
+                .map(|field| Reply::Bulk(hash.and_then(|hash| hash.get(field)).cloned()))
+                .collect(),
+        ))
+    }
+
+    fn hgetall(&self, args: &[String]) -> Result<Reply, String> {
+        let hash = self.hash(arg(args, 1)?)?;
+        Ok(Reply::Array(
+            hash.into_iter()
+                .flatten()
+                .flat_map(|(field, value)| [bulk(field), bulk(value)])
+                .collect(),
+        ))
+    }
+
+    fn sadd(&mut self, args: &[String]) -> Result<Reply, String> {
+        let set = self.members_mut(arg(args, 1)?)?;
+        let added = args[2..]
+            .iter()
+            .filter(|member| set.insert((*member).clone()))
+            .count();
+        Ok(Reply::Integer(added as i64))
+    }
+
+    fn smembers(&self, args: &[String]) -> Result<Reply, String> {
+        let set = self.members(arg(args, 1)?)?;
+        Ok(Reply::Array(set.into_iter().flatten().map(bulk).collect()))
+    }
+
+    /// `ZADD key [NX] score member [score member ...]`.
+    fn zadd(&mut self, args: &[String]) -> Result<Reply, String> {
+        let key = arg(args, 1)?;
+        let nx = arg(args, 2)?.eq_ignore_ascii_case("NX");
+        let pairs = &args[if nx { 3 } else { 2 }..];
+        if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+            return Err("ERR syntax error".to_string());
+        }
+        let scored = pairs
+            .chunks(2)
+            .map(|pair| Ok((number(&pair[0])?, pair[1].clone())))
+            .collect::<Result<Vec<_>, String>>()?;
+        let set = self.sorted_set_mut(key)?;
+        let mut added = 0;
+        for (score, member) in scored {
+            if nx && set.contains_key(&member) {
+                continue;
+            }
+            if set.insert(member, score).is_none() {
+                added += 1;
+            }
+        }
+        Ok(Reply::Integer(added))
+    }
+
+    fn zrem(&mut self, args: &[String]) -> Result<Reply, String> {
+        let key = arg(args, 1)?;
+        if self.sorted_set(key)?.is_none() {
+            return Ok(Reply::Integer(0));
+        }
+        let set = self.sorted_set_mut(key)?;
+        let removed = args[2..]
+            .iter()
+            .filter(|member| set.remove(*member).is_some())
+            .count();
+        if set.is_empty() {
+            self.entries.remove(key);
+        }
+        Ok(Reply::Integer(removed as i64))
+    }
+
+    fn zscore(&self, args: &[String]) -> Result<Reply, String> {
+        let set = self.sorted_set(arg(args, 1)?)?;
+        let member = arg(args, 2)?;
+        Ok(Reply::Bulk(
+            set.and_then(|set| set.get(member))
+                .map(|score| format_number(*score)),
+        ))
+    }
+
+    /// `ZINTERSTORE destination numkeys key... [WEIGHTS weight...]`, summing weighted
+    /// scores. GEO sets take part with a score of 0, where Redis would use the geohash.
+    fn zinterstore(&mut self, args: &[String]) -> Result<Reply, String> {
+        let destination = arg(args, 1)?.clone();
+        let count = usize::try_from(integer(arg(args, 2)?)?)
+            .map_err(|_| "ERR numkeys should be greater than 0".to_string())?;
+        let keys = args.get(3..3 + count).ok_or("ERR syntax error")?;
+        let weights = match args.get(3 + count) {
+            None => vec![1.0; count],
+            Some(option) if option.eq_ignore_ascii_case("WEIGHTS") => args
+                .get(4 + count..4 + 2 * count)
+                .ok_or("ERR syntax error")?
+                .iter()
+                .map(|weight| number(weight))
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err("ERR syntax error".to_string()),
+        };
+        let mut sets = Vec::with_capacity(count);
+        for key in keys {
+            let scores: HashMap<String, f64> = match self.geo(key) {
+                Ok(geo) => geo
+                    .into_iter()
+                    .flatten()
+                    .map(|(member, _)| (member.clone(), 0.0))
+                    .collect(),
+                Err(_) => self.sorted_set(key)?.cloned().unwrap_or_default(),
+            };
+            sets.push(scores);
+        }
+        let (first, rest) = sets.split_first().ok_or("ERR syntax error")?;
+        let stored: HashMap<String, f64> = first
+            .iter()
+            .filter_map(|(member, score)| {
+                rest.iter()
+                    .zip(&weights[1..])
+                    .try_fold(score * weights[0], |sum, (set, weight)| {
+                        set.get(member).map(|score| sum + score * weight)
+                    })
+                    .map(|sum| (member.clone(), sum))
+            })
+            .collect();
+        let len = stored.len();
+        self.expires.remove(&destination);
+        if stored.is_empty() {
+            self.entries.remove(&destination);
+        } else {
+            self.entries.insert(destination, Entry::SortedSet(stored));
+        }
+        Ok(Reply::Integer(len as i64))
+    }
+
+    /// `ZRANGE`/`ZREVRANGE key start stop [WITHSCORES]` by rank.
+    fn zrange(&self, args: &[String], reverse: bool) -> Result<Reply, String> {
+        let mut ranked = ranked(self.sorted_set(arg(args, 1)?)?);
+        if reverse {
+            ranked.reverse();
+        }
+        let len = ranked.len() as i64;
+        let index = |value: i64| if value < 0 { len + value } else { value };
+        let start = index(integer(arg(args, 2)?)?).max(0);
+        let stop = index(integer(arg(args, 3)?)?).min(len - 1);
+        let with_scores = args
+            .get(4)
+            .is_some_and(|option| option.eq_ignore_ascii_case("WITHSCORES"));
+        let selected = if start > stop {
+            &[][..]
+        } else {
+            &ranked[start as usize..=stop as usize]
+        };
+        Ok(scored_reply(selected, with_scores))
+    }
+
+    /// `ZRANGEBYSCORE key min max [WITHSCORES] [LIMIT offset count]`.
+    fn zrangebyscore(&self, args: &[String]) -> Result<Reply, String> {
+        let ranked = ranked(self.sorted_set(arg(args, 1)?)?);
+        let (min, min_open) = score_bound(arg(args, 2)?)?;
+        let (max, max_open) = score_bound(arg(args, 3)?)?;
+        let mut with_scores = false;
+        let (mut offset, mut count) = (0, usize::MAX);
+        let mut at = 4;
+        while at < args.len() {
+            if args[at].eq_ignore_ascii_case("WITHSCORES") {
+                with_scores = true;
+            } else if args[at].eq_ignore_ascii_case("LIMIT") {
+                offset = integer(arg(args, at + 1)?)?.max(0) as usize;
+                count = usize::try_from(integer(arg(args, at + 2)?)?).unwrap_or(usize::MAX);
+                at += 2;
+            } else {
+                return Err("ERR syntax error".to_string());
+            }
+            at += 1;
+        }
+        let selected: Vec<_> = ranked
+            .into_iter()
+            .filter(|(_, score)| {
+                if min_open {
+                    *score > min
+                } else {
+                    *score >= min
+                }
+            })
+            .filter(|(_, score)| {
+                if max_open {
+                    *score < max
+                } else {
+                    *score <= max
+                }
+            })
+            .skip(offset)
+            .take(count)
+            .collect();
+        Ok(scored_reply(&selected, with_scores))
+    }
+
+    fn geoadd(&mut self, args: &[String]) -> Result<Reply, String> {
+        let triples = &args[2.min(args.len())..];
+        if triples.is_empty() || !triples.len().is_multiple_of(3) {
+            return Err("ERR syntax error".to_string());
+        }
+        let located = triples
+            .chunks(3)
+            .map(|triple| {
+                Ok((
+                    triple[2].clone(),
+                    (number(&triple[0])?, number(&triple[1])?),
+                ))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let members = self.geo_mut(arg(args, 1)?)?;
+        let mut added = 0;
+        for (member, at) in located {
+            if members.insert(member, at).is_none() {
+                added += 1;
+            }
+        }
+        Ok(Reply::Integer(added))
+    }
+
+    /// `GEOSEARCH key FROMLONLAT lng lat (BYRADIUS r unit | BYBOX w h unit)
+    /// [WITHDIST] [WITHCOORD] [ASC] [COUNT n]`, with distances in km. Replies always carry
+    /// distance and coordinates, which is all the engine asks for.
+    fn geosearch(&self, args: &[String]) -> Result<Reply, String> {
+        let (hits, rest) = self.geo_search_from(args, 1)?;
+        Ok(geo_reply(args, hits, count_option(args, rest)?))
+    }
+
+    /// `GEOSEARCHSTORE destination key FROMLONLAT lng lat (BYRADIUS ... | BYBOX ...)`.
+    fn geosearchstore(&mut self, args: &[String]) -> Result<Reply, String> {
+        let (hits, rest) = self.geo_search_from(args, 2)?;
+        let count = count_option(args, rest)?;
+        Ok(self.store_geo(arg(args, 1)?, hits, count))
+    }
+
+    /// `GEORADIUS key lng lat radius unit [WITHDIST] [WITHCOORD] [ASC] [COUNT n]
+    /// [STORE destination]`.
+    fn georadius(&mut self, args: &[String]) -> Result<Reply, String> {
+        let center = RideLocation::new(number(arg(args, 3)?)?, number(arg(args, 2)?)?);
+        let shape = Shape::Radius(number(arg(args, 4)?)? * unit_km(arg(args, 5)?)?);
+        let hits = self.geo_hits(arg(args, 1)?, &center, &shape)?;
+        let count = count_option(args, 6)?;
+        match args
+            .iter()
+            .position(|arg| arg.eq_ignore_ascii_case("STORE"))
+        {
+            Some(at) => Ok(self.store_geo(arg(args, at + 1)?, hits, count)),
+            None => Ok(geo_reply(args, hits, count)),
+        }
+    }
+
+    /// Members matching the `key FROMLONLAT lng lat shape` starting at `args[at]`, and the
+    /// index of the first option after them.
+    fn geo_search_from(&self, args: &[String], at: usize) -> Result<(Vec<GeoHit>, usize), String> {
+        if !arg(args, at + 1)?.eq_ignore_ascii_case("FROMLONLAT") {
+            return Err("ERR only FROMLONLAT is supported".to_string());
+        }
+        let center = RideLocation::new(number(arg(args, at + 3)?)?, number(arg(args, at + 2)?)?);
+        let shape_at = at + 4;
+        let (shape, rest) = match arg(args, shape_at)?.to_uppercase().as_str() {
+            "BYRADIUS" => (
+                Shape::Radius(
+                    number(arg(args, shape_at + 1)?)? * unit_km(arg(args, shape_at + 2)?)?,
+                ),
+                shape_at + 3,
+            ),
+            "BYBOX" => {
+                let unit = unit_km(arg(args, shape_at + 3)?)?;
+                let shape = Shape::Box {
+                    half_width: number(arg(args, shape_at + 1)?)? * unit / 2.0,
+                    half_height: number(arg(args, shape_at + 2)?)? * unit / 2.0,
+                };
+                (shape, shape_at + 4)
+            }
+            _ => return Err("ERR syntax error".to_string()),
+        };
+        Ok((self.geo_hits(arg(args, at)?, &center, &shape)?, rest))
+    }
+
+    /// Members inside `shape`, nearest first.
+    fn geo_hits(
+        &self,
+        key: &str,
+        center: &RideLocation,
+        shape: &Shape,
+    ) -> Result<Vec<GeoHit>, String> {
+        let Some(members) = self.geo(key)? else {
+            return Ok(Vec::new());
+        };
+        let mut found: Vec<GeoHit> = members
+            .iter()
+            .filter(|(_, (lng, lat))| shape.contains(center, &RideLocation::new(*lat, *lng)))
+            .map(|(member, (lng, lat))| {
+                (
+                    km(center, &RideLocation::new(*lat, *lng)),
+                    member.clone(),
+                    (*lng, *lat),
+                )
+            })
+            .collect();
+        found.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+        Ok(found)
+    }
+
+    /// Replace `destination` with the first `count` hits.
+    fn store_geo(&mut self, destination: &str, hits: Vec<GeoHit>, count: usize) -> Reply {
+        let members: HashMap<_, _> = hits
+            .into_iter()
+            .take(count)
+            .map(|(_, member, at)| (member, at))
+            .collect();
+        let stored = members.len();
+        self.expires.remove(destination);
+        if members.is_empty() {
+            self.entries.remove(destination);
+        } else {
+            self.entries
+                .insert(destination.to_string(), Entry::Geo(members));
+        }
+        Reply::Integer(stored as i64)
+    }
+}
+
+/// Distance in km, member and `(lng, lat)`.
+type GeoHit = (f64, String, (f64, f64));
+
+/// Matches as bare members, or as `[member, distance?, [lng, lat]?]` with `WITHDIST` and
+/// `WITHCOORD` among `args`.
+fn geo_reply(args: &[String], hits: Vec<GeoHit>, count: usize) -> Reply {
+    let flag = |name: &str| args.iter().any(|arg| arg.eq_ignore_ascii_case(name));
+    let (with_dist, with_coord) = (flag("WITHDIST"), flag("WITHCOORD"));
+    Reply::Array(
+        hits.into_iter()
+            .take(count)
+            .map(|(distance, member, (lng, lat))| {
+                if !with_dist && !with_coord {
+                    return Reply::Bulk(Some(member));
+                }
+                let mut item = vec![Reply::Bulk(Some(member))];
+                if with_dist {
+                    item.push(Reply::Bulk(Some(format!("{distance:.4}"))));
+                }
+                if with_coord {
+                    item.push(Reply::Array(vec![
+                        Reply::Bulk(Some(lng.to_string())),
+                        Reply::Bulk(Some(lat.to_string())),
+                    ]));
+                }
+                Reply::Array(item)
+            })
+            .collect(),
+    )
+}
+
+/// The `COUNT n` option at or after `args[from]`, or no limit.
+fn count_option(args: &[String], from: usize) -> Result<usize, String> {
+    match args
+        .iter()
+        .skip(from)
+        .position(|arg| arg.eq_ignore_ascii_case("COUNT"))
+    {
+        Some(at) => Ok(integer(arg(args, from + at + 1)?)?.max(0) as usize),
+        None => Ok(usize::MAX),
+    }
+}
+
+/// Members ordered by score, then member.
+fn ranked(set: Option<&HashMap<String, f64>>) -> Vec<(String, f64)> {
+    let mut ranked: Vec<(String, f64)> = set
+        .into_iter()
+        .flatten()
+        .map(|(member, score)| (member.clone(), *score))
+        .collect();
+    ranked.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    ranked
+}
+
+fn scored_reply(members: &[(String, f64)], with_scores: bool) -> Reply {
+    Reply::Array(
+        members
+            .iter()
+            .flat_map(|(member, score)| {
+                let score = with_scores.then(|| Reply::Bulk(Some(format_number(*score))));
+                std::iter::once(bulk(member)).chain(score)
+            })
+            .collect(),
+    )
+}
+
+/// A `ZRANGEBYSCORE` bound and whether it is exclusive.
+fn score_bound(value: &str) -> Result<(f64, bool), String> {
+    let (value, open) = match value.strip_prefix('(') {
+        Some(value) => (value, true),
+        None => (value, false),
+    };
+    let bound = match value.to_lowercase().as_str() {
+        "-inf" => f64::NEG_INFINITY,
+        "+inf" | "inf" => f64::INFINITY,
+        _ => value
+            .parse()
+            .map_err(|_| "ERR min or max is not a float".to_string())?,
+    };
+    Ok((bound, open))
+}
+
+enum Shape {
+    Radius(f64),
+    Box { half_width: f64, half_height: f64 },
+}
+
+impl Shape {
+    fn contains(&self, center: &RideLocation, at: &RideLocation) -> bool {
+        match self {
+            Shape::Radius(radius) => km(center, at) <= *radius,
+            Shape::Box {
+                half_width,
+                half_height,
+            } => {
+                let on_meridian = RideLocation::new(at.lat, center.lng);
+                km(center, &on_meridian) <= *half_height && km(&on_meridian, at) <= *half_width
+            }
+        }
+    }
+}
+
+fn km(from: &RideLocation, to: &RideLocation) -> f64 {
+    haversine_miles(from, to) * KM_PER_MILE
+}
+
+fn bulk(value: impl Into<String>) -> Reply {
+    Reply::Bulk(Some(value.into()))
+}
+
+fn arg(args: &[String], index: usize) -> Result<&String, String> {
+    args.get(index)
+        .ok_or_else(|| format!("ERR wrong number of arguments for '{}'", args[0]))
+}
+
+fn number(value: &str) -> Result<f64, String> {
+    value
+        .parse()
+        .map_err(|_| "ERR value is not a valid float".to_string())
+}
+
+fn integer(value: &str) -> Result<i64, String> {
+    value
+        .parse()
+        .map_err(|_| "ERR value is not an integer or out of range".to_string())
+}
+
+/// Scores and Lua numbers as Redis prints them: integral values without a fraction.
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e17 {
+        format!("{}", value as i64)
+    } else {
+        value.to_string()
+    }
+}
+
+fn unit_km(unit: &str) -> Result<f64, String> {
+    match unit.to_lowercase().as_str() {
+        "m" => Ok(0.001),
+        "km" => Ok(1.0),
+        "mi" => Ok(KM_PER_MILE),
+        "ft" => Ok(0.0003048),
+        _ => Err("ERR unsupported unit provided. please use M, KM, FT, MI".to_string()),
+    }
+}
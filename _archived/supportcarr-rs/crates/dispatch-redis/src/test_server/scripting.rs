@@ -0,0 +1,186 @@
+//! Lua scripting for the stand-in: `EVAL`, `EVALSHA` and `SCRIPT LOAD`, with `redis.call`
+//! running against the store and `cjson.decode`. As on a cluster, every key a script
+//! touches must be passed in `KEYS`; the stand-in checks each command's first key.
+
+use mlua::{Lua, Table, Value, Variadic};
+
+use super::{arg, format_number, integer, Reply, Store};
+
+impl Store {
+    /// `SCRIPT LOAD source` or `SCRIPT FLUSH`.
+    pub(super) fn script(&mut self, args: &[String]) -> Result<Reply, String> {
+        match arg(args, 1)?.to_uppercase().as_str() {
+            "LOAD" => Ok(Reply::Bulk(Some(self.load_script(arg(args, 2)?)))),
+            "FLUSH" => {
+                self.scripts.clear();
+                Ok(Reply::ok())
+            }
+            _ => Err(format!("ERR unknown subcommand '{}'", args[1])),
+        }
+    }
+
+    /// `EVAL source numkeys key [key ...] arg [arg ...]`.
+    pub(super) fn eval(&mut self, args: &[String]) -> Result<Reply, String> {
+        let source = arg(args, 1)?.clone();
+        self.load_script(&source);
+        self.run_script(&source, &args[2..])
+    }
+
+    /// `EVALSHA sha1 numkeys key [key ...] arg [arg ...]`.
+    pub(super) fn evalsha(&mut self, args: &[String]) -> Result<Reply, String> {
+        let source = self
+            .scripts
+            .get(&arg(args, 1)?.to_lowercase())
+            .cloned()
+            .ok_or_else(|| "NOSCRIPT No matching script. Please use EVAL.".to_string())?;
+        self.run_script(&source, &args[2..])
+    }
+
+    fn load_script(&mut self, source: &str) -> String {
+        let sha = sha1_smol::Sha1::from(source).digest().to_string();
+        self.scripts.insert(sha.clone(), source.to_string());
+        sha
+    }
+
+    fn run_script(&mut self, source: &str, args: &[String]) -> Result<Reply, String> {
+        let num_keys = integer(arg(args, 0)?)?;
+        let num_keys = usize::try_from(num_keys)
+            .ok()
+            .filter(|num_keys| *num_keys < args.len())
+            .ok_or_else(|| "ERR Number of keys can't be greater than number of args".to_string())?;
+        let (keys, argv) = args[1..].split_at(num_keys);
+
+        let lua = Lua::new();
+        lua.scope(|scope| {
+            let globals = lua.globals();
+            globals.set("KEYS", keys.to_vec())?;
+            globals.set("ARGV", argv.to_vec())?;
+
+            let redis = lua.create_table()?;
+            let call = scope.create_function_mut(|lua, call: Variadic<Value>| {
+                let command = call
+                    .iter()
+                    .map(command_arg)
+                    .collect::<mlua::Result<Vec<String>>>()?;
+                if let Some(key) = command.get(1).filter(|key| !keys.contains(key)) {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "ERR script accessed undeclared key '{key}'"
+                    )));
+                }
+                match self.execute(&command) {
+                    Reply::Error(message) => Err(mlua::Error::RuntimeError(message)),
+                    reply => to_lua(lua, reply),
+                }
+            })?;
+            redis.set("call", call)?;
+            globals.set("redis", redis)?;
+
+            let cjson = lua.create_table()?;
+            let decode = lua.create_function(|lua, json: String| {
+                let value: serde_json::Value =
+                    serde_json::from_str(&json).map_err(mlua::Error::external)?;
+                json_to_lua(lua, value)
+            })?;
+            cjson.set("decode", decode)?;
+            globals.set("cjson", cjson)?;
+
+            from_lua(lua.load(source).eval()?)
+        })
+        .map_err(|err| {
+            let message = err.to_string().replace(['\r', '\n'], " ");
+            format!("ERR Error running script: {message}")
+        })
+    }
+}
+
+/// Arguments to `redis.call` must be strings or numbers.
+fn command_arg(value: &Value) -> mlua::Result<String> {
+    match value {
+        Value::String(value) => Ok(value.to_str()?.to_string()),
+        Value::Integer(value) => Ok(value.to_string()),
+        Value::Number(value) => Ok(format_number(*value)),
+        _ => Err(mlua::Error::RuntimeError(
+            "ERR Lua redis lib command arguments must be strings or integers".to_string(),
+        )),
+    }
+}
+
+/// Redis' reply conversion: nil becomes `false` and status replies `{ok = ...}` tables.
+fn to_lua(lua: &Lua, reply: Reply) -> mlua::Result<Value<'_>> {
+    Ok(match reply {
+        Reply::Status(status) => {
+            let table = lua.create_table()?;
+            table.set("ok", status)?;
+            Value::Table(table)
+        }
+        Reply::Error(message) => return Err(mlua::Error::RuntimeError(message)),
+        Reply::Integer(n) => Value::Integer(n as _),
+        Reply::Bulk(None) => Value::Boolean(false),
+        Reply::Bulk(Some(value)) => Value::String(lua.create_string(&value)?),
+        Reply::Array(items) => {
+            let items = items
+                .into_iter()
+                .map(|item| to_lua(lua, item))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            Value::Table(lua.create_sequence_from(items)?)
+        }
+    })
+}
+
+/// Script results: numbers are truncated to integers and tables read as arrays up to the
+/// first nil, unless they are `{err = ...}` or `{ok = ...}`.
+fn from_lua(value: Value) -> mlua::Result<Reply> {
+    Ok(match value {
+        Value::Nil | Value::Boolean(false) => Reply::Bulk(None),
+        Value::Boolean(true) => Reply::Integer(1),
+        Value::Integer(n) => Reply::Integer(n),
+        Value::Number(n) => Reply::Integer(n as i64),
+        Value::String(value) => Reply::Bulk(Some(value.to_str()?.to_string())),
+        Value::Table(table) => table_reply(table)?,
+        other => {
+            return Err(mlua::Error::RuntimeError(format!(
+                "cannot return a {} from a script",
+                other.type_name()
+            )))
+        }
+    })
+}
+
+fn table_reply(table: Table) -> mlua::Result<Reply> {
+    if let Some(message) = table.get::<_, Option<String>>("err")? {
+        return Ok(Reply::Error(message));
+    }
+    if let Some(status) = table.get::<_, Option<String>>("ok")? {
+        return Ok(Reply::Status(status));
+    }
+    Ok(Reply::Array(
+        table
+            .sequence_values::<Value>()
+            .map(|item| from_lua(item?))
+            .collect::<mlua::Result<_>>()?,
+    ))
+}
+
+/// `cjson.decode`, except that JSON `null` becomes `nil` rather than `cjson.null`.
+fn json_to_lua(lua: &Lua, value: serde_json::Value) -> mlua::Result<Value<'_>> {
+    Ok(match value {
+        serde_json::Value::Null => Value::Nil,
+        serde_json::Value::Bool(value) => Value::Boolean(value),
+        serde_json::Value::Number(value) => Value::Number(value.as_f64().unwrap_or_default()),
+        serde_json::Value::String(value) => Value::String(lua.create_string(&value)?),
+        serde_json::Value::Array(items) => {
+            let items = items
+                .into_iter()
+                .map(|item| json_to_lua(lua, item))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            Value::Table(lua.create_sequence_from(items)?)
+        }
+        serde_json::Value::Object(fields) => {
+            let table = lua.create_table()?;
+            for (name, value) in fields {
+                table.set(name, json_to_lua(lua, value)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
@@ -0,0 +1,402 @@
+//! Streams and consumer groups for the stand-in: `XADD`, `XRANGE`, `XTRIM`, `XGROUP
+//! CREATE`, `XREADGROUP` (new entries only), `XACK` and `XAUTOCLAIM`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use supportcarr_core::dispatch::now_millis;
+
+use super::{arg, bulk, count_option, integer, Reply, Store};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct StreamId {
+    ms: u64,
+    seq: u64,
+}
+
+impl StreamId {
+    const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// `ms-seq`, or `ms` with `seq` filled in as `missing_seq`.
+    fn parse(value: &str, missing_seq: u64) -> Result<Self, String> {
+        let invalid = || "ERR Invalid stream ID specified as stream command argument".to_string();
+        let (ms, seq) = match value.split_once('-') {
+            Some((ms, seq)) => (ms, seq.parse().map_err(|_| invalid())?),
+            None => (value, missing_seq),
+        };
+        Ok(Self {
+            ms: ms.parse().map_err(|_| invalid())?,
+            seq,
+        })
+    }
+
+    /// Inclusive lower bound of a range: `-`, an id, or `(id` for exclusive.
+    fn range_start(value: &str) -> Result<Self, String> {
+        match value {
+            "-" => Ok(Self::default()),
+            _ => match value.strip_prefix('(') {
+                Some(id) => Ok(Self::parse(id, 0)?.next()),
+                None => Self::parse(value, 0),
+            },
+        }
+    }
+
+    /// Inclusive upper bound of a range: `+`, an id, or `(id` for exclusive.
+    fn range_end(value: &str) -> Result<Self, String> {
+        match value {
+            "+" => Ok(Self::MAX),
+            _ => match value.strip_prefix('(') {
+                Some(id) => Ok(Self::parse(id, u64::MAX)?.previous()),
+                None => Self::parse(value, u64::MAX),
+            },
+        }
+    }
+
+    fn next(self) -> Self {
+        match self.seq.checked_add(1) {
+            Some(seq) => Self { seq, ..self },
+            None => Self {
+                ms: self.ms.saturating_add(1),
+                seq: 0,
+            },
+        }
+    }
+
+    fn previous(self) -> Self {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Self { seq, ..self },
+            None => Self {
+                ms: self.ms.saturating_sub(1),
+                seq: u64::MAX,
+            },
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Stream {
+    /// Entry id to its flattened field-value pairs.
+    entries: BTreeMap<StreamId, Vec<String>>,
+    last_id: StreamId,
+    groups: HashMap<String, Group>,
+}
+
+struct Group {
+    last_delivered: StreamId,
+    /// Delivered, unacknowledged entries: who has them and since when.
+    pending: BTreeMap<StreamId, (String, Instant)>,
+}
+
+impl Stream {
+    fn trim(&mut self, strategy: &str, threshold: &str) -> Result<usize, String> {
+        let before = self.entries.len();
+        match strategy.to_uppercase().as_str() {
+            "MAXLEN" => {
+                let max_len = integer(threshold)?.max(0) as usize;
+                while self.entries.len() > max_len {
+                    self.entries.pop_first();
+                }
+            }
+            "MINID" => {
+                let min_id = StreamId::parse(threshold, 0)?;
+                self.entries = self.entries.split_off(&min_id);
+            }
+            _ => return Err("ERR syntax error".to_string()),
+        }
+        Ok(before - self.entries.len())
+    }
+}
+
+/// Trim arguments starting at `args[at]`: `(MAXLEN | MINID) [= | ~] threshold [LIMIT n]`.
+/// Returns the strategy, threshold and the index after them.
+fn trim_args(args: &[String], mut at: usize) -> Result<(String, String, usize), String> {
+    let strategy = arg(args, at)?.clone();
+    at += 1;
+    if matches!(arg(args, at)?.as_str(), "=" | "~") {
+        at += 1;
+    }
+    let threshold = arg(args, at)?.clone();
+    at += 1;
+    if args
+        .get(at)
+        .is_some_and(|option| option.eq_ignore_ascii_case("LIMIT"))
+    {
+        at += 2;
+    }
+    Ok((strategy, threshold, at))
+}
+
+fn entry_reply(id: &StreamId, fields: &[String]) -> Reply {
+    Reply::Array(vec![
+        Reply::Bulk(Some(id.to_string())),
+        Reply::Array(fields.iter().map(bulk).collect()),
+    ])
+}
+
+fn no_group(key: &str, group: &str) -> String {
+    format!("NOGROUP No such key '{key}' or consumer group '{group}'")
+}
+
+impl Store {
+    /// `XADD key [MAXLEN | MINID [= | ~] threshold] (* | id) field value [field value ...]`.
+    pub(super) fn xadd(&mut self, args: &[String]) -> Result<Reply, String> {
+        let key = arg(args, 1)?;
+        let mut at = 2;
+        let mut trim = None;
+        if ["MAXLEN", "MINID"]
+            .iter()
+            .any(|strategy| arg(args, at).is_ok_and(|arg| arg.eq_ignore_ascii_case(strategy)))
+        {
+            let (strategy, threshold, next) = trim_args(args, at)?;
+            trim = Some((strategy, threshold));
+            at = next;
+        }
+        let requested = arg(args, at)?;
+        let fields = &args[at + 1..];
+        if fields.is_empty() || !fields.len().is_multiple_of(2) {
+            return Err("ERR wrong number of arguments for 'xadd' command".to_string());
+        }
+
+        let stream = self.stream_mut(key)?;
+        let id = if requested == "*" {
+            let now = now_millis();
+            if now > stream.last_id.ms {
+                StreamId { ms: now, seq: 0 }
+            } else {
+                stream.last_id.next()
+            }
+        } else {
+            let id = StreamId::parse(requested, 0)?;
+            if id <= stream.last_id {
+                let message =
+                    "The ID specified in XADD is equal or smaller than the target stream \
+                               top item";
+                return Err(format!("ERR {message}"));
+            }
+            id
+        };
+        stream.entries.insert(id, fields.to_vec());
+        stream.last_id = id;
+        if let Some((strategy, threshold)) = trim {
+            stream.trim(&strategy, &threshold)?;
+        }
+        Ok(Reply::Bulk(Some(id.to_string())))
+    }
+
+    /// `XRANGE key start end [COUNT n]`.
+    pub(super) fn xrange(&self, args: &[String]) -> Result<Reply, String> {
+        let start = StreamId::range_start(arg(args, 2)?)?;
+        let end = StreamId::range_end(arg(args, 3)?)?;
+        let count = count_option(args, 4)?;
+        let Some(stream) = self.stream(arg(args, 1)?)? else {
+            return Ok(Reply::Array(Vec::new()));
+        };
+        if start > end {
+            return Ok(Reply::Array(Vec::new()));
+        }
+        Ok(Reply::Array(
+            stream
+                .entries
+                .range(start..=end)
+                .take(count)
+                .map(|(id, fields)| entry_reply(id, fields))
+                .collect(),
+        ))
+    }
+
+    /// `XTRIM key (MAXLEN | MINID) [= | ~] threshold`. Trimming is always exact, which is
+    /// one of the outcomes `~` allows.
+    pub(super) fn xtrim(&mut self, args: &[String]) -> Result<Reply, String> {
+        let key = arg(args, 1)?;
+        let (strategy, threshold, _) = trim_args(args, 2)?;
+        if self.stream(key)?.is_none() {
+            return Ok(Reply::Integer(0));
+        }
+        let removed = self.stream_mut(key)?.trim(&strategy, &threshold)?;
+        Ok(Reply::Integer(removed as i64))
+    }
+
+    /// `XGROUP CREATE key group (id | $) [MKSTREAM]`.
+    pub(super) fn xgroup(&mut self, args: &[String]) -> Result<Reply, String> {
+        if !arg(args, 1)?.eq_ignore_ascii_case("CREATE") {
+            return Err(format!("ERR unknown subcommand '{}'", args[1]));
+        }
+        let key = arg(args, 2)?;
+        let group = arg(args, 3)?;
+        let start = arg(args, 4)?;
+        let create = args
+            .get(5)
+            .is_some_and(|option| option.eq_ignore_ascii_case("MKSTREAM"));
+        if self.stream(key)?.is_none() && !create {
+            return Err("ERR The XGROUP subcommand requires the key to exist".to_string());
+        }
+        let stream = self.stream_mut(key)?;
+        if stream.groups.contains_key(group) {
+            return Err("BUSYGROUP Consumer Group name already exists".to_string());
+        }
+        let last_delivered = match start.as_str() {
+            "$" => stream.last_id,
+            id => StreamId::parse(id, 0)?,
+        };
+        stream.groups.insert(
+            group.clone(),
+            Group {
+                last_delivered,
+                pending: BTreeMap::new(),
+            },
+        );
+        Ok(Reply::ok())
+    }
+
+    /// `XREADGROUP GROUP group consumer [COUNT n] [BLOCK ms] [NOACK] STREAMS key [key ...] >
+    /// [> ...]`. Replies nil when nothing is new; the connection handles `BLOCK` by
+    /// retrying.
+    pub(super) fn xreadgroup(&mut self, args: &[String]) -> Result<Reply, String> {
+        if !arg(args, 1)?.eq_ignore_ascii_case("GROUP") {
+            return Err("ERR syntax error".to_string());
+        }
+        let group = arg(args, 2)?;
+        let consumer = arg(args, 3)?;
+        let mut count = usize::MAX;
+        let mut noack = false;
+        let mut at = 4;
+        loop {
+            match arg(args, at)?.to_uppercase().as_str() {
+                "COUNT" => {
+                    count = integer(arg(args, at + 1)?)?.max(0) as usize;
+                    at += 2;
+                }
+                "BLOCK" => at += 2,
+                "NOACK" => {
+                    noack = true;
+                    at += 1;
+                }
+                "STREAMS" => break,
+                _ => return Err("ERR syntax error".to_string()),
+            }
+        }
+        let streams = &args[at + 1..];
+        if streams.is_empty() || !streams.len().is_multiple_of(2) {
+            return Err("ERR Unbalanced 'xreadgroup' list of streams".to_string());
+        }
+        let (keys, ids) = streams.split_at(streams.len() / 2);
+
+        let mut replies = Vec::new();
+        for (key, id) in keys.iter().zip(ids) {
+            if id != ">" {
+                return Err("ERR the stand-in only reads new entries ('>')".to_string());
+            }
+            if self.stream(key)?.is_none() {
+                return Err(no_group(key, group));
+            }
+            let Stream {
+                entries, groups, ..
+            } = self.stream_mut(key)?;
+            let state = groups.get_mut(group).ok_or_else(|| no_group(key, group))?;
+            let delivered: Vec<Reply> = entries
+                .range(state.last_delivered.next()..)
+                .take(count)
+                .map(|(id, fields)| {
+                    state.last_delivered = *id;
+                    if !noack {
+                        state
+                            .pending
+                            .insert(*id, (consumer.clone(), Instant::now()));
+                    }
+                    entry_reply(id, fields)
+                })
+                .collect();
+            if !delivered.is_empty() {
+                replies.push(Reply::Array(vec![bulk(key), Reply::Array(delivered)]));
+            }
+        }
+        if replies.is_empty() {
+            return Ok(Reply::Bulk(None));
+        }
+        Ok(Reply::Array(replies))
+    }
+
+    /// `XACK key group id [id ...]`.
+    pub(super) fn xack(&mut self, args: &[String]) -> Result<Reply, String> {
+        let key = arg(args, 1)?;
+        let group = arg(args, 2)?;
+        let ids = args[3..]
+            .iter()
+            .map(|id| StreamId::parse(id, 0))
+            .collect::<Result<Vec<_>, String>>()?;
+        if self.stream(key)?.is_none() {
+            return Ok(Reply::Integer(0));
+        }
+        let Some(state) = self.stream_mut(key)?.groups.get_mut(group) else {
+            return Ok(Reply::Integer(0));
+        };
+        let acked = ids
+            .iter()
+            .filter(|id| state.pending.remove(id).is_some())
+            .count();
+        Ok(Reply::Integer(acked as i64))
+    }
+
+    /// `XAUTOCLAIM key group consumer min-idle-ms start [COUNT n]`. Replies with the next
+    /// start id, the claimed entries and the ids of pending entries trimmed from the stream,
+    /// which are dropped from the pending list.
+    pub(super) fn xautoclaim(&mut self, args: &[String]) -> Result<Reply, String> {
+        let key = arg(args, 1)?;
+        let group = arg(args, 2)?;
+        let consumer = arg(args, 3)?;
+        let min_idle = Duration::from_millis(integer(arg(args, 4)?)?.max(0) as u64);
+        let start = StreamId::range_start(arg(args, 5)?)?;
+        let count = match count_option(args, 6)? {
+            usize::MAX => 100,
+            count => count,
+        };
+        if self.stream(key)?.is_none() {
+            return Err(no_group(key, group));
+        }
+        let Stream {
+            entries, groups, ..
+        } = self.stream_mut(key)?;
+        let state = groups.get_mut(group).ok_or_else(|| no_group(key, group))?;
+
+        let now = Instant::now();
+        let idle: Vec<StreamId> = state
+            .pending
+            .range(start..)
+            .filter(|(_, (_, since))| now.duration_since(*since) >= min_idle)
+            .map(|(id, _)| *id)
+            .collect();
+        let (mut claimed, mut deleted) = (Vec::new(), Vec::new());
+        let mut next = StreamId::default();
+        for (seen, id) in idle.into_iter().enumerate() {
+            if seen == count {
+                next = id;
+                break;
+            }
+            match entries.get(&id) {
+                Some(fields) => {
+                    state.pending.insert(id, (consumer.clone(), now));
+                    claimed.push(entry_reply(&id, fields));
+                }
+                None => {
+                    state.pending.remove(&id);
+                    deleted.push(Reply::Bulk(Some(id.to_string())));
+                }
+            }
+        }
+        Ok(Reply::Array(vec![
+            Reply::Bulk(Some(next.to_string())),
+            Reply::Array(claimed),
+            Reply::Array(deleted),
+        ]))
+    }
+}
@@ -0,0 +1,223 @@
+//! Assembles one process from [`ServerConfig`]: the ride repository, the Redis dispatch
+//! engine, the API and Twilio routers, and the background dispatch workers.
+
+use std::error::Error;
+use std::sync::Arc;
+
+use supportcarr_api::auth::JwtVerifier;
+use supportcarr_api::batch::{spawn_batch_dispatch, BatchPolicy};
+use supportcarr_api::health::Readiness;
+use supportcarr_api::lifecycle::SmsRideStore;
+use supportcarr_api::offers::OfferPolicy;
+use supportcarr_api::pilot_api::LocationPolicy;
+use supportcarr_api::pilots::InMemoryPilotProfileStore;
+use supportcarr_api::postgres_repository::PostgresRideRepository;
+use supportcarr_api::redis_repository::RedisRideRepository;
+use supportcarr_api::redispatch::{spawn_redispatch_worker, RedispatchPolicy};
+use supportcarr_api::repository::{InMemoryRideRepository, RideRepository};
+use supportcarr_api::sqlite_repository::SqliteRideRepository;
+use supportcarr_api::updates::RideUpdateHub;
+use supportcarr_api::ApiState;
+use supportcarr_core::dispatch::DispatchEngineConfig;
+use supportcarr_core::WeightedPilotScorer;
+use supportcarr_dispatch_redis::RedisDispatchEngine;
+use supportcarr_twilio::{TwilioConfig, TwilioRideStore, TwilioState};
+use tokio::task::JoinHandle;
+
+use crate::config::{AuthSettings, ServerConfig, StorageSettings};
+
+/// A configured process: serve `router`; `workers` run until `state` begins shutting down.
+pub struct App {
+    pub router: axum::Router,
+    pub state: ApiState,
+    pub workers: Vec<JoinHandle<()>>,
+}
+
+impl App {
+    /// Connect storage, build the routers and start the workers. Redis is connected on
+    /// first use, so an unreachable server surfaces on the first dispatch call.
+    pub async fn build(config: &ServerConfig) -> Result<Self, Box<dyn Error>> {
+        let redis = redis::Client::open(config.redis.url.as_str())?;
+        let engine = Arc::new(RedisDispatchEngine::new(
+            redis.clone(),
+            DispatchEngineConfig {
+                key_prefix: config.redis.key_prefix.clone(),
+                ..DispatchEngineConfig::default()
+            },
+        ));
+        let (repo, sms_store) = ride_storage(config, redis).await?;
+
+        let state = ApiState {
+            repo,
+            dispatch: engine.clone(),
+            pilots: Arc::new(InMemoryPilotProfileStore::default()),
+            scorer: Arc::new(WeightedPilotScorer::default()),
+            queue: engine.clone(),
+            history: engine.clone(),
+            events: engine,
+            offers: OfferPolicy {
+                immediate: !config.dispatch.batch,
+                ..OfferPolicy::default()
+            },
+            locations: LocationPolicy::default(),
+            auth: Arc::new(verifier(&config.auth)?),
+            updates: RideUpdateHub::default(),
+            readiness: Readiness::default(),
+        };
+
+        let mut router = supportcarr_api::router(state.clone());
+        match &config.twilio {
+            Some(twilio) => {
+                router = router.merge(supportcarr_twilio::router(TwilioState {
+                    config: TwilioConfig {
+                        auth_token: twilio.auth_token.clone(),
+                        webhook_url: twilio.webhook_url.clone(),
+                    },
+                    store: Arc::new(SmsRideStore {
+                        state: state.clone(),
+                        store: sms_store,
+                    }),
+                }));
+            }
+            None => tracing::warn!("twilio settings missing; SMS webhook disabled"),
+        }
+
+        let mut workers = Vec::new();
+        if config.dispatch.batch {
+            workers.push(spawn_batch_dispatch(state.clone(), BatchPolicy::default()));
+        }
+        if config.dispatch.redispatch {
+            workers.push(spawn_redispatch_worker(
+                state.clone(),
+                RedispatchPolicy::default(),
+            ));
+        }
+
+        Ok(Self {
+            router,
+            state,
+            workers,
+        })
+    }
+}
+
+/// The configured ride repository, shared with the SMS webhook.
+async fn ride_storage(
+    config: &ServerConfig,
+    redis: redis::Client,
+) -> Result<(Arc<dyn RideRepository>, Arc<dyn TwilioRideStore>), Box<dyn Error>> {
+    Ok(match &config.storage {
+        StorageSettings::Memory => {
+            let repo = Arc::new(InMemoryRideRepository::default());
+            (repo.clone(), repo)
+        }
+        StorageSettings::Redis => {
+            let repo = Arc::new(RedisRideRepository::new(
+                redis,
+                config.redis.key_prefix.clone(),
+            ));
+            (repo.clone(), repo)
+        }
+        StorageSettings::Postgres { url, migrate } => {
+            let repo = Arc::new(PostgresRideRepository::connect(url).await?);
+            if *migrate {
+                repo.migrate().await?;
+            }
+            (repo.clone(), repo)
+        }
+        StorageSettings::Sqlite { path } => {
+            let repo = Arc::new(SqliteRideRepository::open(path).await?);
+            (repo.clone(), repo)
+        }
+    })
+}
+
+/// The `hs256_secret` of the example config, which anyone could sign tokens with.
+const PLACEHOLDER_SECRET: &str = "change-me";
+
+fn verifier(settings: &AuthSettings) -> Result<JwtVerifier, Box<dyn Error>> {
+    if settings.hs256_secret.is_none() && settings.jwks_file.is_none() {
+        return Err("auth: set hs256_secret or jwks_file, or every request is refused".into());
+    }
+    if settings.hs256_secret.as_deref() == Some(PLACEHOLDER_SECRET) {
+        return Err("auth: hs256_secret is still the example placeholder".into());
+    }
+    let mut verifier = JwtVerifier::new();
+    if let Some(secret) = &settings.hs256_secret {
+        verifier = verifier.with_hs256_secret(secret.as_bytes());
+    }
+    if let Some(path) = &settings.jwks_file {
+        verifier = verifier.with_jwks_file(path)?;
+    }
+    if let Some(issuer) = &settings.issuer {
+        verifier = verifier.with_issuer(issuer);
+    }
+    if let Some(audience) = &settings.audience {
+        verifier = verifier.with_audience(audience);
+    }
+    Ok(verifier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TwilioSettings;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn serves_api_and_twilio_routes_together() {
+        let config = ServerConfig {
+            storage: StorageSettings::Memory,
+            auth: AuthSettings {
+                hs256_secret: Some("secret".to_string()),
+                ..AuthSettings::default()
+            },
+            twilio: Some(TwilioSettings {
+                auth_token: "token".to_string(),
+                webhook_url: "https://example.com/twilio/sms".to_string(),
+            }),
+            ..ServerConfig::default()
+        };
+        let app = App::build(&config).await.unwrap();
+        app.state.begin_shutdown();
+
+        let status = |method: &str, uri: &str| {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            let router = app.router.clone();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+        assert_eq!(status("GET", "/openapi.json").await, StatusCode::OK);
+        assert_eq!(status("GET", "/healthz").await, StatusCode::OK);
+        assert_eq!(status("GET", "/rides").await, StatusCode::UNAUTHORIZED);
+        // Unsigned webhook calls are refused, which shows the route is mounted.
+        assert_eq!(
+            status("POST", "/twilio/sms").await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_to_start_without_token_keys() {
+        let config = ServerConfig {
+            storage: StorageSettings::Memory,
+            ..ServerConfig::default()
+        };
+        assert!(App::build(&config).await.is_err());
+
+        let placeholder = ServerConfig {
+            auth: AuthSettings {
+                hs256_secret: Some(PLACEHOLDER_SECRET.to_string()),
+                ..AuthSettings::default()
+            },
+            ..config
+        };
+        let err = App::build(&placeholder).await.err().unwrap();
+        assert!(err.to_string().contains("placeholder"), "{err}");
+    }
+}
@@ -0,0 +1,311 @@
+//! Server settings, layered: built-in defaults, then the TOML file, then `SUPPORTCARR_*`
+//! environment variables (`__` separates sections, e.g. `SUPPORTCARR_REDIS__URL`), then
+//! command-line flags.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+use figment::providers::{Env, Format, Serialized, Toml};
+use figment::Figment;
+use serde::Deserialize;
+use supportcarr_api::shutdown::GracePeriod;
+
+/// Read when `--config` is not given, if it exists.
+pub const DEFAULT_CONFIG_FILE: &str = "supportcarr.toml";
+
+#[derive(Debug, Default, Parser)]
+#[command(
+    name = "supportcarr-server",
+    version,
+    about = "SupportCarr API and Twilio webhook"
+)]
+pub struct Cli {
+    /// TOML settings file; `supportcarr.toml` is read when present.
+    #[arg(long, short, env = "SUPPORTCARR_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long)]
+    pub bind: Option<SocketAddr>,
+    #[arg(long)]
+    pub redis_url: Option<String>,
+    /// Prefix of every Redis key, shared with the Node server.
+    #[arg(long)]
+    pub key_prefix: Option<String>,
+    /// Where rides are stored: `memory`, `redis`, `postgres` or `sqlite`.
+    #[arg(long)]
+    pub storage: Option<String>,
+    /// Postgres connection string, with `--storage postgres`.
+    #[arg(long)]
+    pub database_url: Option<String>,
+    /// SQLite database file, with `--storage sqlite`.
+    #[arg(long)]
+    pub sqlite_path: Option<PathBuf>,
+    /// Prefer `SUPPORTCARR_TWILIO__AUTH_TOKEN`; flags show up in process listings.
+    #[arg(long)]
+    pub twilio_auth_token: Option<String>,
+    /// Public URL Twilio posts to, as signed in `X-Twilio-Signature`.
+    #[arg(long)]
+    pub twilio_webhook_url: Option<String>,
+}
+
+impl Cli {
+    /// Flags that were given, as `(settings key, value)`.
+    fn overrides(&self) -> Vec<(&'static str, String)> {
+        let path = |path: &PathBuf| path.display().to_string();
+        [
+            ("bind", self.bind.map(|bind| bind.to_string())),
+            ("redis.url", self.redis_url.clone()),
+            ("redis.key_prefix", self.key_prefix.clone()),
+            ("storage.backend", self.storage.clone()),
+            ("storage.url", self.database_url.clone()),
+            ("storage.path", self.sqlite_path.as_ref().map(path)),
+            ("twilio.auth_token", self.twilio_auth_token.clone()),
+            ("twilio.webhook_url", self.twilio_webhook_url.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind: SocketAddr,
+    pub redis: RedisSettings,
+    pub storage: StorageSettings,
+    pub auth: AuthSettings,
+    /// The SMS webhook is mounted only when this is set.
+    pub twilio: Option<TwilioSettings>,
+    pub dispatch: DispatchSettings,
+    pub shutdown: ShutdownSettings,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            redis: RedisSettings::default(),
+            storage: StorageSettings::default(),
+            auth: AuthSettings::default(),
+            twilio: None,
+            dispatch: DispatchSettings::default(),
+            shutdown: ShutdownSettings::default(),
+        }
+    }
+}
+
+/// Redis holds dispatch state (pilot locations, offers, the pending queue) whatever the
+/// ride storage.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RedisSettings {
+    pub url: String,
+    pub key_prefix: String,
+}
+
+impl Default for RedisSettings {
+    fn default() -> Self {
+        Self {
+            url: "redis://127.0.0.1:6379".to_string(),
+            key_prefix: "supportcarr".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum StorageSettings {
+    /// Lost on restart; for local development.
+    Memory,
+    /// Next to the dispatch state, under the same key prefix.
+    #[default]
+    Redis,
+    /// The Node server's database. With `migrate`, the embedded migrations run at startup.
+    Postgres {
+        url: String,
+        #[serde(default)]
+        migrate: bool,
+    },
+    /// One file in WAL mode, migrated on open.
+    Sqlite { path: PathBuf },
+}
+
+/// Keys for verifying bearer tokens; at least one of `hs256_secret` and `jwks_file`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AuthSettings {
+    pub hs256_secret: Option<String>,
+    pub jwks_file: Option<PathBuf>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TwilioSettings {
+    pub auth_token: String,
+    pub webhook_url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DispatchSettings {
+    /// Match pending rides in periodic batches instead of offering each as it is created.
+    pub batch: bool,
+    /// Re-offer rides nobody accepted, widening the search, and enforce the SLA.
+    pub redispatch: bool,
+}
+
+impl Default for DispatchSettings {
+    fn default() -> Self {
+        Self {
+            batch: false,
+            redispatch: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ShutdownSettings {
+    /// After SIGTERM or SIGINT, how long `/readyz` fails before the listener closes.
+    pub pre_drain_delay_secs: u64,
+    /// After SIGTERM or SIGINT, how long in-flight requests and the background workers
+    /// get to finish, counting the pre-drain delay.
+    pub drain_timeout_secs: u64,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        Self {
+            pre_drain_delay_secs: supportcarr_api::shutdown::PRE_DRAIN_DELAY.as_secs(),
+            drain_timeout_secs: supportcarr_api::shutdown::DRAIN_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+impl ShutdownSettings {
+    pub fn grace_period(&self) -> GracePeriod {
+        GracePeriod::new(
+            Duration::from_secs(self.pre_drain_delay_secs),
+            Duration::from_secs(self.drain_timeout_secs),
+        )
+    }
+}
+
+impl ServerConfig {
+    pub fn load(cli: &Cli) -> Result<Self, Box<figment::Error>> {
+        // A file named with `--config` must exist; the default one is optional.
+        let file = match &cli.config {
+            Some(path) => Some(path.clone()),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()),
+        };
+        let mut figment = Figment::new();
+        if let Some(file) = file {
+            figment = figment.merge(Toml::file_exact(file));
+        }
+        figment = figment.merge(
+            Env::prefixed("SUPPORTCARR_")
+                .split("__")
+                .ignore(&["config"]),
+        );
+        cli.overrides()
+            .into_iter()
+            .fold(figment, |figment, (key, value)| {
+                figment.merge(Serialized::default(key, value))
+            })
+            .extract()
+            .map_err(Box::new)
+    }
+}
+
+#[cfg(test)]
+// `Jail` closures return `figment::Result`.
+#[allow(clippy::result_large_err)]
+mod tests {
+    use super::*;
+    use figment::Jail;
+
+    #[test]
+    fn flags_override_env_which_overrides_the_file() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                DEFAULT_CONFIG_FILE,
+                r#"
+                bind = "127.0.0.1:4000"
+
+                [redis]
+                url = "redis://file:6379"
+                key_prefix = "from-file"
+
+                [storage]
+                backend = "sqlite"
+                path = "rides.db"
+                "#,
+            )?;
+            jail.set_env("SUPPORTCARR_REDIS__URL", "redis://env:6379");
+            jail.set_env("SUPPORTCARR_TWILIO__AUTH_TOKEN", "token-from-env");
+            jail.set_env(
+                "SUPPORTCARR_TWILIO__WEBHOOK_URL",
+                "https://example.com/twilio/sms",
+            );
+
+            let cli = Cli {
+                key_prefix: Some("from-flag".to_string()),
+                storage: Some("postgres".to_string()),
+                database_url: Some("postgres://db/supportcarr".to_string()),
+                ..Cli::default()
+            };
+            let config = ServerConfig::load(&cli).unwrap();
+            assert_eq!(config.bind, "127.0.0.1:4000".parse().unwrap());
+            assert_eq!(
+                config.redis,
+                RedisSettings {
+                    url: "redis://env:6379".to_string(),
+                    key_prefix: "from-flag".to_string(),
+                }
+            );
+            assert_eq!(
+                config.storage,
+                StorageSettings::Postgres {
+                    url: "postgres://db/supportcarr".to_string(),
+                    migrate: false,
+                }
+            );
+            assert_eq!(
+                config.twilio.map(|twilio| twilio.auth_token).as_deref(),
+                Some("token-from-env")
+            );
+            assert_eq!(config.dispatch, DispatchSettings::default());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn defaults_apply_without_a_file_and_a_named_file_must_exist() {
+        Jail::expect_with(|_| {
+            assert_eq!(
+                ServerConfig::load(&Cli::default()).unwrap(),
+                ServerConfig::default()
+            );
+            let missing = Cli {
+                config: Some(PathBuf::from("missing.toml")),
+                ..Cli::default()
+            };
+            assert!(ServerConfig::load(&missing).is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn example_config_parses() {
+        let cli = Cli {
+            config: Some(concat!(env!("CARGO_MANIFEST_DIR"), "/supportcarr.example.toml").into()),
+            ..Cli::default()
+        };
+        let config = ServerConfig::load(&cli).unwrap();
+        assert!(matches!(config.storage, StorageSettings::Postgres { .. }));
+        assert!(config.twilio.is_some());
+    }
+}
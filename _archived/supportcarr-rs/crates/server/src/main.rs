@@ -0,0 +1,49 @@
+//! `supportcarr-server`: the ride API and the Twilio SMS webhook in one process.
+//! See [`config`] for the settings and how they are layered.
+
+use std::error::Error;
+use std::process::ExitCode;
+
+use clap::Parser;
+use supportcarr_api::shutdown;
+use tokio::net::TcpListener;
+use tracing_subscriber::EnvFilter;
+
+use crate::app::App;
+use crate::config::{Cli, ServerConfig};
+
+mod app;
+mod config;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            tracing::error!(error = %err, "server stopped");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let config = ServerConfig::load(&cli)?;
+    let app = App::build(&config).await?;
+    let grace = config.shutdown.grace_period();
+    let served = match TcpListener::bind(config.bind).await {
+        Ok(listener) => {
+            let signal = grace.clone().signal(app.state.clone());
+            supportcarr_api::serve(listener, app.router, signal).await
+        }
+        Err(err) => Err(err.into()),
+    };
+    // Also when serving failed, so the workers are not cut off mid-round.
+    app.state.begin_shutdown();
+    shutdown::stop_workers(&app.state, app.workers, grace.deadline()).await;
+    tracing::info!("stopped");
+    served
+}
@@ -30,6 +30,9 @@ pub struct TwilioConfig {
 pub trait TwilioRideStore: Send + Sync {
     async fn find_by_phone(&self, phone: &str) -> Result<Option<Ride>, CoreError>;
     async fn save(&self, ride: Ride) -> Result<(), CoreError>;
+    /// Apply `event` to `ride` only if its stored status is still the one read, failing
+    /// with [`CoreError::StatusChanged`] otherwise.
+    async fn update_status(&self, ride: &Ride, event: RideEvent) -> Result<Ride, CoreError>;
 }
 
 #[derive(Clone)]
@@ -75,17 +78,14 @@ async fn inbound_sms(
         .await?
         .ok_or(TwilioError::NotFound)?;
 
-    let new_status = if payload.body.to_uppercase().contains("CANCEL") {
-        RideStatusMachine::apply_event(RideStatus::try_from(ride.status.as_str())?, RideEvent::Cancel)?
+    let event = if payload.body.to_uppercase().contains("CANCEL") {
+        RideEvent::Cancel
     } else {
-        RideStatusMachine::apply_event(RideStatus::try_from(ride.status.as_str())?, RideEvent::Complete)?
+        RideEvent::Complete
     };
+    let ride = state.store.update_status(&ride, event).await?;
 
-    let mut ride = ride;
-    ride.status = new_status.to_string();
-    state.store.save(ride).await?;
-
-    let reply = if new_status == RideStatus::Completed {
+    let reply = if ride.status == RideStatus::Completed.as_str() {
         "Thanks! Your rescue is marked complete."
     } else {
         "Your rescue has been cancelled."
@@ -100,12 +100,17 @@ pub fn verify_signature(
     body: &[u8],
     signature: &str,
 ) -> Result<bool, TwilioError> {
+    let expected = sign(auth_token, webhook_url, body)?;
+    Ok(subtle::ConstantTimeEq::ct_eq(expected.as_bytes(), signature.as_bytes()).into())
+}
+
+/// The `X-Twilio-Signature` Twilio sends with `body` posted to `webhook_url`.
+pub fn sign(auth_token: &str, webhook_url: &str, body: &[u8]) -> Result<String, TwilioError> {
     let mut mac = HmacSha1::new_from_slice(auth_token.as_bytes())
         .map_err(|_| TwilioError::Unauthorized)?;
     mac.update(webhook_url.as_bytes());
     mac.update(body);
-    let expected = base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
-    Ok(subtle::ConstantTimeEq::ct_eq(expected.as_bytes(), signature.as_bytes()).into())
+    Ok(base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes()))
 }
 
 #[derive(Default)]
@@ -128,6 +133,25 @@ impl TwilioRideStore for InMemoryTwilioRideStore {
             Err(CoreError::InvalidLocation("ride missing phone".into()))
         }
     }
+
+    async fn update_status(&self, ride: &Ride, event: RideEvent) -> Result<Ride, CoreError> {
+        let mut lock = self.rides.write().await;
+        let stored = ride
+            .rider_phone
+            .as_ref()
+            .and_then(|phone| lock.get_mut(phone))
+            .filter(|stored| stored.id == ride.id)
+            .ok_or(CoreError::NotFound)?;
+        if stored.status != ride.status {
+            return Err(CoreError::StatusChanged {
+                expected: ride.status.clone(),
+                found: stored.status.clone(),
+            });
+        }
+        let current = RideStatus::try_from(stored.status.as_str())?;
+        stored.status = RideStatusMachine::apply_event(current, event)?.to_string();
+        Ok(stored.clone())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -151,6 +175,7 @@ impl IntoResponse for TwilioError {
             TwilioError::Core(err) => {
                 let status = match err {
                     CoreError::InvalidStatusTransition { .. } => StatusCode::BAD_REQUEST,
+                    CoreError::StatusChanged { .. } => StatusCode::CONFLICT,
                     _ => StatusCode::INTERNAL_SERVER_ERROR,
                 };
                 (status, err.to_string()).into_response()